@@ -0,0 +1,312 @@
+use crate::api::{
+    claims::{AuthError, Claims},
+    state::AppState,
+};
+use crate::opts::{HttpOpts, WebSocketOpts};
+use crate::shutdown::ShutdownTrigger;
+
+use atb_types::Uuid;
+use axum::{
+    Json, Router,
+    extract::State,
+    http::StatusCode,
+    routing::{get, post},
+};
+use backend_core::llm::tools::linter::LINTER_MODEL;
+use backend_core::refiner::processor::REFINE_MODEL;
+use serde::Serialize;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tracing::instrument;
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/admin/config", get(config_handler))
+        .route("/admin/shutdown", post(shutdown_handler))
+        .route("/admin/auto-linter/pause", post(pause_auto_linter_handler))
+        .route(
+            "/admin/auto-linter/resume",
+            post(resume_auto_linter_handler),
+        )
+}
+
+/// Effective server configuration with secrets masked.
+///
+/// JWT keys are reported by path only; their contents are never read here.
+#[derive(Debug, Serialize)]
+pub struct RedactedConfig {
+    pub host: String,
+    pub origins: Vec<String>,
+    pub client_ip_source: String,
+    pub jwt_priv_key: Option<PathBuf>,
+    pub jwt_pub_key: Option<PathBuf>,
+    pub rate_limit_per_minute: u32,
+    pub rate_limit_subject_per_minute: u32,
+    pub ws_ai_commands_per_minute: u32,
+    pub request_id_header: String,
+    pub admin_subjects: Vec<Uuid>,
+    pub record_updates: Option<PathBuf>,
+    pub max_doc_bytes: usize,
+    pub max_ws_update_bytes: usize,
+    pub ws_ping_interval_ms: u64,
+    pub ws_max_missed_pongs: u32,
+    pub max_text_chars: usize,
+    pub max_body_bytes: usize,
+    pub readyz_check_openai: bool,
+    pub ws: WebSocketOpts,
+    pub openai_api_key: String,
+    pub refine_model: &'static str,
+    pub linter_model: &'static str,
+}
+
+impl RedactedConfig {
+    pub fn new(opts: &HttpOpts, api_key: &str) -> Self {
+        Self {
+            host: opts.host.clone(),
+            origins: opts.origins.clone(),
+            client_ip_source: format!("{:?}", opts.client_ip_source),
+            jwt_priv_key: opts.jwt_priv_key.clone(),
+            jwt_pub_key: opts.jwt_pub_key.clone(),
+            rate_limit_per_minute: opts.rate_limit_per_minute,
+            rate_limit_subject_per_minute: opts.rate_limit_subject_per_minute,
+            ws_ai_commands_per_minute: opts.ws_ai_commands_per_minute,
+            request_id_header: opts.request_id_header.clone(),
+            admin_subjects: opts.admin_subjects.clone(),
+            record_updates: opts.record_updates.clone(),
+            max_doc_bytes: opts.max_doc_bytes,
+            max_ws_update_bytes: opts.max_ws_update_bytes,
+            ws_ping_interval_ms: opts.ws_ping_interval_ms,
+            ws_max_missed_pongs: opts.ws_max_missed_pongs,
+            max_text_chars: opts.max_text_chars,
+            max_body_bytes: opts.max_body_bytes,
+            readyz_check_openai: opts.readyz_check_openai,
+            ws: opts.ws.clone(),
+            openai_api_key: mask_secret(api_key),
+            refine_model: REFINE_MODEL,
+            linter_model: LINTER_MODEL,
+        }
+    }
+}
+
+/// Enough of a secret to tell which one is configured, never the secret itself
+fn mask_secret(secret: &str) -> String {
+    let chars: Vec<char> = secret.chars().collect();
+    match chars.len() {
+        0 => String::new(),
+        n if n < 16 => "********".to_string(),
+        n => format!("********{}", chars[n - 4..].iter().collect::<String>()),
+    }
+}
+
+/// Only subjects listed in `--admin-subjects` pass; an empty list locks the endpoints.
+pub(crate) fn require_admin(claims: &Claims, admins: &[Uuid]) -> Result<Uuid, AuthError> {
+    let subject = claims
+        .subject_as_uuid()
+        .map_err(|_| AuthError::InvalidToken)?;
+    if !admins.contains(&subject) {
+        return Err(AuthError::Forbidden);
+    }
+    Ok(subject)
+}
+
+/// Show the resolved configuration, for debugging a deployment without shell access.
+#[instrument(skip(claims, state))]
+pub async fn config_handler(
+    claims: Claims,
+    State(state): State<AppState>,
+) -> Result<Json<RedactedConfig>, AuthError> {
+    let admin = require_admin(&claims, &state.http_opts.admin_subjects)?;
+    tracing::info!(%admin, "🔧 config requested");
+    Ok(Json(RedactedConfig::new(&state.http_opts, &state.api_key)))
+}
+
+/// Start the same graceful shutdown as SIGTERM: stop accepting, drain, then exit.
+/// Calling it again while draining is a no-op.
+#[instrument(skip(claims, opts, shutdown))]
+pub async fn shutdown_handler(
+    claims: Claims,
+    State(opts): State<Arc<HttpOpts>>,
+    State(shutdown): State<ShutdownTrigger>,
+) -> Result<StatusCode, AuthError> {
+    let admin = require_admin(&claims, &opts.admin_subjects)?;
+    if shutdown.trigger() {
+        tracing::warn!(%admin, "🛑 shutdown requested, draining connections");
+    } else {
+        tracing::info!(%admin, "🛑 shutdown already in progress");
+    }
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Hold the background auto-agents, e.g. to stop OpenAI spend without a restart.
+#[instrument(skip(claims, state))]
+pub async fn pause_auto_linter_handler(
+    claims: Claims,
+    State(state): State<AppState>,
+) -> Result<StatusCode, AuthError> {
+    let admin = require_admin(&claims, &state.http_opts.admin_subjects)?;
+    state.auto_linter.pause();
+    tracing::warn!(%admin, "⏸️ auto-linter paused");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Let the background auto-agents run again; edits made while paused are checked next.
+#[instrument(skip(claims, state))]
+pub async fn resume_auto_linter_handler(
+    claims: Claims,
+    State(state): State<AppState>,
+) -> Result<StatusCode, AuthError> {
+    let admin = require_admin(&claims, &state.http_opts.admin_subjects)?;
+    state.auto_linter.resume();
+    tracing::info!(%admin, "▶️ auto-linter resumed");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opts::Decoder;
+    use atb_cli_utils::clap::Parser;
+    use axum::{body::Body, extract::FromRef, http::Request};
+    use std::time::Duration;
+    use tokio::sync::Notify;
+    use tower::ServiceExt;
+
+    #[derive(Clone, FromRef)]
+    struct ShutdownState {
+        opts: Arc<HttpOpts>,
+        shutdown: ShutdownTrigger,
+        decoder: Decoder,
+    }
+
+    fn admin() -> Uuid {
+        Uuid::from_u128(1)
+    }
+
+    fn shutdown_app() -> Router<ShutdownState> {
+        Router::new().route("/admin/shutdown", post(shutdown_handler))
+    }
+
+    fn shutdown_state(shutdown: ShutdownTrigger) -> ShutdownState {
+        let admins = admin().to_string();
+        ShutdownState {
+            opts: Arc::new(HttpOpts::parse_from([
+                "backend",
+                "--admin-subjects",
+                &admins,
+            ])),
+            shutdown,
+            decoder: Decoder(atb::fixtures::jwt::JWT_DECODING_KEY.clone()),
+        }
+    }
+
+    fn token(subject: Uuid) -> String {
+        atb_types::prelude::Builder::with_custom("tt", atb_types::Duration::minutes(5), None::<()>)
+            .subject(subject)
+            .audience(vec![])
+            .build_fingerprinted()
+            .0
+            .encode(
+                &atb_types::jwt::HEADER_RS256,
+                &atb::fixtures::jwt::JWT_ENCODING_KEY,
+            )
+            .unwrap()
+    }
+
+    fn shutdown_request(subject: Uuid) -> Request<Body> {
+        Request::post("/admin/shutdown")
+            .header("authorization", format!("Bearer {}", token(subject)))
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_only_admins_can_shut_down() {
+        let shutdown = ShutdownTrigger::new();
+        let app = shutdown_app().with_state(shutdown_state(shutdown.clone()));
+
+        let response = app
+            .clone()
+            .oneshot(shutdown_request(Uuid::from_u128(2)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let anonymous = Request::post("/admin/shutdown")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(anonymous).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(!shutdown.is_triggered());
+
+        let response = app.oneshot(shutdown_request(admin())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        assert!(shutdown.is_triggered());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_drains_in_flight_requests() {
+        let shutdown = ShutdownTrigger::new();
+        // Stands in for a request still being served when shutdown starts
+        let finish = Arc::new(Notify::new());
+        let slow = finish.clone();
+        let app = shutdown_app()
+            .route(
+                "/slow",
+                get(move || async move {
+                    slow.notified().await;
+                    "done"
+                }),
+            )
+            .with_state(shutdown_state(shutdown.clone()));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let server =
+            tokio::spawn(axum::serve(listener, app).with_graceful_shutdown(shutdown.wait()));
+
+        let client = reqwest::Client::new();
+        let in_flight = tokio::spawn(client.get(format!("{base}/slow")).send());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let response = client
+            .post(format!("{base}/admin/shutdown"))
+            .bearer_auth(token(admin()))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::ACCEPTED);
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(
+            !server.is_finished(),
+            "the slow request is still being served"
+        );
+
+        finish.notify_one();
+        let response = in_flight.await.unwrap().unwrap();
+        assert_eq!(response.text().await.unwrap(), "done");
+        tokio::time::timeout(Duration::from_secs(1), server)
+            .await
+            .expect("server stops once drained")
+            .unwrap()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_api_key_is_redacted() {
+        let api_key = "sk-proj-0123456789abcdefSECRET";
+        let opts = HttpOpts::parse_from(["backend", "--jwt-pub-key", "/etc/backend/jwt.pub"]);
+
+        let body = serde_json::to_value(RedactedConfig::new(&opts, api_key)).unwrap();
+        let text = body.to_string();
+
+        assert!(!text.contains(api_key));
+        assert!(!text.contains("0123456789"));
+        assert_eq!(body["openai_api_key"], "********CRET");
+        assert_eq!(body["jwt_pub_key"], "/etc/backend/jwt.pub");
+        assert_eq!(body["refine_model"], REFINE_MODEL);
+    }
+
+    #[test]
+    fn test_short_secrets_are_fully_masked() {
+        assert_eq!(mask_secret(""), "");
+        assert_eq!(mask_secret("sk-short"), "********");
+    }
+}
@@ -1,59 +1,605 @@
-use crate::api::{
-    errors::Error,
-    state::{AppState, MessageStructure},
+use crate::api::{errors::Error, state::AppState};
+use crate::linter_task::corrections_preview;
+use crate::model::{
+    CustomRefineRequest, DocTarget, LintPreviewRequest, LintPreviewResponse, LinterResponse,
+    RefineAction, RefineRequest, RefineResponse, SummarizeRequest, ToneRequest, TranslateRequest,
 };
-use crate::model::{RefineRequest, RefineResponse};
-use atb_ai_utils::agent::AgentContext;
-use atb_types::Uuid;
+use crate::opts::HttpOpts;
 use axum::{
     Router,
-    extract::{Json, State},
+    extract::{Json, State, rejection::JsonRejection},
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
     routing::post,
 };
+use backend_core::editor::{TextPatch, get_doc_text, patch_text_nodes, text_node_contents};
+use backend_core::llm::coalesce::CoalesceKey;
+use backend_core::llm::lint_preview;
 use backend_core::llm::new_linter;
+use backend_core::llm::tools::linter::{DocumentChanged, LINTER_MODEL};
+use backend_core::llm::tools::summarizer::{self, SUMMARIZER_MODEL};
+use backend_core::llm::tools::tone;
+use backend_core::llm::tools::translator;
+use backend_core::refiner::error::RefineError;
+use backend_core::refiner::language::Language;
 use backend_core::refiner::processor::{
-    call_fix_api, call_improve_api, call_longer_api, call_shorter_api,
+    REFINE_MODEL, call_custom_api, call_fix_api, call_improve_api, call_longer_api,
+    call_shorter_api, check_instruction,
 };
 use backend_core::refiner::types::{RefineInput, RefineOutput};
-use std::pin::Pin;
+use backend_core::sqlx_postgres::ai_events::{self, AiEventStatus, NewAiEvent, SHARED_DOC};
+use futures::future::{BoxFuture, FutureExt};
+use std::sync::Arc;
+use std::time::Instant;
+use tower_http::limit::RequestBodyLimitLayer;
 use tracing::instrument;
-use yrs::{ReadTxn, Transact};
+use yrs::{Doc, ReadTxn, StateVector, Transact};
 
-pub type AgentCache = mini_moka::sync::Cache<Uuid, (String, AgentContext)>;
-
-type RefineFuture<'a> =
-    Pin<Box<dyn std::future::Future<Output = anyhow::Result<RefineOutput>> + Send + 'a>>;
+type RefineFuture = BoxFuture<'static, Result<RefineOutput, RefineError>>;
 
 pub fn routes() -> Router<AppState> {
     Router::new()
         // refine API
+        .route("/refine", post(refine_handler))
+        .route("/refine/custom", post(custom_refine_handler))
+        .route("/refine/tone", post(tone_refine_handler))
+        .route("/translate", post(translate_handler))
+        .route("/summarize", post(summarize_handler))
         .route("/improve", post(improve_text_handler))
         .route("/fix", post(fix_text_handler))
         .route("/longer", post(longer_text_handler))
         .route("/shorter", post(shorter_text_handler))
         .route("/linter", post(linter_text_handler))
+        .route("/editor/lint/preview", post(lint_preview_handler))
+}
+
+/// Cap AI request bodies at `--max-body-bytes`; larger ones get 413 before any handler runs
+pub fn body_limit(opts: &HttpOpts) -> RequestBodyLimitLayer {
+    RequestBodyLimitLayer::new(opts.max_body_bytes)
+}
+
+/// `body_limit` answers in plain text; give its 413 the JSON error envelope.
+/// Layer this outside `body_limit` so it sees that response.
+pub async fn oversized_body_as_json(response: Response) -> Response {
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|value| value.as_bytes().starts_with(b"application/json"));
+    if response.status() == StatusCode::PAYLOAD_TOO_LARGE && !is_json {
+        return Error::PayloadTooLarge.into_response();
+    }
+    response
+}
+
+/// The upstream call behind each refine action
+pub(crate) fn refine_call(action: RefineAction) -> fn(RefineInput, Arc<str>) -> RefineFuture {
+    match action {
+        RefineAction::Improve => {
+            |input, key| Box::pin(async move { call_improve_api(input, &key).await })
+        }
+        RefineAction::Fix => |input, key| Box::pin(async move { call_fix_api(input, &key).await }),
+        RefineAction::Longer => {
+            |input, key| Box::pin(async move { call_longer_api(input, &key).await })
+        }
+        RefineAction::Shorter => {
+            |input, key| Box::pin(async move { call_shorter_api(input, &key).await })
+        }
+    }
+}
+
+/// Resolve an optional target language against the allow-list
+fn validate_language(language: Option<&str>) -> Result<Option<Language>, Error> {
+    Language::parse_optional(language).map_err(|e| Error::Validation {
+        field: "language",
+        message: e.to_string(),
+    })
 }
 
-// refine by single task
-async fn handle_refine_request<F>(
+/// What makes two requests for the same tool identical: the text and its target language
+fn coalesce_content(text: &str, language: Option<Language>) -> String {
+    match language {
+        Some(language) => format!("{}\n{}", language.tag, text),
+        None => text.to_string(),
+    }
+}
+
+/// Tone and audience change the prompt, so they also keep coalesced refines apart
+fn coalesce_style(content: String, tone: Option<&str>, audience: Option<&str>) -> String {
+    match (tone, audience) {
+        (None, None) => content,
+        (tone, audience) => format!(
+            "{}\n{}\n{content}",
+            tone.unwrap_or_default(),
+            audience.unwrap_or_default()
+        ),
+    }
+}
+
+/// Reject empty text, and text over `max_chars`, before it is sent upstream
+pub(crate) fn validate_text(text: &str, max_chars: usize) -> Result<(), Error> {
+    if text.trim().is_empty() {
+        return Err(Error::Validation {
+            field: "text",
+            message: "text must not be empty".to_string(),
+        });
+    }
+    let chars = text.chars().count();
+    if chars > max_chars {
+        return Err(Error::Validation {
+            field: "text",
+            message: format!("text is {chars} characters long; the limit is {max_chars}"),
+        });
+    }
+    Ok(())
+}
+
+/// Check a refine request before spending an upstream call on it
+fn validate_refine(req: &RefineRequest, max_chars: usize) -> Result<RefineAction, Error> {
+    let action = refine_action(req)?;
+    validate_text(&req.text, max_chars)?;
+    Ok(action)
+}
+
+fn refine_action(req: &RefineRequest) -> Result<RefineAction, Error> {
+    req.action.ok_or_else(|| Error::Validation {
+        field: "action",
+        message: "action is required: one of IMPROVE, FIX, LONGER, SHORTER".to_string(),
+    })
+}
+
+/// A passage of the shared document that a targeted refine rewrites in place
+#[derive(Debug, PartialEq, Eq)]
+enum Passage {
+    /// A whole text node, addressed by its position in `text_node_contents`
+    Node { node: usize, original: String },
+    /// The selected text `original`, found at byte `offset` of text node `node`
+    /// whose whole text was `node_text` when it was read
+    Selection {
+        node: usize,
+        offset: usize,
+        original: String,
+        node_text: String,
+    },
+}
+
+impl Passage {
+    fn text(&self) -> &str {
+        match self {
+            Self::Node { original, .. } | Self::Selection { original, .. } => original,
+        }
+    }
+}
+
+fn invalid_target(message: &str) -> Error {
+    Error::Validation {
+        field: "target",
+        message: message.to_string(),
+    }
+}
+
+/// Only the shared document is live so far; any other id is refused
+fn validate_doc_id(doc_id: Option<&str>) -> Result<(), Error> {
+    match doc_id {
+        Some(doc_id) if doc_id != SHARED_DOC => Err(Error::Validation {
+            field: "doc_id",
+            message: format!("unknown live document: {doc_id}"),
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Read the passages a request targets from the shared document: every
+/// non-blank paragraph for the whole document, or the selected text.
+///
+/// Offsets and text are taken from the document's plain text, without the
+/// formatting marks `get_doc_content` renders.
+fn read_target(doc: &Arc<Doc>, target: DocTarget) -> Result<Vec<Passage>, Error> {
+    let (start, end) = match target {
+        DocTarget::Document => {
+            return Ok(text_node_contents(doc)
+                .into_iter()
+                .enumerate()
+                .filter(|(_, text)| !text.trim().is_empty())
+                .map(|(node, original)| Passage::Node { node, original })
+                .collect());
+        }
+        DocTarget::Selection { start, end } => (start, end),
+    };
+    let content = get_doc_text(doc);
+    // Character offsets to byte offsets; `end` may point just past the last character
+    let byte_offset = |i: usize| {
+        content
+            .char_indices()
+            .map(|(b, _)| b)
+            .chain([content.len()])
+            .nth(i)
+    };
+    let (Some(start), Some(end)) = (byte_offset(start), byte_offset(end)) else {
+        return Err(invalid_target("selection is outside the document"));
+    };
+    if start >= end {
+        return Err(invalid_target("selection is empty"));
+    }
+    let original = &content[start..end];
+    if original.contains('\n') {
+        return Err(invalid_target("selection must stay within one paragraph"));
+    }
+    // The same occurrence among the text nodes, where the result is written back
+    let nth = content[..start].matches(original).count();
+    let nodes = text_node_contents(doc);
+    let found = nodes
+        .iter()
+        .enumerate()
+        .flat_map(|(node, text)| {
+            text.match_indices(original)
+                .map(move |(offset, _)| (node, offset))
+        })
+        .nth(nth);
+    let Some((node, offset)) = found else {
+        return Err(invalid_target("selection must stay within one paragraph"));
+    };
+    Ok(vec![Passage::Selection {
+        node,
+        offset,
+        original: original.to_string(),
+        node_text: nodes[node].clone(),
+    }])
+}
+
+/// Put refined passages back into the shared document, editing only their text
+/// so the structure and marks around them stay; observers broadcast it like any edit.
+///
+/// A paragraph edited in the meantime keeps the writer's text; so does a
+/// selection, but that is reported as an error.
+fn write_back(doc: &Arc<Doc>, refined: &[(Passage, String)]) -> Result<(), Error> {
+    let mut patches = Vec::new();
+    for (passage, text) in refined {
+        match passage {
+            Passage::Node { node, original } => {
+                // The refiner answers trimmed; keep the paragraph's own outer whitespace
+                let trimmed = original.trim_start();
+                let leading = &original[..original.len() - trimmed.len()];
+                let trailing = &trimmed[trimmed.trim_end().len()..];
+                patches.push(TextPatch {
+                    node: *node,
+                    original: original.clone(),
+                    replacement: format!("{leading}{}{trailing}", text.trim()),
+                });
+            }
+            Passage::Selection {
+                node,
+                offset,
+                original,
+                node_text,
+            } => {
+                if text == original {
+                    continue;
+                }
+                let end = offset + original.len();
+                let patch = TextPatch {
+                    node: *node,
+                    original: node_text.clone(),
+                    replacement: format!("{}{text}{}", &node_text[..*offset], &node_text[end..]),
+                };
+                if patch_text_nodes(doc, &[patch]).map_err(Error::Internal)? == 0 {
+                    return Err(invalid_target(
+                        "the selection changed while it was being refined",
+                    ));
+                }
+            }
+        }
+    }
+    if !patches.is_empty() {
+        patch_text_nodes(doc, &patches).map_err(Error::Internal)?;
+    }
+    Ok(())
+}
+
+// refine by single task; identical concurrent requests share one upstream call
+async fn handle_refine_request(
     state: &AppState,
     req: RefineRequest,
-    refine_fn: F,
-) -> Result<Json<RefineResponse>, Error>
-where
-    F: for<'a> FnOnce(RefineInput, &'a str) -> RefineFuture<'a>,
-{
-    let input = RefineInput { content: req.text };
-    refine_fn(input, &state.api_key)
-        .await
-        .map(|result| {
-            Json(RefineResponse {
-                text: result.content,
+) -> Result<Json<RefineResponse>, Error> {
+    let Some(target) = req.target else {
+        let action = validate_refine(&req, state.http_opts.max_text_chars)?;
+        let language = validate_language(req.language.as_deref())?;
+        let text = refine_text(state, action, language, &req, req.text.clone()).await?;
+        return Ok(Json(RefineResponse { text }));
+    };
+    if !req.text.is_empty() {
+        return Err(invalid_target("send either text or target, not both"));
+    }
+    validate_doc_id(req.doc_id.as_deref())?;
+    let action = refine_action(&req)?;
+    let language = validate_language(req.language.as_deref())?;
+
+    // Keep the linter off the fragment until the results are written back
+    let _lint_guard = state.auto_agents.lint_running.clone().lock_owned().await;
+    let passages = read_target(&state.editor_doc, target)?;
+    let joined = passages
+        .iter()
+        .map(Passage::text)
+        .collect::<Vec<_>>()
+        .join("\n");
+    validate_text(&joined, state.http_opts.max_text_chars)?;
+
+    // Each paragraph is refined on its own, so it can be written back on its own
+    let mut refined = Vec::new();
+    for passage in passages {
+        let text = refine_text(state, action, language, &req, passage.text().to_string()).await?;
+        refined.push((passage, text));
+    }
+    write_back(&state.editor_doc, &refined)?;
+    tracing::info!(
+        "✍️ wrote {} {} results back into the document",
+        refined.len(),
+        action.tool()
+    );
+    let text = refined
+        .into_iter()
+        .map(|(_, text)| text)
+        .collect::<Vec<_>>()
+        .join("\n");
+    Ok(Json(RefineResponse { text }))
+}
+
+/// One refine call for `text`, shared with identical requests in flight
+async fn refine_text(
+    state: &AppState,
+    action: RefineAction,
+    language: Option<Language>,
+    req: &RefineRequest,
+    text: String,
+) -> Result<String, Error> {
+    let key = CoalesceKey::new(
+        action.tool(),
+        &coalesce_style(
+            coalesce_content(&text, language),
+            req.tone.as_deref(),
+            req.audience.as_deref(),
+        ),
+        REFINE_MODEL,
+    );
+    let api_key = state.api_key.clone();
+    let refine_fn = refine_call(action);
+    let original = text.clone();
+    let input = RefineInput {
+        content: text,
+        language: language.map(|language| language.tag.to_string()),
+        tone: req.tone.clone(),
+        audience: req.audience.clone(),
+    };
+    let started = Instant::now();
+    let result = state
+        .coalescer
+        .run(key, move || {
+            refine_fn(input, api_key).map(|result| {
+                result
+                    .map(|output| output.content)
+                    .map_err(anyhow::Error::from)
             })
         })
+        .await;
+    record_history(state, action.tool(), &original, &result, started);
+    result.map_err(|e| {
+        tracing::error!("Refine failed: {:?}", e);
+        Error::from_ai(&e)
+    })
+}
+
+/// Add a finished AI call to the suggestion history without waiting on the write
+fn record_history(
+    state: &AppState,
+    tool: &str,
+    input: &str,
+    result: &anyhow::Result<String>,
+    started: Instant,
+) {
+    let (output, status) = match result {
+        Ok(text) => (text.as_str(), AiEventStatus::Ok),
+        Err(_) => ("", AiEventStatus::Error),
+    };
+    let event = NewAiEvent::new(tool, input, output, status, started.elapsed());
+    ai_events::spawn_record(&state.pg_pool, event);
+}
+
+#[instrument(skip(state, req))]
+pub async fn refine_handler(
+    State(state): State<AppState>,
+    req: Result<Json<RefineRequest>, JsonRejection>,
+) -> Result<Json<RefineResponse>, Error> {
+    let Json(req) = req?;
+    handle_refine_request(&state, req).await
+}
+
+/// Check a custom refine request; the instruction comes back trimmed
+fn validate_custom_refine(req: &CustomRefineRequest, max_chars: usize) -> Result<String, Error> {
+    validate_text(&req.text, max_chars)?;
+    check_instruction(&req.instruction)
+        .map(str::to_string)
+        .map_err(|e| Error::Validation {
+            field: "instruction",
+            message: e.to_string(),
+        })
+}
+
+/// Refine text following the writer's own instruction.
+#[instrument(skip(state, req))]
+pub async fn custom_refine_handler(
+    State(state): State<AppState>,
+    req: Result<Json<CustomRefineRequest>, JsonRejection>,
+) -> Result<Json<RefineResponse>, Error> {
+    let Json(req) = req?;
+    let instruction = validate_custom_refine(&req, state.http_opts.max_text_chars)?;
+    let language = validate_language(req.language.as_deref())?;
+    // The instruction is part of the prompt, so it is part of what makes two requests identical
+    let key = CoalesceKey::new(
+        "custom",
+        &format!("{instruction}\n{}", coalesce_content(&req.text, language)),
+        REFINE_MODEL,
+    );
+    let api_key = state.api_key.clone();
+    let input = RefineInput {
+        content: req.text,
+        language: language.map(|language| language.tag.to_string()),
+        tone: None,
+        audience: None,
+    };
+    state
+        .coalescer
+        .run(key, move || async move {
+            call_custom_api(input, &instruction, &api_key)
+                .await
+                .map(|output| output.content)
+                .map_err(anyhow::Error::from)
+        })
+        .await
+        .map(|text| Json(RefineResponse { text }))
+        .map_err(|e| {
+            tracing::error!("Custom refine failed: {:?}", e);
+            Error::from_ai(&e)
+        })
+}
+
+/// Rewrite text in one of the preset tones; an unknown preset fails to parse, a 422.
+#[instrument(skip(state, req))]
+pub async fn tone_refine_handler(
+    State(state): State<AppState>,
+    req: Result<Json<ToneRequest>, JsonRejection>,
+) -> Result<Json<RefineResponse>, Error> {
+    let Json(req) = req?;
+    validate_text(&req.text, state.http_opts.max_text_chars)?;
+    let language = validate_language(req.language.as_deref())?;
+    let preset = req.preset;
+    let key = CoalesceKey::new(
+        "tone",
+        &format!(
+            "{}\n{}",
+            preset.as_str(),
+            coalesce_content(&req.text, language)
+        ),
+        REFINE_MODEL,
+    );
+    let api_key = state.api_key.clone();
+    let input = RefineInput {
+        content: req.text,
+        language: language.map(|language| language.tag.to_string()),
+        tone: None,
+        audience: None,
+    };
+    state
+        .coalescer
+        .run(key, move || async move {
+            tone::execute_tool(input, preset, &api_key)
+                .await
+                .map(|output| output.content)
+                .map_err(anyhow::Error::from)
+        })
+        .await
+        .map(|text| Json(RefineResponse { text }))
+        .map_err(|e| {
+            tracing::error!("Tone refine failed: {:?}", e);
+            Error::from_ai(&e)
+        })
+}
+
+/// Check a translate request; the target comes back resolved against the allow-list
+fn validate_translate(req: &TranslateRequest, max_chars: usize) -> Result<Language, Error> {
+    validate_text(&req.text, max_chars)?;
+    Language::parse(&req.target_lang).map_err(|e| Error::Validation {
+        field: "target_lang",
+        message: e.to_string(),
+    })
+}
+
+/// Translate text into another supported language.
+#[instrument(skip(state, req))]
+pub async fn translate_handler(
+    State(state): State<AppState>,
+    req: Result<Json<TranslateRequest>, JsonRejection>,
+) -> Result<Json<RefineResponse>, Error> {
+    let Json(req) = req?;
+    let target = validate_translate(&req, state.http_opts.max_text_chars)?;
+    let key = CoalesceKey::new(
+        "translate",
+        &coalesce_content(&req.text, Some(target)),
+        REFINE_MODEL,
+    );
+    let api_key = state.api_key.clone();
+    state
+        .coalescer
+        .run(key, move || async move {
+            translator::execute_tool(&req.text, target.tag, &api_key)
+                .await
+                .map_err(anyhow::Error::from)
+        })
+        .await
+        .map(|text| Json(RefineResponse { text }))
         .map_err(|e| {
-            tracing::error!("Refine failed: {:?}", e);
-            Error::InvalidInput(e.to_string())
+            tracing::error!("Translate failed: {:?}", e);
+            Error::from_ai(&e)
+        })
+}
+
+/// The shared document's text, or 422 when there is nothing in it to work on
+fn document_text(doc: &Arc<Doc>) -> Result<String, Error> {
+    let content = backend_core::editor::get_doc_content(doc);
+    if content.trim().is_empty() {
+        return Err(Error::Validation {
+            field: "document",
+            message: "the document is empty".to_string(),
+        });
+    }
+    Ok(content)
+}
+
+/// The request's own text, checked like any refine input, or else the shared document
+fn text_to_summarize(
+    req: &SummarizeRequest,
+    doc: &Arc<Doc>,
+    max_chars: usize,
+) -> Result<String, Error> {
+    match &req.text {
+        Some(text) => {
+            validate_text(text, max_chars)?;
+            Ok(text.clone())
+        }
+        None => document_text(doc),
+    }
+}
+
+/// Summarize raw text, or the whole shared document, in the requested style.
+#[instrument(skip(state, req))]
+pub async fn summarize_handler(
+    State(state): State<AppState>,
+    req: Result<Json<SummarizeRequest>, JsonRejection>,
+) -> Result<Json<RefineResponse>, Error> {
+    let Json(req) = req?;
+    let language = validate_language(req.language.as_deref())?;
+    let content = text_to_summarize(&req, &state.editor_doc, state.http_opts.max_text_chars)?;
+    let style = req.style;
+    let key = CoalesceKey::new(
+        "summarize",
+        &format!(
+            "{}\n{}",
+            style.as_str(),
+            coalesce_content(&content, language)
+        ),
+        SUMMARIZER_MODEL,
+    );
+    let api_key = state.api_key.clone();
+    state
+        .coalescer
+        .run(key, move || async move {
+            summarizer::execute_tool(&content, style, language, &api_key)
+                .await
+                .map_err(anyhow::Error::from)
+        })
+        .await
+        .map(|text| Json(RefineResponse { text }))
+        .map_err(|e| {
+            tracing::error!("Summarize failed: {:?}", e);
+            Error::from_ai(&e)
         })
 }
 
@@ -63,10 +609,7 @@ pub async fn improve_text_handler(
     State(state): State<AppState>,
     Json(req): Json<RefineRequest>,
 ) -> Result<Json<RefineResponse>, Error> {
-    handle_refine_request(&state, req, |input, key| {
-        Box::pin(call_improve_api(input, key))
-    })
-    .await
+    handle_refine_request(&state, with_action(req, RefineAction::Improve)).await
 }
 
 /// Fix grammar and spelling errors in text.
@@ -75,7 +618,7 @@ pub async fn fix_text_handler(
     State(state): State<AppState>,
     Json(req): Json<RefineRequest>,
 ) -> Result<Json<RefineResponse>, Error> {
-    handle_refine_request(&state, req, |input, key| Box::pin(call_fix_api(input, key))).await
+    handle_refine_request(&state, with_action(req, RefineAction::Fix)).await
 }
 
 /// Lengthen text while maintaining meaning.
@@ -84,10 +627,7 @@ pub async fn longer_text_handler(
     State(state): State<AppState>,
     Json(req): Json<RefineRequest>,
 ) -> Result<Json<RefineResponse>, Error> {
-    handle_refine_request(&state, req, |input, key| {
-        Box::pin(call_longer_api(input, key))
-    })
-    .await
+    handle_refine_request(&state, with_action(req, RefineAction::Longer)).await
 }
 
 /// Shorten text while maintaining meaning.
@@ -96,51 +636,578 @@ pub async fn shorter_text_handler(
     State(state): State<AppState>,
     Json(req): Json<RefineRequest>,
 ) -> Result<Json<RefineResponse>, Error> {
-    handle_refine_request(&state, req, |input, key| {
-        Box::pin(call_shorter_api(input, key))
-    })
-    .await
+    handle_refine_request(&state, with_action(req, RefineAction::Shorter)).await
+}
+
+/// The per-action routes predate `action`; the path decides it, whatever the body says
+fn with_action(req: RefineRequest, action: RefineAction) -> RefineRequest {
+    RefineRequest {
+        action: Some(action),
+        ..req
+    }
 }
 
-#[instrument(skip(state, _req))]
+#[instrument(skip(state, req))]
 pub async fn linter_text_handler(
     State(state): State<AppState>,
-    Json(_req): Json<RefineRequest>,
-) -> Result<Json<RefineResponse>, Error> {
+    Json(req): Json<RefineRequest>,
+) -> Result<Json<LinterResponse>, Error> {
+    let language = validate_language(req.language.as_deref())?;
     tracing::info!(
         "Linter handler called, modifying document. Current subscribers: {}",
         state.editor_broadcast_tx.receiver_count()
     );
 
-    // The linter modifies the document, which should trigger the observer
-    // in mono.rs to automatically broadcast the update via WebSocket
-    new_linter(&state.api_key, state.editor_doc.clone())
+    // Remember where the doc was so we can tell how big the linter's edit really is
+    let before_sv = state.editor_doc.transact().state_vector();
+
+    // The linter commits its own transaction; the update observer broadcasts
+    // exactly that delta to every WebSocket client, so no manual send is needed.
+    // Several clients asking to lint the same content share one linter pass.
+    let key = CoalesceKey::new(
+        "linter",
+        &coalesce_content(
+            &backend_core::editor::get_doc_content(&state.editor_doc),
+            language,
+        ),
+        LINTER_MODEL,
+    );
+    let api_key = state.api_key.clone();
+    let doc = state.editor_doc.clone();
+    let lint_running = state.auto_agents.lint_running.clone();
+    let lint_mode = state.editor_opts.lint_mode();
+    let started = Instant::now();
+    let input = backend_core::editor::get_doc_content(&state.editor_doc);
+    let corrections = state
+        .lint_coalescer
+        .run(key, move || async move {
+            // Wait out an auto-linter pass rather than interleave with it
+            let _lint_guard = lint_running.lock_owned().await;
+            // Shared errors lose their type, so a stale result travels as a value
+            match new_linter(&api_key, doc, None, language, lint_mode).await {
+                Err(e) if e.is::<DocumentChanged>() => Ok(Err(DocumentChanged)),
+                result => result.map(Ok),
+            }
+        })
+        .await
+        .and_then(|result| result.map_err(anyhow::Error::from));
+    let summary = match &corrections {
+        Ok(corrections) => Ok(corrections_preview(corrections)),
+        Err(e) => Err(anyhow::anyhow!("{e}")),
+    };
+    record_history(&state, "linter", &input, &summary, started);
+    let corrections = corrections.map_err(|e| {
+        tracing::error!("Linter failed: {:?}", e);
+        Error::from_ai(&e)
+    })?;
+
+    tracing::info!(
+        "✅ Linter made {} corrections, {} new items for {} subscribers",
+        corrections.len(),
+        changes_since(&state.editor_doc, &before_sv),
+        state.editor_broadcast_tx.receiver_count()
+    );
+
+    Ok(Json(LinterResponse { corrections }))
+}
+
+/// What the linter would change in the shared document, without changing it.
+///
+/// Nothing is written, so this neither waits on nor blocks the auto-linter.
+#[instrument(skip(state, req))]
+pub async fn lint_preview_handler(
+    State(state): State<AppState>,
+    req: Result<Json<LintPreviewRequest>, JsonRejection>,
+) -> Result<Json<LintPreviewResponse>, Error> {
+    let Json(req) = req?;
+    let language = validate_language(req.language.as_deref())?;
+    document_text(&state.editor_doc)?;
+
+    let diffs = lint_preview(&state.api_key, state.editor_doc.clone(), language)
         .await
         .map_err(|e| {
-            tracing::error!("Linter failed: {:?}", e);
-            Error::InvalidInput(e.to_string())
+            tracing::error!("Lint preview failed: {:?}", e);
+            Error::from_ai(&e)
         })?;
+    tracing::info!("🔍 Lint preview suggests {} changes", diffs.len());
+    Ok(Json(LintPreviewResponse {
+        diffs: state.pending_edits.offer(diffs),
+    }))
+}
 
-    // Manually encode and broadcast the update to ensure it's sent
-    // (Observer might not trigger in async context, so we do it manually)
-    let update = {
-        let txn = state.editor_doc.transact();
-        txn.encode_state_as_update_v1(&yrs::StateVector::default())
-    };
+/// How far the clocks of `doc` moved since `before_sv` was captured, i.e. how
+/// many items the edit added; cheaper than encoding the delta just to size it.
+fn changes_since(doc: &Doc, before_sv: &StateVector) -> u32 {
+    doc.transact()
+        .state_vector()
+        .iter()
+        .map(|(client, clock)| clock - before_sv.get(client))
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use atb_cli_utils::clap::Parser;
+    use axum::body::Body;
+    use backend_core::editor::{export_html, import_markdown};
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+    use tower::ServiceExt;
+    use yrs::{GetString, Text, Update, updates::decoder::Decode};
+
+    #[test]
+    fn test_observer_broadcasts_a_small_delta() {
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("content");
+        {
+            let mut txn = doc.transact_mut();
+            text.insert(&mut txn, 0, &"lorem ipsum ".repeat(200));
+        }
+        let old_state = doc
+            .transact()
+            .encode_state_as_update_v1(&StateVector::default());
+
+        // Capture what the observer sees, like the WebSocket broadcast does
+        let observed = Arc::new(Mutex::new(Vec::new()));
+        let observed_clone = observed.clone();
+        let _sub = doc.observe_update_v1(move |_txn, e| {
+            observed_clone.lock().unwrap().push(e.update.clone());
+        });
+
+        let before_sv = doc.transact().state_vector();
+        {
+            let mut txn = doc.transact_mut();
+            text.insert(&mut txn, 0, "Fixed. ");
+        }
 
-    if let Err(e) = state
-        .editor_broadcast_tx
-        .send(MessageStructure::YjsUpdate(update.to_vec()))
-    {
-        tracing::warn!("Failed to manually broadcast linter update: {:?}", e);
-    } else {
-        tracing::info!(
-            "✅ Manually broadcasted linter update to {} subscribers",
-            state.editor_broadcast_tx.receiver_count()
+        assert_eq!(changes_since(&doc, &before_sv), "Fixed. ".len() as u32);
+        let observed = observed.lock().unwrap();
+        assert_eq!(observed.len(), 1);
+        let delta = &observed[0];
+        let full = doc
+            .transact()
+            .encode_state_as_update_v1(&StateVector::default());
+        assert!(delta.len() < full.len() / 10);
+
+        // A client holding the old state converges by applying only the delta
+        let client = Doc::new();
+        let client_text = client.get_or_insert_text("content");
+        {
+            let mut txn = client.transact_mut();
+            txn.apply_update(Update::decode_v1(&old_state).unwrap())
+                .unwrap();
+            txn.apply_update(Update::decode_v1(delta).unwrap()).unwrap();
+        }
+        assert_eq!(
+            client_text.get_string(&client.transact()),
+            text.get_string(&doc.transact())
         );
     }
 
-    Ok(Json(RefineResponse {
-        text: "".to_string(),
-    }))
+    const MAX: usize = 100;
+
+    fn request(body: serde_json::Value) -> RefineRequest {
+        serde_json::from_value(body).unwrap()
+    }
+
+    async fn error_body(e: Error) -> (StatusCode, serde_json::Value) {
+        let response = e.into_response();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    #[test]
+    fn test_refine_dispatches_on_every_action() {
+        for (name, action, tool) in [
+            ("IMPROVE", RefineAction::Improve, "improve"),
+            ("FIX", RefineAction::Fix, "fix"),
+            ("LONGER", RefineAction::Longer, "longer"),
+            ("SHORTER", RefineAction::Shorter, "shorter"),
+        ] {
+            let req = request(json!({ "text": "some text", "action": name }));
+            assert_eq!(validate_refine(&req, MAX).unwrap(), action);
+            assert_eq!(action.tool(), tool);
+        }
+    }
+
+    #[test]
+    fn test_legacy_routes_set_the_action_from_the_path() {
+        // An old client posting to /shorter with no action is still served
+        let req = with_action(request(json!({ "text": "x" })), RefineAction::Shorter);
+        assert_eq!(validate_refine(&req, MAX).unwrap(), RefineAction::Shorter);
+
+        let req = with_action(
+            request(json!({ "text": "x", "action": "LONGER" })),
+            RefineAction::Fix,
+        );
+        assert_eq!(req.action, Some(RefineAction::Fix));
+    }
+
+    #[tokio::test]
+    async fn test_refine_without_action_is_unprocessable() {
+        let e = validate_refine(&request(json!({ "text": "some text" })), MAX).unwrap_err();
+        let (status, body) = error_body(e).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["error"]["code"], "VALIDATION");
+        assert_eq!(body["error"]["field"], "action");
+    }
+
+    #[tokio::test]
+    async fn test_refine_with_empty_text_is_unprocessable() {
+        let e =
+            validate_refine(&request(json!({ "text": "  ", "action": "FIX" })), MAX).unwrap_err();
+        let (status, body) = error_body(e).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["error"]["field"], "text");
+    }
+
+    #[tokio::test]
+    async fn test_text_over_the_limit_is_unprocessable() {
+        let at_limit = "字".repeat(MAX);
+        let req = request(json!({ "text": at_limit, "action": "FIX" }));
+        assert!(
+            validate_refine(&req, MAX).is_ok(),
+            "the limit counts characters"
+        );
+
+        let req = request(json!({ "text": "a".repeat(MAX + 1), "action": "FIX" }));
+        let (status, body) = error_body(validate_refine(&req, MAX).unwrap_err()).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["error"]["field"], "text");
+        assert_eq!(
+            body["error"]["message"],
+            "text is 101 characters long; the limit is 100"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_oversized_body_is_rejected_with_413() {
+        let opts = HttpOpts::parse_from(["backend", "--max-body-bytes", "64"]);
+        let app = Router::new()
+            .route(
+                "/improve",
+                post(
+                    |req: Result<Json<RefineRequest>, JsonRejection>| async move {
+                        req.map(|_| StatusCode::OK).map_err(Error::from)
+                    },
+                ),
+            )
+            .layer(body_limit(&opts))
+            .layer(axum::middleware::map_response(oversized_body_as_json));
+        let improve = |text: String| {
+            axum::http::Request::post("/improve")
+                .header(axum::http::header::CONTENT_TYPE, "application/json")
+                .body(Body::from(json!({ "text": text }).to_string()))
+                .unwrap()
+        };
+
+        let response = app.clone().oneshot(improve("short".into())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = app.oneshot(improve("x".repeat(1000))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["error"]["code"], "PAYLOAD_TOO_LARGE");
+    }
+
+    #[tokio::test]
+    async fn test_unknown_tone_preset_is_unprocessable() {
+        let app = Router::new().route(
+            "/refine/tone",
+            post(|req: Result<Json<ToneRequest>, JsonRejection>| async move {
+                req.map(|Json(req)| Json(req.preset.as_str()))
+                    .map_err(Error::from)
+            }),
+        );
+        let tone = |preset: &str| {
+            axum::http::Request::post("/refine/tone")
+                .header(axum::http::header::CONTENT_TYPE, "application/json")
+                .body(Body::from(
+                    json!({ "text": "we shipped it", "preset": preset }).to_string(),
+                ))
+                .unwrap()
+        };
+
+        let response = app.clone().oneshot(tone("empathetic")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = app.oneshot(tone("sarcastic")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn test_custom_refine_validates_text_and_instruction() {
+        let req = |text: &str, instruction: &str| CustomRefineRequest {
+            text: text.to_string(),
+            instruction: instruction.to_string(),
+            language: None,
+        };
+        assert_eq!(
+            validate_custom_refine(&req("we shipped", "  make it upbeat "), MAX).unwrap(),
+            "make it upbeat"
+        );
+
+        let e = validate_custom_refine(&req("", "make it upbeat"), MAX).unwrap_err();
+        let (status, body) = error_body(e).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["error"]["field"], "text");
+
+        for instruction in ["   ", "x".repeat(501).as_str()] {
+            let e = validate_custom_refine(&req("we shipped", instruction), MAX).unwrap_err();
+            let (status, body) = error_body(e).await;
+            assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+            assert_eq!(body["error"]["field"], "instruction");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_language_is_checked_against_the_allow_list() {
+        let language = validate_language(Some("zh-tw")).unwrap().unwrap();
+        assert_eq!(language.tag, "zh-TW");
+        assert_eq!(validate_language(None).unwrap(), None);
+
+        let (status, body) = error_body(validate_language(Some("xx-YY")).unwrap_err()).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["error"]["field"], "language");
+    }
+
+    #[test]
+    fn test_language_keeps_coalesced_requests_apart() {
+        let ja = Language::parse("ja").ok();
+        assert_eq!(coalesce_content("some text", None), "some text");
+        assert_ne!(
+            coalesce_content("some text", ja),
+            coalesce_content("some text", None)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_summarize_needs_a_non_empty_document() {
+        let doc = Arc::new(Doc::new());
+        let (status, body) = error_body(document_text(&doc).unwrap_err()).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["error"]["field"], "document");
+
+        backend_core::editor::append_ai_content_to_doc(&doc, "We shipped the editor.").unwrap();
+        assert_eq!(
+            document_text(&doc).unwrap().trim(),
+            "We shipped the editor."
+        );
+    }
+
+    #[tokio::test]
+    async fn test_summarize_prefers_the_request_text() {
+        let doc = Arc::new(Doc::new());
+        let req: SummarizeRequest = serde_json::from_value(
+            serde_json::json!({ "text": "Release notes", "style": "tl_dr" }),
+        )
+        .unwrap();
+        assert_eq!(req.style, summarizer::SummaryStyle::TlDr);
+        assert_eq!(text_to_summarize(&req, &doc, 100).unwrap(), "Release notes");
+
+        let (status, body) = error_body(text_to_summarize(&req, &doc, 5).unwrap_err()).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["error"]["field"], "text");
+
+        // No text: the (empty) document is what gets summarized
+        let req = SummarizeRequest::default();
+        assert_eq!(req.style, summarizer::SummaryStyle::Bullet);
+        assert!(text_to_summarize(&req, &doc, 100).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_translate_validates_text_and_target() {
+        let req = |text: &str, target_lang: &str| TranslateRequest {
+            text: text.to_string(),
+            target_lang: target_lang.to_string(),
+        };
+        assert_eq!(
+            validate_translate(&req("Hello", "zh-tw"), MAX).unwrap().tag,
+            "zh-TW"
+        );
+
+        let (status, body) =
+            error_body(validate_translate(&req(" ", "ja"), MAX).unwrap_err()).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["error"]["field"], "text");
+
+        for target_lang in ["", "klingon"] {
+            let e = validate_translate(&req("Hello", target_lang), MAX).unwrap_err();
+            let (status, body) = error_body(e).await;
+            assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+            assert_eq!(body["error"]["field"], "target_lang");
+        }
+    }
+
+    #[test]
+    fn test_tone_and_audience_are_optional_and_keep_requests_apart() {
+        let req = request(json!({ "text": "some text", "action": "FIX" }));
+        assert_eq!((req.tone, req.audience), (None, None));
+        assert_eq!(coalesce_style("some text".into(), None, None), "some text");
+
+        let req = request(json!({
+            "text": "some text",
+            "action": "FIX",
+            "tone": "swashbuckling",
+            "audience": "executives"
+        }));
+        assert_eq!(req.tone.as_deref(), Some("swashbuckling"));
+        assert_ne!(
+            coalesce_style("some text".into(), req.tone.as_deref(), None),
+            coalesce_style("some text".into(), None, req.tone.as_deref())
+        );
+    }
+
+    #[test]
+    fn test_requests_may_target_the_document_instead_of_carrying_text() {
+        let req = request(json!({ "text": "some text", "action": "FIX" }));
+        assert_eq!(req.target, None);
+
+        let req = request(json!({
+            "action": "FIX",
+            "target": { "scope": "selection", "start": 0, "end": 5 }
+        }));
+        assert_eq!(req.text, "");
+        assert_eq!(req.target, Some(DocTarget::Selection { start: 0, end: 5 }));
+        let req = request(json!({ "action": "FIX", "target": { "scope": "document" } }));
+        assert_eq!(req.target, Some(DocTarget::Document));
+    }
+
+    #[test]
+    fn test_selection_is_refined_in_place() {
+        let doc = Arc::new(Doc::new());
+        import_markdown(&doc, "你好 Hello **world**\n\nHello again").unwrap();
+
+        // Offsets are characters of the plain text, and the second "Hello" is the one selected
+        let passages = read_target(&doc, DocTarget::Selection { start: 15, end: 20 }).unwrap();
+        let [
+            Passage::Selection {
+                node,
+                offset,
+                original,
+                node_text,
+            },
+        ] = passages.as_slice()
+        else {
+            panic!("expected one selection, got {passages:?}");
+        };
+        assert_eq!(text_node_contents(&doc)[*node], "Hello again");
+        assert_eq!((*offset, original.as_str()), (0, "Hello"));
+        assert_eq!(node_text, "Hello again");
+
+        let refined = passages
+            .into_iter()
+            .map(|passage| (passage, "Hi".to_string()))
+            .collect::<Vec<_>>();
+        write_back(&doc, &refined).unwrap();
+        assert_eq!(
+            export_html(&doc),
+            "<p>你好 Hello <strong>world</strong></p><p>Hi again</p>"
+        );
+        // The passage is gone, so writing the same result again is refused
+        assert!(write_back(&doc, &refined).is_err());
+    }
+
+    #[test]
+    fn test_selection_is_not_written_over_a_concurrent_edit() {
+        let doc = Arc::new(Doc::new());
+        import_markdown(&doc, "Hello world").unwrap();
+        let passages = read_target(&doc, DocTarget::Selection { start: 6, end: 11 }).unwrap();
+
+        // Someone else edits the paragraph while the selection is being refined
+        patch_text_nodes(
+            &doc,
+            &[TextPatch {
+                node: 0,
+                original: "Hello world".to_string(),
+                replacement: "Oh, hello world".to_string(),
+            }],
+        )
+        .unwrap();
+
+        let refined = passages
+            .into_iter()
+            .map(|passage| (passage, "there".to_string()))
+            .collect::<Vec<_>>();
+        let e = write_back(&doc, &refined).unwrap_err();
+        assert!(matches!(
+            e,
+            Error::Validation {
+                field: "target",
+                ..
+            }
+        ));
+        assert_eq!(export_html(&doc), "<p>Oh, hello world</p>");
+    }
+
+    #[tokio::test]
+    async fn test_only_the_shared_document_can_be_targeted() {
+        assert!(validate_doc_id(None).is_ok());
+        assert!(validate_doc_id(Some(SHARED_DOC)).is_ok());
+        let req = request(json!({
+            "action": "FIX",
+            "doc_id": "draft",
+            "target": { "scope": "document" }
+        }));
+        let e = validate_doc_id(req.doc_id.as_deref()).unwrap_err();
+        let (status, body) = error_body(e).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["error"]["field"], "doc_id");
+    }
+
+    #[test]
+    fn test_whole_document_is_refined_paragraph_by_paragraph() {
+        let doc = Arc::new(Doc::new());
+        import_markdown(&doc, "# Draft one\n\nDraft **two**").unwrap();
+
+        let passages = read_target(&doc, DocTarget::Document).unwrap();
+        let texts: Vec<_> = passages.iter().map(Passage::text).collect();
+        assert_eq!(texts, ["Draft one", "Draft two"]);
+
+        let refined = passages
+            .into_iter()
+            .map(|passage| {
+                let text = passage.text().replace("Draft", "Final");
+                (passage, text)
+            })
+            .collect::<Vec<_>>();
+        write_back(&doc, &refined).unwrap();
+        // The heading and the bold mark are still there
+        assert_eq!(
+            export_html(&doc),
+            "<h1>Final one</h1><p>Final <strong>two</strong></p>"
+        );
+    }
+
+    #[test]
+    fn test_invalid_selections_are_rejected() {
+        let doc = Arc::new(Doc::new());
+        import_markdown(&doc, "Hello world\n\nHello again").unwrap();
+        for (start, end) in [(6, 17), (3, 3), (0, 100)] {
+            let e = read_target(&doc, DocTarget::Selection { start, end }).unwrap_err();
+            assert!(matches!(
+                e,
+                Error::Validation {
+                    field: "target",
+                    ..
+                }
+            ));
+        }
+    }
+
+    #[test]
+    fn test_unknown_action_is_rejected() {
+        let parsed = serde_json::from_value::<RefineRequest>(json!({
+            "text": "some text",
+            "action": "SUMMARIZE"
+        }));
+        assert!(parsed.is_err());
+    }
 }
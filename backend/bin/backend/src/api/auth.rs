@@ -49,7 +49,7 @@ async fn issue_user_tokens(state: &AppState, user_id: &Uuid) -> Result<LoginOutp
     let (token, _, _) = state
         .jwt_encoder
         .claims_encoded(user_id, vec![], Duration::days(30), None::<()>)
-        .unwrap();
+        .map_err(|e| Error::Internal(anyhow::anyhow!("failed to encode claims: {e:?}")))?;
     let refresh_token = generate_refresh_token();
     let refresh_expires_at = Utc::now() + Duration::days(30);
     Ok(LoginOutput {
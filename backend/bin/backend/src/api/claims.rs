@@ -1,10 +1,10 @@
-use crate::opts::Decoder;
+use crate::{api::errors::Error, opts::Decoder};
 
 use atb_types::prelude::{Claims as ClaimsInner, NoCustom, jwt::HEADER_RS256};
 use axum::{
-    Json, RequestPartsExt,
+    RequestPartsExt,
     extract::{FromRef, FromRequestParts},
-    http::{StatusCode, request::Parts},
+    http::request::Parts,
     response::{IntoResponse, Response},
 };
 use axum_extra::{
@@ -45,13 +45,21 @@ where
             .await
             .map_err(|_| AuthError::InvalidToken)?;
         let decoder = Decoder::from_ref(state);
-        let claims = ClaimsInner::<T>::decode_custom(bearer.token(), &HEADER_RS256, &decoder.0)
-            .map_err(|_| AuthError::InvalidToken)?;
-        if claims.issuer() != "tt" || !validate_expiry_custom(&claims) {
-            return Err(AuthError::InvalidToken);
-        }
-        Ok(Claims(claims))
+        decode_token(bearer.token(), &decoder).map(Claims)
+    }
+}
+
+/// Decode and validate a raw JWT (issuer and expiry), independent of where it was sent
+pub fn decode_token<T>(token: &str, decoder: &Decoder) -> Result<ClaimsInner<T>, AuthError>
+where
+    T: Serialize + DeserializeOwned,
+{
+    let claims = ClaimsInner::<T>::decode_custom(token, &HEADER_RS256, &decoder.0)
+        .map_err(|_| AuthError::InvalidToken)?;
+    if claims.issuer() != "tt" || !validate_expiry_custom(&claims) {
+        return Err(AuthError::InvalidToken);
     }
+    Ok(claims)
 }
 
 //#TODO: This shouldn't be needed, but ClaimsInner doesn't seem to validate customs correctly
@@ -64,25 +72,18 @@ where
     claims.expiry() > now - LEEWAY
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum AuthError {
     // WrongCredentials,
-    // MissingCredentials,
+    MissingCredentials,
     // TokenCreation,
     InvalidToken,
+    /// Valid token, but the subject may not use this endpoint
+    Forbidden,
 }
 
 impl IntoResponse for AuthError {
     fn into_response(self) -> Response {
-        let (status, error_message) = match self {
-            // AuthError::WrongCredentials => (StatusCode::UNAUTHORIZED, "Wrong credentials"),
-            // AuthError::MissingCredentials => (StatusCode::BAD_REQUEST, "Missing credentials"),
-            // AuthError::TokenCreation => (StatusCode::INTERNAL_SERVER_ERROR, "Token creation error"),
-            AuthError::InvalidToken => (StatusCode::UNAUTHORIZED, "Invalid token"),
-        };
-        let body = Json(serde_json::json!({
-            "error": error_message,
-        }));
-        (status, body).into_response()
+        Error::from(self).into_response()
     }
 }
@@ -0,0 +1,59 @@
+use crate::api::{
+    admin::require_admin,
+    claims::{AuthError, Claims},
+    state::AppState,
+};
+use crate::model::ChunkPreviewRequest;
+
+use axum::{Json, Router, extract::State, routing::post};
+use backend_core::editor::prepare_chunks;
+use tracing::instrument;
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/debug/chunks", post(chunks_handler))
+}
+
+/// Show how text would be split into streaming chunks, without calling the AI.
+/// Admins only, like the rest of the operator endpoints.
+#[instrument(skip(claims, state, req), fields(granularity = ?req.granularity))]
+pub async fn chunks_handler(
+    claims: Claims,
+    State(state): State<AppState>,
+    Json(req): Json<ChunkPreviewRequest>,
+) -> Result<Json<Vec<String>>, AuthError> {
+    require_admin(&claims, &state.http_opts.admin_subjects)?;
+    Ok(Json(prepare_chunks(&req.text, req.granularity)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use backend_core::editor::{ChunkGranularity, prepare_chars, prepare_sentences, prepare_words};
+
+    async fn preview(text: &str, granularity: &str) -> Vec<String> {
+        let req: ChunkPreviewRequest =
+            serde_json::from_value(serde_json::json!({ "text": text, "granularity": granularity }))
+                .unwrap();
+        prepare_chunks(&req.text, req.granularity)
+    }
+
+    #[tokio::test]
+    async fn test_chunks_match_streaming_preparation() {
+        let text = "The quick fox jumps. It lands!  Done";
+
+        assert_eq!(preview(text, "words").await, prepare_words(text));
+        assert_eq!(preview(text, "sentences").await, prepare_sentences(text));
+        assert_eq!(preview(text, "chars").await, prepare_chars(text));
+    }
+
+    #[tokio::test]
+    async fn test_granularity_defaults_to_words() {
+        let req: ChunkPreviewRequest =
+            serde_json::from_value(serde_json::json!({ "text": "a b" })).unwrap();
+        assert_eq!(req.granularity, ChunkGranularity::Words);
+        assert_eq!(
+            prepare_chunks(&req.text, req.granularity),
+            vec!["a ", "b\n"]
+        );
+    }
+}
@@ -0,0 +1,99 @@
+use crate::api::{claims::Claims, errors::Error, state::AppState};
+use crate::model::{CreateDocumentRequest, DocumentResponse};
+
+use atb_types::Uuid;
+use axum::{
+    Json, Router,
+    extract::{Path, State, rejection::JsonRejection},
+    http::StatusCode,
+    routing::get,
+};
+use backend_core::sqlx_postgres::documents;
+use sqlx::PgPool;
+use tracing::instrument;
+
+/// Longest document title, in characters
+pub const MAX_TITLE_CHARS: usize = 200;
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/documents", get(list_handler).post(create_handler))
+        .route("/documents/{id}", get(get_handler).delete(delete_handler))
+}
+
+/// The title, trimmed, or 422 when it is empty or too long
+pub(crate) fn validate_title(title: &str) -> Result<&str, Error> {
+    let title = title.trim();
+    if title.is_empty() {
+        return Err(Error::Validation {
+            field: "title",
+            message: "title must not be empty".to_string(),
+        });
+    }
+    if title.chars().count() > MAX_TITLE_CHARS {
+        return Err(Error::Validation {
+            field: "title",
+            message: format!("title must be at most {MAX_TITLE_CHARS} characters"),
+        });
+    }
+    Ok(title)
+}
+
+/// Create an empty document owned by the caller.
+#[instrument(skip(pool, claims, req))]
+async fn create_handler(
+    claims: Claims,
+    State(pool): State<PgPool>,
+    req: Result<Json<CreateDocumentRequest>, JsonRejection>,
+) -> Result<(StatusCode, Json<DocumentResponse>), Error> {
+    let Json(req) = req?;
+    let title = validate_title(&req.title)?;
+    let created_by = claims.into_inner().subject_as_uuid().ok();
+    let record = documents::create_document(&pool, title, created_by).await?;
+    tracing::info!("📄 created document {}", record.id);
+    Ok((StatusCode::CREATED, Json(record.into())))
+}
+
+/// Every document, most recently updated first.
+async fn list_handler(
+    _claims: Claims,
+    State(pool): State<PgPool>,
+) -> Result<Json<Vec<DocumentResponse>>, Error> {
+    let records = documents::list_documents(&pool).await?;
+    Ok(Json(records.into_iter().map(Into::into).collect()))
+}
+
+/// One document's metadata and text preview; 404 when it doesn't exist.
+async fn get_handler(
+    _claims: Claims,
+    State(pool): State<PgPool>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<DocumentResponse>, Error> {
+    let record = documents::get_document(&pool, id).await?;
+    Ok(Json(record.into()))
+}
+
+/// Delete a document; 404 when it doesn't exist.
+#[instrument(skip(pool, _claims))]
+async fn delete_handler(
+    _claims: Claims,
+    State(pool): State<PgPool>,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, Error> {
+    documents::delete_document(&pool, id).await?;
+    tracing::info!("🗑️ deleted document {}", id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_titles_are_trimmed_and_bounded() {
+        assert_eq!(validate_title("  Plan  ").unwrap(), "Plan");
+        assert!(validate_title("   ").is_err());
+        assert!(validate_title(&"字".repeat(MAX_TITLE_CHARS)).is_ok());
+        assert!(validate_title(&"a".repeat(MAX_TITLE_CHARS + 1)).is_err());
+    }
+}
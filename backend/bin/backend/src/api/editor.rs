@@ -1,33 +1,343 @@
-use crate::api::state::{AiCommand, AppState, MessageStructure};
-use atb_ai_utils::agent::AgentContext;
-use atb_types::Uuid;
+use crate::api::claims::{AuthError, Claims, decode_token};
+use crate::api::errors::Error;
+use crate::api::presence;
+use crate::api::prometheus::ActiveConnection;
+use crate::api::rate_limit::{AiRateLimits, retry_after_secs};
+use crate::api::state::{AiAction, AiCommand, AiEvent, AppState, ConnId, MessageStructure};
+use crate::api::tools;
+use crate::model::{
+    AgentStatusResponse, AiHistoryResponse, DocContent, HistoryQuery, ImportRequest, UsageQuery,
+    UsageResponse,
+};
+use crate::opts::{Decoder, WebSocketOpts};
+use crate::shutdown::ShutdownTrigger;
+use atb_types::{DateTime, Utc, Uuid, prelude::NoCustom};
 use axum::{
+    Json,
     extract::{
-        State,
-        ws::{Message, WebSocket, WebSocketUpgrade},
+        Path, Query, State,
+        rejection::JsonRejection,
+        ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade, close_code},
     },
-    response::IntoResponse,
-    routing::get,
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+    routing::{get, post},
 };
-use backend_core::llm::new_composer;
-use backend_core::refiner::processor::{
-    call_fix_api, call_improve_api, call_longer_api, call_shorter_api,
+use axum_client_ip::ClientIp;
+use backend_core::editor::{
+    DocStats, UserUndo, export_html, export_markdown, get_doc_content, get_doc_stats, get_doc_text,
+    import_markdown,
 };
-use backend_core::refiner::types::RefineInput;
-use futures::{sink::SinkExt, stream::StreamExt};
-use std::time::Duration;
-use yrs::{ReadTxn, Transact, Update, updates::decoder::Decode};
-pub type AgentCache = mini_moka::sync::Cache<Uuid, (String, AgentContext)>;
-
+use backend_core::llm::tools::readability::{Readability, document_readability};
+use backend_core::sqlx_postgres::{ai_events, ai_usage};
+use backend_core::temporal::{WorkflowEngine, compose::WF_COMPOSE};
+use futures::{
+    sink::{Sink, SinkExt},
+    stream::StreamExt,
+};
+use serde::Deserialize;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{
+    broadcast::{self, error::RecvError},
+    mpsc,
+};
+use tokio::task::JoinHandle;
+use tracing::Instrument;
+use yrs::{Doc, ReadTxn, Transact, Update, updates::decoder::Decode};
 pub fn routes() -> axum::Router<AppState> {
-    axum::Router::new().route("/ws", get(ws_handler))
+    axum::Router::new()
+        .route("/ws", get(ws_handler))
+        .route("/editor/export", get(export_handler))
+        .route("/editor/stats", get(stats_handler))
+        .route("/editor/readability", get(readability_handler))
+        .route("/editor/content", get(content_handler))
+        .route("/editor/history", get(history_handler))
+        .route("/editor/usage", get(usage_handler))
+        .route(
+            "/editor/agent/{workflow_id}/status",
+            get(agent_status_handler),
+        )
+        .route("/editor/import", post(import_handler))
+}
+
+/// Subprotocol a browser client offers alongside its token, e.g.
+/// `new WebSocket(url, ["bearer", token])`; we echo it back so the handshake completes.
+const WS_AUTH_PROTOCOL: &str = "bearer";
+
+#[derive(Debug, Deserialize)]
+struct WsAuthQuery {
+    token: Option<String>,
+}
+
+async fn ws_handler(
+    ws: WebSocketUpgrade,
+    Query(query): Query<WsAuthQuery>,
+    headers: HeaderMap,
+    ClientIp(ip): ClientIp,
+    State(state): State<AppState>,
+) -> Response {
+    let subject = match authorize_ws(
+        query.token.as_deref(),
+        &headers,
+        &state.jwt_decoder,
+        &state.ws_opts,
+    ) {
+        Ok(subject) => subject,
+        Err(e) => {
+            tracing::warn!("Rejecting websocket upgrade: {:?}", e);
+            return e.into_response();
+        }
+    };
+
+    if let Some(subject) = subject {
+        tracing::Span::current().record("user_id", tracing::field::display(subject));
+    }
+
+    // Keep the request span (with its client ip) on the connection's logs
+    let span = tracing::Span::current();
+    ws.protocols([WS_AUTH_PROTOCOL])
+        .on_upgrade(move |socket| handle_socket(socket, state, subject, ip).instrument(span))
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ExportFormat {
+    #[default]
+    Markdown,
+    Html,
+    Text,
+}
+
+impl ExportFormat {
+    fn render(self, doc: &Arc<Doc>) -> (&'static str, String) {
+        match self {
+            Self::Markdown => ("text/markdown; charset=utf-8", export_markdown(doc)),
+            Self::Html => ("text/html; charset=utf-8", export_html(doc)),
+            Self::Text => ("text/plain; charset=utf-8", get_doc_content(doc)),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ExportQuery {
+    #[serde(default)]
+    format: ExportFormat,
 }
 
-async fn ws_handler(ws: WebSocketUpgrade, State(state): State<AppState>) -> impl IntoResponse {
-    ws.on_upgrade(|socket| handle_socket(socket, state))
+/// Download the shared document as Markdown, or `?format=html` / `?format=text`.
+async fn export_handler(
+    claims: Result<Claims, AuthError>,
+    Query(query): Query<ExportQuery>,
+    State(state): State<AppState>,
+) -> Result<Response, AuthError> {
+    require_editor(claims, &state.ws_opts)?;
+    let (content_type, body) = query.format.render(&state.editor_doc);
+    Ok(([(header::CONTENT_TYPE, content_type)], body).into_response())
 }
 
-async fn handle_socket(socket: WebSocket, state: AppState) {
+/// Current document statistics; the same numbers `DOC_STATS` pushes over `/ws`.
+async fn stats_handler(
+    claims: Result<Claims, AuthError>,
+    State(state): State<AppState>,
+) -> Result<Json<DocStats>, AuthError> {
+    require_editor(claims, &state.ws_opts)?;
+    Ok(Json(get_doc_stats(&state.editor_doc)))
+}
+
+/// Readability of the current document; the same scores `READABILITY` pushes
+/// over `/ws` after each auto-lint pass.
+async fn readability_handler(
+    claims: Result<Claims, AuthError>,
+    State(state): State<AppState>,
+) -> Result<Json<Readability>, AuthError> {
+    require_editor(claims, &state.ws_opts)?;
+    Ok(Json(document_readability(&state.editor_doc)))
+}
+
+/// Current document text, without formatting marks, as JSON for clients
+/// that don't speak the Yjs protocol.
+async fn content_handler(
+    claims: Result<Claims, AuthError>,
+    State(doc): State<Arc<Doc>>,
+    State(opts): State<WebSocketOpts>,
+) -> Result<Json<DocContent>, AuthError> {
+    require_editor(claims, &opts)?;
+    Ok(Json(DocContent {
+        text: get_doc_text(&doc),
+    }))
+}
+
+/// Page size of the AI history when the client doesn't ask for one
+const HISTORY_PAGE: i64 = 50;
+/// Largest AI history page a client may ask for
+const MAX_HISTORY_PAGE: i64 = 200;
+
+/// One page of the AI history, newest first, with the cursor for the next page.
+///
+/// The cursor is the oldest event's `(created_at, id)`; without `before_id`, every
+/// event at `before` itself is still included.
+pub(crate) async fn history_page(
+    pool: &PgPool,
+    limit: Option<i64>,
+    before: Option<DateTime<Utc>>,
+    before_id: Option<Uuid>,
+) -> sqlx::Result<AiHistoryResponse> {
+    let limit = limit.unwrap_or(HISTORY_PAGE).clamp(1, MAX_HISTORY_PAGE);
+    let cursor = before.map(|at| (at, before_id.unwrap_or(Uuid::from_u128(u128::MAX))));
+    let events = ai_events::list_ai_events(pool, limit, cursor).await?;
+    // A short page is the last one
+    let next = match events.last() {
+        Some(oldest) if events.len() as i64 == limit => Some((oldest.created_at, oldest.id)),
+        _ => None,
+    };
+    Ok(AiHistoryResponse {
+        events: events.into_iter().map(Into::into).collect(),
+        next_before: next.map(|(at, _)| at),
+        next_before_id: next.map(|(_, id)| id),
+    })
+}
+
+/// Past AI suggestions on the shared document, for review.
+async fn history_handler(
+    claims: Result<Claims, AuthError>,
+    State(pool): State<PgPool>,
+    State(opts): State<WebSocketOpts>,
+    Query(query): Query<HistoryQuery>,
+) -> Result<Json<AiHistoryResponse>, Error> {
+    require_editor(claims, &opts)?;
+    let page = history_page(&pool, query.limit, query.before, query.before_id).await?;
+    Ok(Json(page))
+}
+
+/// How far back `GET /editor/usage` looks when the client doesn't say
+const USAGE_PERIOD_DAYS: i64 = 30;
+
+/// Tokens and estimated cost of the AI calls since `?since=`, per tool, so the
+/// bill can be split between the auto-linter and what writers asked for.
+async fn usage_handler(
+    claims: Result<Claims, AuthError>,
+    State(pool): State<PgPool>,
+    State(opts): State<WebSocketOpts>,
+    Query(query): Query<UsageQuery>,
+) -> Result<Json<UsageResponse>, Error> {
+    require_editor(claims, &opts)?;
+    let since = query
+        .since
+        .unwrap_or_else(|| Utc::now() - atb_types::Duration::days(USAGE_PERIOD_DAYS));
+    let tools = ai_usage::usage_since(&pool, since).await?;
+    Ok(Json(UsageResponse::new(since, tools)))
+}
+
+/// Where an AGENT command's compose workflow is; only compose workflows can be looked up.
+async fn agent_status_handler(
+    claims: Result<Claims, AuthError>,
+    State(engine): State<WorkflowEngine>,
+    State(opts): State<WebSocketOpts>,
+    Path(workflow_id): Path<String>,
+) -> Result<Json<AgentStatusResponse>, Error> {
+    require_editor(claims, &opts)?;
+    if !workflow_id.starts_with(&format!("{WF_COMPOSE}_")) {
+        return Err(Error::Validation {
+            field: "workflow_id",
+            message: "not a compose workflow".to_string(),
+        });
+    }
+    let status = engine
+        .workflow_status(&workflow_id)
+        .await
+        .map_err(Error::Internal)?;
+    Ok(Json(AgentStatusResponse {
+        workflow_id,
+        status,
+    }))
+}
+
+/// Replace the shared document with parsed Markdown.
+///
+/// Connected clients receive the result as one Yjs update, like any other edit.
+async fn import_handler(
+    claims: Result<Claims, AuthError>,
+    State(state): State<AppState>,
+    req: Result<Json<ImportRequest>, JsonRejection>,
+) -> Result<StatusCode, Response> {
+    require_editor(claims, &state.ws_opts).map_err(IntoResponse::into_response)?;
+    let Json(req) = req.map_err(|e| Error::from(e).into_response())?;
+
+    import_markdown(&state.editor_doc, &req.markdown).map_err(|e| {
+        Error::Validation {
+            field: "markdown",
+            message: e.to_string(),
+        }
+        .into_response()
+    })?;
+    tracing::info!("📥 imported {} bytes of markdown", req.markdown.len());
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The document HTTP routes follow the WebSocket's auth policy: anyone who may
+/// edit the document over `/ws` may export or import it.
+fn require_editor(
+    claims: Result<Claims, AuthError>,
+    opts: &WebSocketOpts,
+) -> Result<(), AuthError> {
+    match claims {
+        Err(e) if !opts.ws_auth_disabled => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Validate the connection's JWT from `?token=` or `Sec-WebSocket-Protocol`.
+/// Returns the subject, or `None` when auth is disabled and no token was sent.
+fn authorize_ws(
+    query_token: Option<&str>,
+    headers: &HeaderMap,
+    decoder: &Decoder,
+    opts: &WebSocketOpts,
+) -> Result<Option<Uuid>, AuthError> {
+    let token = query_token
+        .filter(|t| !t.is_empty())
+        .map(str::to_owned)
+        .or_else(|| protocol_token(headers));
+
+    let Some(token) = token else {
+        if opts.ws_auth_disabled {
+            return Ok(None);
+        }
+        return Err(AuthError::MissingCredentials);
+    };
+
+    let claims = decode_token::<NoCustom>(&token, decoder)?;
+    let subject = claims
+        .subject_as_uuid()
+        .map_err(|_| AuthError::InvalidToken)?;
+    Ok(Some(subject))
+}
+
+/// Pick the token out of `Sec-WebSocket-Protocol: bearer, <token>`
+fn protocol_token(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get_all(header::SEC_WEBSOCKET_PROTOCOL)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .find(|p| !p.is_empty() && !p.eq_ignore_ascii_case(WS_AUTH_PROTOCOL))
+        .map(str::to_owned)
+}
+
+async fn handle_socket(socket: WebSocket, state: AppState, subject: Option<Uuid>, ip: IpAddr) {
+    let conn_id = ConnId::next();
+    tracing::info!(
+        "🔌 websocket connected: {} (conn {})",
+        subject.map_or_else(|| "anonymous".to_string(), |s| s.to_string()),
+        conn_id
+    );
+    let _active = ActiveConnection::open();
     let (mut sender, mut receiver) = socket.split();
 
     // 1. ON CONNECT: Send the full document state immediately
@@ -36,44 +346,119 @@ async fn handle_socket(socket: WebSocket, state: AppState) {
         let txn = state.editor_doc.transact();
         txn.encode_state_as_update_v1(&yrs::StateVector::default())
     };
-    if sender
-        .send(Message::Binary(full_state.into()))
+    if send_with_retry(&mut sender, Message::Binary(full_state.into()), &state.ws_opts)
         .await
         .is_err()
     {
         return;
     }
+    // ...and who is already here
+    if let Some(snapshot) = state.presence.snapshot() {
+        let frame = Message::Binary(presence::awareness_frame(&snapshot).into());
+        if send_with_retry(&mut sender, frame, &state.ws_opts)
+            .await
+            .is_err()
+        {
+            return;
+        }
+    }
 
     // 2. Subscribe to server broadcasts
     let mut rx = state.editor_broadcast_tx.subscribe();
 
     // 3. Handle Incoming/Outgoing Tasks
+    let ws_opts = state.ws_opts.clone();
+    let doc = state.editor_doc.clone();
+    let (control_tx, mut control_rx) = mpsc::channel::<Message>(4);
     let mut send_task = tokio::spawn(async move {
-        // rx.recv() now returns a SyncMessage
-        while let Ok(msg) = rx.recv().await {
-            let ws_msg = match msg {
-                // Unpack Lane A -> Binary
-                MessageStructure::YjsUpdate(data) => Message::Binary(data.into()),
-
-                // Unpack Lane B -> Text
-                MessageStructure::AiCommand(json_string) => Message::Text(json_string.into()),
-            };
-
-            if sender.send(ws_msg).await.is_err() {
-                break;
-            }
-        }
+        forward_broadcasts(&mut sender, &mut rx, &mut control_rx, &doc, conn_id, &ws_opts).await;
     });
 
+    // Any frame from the client (including pongs) proves the connection is alive
+    let last_seen = Arc::new(Mutex::new(Instant::now()));
+    let mut heartbeat_task = tokio::spawn(heartbeat(
+        control_tx.clone(),
+        last_seen.clone(),
+        Duration::from_millis(state.http_opts.ws_ping_interval_ms),
+        state.http_opts.ws_max_missed_pongs,
+    ));
+    // Only edits and commands count as activity; pongs just prove the tab is open
+    let last_active = Arc::new(Mutex::new(tokio::time::Instant::now()));
+    let mut idle_task = tokio::spawn(close_when_idle(
+        control_tx.clone(),
+        last_active.clone(),
+        state.ws_opts.clone(),
+    ));
+    let mut shutdown_task = tokio::spawn(close_on_shutdown(
+        control_tx.clone(),
+        state.shutdown.clone(),
+    ));
+
     let state_clone = state.clone();
+    let conn_presence = state.presence.clone();
+    let conn_agents = state.auto_agents.clone();
+    let broadcast_tx = state.editor_broadcast_tx.clone();
+    let max_doc_bytes = state.http_opts.max_doc_bytes;
+    let max_update_bytes = state.http_opts.max_ws_update_bytes;
+    // Resolves to true when the server queued a close frame for this client
     let mut recv_task = tokio::spawn(async move {
+        let mut in_flight = InFlight::default();
+        let mut undo = UserUndo::new(&state_clone.editor_doc, conn_id.origin());
         while let Some(Ok(msg)) = receiver.next().await {
+            *last_seen.lock().unwrap() = Instant::now();
+            // Before anything parses it, presence included
+            if let Message::Binary(data) = &msg {
+                if !update_within_limit(data, max_update_bytes) {
+                    continue;
+                }
+            }
+            let awareness = match &msg {
+                Message::Binary(data) => presence::parse_awareness_frame(data),
+                _ => None,
+            };
+            // An open tab renews its awareness on its own, so presence is not activity
+            if matches!(msg, Message::Binary(_) | Message::Text(_)) && awareness.is_none() {
+                *last_active.lock().unwrap() = tokio::time::Instant::now();
+            }
+            if let Some(update) = awareness {
+                if state_clone.presence.apply(conn_id, &update) {
+                    let _ = state_clone
+                        .editor_broadcast_tx
+                        .send(MessageStructure::Awareness {
+                            update,
+                            origin: Some(conn_id),
+                        });
+                }
+                continue;
+            }
             match msg {
                 // LANE A: Binary Sync (Existing)
                 Message::Binary(data) => {
+                    // Checked before applying, so the document never grows past the limit
+                    if let Some(size) = state_clone.doc_size.oversized(data.len(), max_doc_bytes) {
+                        tracing::warn!(
+                            "📏 update would grow the document to {} bytes (max {}), closing client",
+                            size,
+                            max_doc_bytes
+                        );
+                        let _ = control_tx
+                            .send(Message::Close(Some(CloseFrame {
+                                code: close_code::SIZE,
+                                reason: "document too large".into(),
+                            })))
+                            .await;
+                        return true;
+                    }
                     // 標記用戶正在寫入
                     if let Some(user_state) = &state_clone.user_writing_state {
                         user_state.mark_user_writing();
+                        // The writer took over; a composer still drafting would write stale output
+                        for request_id in in_flight.cancel_action(&AiAction::Agent) {
+                            tracing::info!("✋ composer {} cancelled, user is typing", request_id);
+                            let _ = state_clone
+                                .editor_broadcast_tx
+                                .send(AiEvent::Cancelled { request_id }.into_message());
+                        }
 
                         // 設置定時器，自動清除標記
                         let user_state_clone = user_state.clone();
@@ -86,344 +471,1211 @@ async fn handle_socket(socket: WebSocket, state: AppState) {
                         });
                     }
 
-                    let mut txn = state_clone.editor_doc.transact_mut();
-                    if let Ok(update) = Update::decode_v1(&data) {
-                        if let Err(e) = txn.apply_update(update) {
-                            tracing::warn!("Failed to apply update: {:?}", e);
-                        }
-                    }
+                    apply_client_update(&state_clone.editor_doc, &data, conn_id);
                 }
                 // LANE B: AI Commands
                 Message::Text(text) => {
-                    println!("Received command: {:?}", text);
-                    if let Ok(cmd) = serde_json::from_str::<AiCommand>(&text) {
-                        println!("Command: {:?}", cmd);
-                        // CLONE STATE FOR THE ASYNC TASK
-                        // We spawn a new thread/task so we don't block the websocket heartbeat
-                        let state_for_task = state.clone();
-                        let cmd_action = cmd.action.clone();
-                        let cmd_payload = cmd.payload.clone();
-                        let _ =
-                            state_for_task
-                                .editor_broadcast_tx
-                                .send(MessageStructure::AiCommand(
-                                    serde_json::json!({
-                                        "type": "AI_STATUS",
-                                        "status": "thinking",
-                                        "message": "Polishing your text..."
-                                    })
-                                    .to_string(),
-                                ));
-                        tokio::spawn(async move {
-                            match cmd_action.as_str() {
-                                "IMPROVE" | "FIX" | "LONGER" | "SHORTER" => {
-                                    tracing::info!("🤖 processing {}...", cmd_action);
-
-                                    // Extract text from Refiner payload
-                                    let content = match cmd_payload {
-                                        Some(crate::api::state::AiCommandPayload::Refiner(
-                                            text,
-                                        )) => text,
-                                        Some(crate::api::state::AiCommandPayload::Agent(_)) => {
-                                            tracing::error!(
-                                                "Refiner command received Agent payload"
-                                            );
-                                            delegate_to_frontend(
-                                                &state_for_task,
-                                                "AI_STATUS",
-                                                "error",
-                                                "Invalid payload type for refiner command",
-                                            );
-                                            return;
-                                        }
-                                        None => {
-                                            tracing::error!(
-                                                "No payload found for command: {:?}",
-                                                cmd_action
-                                            );
-                                            delegate_to_frontend(
-                                                &state_for_task,
-                                                "AI_STATUS",
-                                                "error",
-                                                "No payload found for command",
-                                            );
-                                            return;
-                                        }
-                                    };
-
-                                    // Create the input struct your existing processor expects
-                                    let input = RefineInput { content };
-                                    let api_key = &state_for_task.api_key;
-
-                                    // Select the correct function based on action
-                                    let result = match cmd_action.as_str() {
-                                        "IMPROVE" => call_improve_api(input, api_key).await,
-                                        "FIX" => call_fix_api(input, api_key).await,
-                                        "LONGER" => call_longer_api(input, api_key).await,
-                                        "SHORTER" => call_shorter_api(input, api_key).await,
-                                        _ => return, // Should be unreachable
-                                    };
-
-                                    // 3. APPLY PHASE (Mutation)
-                                    match result {
-                                        Ok(output) => {
-                                            delegate_to_frontend(
-                                                &state_for_task,
-                                                "AI_STATUS",
-                                                "complete",
-                                                &format!("Applied {}", cmd_action),
-                                            );
-                                            delegate_to_frontend(
-                                                &state_for_task,
-                                                "AI_RESULT",
-                                                "complete",
-                                                &output.content,
-                                            );
-                                        }
-                                        Err(e) => {
-                                            tracing::error!("❌ AI failed: {:?}", e);
-                                            delegate_to_frontend(
-                                                &state_for_task,
-                                                "AI_STATUS",
-                                                "error",
-                                                &format!("AI failed: {:?}", e),
-                                            );
-                                        }
-                                    }
-                                }
-                                "AGENT" => {
-                                    tracing::info!("🤖 processing {}...", cmd_action);
-
-                                    // Extract role from Agent payload
-                                    let role = match cmd_payload {
-                                        Some(crate::api::state::AiCommandPayload::Agent(
-                                            agent_payload,
-                                        )) => agent_payload.role,
-                                        Some(crate::api::state::AiCommandPayload::Refiner(_)) => {
-                                            tracing::error!(
-                                                "Agent command received Refiner payload"
-                                            );
-                                            delegate_to_frontend(
-                                                &state_for_task,
-                                                "AI_STATUS",
-                                                "error",
-                                                "Invalid payload type for agent command",
-                                            );
-                                            return;
-                                        }
-                                        None => {
-                                            tracing::error!(
-                                                "No payload found for command: {:?}",
-                                                cmd_action
-                                            );
-                                            delegate_to_frontend(
-                                                &state_for_task,
-                                                "AI_STATUS",
-                                                "error",
-                                                "No payload found for command",
-                                            );
-                                            return;
-                                        }
-                                    };
-
-                                    // 0. PRE-CHECK: Verify document has content structure
-                                    if !backend_core::editor::write::has_content_structure(
-                                        &state_for_task.editor_doc,
-                                    ) {
-                                        tracing::warn!("Document has no content structure yet");
-                                        delegate_to_frontend(
-                                            &state_for_task,
-                                            "AI_STATUS",
-                                            "error",
-                                            "Please start typing in the editor first. The AI agent needs existing content to work with.",
-                                        );
-                                        return;
-                                    }
-
-                                    // 1. AI PROCESSING PHASE
-                                    // Create the input struct your existing processor expects
-                                    let api_key = &state_for_task.api_key;
-
-                                    // Select the correct function based on action
-                                    let result: Result<String, anyhow::Error> = match cmd_action
-                                        .as_str()
-                                    {
-                                        // #TODO: This should definitely be matching agent_payload's content to determine which agent to run. We only have one right now.
-                                        "AGENT" => {
-                                            // 獲取共享的 UserWritingState
-                                            let Some(user_state) =
-                                                &state_for_task.user_writing_state
-                                            else {
-                                                return delegate_to_frontend(
-                                                    &state_for_task,
-                                                    "AI_STATUS",
-                                                    "error",
-                                                    "User writing state not available",
-                                                );
-                                            };
-
-                                            match new_composer(
-                                                api_key,
-                                                &role,
-                                                &state_for_task.editor_doc,
-                                                user_state,
-                                            )
-                                            .await
-                                            {
-                                                Ok(_) => {
-                                                    Ok("Agent executed successfully".to_string())
-                                                }
-                                                Err(e) => {
-                                                    // Check if it's the "no content" error and handle gracefully
-                                                    let error_msg = e.to_string();
-                                                    if error_msg.contains(
-                                                        "Document has no content structure",
-                                                    ) {
-                                                        Err(anyhow::anyhow!(
-                                                            "Document has no content structure yet. User needs to create content first."
-                                                        ))
-                                                    } else {
-                                                        Err(anyhow::anyhow!(
-                                                            "Agent failed: {}",
-                                                            error_msg
-                                                        ))
-                                                    }
-                                                }
-                                            }
-                                        }
-                                        _ => return, // Should be unreachable
-                                    };
-
-                                    // 3. APPLY PHASE (Mutation)
-                                    match result {
-                                        Ok(_output) => {
-                                            // The agent modifies the doc directly via new_composer
-                                            tracing::info!("✅ Applied AI changes via CRDT");
-                                            delegate_to_frontend(
-                                                &state_for_task,
-                                                "AI_STATUS",
-                                                "complete",
-                                                "AI agent finished successfully",
-                                            );
-                                        }
-                                        Err(e) => {
-                                            let error_msg = e.to_string();
-                                            // Provide user-friendly error messages
-                                            let user_message: String = if error_msg
-                                                .contains("Document has no content structure")
-                                            {
-                                                "Please start typing in the editor first. The AI agent needs existing content to work with.".to_string()
-                                            } else if error_msg.contains("Agent failed: ") {
-                                                // Extract a cleaner error message if possible
-                                                error_msg
-                                                    .strip_prefix("Agent failed: ")
-                                                    .map(|s| s.to_string())
-                                                    .unwrap_or(error_msg)
-                                            } else {
-                                                error_msg
-                                            };
-
-                                            tracing::warn!("❌ AI agent failed: {}", user_message);
-                                            delegate_to_frontend(
-                                                &state_for_task,
-                                                "AI_STATUS",
-                                                "error",
-                                                &user_message,
-                                            );
-                                        }
-                                    }
-                                }
-                                "TOGGLE" => {
-                                    let content = match cmd_payload {
-                                        Some(crate::api::state::AiCommandPayload::Refiner(
-                                            text,
-                                        )) => text,
-                                        Some(crate::api::state::AiCommandPayload::Agent(_)) => {
-                                            tracing::error!(
-                                                "Refiner command received Agent payload"
-                                            );
-                                            delegate_to_frontend(
-                                                &state_for_task,
-                                                "AI_STATUS",
-                                                "error",
-                                                "Invalid payload type for refiner command",
-                                            );
-                                            return;
-                                        }
-                                        None => {
-                                            tracing::error!(
-                                                "No payload found for command: {:?}",
-                                                cmd_action
-                                            );
-                                            delegate_to_frontend(
-                                                &state_for_task,
-                                                "AI_STATUS",
-                                                "error",
-                                                "No payload found for command",
-                                            );
-                                            return;
-                                        }
-                                    };
-                                    match content.as_str() {
-                                        "LINTER" => {
-                                            tracing::info!("🤖 toggling linter...");
-                                            let current = crate::mono::LINTER_FLAG.load(std::sync::atomic::Ordering::Relaxed);
-                                            crate::mono::LINTER_FLAG.store(!current, std::sync::atomic::Ordering::Relaxed);
-                                            delegate_to_frontend(
-                                                &state_for_task,
-                                                "AI_STATUS",
-                                                "complete",
-                                                &format!("Linter {}", if !current { "enabled" } else { "disabled" }),
-                                            );
-                                        }
-                                        "EMOJI_REPLACER" => {
-                                            tracing::info!("🤖 toggling emoji replacer...");
-                                            let current = crate::mono::EMOJI_REPLACER_FLAG.load(std::sync::atomic::Ordering::Relaxed);
-                                            crate::mono::EMOJI_REPLACER_FLAG.store(!current, std::sync::atomic::Ordering::Relaxed);
-                                            delegate_to_frontend(
-                                                &state_for_task,
-                                                "AI_STATUS",
-                                                "complete",
-                                                &format!("Emoji replacer {}", if !current { "enabled" } else { "disabled" }),
-                                            );
-                                        }
-                                        _ => {
-                                            tracing::error!("Unknown toggle target: {}", content);
-                                            delegate_to_frontend(
-                                                &state_for_task,
-                                                "AI_STATUS",
-                                                "error",
-                                                &format!("Unknown toggle target: {}", content),
-                                            );
-                                        }
-                                    }
-                                }
-                                _ => {
-                                    tracing::error!("Unknown command: {:?}", cmd_action);
+                    tracing::debug!("Received command: {:?}", text);
+                    if let Ok(mut cmd) = serde_json::from_str::<AiCommand>(&text) {
+                        tracing::debug!("Command: {:?}", cmd);
+                        if cmd.action == AiAction::Cancel {
+                            if let Some(request_id) = cmd.request_id {
+                                if in_flight.cancel(request_id) {
+                                    tracing::info!("✋ {} cancelled by the client", request_id);
+                                    let _ = state
+                                        .editor_broadcast_tx
+                                        .send(AiEvent::Cancelled { request_id }.into_message());
                                 }
                             }
-                        });
+                            continue;
+                        }
+                        if matches!(cmd.action, AiAction::Undo | AiAction::Redo) {
+                            undo_own_edit(&mut undo, &cmd.action);
+                            continue;
+                        }
+                        let admitted =
+                            admit_ai_command(&state.rate_limits, ip, &cmd, Instant::now());
+                        if let Err(event) = admitted {
+                            tracing::warn!(%ip, "🚦 {} rate limited", cmd.action);
+                            // Only the throttled client needs to back off
+                            let _ = control_tx.send(Message::Text(event.to_json().into())).await;
+                            continue;
+                        }
+                        // Legacy clients send no id; give the command one so it can still be tracked
+                        let request_id = *cmd.request_id.get_or_insert_with(Uuid::new_v4);
+                        let action = cmd.action.clone();
+                        // Run the tool on its own task so we don't block the websocket heartbeat
+                        let task = tokio::spawn(tools::run_command(state.clone(), cmd, conn_id));
+                        in_flight.track(request_id, action, task);
                     }
                 }
                 _ => {}
             }
         }
+        false
     });
 
     // Keep connection alive until one side closes
     tokio::select! {
         _ = (&mut send_task) => recv_task.abort(),
-        _ = (&mut recv_task) => send_task.abort(),
+        closing = (&mut recv_task) => match closing {
+            Ok(true) => finish_closing(&recv_task, &mut send_task).await,
+            _ => send_task.abort(),
+        },
+        _ = (&mut heartbeat_task) => finish_closing(&recv_task, &mut send_task).await,
+        _ = (&mut idle_task) => finish_closing(&recv_task, &mut send_task).await,
+        _ = (&mut shutdown_task) => finish_closing(&recv_task, &mut send_task).await,
+    };
+    heartbeat_task.abort();
+    idle_task.abort();
+    shutdown_task.abort();
+
+    // Tell the others this connection's cursors are gone
+    if let Some(left) = conn_presence.disconnect(conn_id) {
+        let _ = broadcast_tx.send(MessageStructure::Awareness {
+            update: left,
+            origin: None,
+        });
+    }
+    // ...and a paragraph it focused no longer narrows the auto-linter
+    conn_agents.set_focus(conn_id, None);
+}
+
+/// A close frame is already queued; give the send task a moment to flush it
+async fn finish_closing(recv_task: &JoinHandle<bool>, send_task: &mut JoinHandle<()>) {
+    recv_task.abort();
+    if tokio::time::timeout(Duration::from_secs(1), &mut *send_task)
+        .await
+        .is_err()
+    {
+        send_task.abort();
+    }
+}
+
+/// Queue a close frame once the server starts shutting down, so the client can
+/// tell a restart from a dropped connection and reconnect.
+async fn close_on_shutdown(control: mpsc::Sender<Message>, shutdown: ShutdownTrigger) {
+    shutdown.wait().await;
+    let _ = control
+        .send(Message::Close(Some(CloseFrame {
+            code: close_code::RESTART,
+            reason: "server restarting".into(),
+        })))
+        .await;
+}
+
+/// Ping the client every `interval` (never when zero) and queue a close frame once
+/// it has been silent for `max_missed_pongs` intervals. Returns when the client is dropped.
+async fn heartbeat(
+    control: mpsc::Sender<Message>,
+    last_seen: Arc<Mutex<Instant>>,
+    interval: Duration,
+    max_missed_pongs: u32,
+) {
+    if interval.is_zero() {
+        return futures::future::pending().await;
+    }
+    let timeout = interval * max_missed_pongs;
+
+    let mut ticker = tokio::time::interval(interval);
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+
+        let idle = last_seen.lock().unwrap().elapsed();
+        if idle >= timeout {
+            tracing::warn!("💤 websocket client silent for {:?}, closing", idle);
+            let _ = control
+                .send(Message::Close(Some(CloseFrame {
+                    code: close_code::AWAY,
+                    reason: "heartbeat timeout".into(),
+                })))
+                .await;
+            return;
+        }
+
+        if control.send(Message::Ping(Default::default())).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Queue a close frame once the client has sent no edit or command for
+/// `ws_idle_timeout_secs`, so an abandoned tab stops holding a subscription and
+/// write access. Returns when the client is dropped.
+async fn close_when_idle(
+    control: mpsc::Sender<Message>,
+    last_active: Arc<Mutex<tokio::time::Instant>>,
+    opts: WebSocketOpts,
+) {
+    if opts.ws_idle_timeout_secs == 0 {
+        return futures::future::pending().await;
+    }
+    let timeout = Duration::from_secs(opts.ws_idle_timeout_secs);
+    loop {
+        let deadline = *last_active.lock().unwrap() + timeout;
+        tokio::time::sleep_until(deadline).await;
+        if last_active.lock().unwrap().elapsed() >= timeout {
+            break;
+        }
+    }
+    tracing::info!("💤 websocket client idle for {:?}, closing", timeout);
+    let _ = control
+        .send(Message::Close(Some(CloseFrame {
+            code: close_code::AWAY,
+            reason: "idle timeout".into(),
+        })))
+        .await;
+}
+
+/// Apply a client's binary update to the shared doc, tagged with its connection
+fn apply_client_update(doc: &Doc, data: &[u8], conn_id: ConnId) {
+    let mut txn = doc.transact_mut_with(conn_id.origin());
+    if let Ok(update) = Update::decode_v1(data) {
+        match txn.apply_update(update) {
+            Ok(()) => metrics::counter!("yjs_updates_applied_total").increment(1),
+            Err(e) => tracing::warn!("Failed to apply update: {:?}", e),
+        }
+    }
+}
+
+/// Undo or redo one of the connection's own edits; the doc observer broadcasts
+/// the result to every client, this one included
+fn undo_own_edit(undo: &mut UserUndo, action: &AiAction) {
+    let applied = match action {
+        AiAction::Redo => undo.redo(),
+        _ => undo.undo(),
     };
+    match applied {
+        Ok(true) => tracing::debug!("↩️ {} applied", action),
+        Ok(false) => tracing::debug!("↩️ nothing to {}", action),
+        Err(e) => tracing::warn!("❌ {} failed: {:?}", action, e),
+    }
+}
+
+/// AI commands one connection started that may still be running, by request id
+#[derive(Default)]
+struct InFlight(HashMap<Uuid, (AiAction, JoinHandle<()>)>);
+
+impl InFlight {
+    /// Remember a spawned command; a reused request id aborts the command it named before
+    fn track(&mut self, request_id: Uuid, action: AiAction, task: JoinHandle<()>) {
+        self.0.retain(|_, (_, task)| !task.is_finished());
+        if let Some((_, previous)) = self.0.insert(request_id, (action, task)) {
+            previous.abort();
+        }
+    }
+
+    /// Abort the command with `request_id`; false when it already finished or never existed
+    fn cancel(&mut self, request_id: Uuid) -> bool {
+        match self.0.remove(&request_id) {
+            Some((_, task)) if !task.is_finished() => {
+                task.abort();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Abort every running command for `action`, returning their request ids
+    fn cancel_action(&mut self, action: &AiAction) -> Vec<Uuid> {
+        let ids: Vec<Uuid> = self
+            .0
+            .iter()
+            .filter(|(_, (a, task))| a == action && !task.is_finished())
+            .map(|(id, _)| *id)
+            .collect();
+        for id in &ids {
+            self.cancel(*id);
+        }
+        ids
+    }
 }
 
-fn delegate_to_frontend(state: &AppState, command_type: &str, status: &str, message: &str) {
-    let _ = state.editor_broadcast_tx.send(MessageStructure::AiCommand(
-        serde_json::json!({
-            "type": command_type,
-            "status": status,
-            "message": message
+/// Whether a client's Yjs update is small enough to apply (`max_bytes` 0 = unlimited).
+/// Oversized updates are dropped with a warning; the connection stays open.
+fn update_within_limit(data: &[u8], max_bytes: usize) -> bool {
+    if max_bytes == 0 || data.len() <= max_bytes {
+        return true;
+    }
+    tracing::warn!(
+        "📏 dropping a {} byte update (max {})",
+        data.len(),
+        max_bytes
+    );
+    false
+}
+
+/// Take one of the client's AI command tokens, or return the event telling it to back off.
+/// Commands that never reach OpenAI (toggles, stats, ...) are always admitted.
+fn admit_ai_command(
+    limits: &AiRateLimits,
+    ip: IpAddr,
+    cmd: &AiCommand,
+    now: Instant,
+) -> Result<(), AiEvent> {
+    if !cmd.action.calls_openai() {
+        return Ok(());
+    }
+    limits
+        .ws_commands
+        .check(ip, now)
+        .map_err(|wait| AiEvent::RateLimited {
+            request_id: cmd.request_id.unwrap_or_else(Uuid::new_v4),
+            retry_after: retry_after_secs(wait),
         })
-        .to_string(),
-    ));
+}
+
+/// How often the document size estimate is replaced by an exact measurement
+pub const DOC_SIZE_RECALIBRATION: Duration = Duration::from_secs(60);
+
+/// Running estimate of the shared document's encoded size, so the `max_doc_bytes`
+/// guard can check an update before applying it without re-encoding the document.
+///
+/// Every update adds its own length, which over-counts deletions and merged
+/// items until the next exact measurement.
+#[derive(Clone, Default)]
+pub struct DocSize(Arc<AtomicUsize>);
+
+impl DocSize {
+    pub fn measure(doc: &Doc) -> Self {
+        let size = Self::default();
+        size.recalibrate(doc);
+        size
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub fn grow(&self, by: usize) {
+        self.0.fetch_add(by, Ordering::Relaxed);
+    }
+
+    /// Replace the estimate with the exact encoded size of `doc`
+    pub fn recalibrate(&self, doc: &Doc) -> usize {
+        let size = doc
+            .transact()
+            .encode_state_as_update_v1(&yrs::StateVector::default())
+            .len();
+        self.0.store(size, Ordering::Relaxed);
+        size
+    }
+
+    /// The size the document would reach with an `incoming` byte update, when
+    /// that is over `max_bytes` (0 = unlimited)
+    pub fn oversized(&self, incoming: usize, max_bytes: usize) -> Option<usize> {
+        if max_bytes == 0 {
+            return None;
+        }
+        let size = self.bytes() + incoming;
+        (size > max_bytes).then_some(size)
+    }
+}
+
+/// Keep `size` up to date with every user and AI edit on the broadcast channel,
+/// measuring the document exactly every `recalibrate` and after missed updates.
+/// Returns when the channel closes.
+pub async fn track_doc_size(
+    doc: Arc<Doc>,
+    tx: broadcast::Sender<MessageStructure>,
+    size: DocSize,
+    recalibrate: Duration,
+) {
+    let mut rx = tx.subscribe();
+    drop(tx);
+    let mut ticker = tokio::time::interval(recalibrate);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {
+                size.recalibrate(&doc);
+            }
+            msg = rx.recv() => match msg {
+                Ok(MessageStructure::YjsUpdate { data, .. }) => size.grow(data.len()),
+                Ok(MessageStructure::AiCommand(_) | MessageStructure::Awareness { .. }) => {}
+                Err(RecvError::Lagged(_)) => {
+                    size.recalibrate(&doc);
+                }
+                Err(RecvError::Closed) => return,
+            },
+        }
+    }
+}
+
+/// Total number of times a client fell behind the broadcast channel and had to resync
+static WS_LAG_EVENTS: AtomicU64 = AtomicU64::new(0);
+
+/// Text frame sent right before a binary update the AI tools wrote, so clients
+/// can tell AI edits from their collaborators'
+pub const AI_UPDATE_FRAME: &str = r#"{"type":"AI_UPDATE"}"#;
+
+/// Forward server broadcasts to one client until the channel closes or the client drops.
+/// A client that lags behind the channel gets the full document state instead of
+/// silently missing the updates that were overwritten.
+async fn forward_broadcasts<S>(
+    sender: &mut S,
+    rx: &mut broadcast::Receiver<MessageStructure>,
+    control: &mut mpsc::Receiver<Message>,
+    doc: &Doc,
+    conn_id: ConnId,
+    opts: &WebSocketOpts,
+) where
+    S: Sink<Message, Error = axum::Error> + Unpin,
+{
+    loop {
+        let recv = tokio::select! {
+            // Heartbeat pings and close frames go out ahead of document traffic
+            Some(control_msg) = control.recv() => {
+                let closing = matches!(control_msg, Message::Close(_));
+                if let Err(e) = send_with_retry(sender, control_msg, opts).await {
+                    tracing::warn!("Dropping websocket client after send failure: {:?}", e);
+                    break;
+                }
+                if closing {
+                    break;
+                }
+                continue;
+            }
+            recv = rx.recv() => recv,
+        };
+
+        let ws_msg = match recv {
+            // Unpack Lane A -> Binary
+            // The client already has the updates it sent us
+            Ok(MessageStructure::YjsUpdate {
+                origin: Some(origin),
+                ..
+            }) if origin == conn_id => continue,
+            Ok(MessageStructure::YjsUpdate {
+                data, is_ai: true, ..
+            }) => {
+                let marker = Message::Text(AI_UPDATE_FRAME.into());
+                if let Err(e) = send_with_retry(sender, marker, opts).await {
+                    tracing::warn!("Dropping websocket client after send failure: {:?}", e);
+                    break;
+                }
+                Message::Binary(data.into())
+            }
+            Ok(MessageStructure::YjsUpdate { data, .. }) => Message::Binary(data.into()),
+
+            // Unpack Lane B -> Text
+            Ok(MessageStructure::AiCommand(json_string)) => Message::Text(json_string.into()),
+
+            // Presence -> Binary awareness message; a client knows its own cursors
+            Ok(MessageStructure::Awareness {
+                origin: Some(origin),
+                ..
+            }) if origin == conn_id => continue,
+            Ok(MessageStructure::Awareness { update, .. }) => {
+                Message::Binary(presence::awareness_frame(&update).into())
+            }
+
+            Err(RecvError::Lagged(skipped)) => {
+                let total = WS_LAG_EVENTS.fetch_add(1, Ordering::Relaxed) + 1;
+                metrics::counter!("ws_broadcast_lag_total").increment(1);
+                tracing::warn!(
+                    skipped,
+                    ws_lag_events = total,
+                    "⚠️ websocket client lagged, resyncing full document"
+                );
+                let full_state = doc
+                    .transact()
+                    .encode_state_as_update_v1(&yrs::StateVector::default());
+                Message::Binary(full_state.into())
+            }
+
+            Err(RecvError::Closed) => break,
+        };
+
+        if let Err(e) = send_with_retry(sender, ws_msg, opts).await {
+            tracing::warn!("Dropping websocket client after send failure: {:?}", e);
+            break;
+        }
+    }
+}
+
+/// Send a message, retrying transient failures before giving up on the client.
+///
+/// Fatal errors (the connection is closed or reset) are returned immediately;
+/// anything else is retried up to `ws_send_retries` times.
+async fn send_with_retry<S>(
+    sender: &mut S,
+    msg: Message,
+    opts: &WebSocketOpts,
+) -> Result<(), axum::Error>
+where
+    S: Sink<Message, Error = axum::Error> + Unpin,
+{
+    let mut attempt = 0;
+    loop {
+        match sender.send(msg.clone()).await {
+            Ok(()) => return Ok(()),
+            Err(e) if attempt < opts.ws_send_retries && !is_fatal_send_error(&e) => {
+                attempt += 1;
+                tracing::debug!(attempt, "Transient websocket send failure, retrying: {:?}", e);
+                tokio::time::sleep(Duration::from_millis(opts.ws_send_retry_delay_ms)).await;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Whether a failed send should end the connection rather than be retried.
+///
+/// Only an I/O error of a passing kind is worth another attempt. Everything else
+/// (tungstenite's `ConnectionClosed`, `AlreadyClosed`, protocol errors) means the
+/// socket is done, however the error happens to be worded.
+fn is_fatal_send_error(err: &axum::Error) -> bool {
+    let mut source: Option<&(dyn std::error::Error + 'static)> = Some(err);
+    while let Some(e) = source {
+        if let Some(io_err) = e.downcast_ref::<std::io::Error>() {
+            return !matches!(
+                io_err.kind(),
+                std::io::ErrorKind::WouldBlock
+                    | std::io::ErrorKind::TimedOut
+                    | std::io::ErrorKind::Interrupted
+            );
+        }
+        source = e.source();
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::FromRef;
+    use std::{
+        io,
+        pin::Pin,
+        task::{Context, Poll},
+    };
+    use tower::ServiceExt;
+
+    /// A sink that fails the first `failures` sends with the given io error kind.
+    struct FlakySink {
+        failures: usize,
+        kind: io::ErrorKind,
+        sent: Vec<Message>,
+    }
+
+    impl Sink<Message> for FlakySink {
+        type Error = axum::Error;
+
+        fn poll_ready(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn start_send(mut self: Pin<&mut Self>, item: Message) -> Result<(), Self::Error> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err(axum::Error::new(io::Error::from(self.kind)));
+            }
+            self.sent.push(item);
+            Ok(())
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[test]
+    fn test_export_format_defaults_to_markdown() {
+        let parse = |query: &str| {
+            Query::<ExportQuery>::try_from_uri(&format!("/editor/export{query}").parse().unwrap())
+                .map(|q| q.0.format)
+        };
+        assert_eq!(parse("").unwrap(), ExportFormat::Markdown);
+        assert_eq!(parse("?format=markdown").unwrap(), ExportFormat::Markdown);
+        assert_eq!(parse("?format=html").unwrap(), ExportFormat::Html);
+        assert_eq!(parse("?format=text").unwrap(), ExportFormat::Text);
+        assert!(parse("?format=docx").is_err());
+    }
+
+    #[test]
+    fn test_document_routes_follow_ws_auth_policy() {
+        let mut opts = test_opts();
+        assert_eq!(
+            require_editor(Err(AuthError::MissingCredentials), &opts),
+            Err(AuthError::MissingCredentials)
+        );
+
+        opts.ws_auth_disabled = true;
+        assert_eq!(
+            require_editor(Err(AuthError::MissingCredentials), &opts),
+            Ok(())
+        );
+    }
+
+    #[derive(Clone, FromRef)]
+    struct ContentState {
+        doc: Arc<Doc>,
+        opts: WebSocketOpts,
+        decoder: Decoder,
+    }
+
+    #[tokio::test]
+    async fn test_content_route_returns_document_text() {
+        let doc = Arc::new(Doc::new());
+        import_markdown(&doc, "Hello **world**").unwrap();
+        let app = axum::Router::new()
+            .route("/editor/content", get(content_handler))
+            .with_state(ContentState {
+                doc,
+                opts: test_opts(),
+                decoder: test_decoder(),
+            });
+        let get_content = |bearer: Option<String>| {
+            let mut request = axum::http::Request::get("/editor/content");
+            if let Some(token) = bearer {
+                request = request.header(header::AUTHORIZATION, format!("Bearer {token}"));
+            }
+            request.body(axum::body::Body::empty()).unwrap()
+        };
+
+        let response = app.clone().oneshot(get_content(None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let token = test_token(atb_types::Duration::minutes(5));
+        let response = app.oneshot(get_content(Some(token))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let content: DocContent = serde_json::from_slice(&body).unwrap();
+        assert_eq!(content.text, "Hello world");
+    }
+
+    fn test_opts() -> WebSocketOpts {
+        WebSocketOpts {
+            ws_send_retries: 3,
+            ws_send_retry_delay_ms: 1,
+            ws_broadcast_capacity: 100,
+            ws_idle_timeout_secs: 60,
+            ws_auth_disabled: false,
+        }
+    }
+
+    fn test_token(expires_in: atb_types::Duration) -> String {
+        atb_types::prelude::Builder::with_custom("tt", expires_in, None::<()>)
+            .subject(alice())
+            .audience(vec![])
+            .build_fingerprinted()
+            .0
+            .encode(
+                &atb_types::jwt::HEADER_RS256,
+                &atb::fixtures::jwt::JWT_ENCODING_KEY,
+            )
+            .unwrap()
+    }
+
+    fn alice() -> Uuid {
+        let id: &Uuid = &atb::fixtures::ALICE;
+        *id
+    }
+
+    fn test_decoder() -> Decoder {
+        Decoder(atb::fixtures::jwt::JWT_DECODING_KEY.clone())
+    }
+
+    #[test]
+    fn test_ws_auth_accepts_valid_token() {
+        let token = test_token(atb_types::Duration::minutes(5));
+
+        let from_query = authorize_ws(Some(&token), &HeaderMap::new(), &test_decoder(), &test_opts());
+        assert_eq!(from_query, Ok(Some(alice())));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::SEC_WEBSOCKET_PROTOCOL,
+            format!("bearer, {token}").parse().unwrap(),
+        );
+        let from_protocol = authorize_ws(None, &headers, &test_decoder(), &test_opts());
+        assert_eq!(from_protocol, Ok(Some(alice())));
+    }
+
+    #[test]
+    fn test_ws_auth_rejects_expired_token() {
+        let token = test_token(atb_types::Duration::minutes(-5));
+        let err = authorize_ws(Some(&token), &HeaderMap::new(), &test_decoder(), &test_opts())
+            .unwrap_err();
+        assert_eq!(err.into_response().status(), axum::http::StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn test_ws_auth_rejects_invalid_token() {
+        let mut token = test_token(atb_types::Duration::minutes(5));
+        // Corrupt the signature so the decoder refuses it
+        token.push('x');
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::SEC_WEBSOCKET_PROTOCOL,
+            format!("bearer, {token}").parse().unwrap(),
+        );
+        assert_eq!(
+            authorize_ws(None, &headers, &test_decoder(), &test_opts()),
+            Err(AuthError::InvalidToken)
+        );
+        assert_eq!(
+            authorize_ws(Some("not-a-jwt"), &HeaderMap::new(), &test_decoder(), &test_opts()),
+            Err(AuthError::InvalidToken)
+        );
+    }
+
+    #[test]
+    fn test_ws_auth_missing_token() {
+        let err = authorize_ws(None, &HeaderMap::new(), &test_decoder(), &test_opts()).unwrap_err();
+        assert_eq!(err.into_response().status(), axum::http::StatusCode::UNAUTHORIZED);
+
+        let opts = WebSocketOpts {
+            ws_auth_disabled: true,
+            ..test_opts()
+        };
+        assert_eq!(authorize_ws(None, &HeaderMap::new(), &test_decoder(), &opts), Ok(None));
+    }
+
+    #[tokio::test]
+    async fn test_send_with_retry_recovers_from_transient_failure() {
+        let mut sink = FlakySink {
+            failures: 2,
+            kind: io::ErrorKind::WouldBlock,
+            sent: Vec::new(),
+        };
+
+        let result = send_with_retry(&mut sink, Message::Text("hello".into()), &test_opts()).await;
+        assert!(result.is_ok());
+        assert_eq!(sink.sent.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_send_with_retry_gives_up_on_fatal_failure() {
+        let mut sink = FlakySink {
+            failures: 1,
+            kind: io::ErrorKind::BrokenPipe,
+            sent: Vec::new(),
+        };
+
+        let result = send_with_retry(&mut sink, Message::Text("hello".into()), &test_opts()).await;
+        assert!(result.is_err());
+        // The fatal error is not retried, so the single failure is the only attempt
+        assert_eq!(sink.failures, 0);
+        assert!(sink.sent.is_empty());
+    }
+
+    #[test]
+    fn test_fatal_send_errors_are_told_apart_by_kind() {
+        /// A wrapper like tungstenite's `Error::Io`, whose source is the I/O error
+        #[derive(Debug)]
+        struct Wrapped(io::Error);
+
+        impl std::fmt::Display for Wrapped {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "IO error: {}", self.0)
+            }
+        }
+
+        impl std::error::Error for Wrapped {
+            fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+                Some(&self.0)
+            }
+        }
+
+        let transient = io::Error::new(io::ErrorKind::WouldBlock, "connection closed");
+        assert!(!is_fatal_send_error(&axum::Error::new(Wrapped(transient))));
+        let broken = io::Error::from(io::ErrorKind::BrokenPipe);
+        assert!(is_fatal_send_error(&axum::Error::new(Wrapped(broken))));
+        // Not an I/O error at all, whatever its message says
+        assert!(is_fatal_send_error(&axum::Error::new(
+            "Trying to work with closed connection"
+        )));
+        assert!(is_fatal_send_error(&axum::Error::new(
+            "Space limit exceeded"
+        )));
+    }
+
+    #[tokio::test]
+    async fn test_send_with_retry_exhausts_retries() {
+        let mut sink = FlakySink {
+            failures: 10,
+            kind: io::ErrorKind::WouldBlock,
+            sent: Vec::new(),
+        };
+
+        let result = send_with_retry(&mut sink, Message::Text("hello".into()), &test_opts()).await;
+        assert!(result.is_err());
+        // One initial attempt plus three retries
+        assert_eq!(sink.failures, 6);
+    }
+
+    #[tokio::test]
+    async fn test_lagged_client_resyncs_full_document() {
+        use yrs::{GetString, Text};
+
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("content");
+        let (tx, mut rx) = broadcast::channel(4);
+
+        // Same wiring as mono.rs: every committed change is broadcast as a delta
+        let tx_clone = tx.clone();
+        let sub = doc.observe_update_v1(move |txn, e| {
+            let _ = tx_clone.send(MessageStructure::from_update(txn, &e.update));
+        });
+
+        // Nobody is reading yet, so the channel overflows well past its capacity
+        for i in 0..50 {
+            let mut txn = doc.transact_mut();
+            let len = text.len(&txn);
+            text.insert(&mut txn, len, &format!("{i} "));
+        }
+        drop(sub);
+        drop(tx);
+
+        let mut sink = FlakySink {
+            failures: 0,
+            kind: io::ErrorKind::WouldBlock,
+            sent: Vec::new(),
+        };
+        let (_control_tx, mut control_rx) = mpsc::channel(1);
+        forward_broadcasts(&mut sink, &mut rx, &mut control_rx, &doc, ConnId::next(), &test_opts())
+            .await;
+
+        let client = Doc::new();
+        let client_text = client.get_or_insert_text("content");
+        {
+            let mut txn = client.transact_mut();
+            for msg in sink.sent {
+                if let Message::Binary(data) = msg {
+                    txn.apply_update(Update::decode_v1(&data).unwrap()).unwrap();
+                }
+            }
+        }
+        assert_eq!(
+            client_text.get_string(&client.transact()),
+            text.get_string(&doc.transact())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_sends_a_restart_close_frame() {
+        let doc = Doc::new();
+        let (_tx, mut rx) = broadcast::channel::<MessageStructure>(4);
+        let (control_tx, mut control_rx) = mpsc::channel(4);
+        let shutdown = ShutdownTrigger::new();
+        let closing = tokio::spawn(close_on_shutdown(control_tx, shutdown.clone()));
+
+        let opts = test_opts();
+        let send_task = tokio::spawn(async move {
+            let mut sink = FlakySink {
+                failures: 0,
+                kind: io::ErrorKind::WouldBlock,
+                sent: Vec::new(),
+            };
+            forward_broadcasts(
+                &mut sink,
+                &mut rx,
+                &mut control_rx,
+                &doc,
+                ConnId::next(),
+                &opts,
+            )
+            .await;
+            sink.sent
+        });
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!send_task.is_finished());
+
+        shutdown.trigger();
+        let sent = tokio::time::timeout(Duration::from_secs(1), send_task)
+            .await
+            .expect("client was not closed on shutdown")
+            .unwrap();
+        assert!(closing.await.is_ok());
+        match sent.last() {
+            Some(Message::Close(Some(frame))) => {
+                assert_eq!(frame.code, close_code::RESTART);
+                assert_eq!(frame.reason.as_str(), "server restarting");
+            }
+            other => panic!("expected a close frame, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_silent_client_is_dropped_after_missed_heartbeats() {
+        let doc = Doc::new();
+        let (tx, mut rx) = broadcast::channel::<MessageStructure>(4);
+        let (control_tx, mut control_rx) = mpsc::channel(4);
+        assert_eq!(tx.receiver_count(), 1);
+
+        let opts = test_opts();
+        // The client never answers, so last_seen stays at connect time
+        let last_seen = Arc::new(Mutex::new(Instant::now()));
+        let heartbeat_task = tokio::spawn(heartbeat(
+            control_tx,
+            last_seen,
+            Duration::from_millis(10),
+            3,
+        ));
+
+        let send_task = tokio::spawn(async move {
+            let mut sink = FlakySink {
+                failures: 0,
+                kind: io::ErrorKind::WouldBlock,
+                sent: Vec::new(),
+            };
+            forward_broadcasts(&mut sink, &mut rx, &mut control_rx, &doc, ConnId::next(), &opts)
+                .await;
+            sink.sent
+        });
+
+        // 3 missed 10ms heartbeats; allow generous slack for a busy test runner
+        let sent = tokio::time::timeout(Duration::from_secs(2), send_task)
+            .await
+            .expect("silent client was not dropped in time")
+            .unwrap();
+        assert!(heartbeat_task.await.is_ok());
+
+        assert!(sent.iter().any(|m| matches!(m, Message::Ping(_))));
+        match sent.last() {
+            Some(Message::Close(Some(frame))) => {
+                assert_eq!(frame.code, close_code::AWAY);
+                assert_eq!(frame.reason.as_str(), "heartbeat timeout");
+            }
+            other => panic!("expected a close frame, got {other:?}"),
+        }
+        // The dropped connection no longer holds a broadcast receiver
+        assert_eq!(tx.receiver_count(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_idle_client_is_closed_after_the_timeout() {
+        let (control_tx, mut control_rx) = mpsc::channel(4);
+        let last_active = Arc::new(Mutex::new(tokio::time::Instant::now()));
+        let idle_task = tokio::spawn(close_when_idle(
+            control_tx,
+            last_active.clone(),
+            test_opts(),
+        ));
+
+        // An edit halfway through restarts the 60s clock
+        tokio::time::sleep(Duration::from_secs(30)).await;
+        *last_active.lock().unwrap() = tokio::time::Instant::now();
+        tokio::time::sleep(Duration::from_secs(45)).await;
+        assert!(control_rx.try_recv().is_err(), "closed while still active");
+
+        tokio::time::sleep(Duration::from_secs(20)).await;
+        match control_rx.try_recv() {
+            Ok(Message::Close(Some(frame))) => {
+                assert_eq!(frame.code, close_code::AWAY);
+                assert_eq!(frame.reason.as_str(), "idle timeout");
+            }
+            other => panic!("expected a close frame, got {other:?}"),
+        }
+        assert!(idle_task.await.is_ok());
+    }
+
+    #[test]
+    fn test_ai_commands_past_the_limit_are_rejected() {
+        use crate::opts::HttpOpts;
+        use atb_cli_utils::clap::Parser;
+
+        let opts = HttpOpts::parse_from(["backend", "--ws-ai-commands-per-minute", "2"]);
+        let limits = AiRateLimits::new(&opts);
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let command = |action: &str| -> AiCommand {
+            serde_json::from_value(serde_json::json!({
+                "type": "AI_COMMAND",
+                "action": action,
+                "request_id": "6f1c2b9e-2a4d-4c1e-9b7a-0d8e5f3a1c22",
+                "payload": "some text"
+            }))
+            .unwrap()
+        };
+        let now = Instant::now();
+
+        assert!(admit_ai_command(&limits, ip, &command("IMPROVE"), now).is_ok());
+        assert!(admit_ai_command(&limits, ip, &command("FIX"), now).is_ok());
+        let rejected = admit_ai_command(&limits, ip, &command("IMPROVE"), now).unwrap_err();
+        let event = serde_json::to_value(&rejected).unwrap();
+        assert_eq!(event["status"], "error");
+        assert_eq!(event["code"], "RATE_LIMITED");
+        assert_eq!(event["request_id"], "6f1c2b9e-2a4d-4c1e-9b7a-0d8e5f3a1c22");
+        assert_eq!(event["retry_after"], 30);
+
+        // Toggling an agent costs nothing and is never throttled
+        assert!(admit_ai_command(&limits, ip, &command("TOGGLE"), now).is_ok());
+        // One command refills every 60s / 2
+        let later = now + Duration::from_secs(30);
+        assert!(admit_ai_command(&limits, ip, &command("IMPROVE"), later).is_ok());
+    }
+
+    #[test]
+    fn test_oversized_document_is_detected() {
+        use yrs::Text;
+
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("content");
+        text.insert(&mut doc.transact_mut(), 0, &"x".repeat(1000));
+
+        let size = DocSize::measure(&doc);
+        let grown = size.oversized(0, 100).expect("1000 chars exceed 100 bytes");
+        assert!(grown > 1000);
+        assert_eq!(size.oversized(0, 1_000_000), None);
+        // The incoming update counts before it is applied
+        assert!(size.oversized(1_000_000, 1_000_000).is_some());
+        // 0 turns the guard off
+        assert_eq!(size.oversized(1_000_000, 0), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_doc_size_follows_broadcast_updates() {
+        use yrs::Text;
+
+        let doc = Arc::new(Doc::new());
+        let text = doc.get_or_insert_text("content");
+        text.insert(&mut doc.transact_mut(), 0, &"x".repeat(1000));
+        let (tx, _) = broadcast::channel::<MessageStructure>(4);
+        let size = DocSize::default();
+        tokio::spawn(track_doc_size(
+            doc.clone(),
+            tx.clone(),
+            size.clone(),
+            DOC_SIZE_RECALIBRATION,
+        ));
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        let exact = size.bytes();
+        assert!(exact > 1000, "measured when tracking starts");
+
+        let _ = tx.send(MessageStructure::YjsUpdate {
+            data: vec![0; 50],
+            origin: None,
+            is_ai: true,
+        });
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        assert_eq!(size.bytes(), exact + 50);
+
+        // The next exact measurement drops the estimate back to the real size
+        tokio::time::sleep(DOC_SIZE_RECALIBRATION).await;
+        assert_eq!(size.bytes(), exact);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cancel_aborts_the_command_before_it_writes() {
+        use yrs::{GetString, Text};
+
+        let doc = Arc::new(Doc::new());
+        let text = doc.get_or_insert_text("content");
+        let slow_write = |doc: Arc<Doc>, content: &'static str| {
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                let text = doc.get_or_insert_text("content");
+                let len = text.len(&doc.transact());
+                text.insert(&mut doc.transact_mut(), len, content);
+            })
+        };
+
+        let mut in_flight = InFlight::default();
+        let (agent, improve) = (Uuid::new_v4(), Uuid::new_v4());
+        in_flight.track(
+            agent,
+            AiAction::Agent,
+            slow_write(doc.clone(), "stale draft"),
+        );
+        in_flight.track(improve, AiAction::Improve, slow_write(doc.clone(), "kept"));
+
+        // Typing cancels only the composer; an explicit cancel takes the rest
+        assert_eq!(in_flight.cancel_action(&AiAction::Agent), vec![agent]);
+        assert!(!in_flight.cancel(agent), "already cancelled");
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        assert_eq!(text.get_string(&doc.transact()), "kept");
+
+        let cancelled = Uuid::new_v4();
+        in_flight.track(cancelled, AiAction::Fix, slow_write(doc.clone(), " fixed"));
+        assert!(in_flight.cancel(cancelled));
+        assert!(
+            !in_flight.cancel(improve),
+            "finished commands can't be cancelled"
+        );
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        assert_eq!(text.get_string(&doc.transact()), "kept");
+    }
+
+    #[test]
+    fn test_oversized_update_is_dropped_and_later_updates_apply() {
+        use yrs::{GetString, Text};
+
+        // Each frame comes from its own client, so they apply independently
+        let frame = |content: &str| {
+            let client = Doc::new();
+            let text = client.get_or_insert_text("content");
+            text.insert(&mut client.transact_mut(), 0, content);
+            client
+                .transact()
+                .encode_state_as_update_v1(&yrs::StateVector::default())
+        };
+        let big = frame(&"x".repeat(1000));
+        let small = frame("hi");
+
+        let server = Doc::new();
+        let conn_id = ConnId::next();
+        for data in [big, small] {
+            if update_within_limit(&data, 100) {
+                apply_client_update(&server, &data, conn_id);
+            }
+        }
+        let text = server.get_or_insert_text("content");
+        assert_eq!(text.get_string(&server.transact()), "hi");
+        assert!(update_within_limit(&[0; 1000], 0), "0 turns the cap off");
+    }
+
+    #[tokio::test]
+    async fn test_update_is_not_echoed_to_its_sender() {
+        use yrs::{GetString, Text};
+
+        let doc = Doc::new();
+        let (tx, rx_a) = broadcast::channel(16);
+        let rx_b = tx.subscribe();
+        let tx_clone = tx.clone();
+        let sub = doc.observe_update_v1(move |txn, e| {
+            let _ = tx_clone.send(MessageStructure::from_update(txn, &e.update));
+        });
+
+        // Client A types locally and sends its update to the server
+        let (conn_a, conn_b) = (ConnId::next(), ConnId::next());
+        let client_a = Doc::new();
+        let text_a = client_a.get_or_insert_text("content");
+        text_a.insert(&mut client_a.transact_mut(), 0, "hello from A");
+        let update = client_a
+            .transact()
+            .encode_state_as_update_v1(&yrs::StateVector::default());
+        apply_client_update(&doc, &update, conn_a);
+        drop(sub);
+        drop(tx);
+
+        let forward = |mut rx: broadcast::Receiver<MessageStructure>, conn_id| {
+            let doc = &doc;
+            async move {
+                let mut sink = FlakySink {
+                    failures: 0,
+                    kind: io::ErrorKind::WouldBlock,
+                    sent: Vec::new(),
+                };
+                let (_control_tx, mut control_rx) = mpsc::channel(1);
+                forward_broadcasts(&mut sink, &mut rx, &mut control_rx, doc, conn_id, &test_opts())
+                    .await;
+                sink.sent
+            }
+        };
+        let sent_to_a = forward(rx_a, conn_a).await;
+        let sent_to_b = forward(rx_b, conn_b).await;
+
+        assert!(sent_to_a.is_empty());
+        assert_eq!(sent_to_b.len(), 1);
+
+        let client_b = Doc::new();
+        let text_b = client_b.get_or_insert_text("content");
+        if let Message::Binary(data) = &sent_to_b[0] {
+            client_b
+                .transact_mut()
+                .apply_update(Update::decode_v1(data).unwrap())
+                .unwrap();
+        }
+        assert_eq!(text_b.get_string(&client_b.transact()), "hello from A");
+    }
+
+    #[tokio::test]
+    async fn test_ai_updates_are_announced_to_clients() {
+        use yrs::Text;
+
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("content");
+        let (tx, mut rx) = broadcast::channel(16);
+        let tx_clone = tx.clone();
+        let sub = doc.observe_update_v1(move |txn, e| {
+            let _ = tx_clone.send(MessageStructure::from_update(txn, &e.update));
+        });
+
+        {
+            let mut txn = doc.transact_mut_with(backend_core::editor::AI_ORIGIN);
+            text.insert(&mut txn, 0, "AI wrote this. ");
+        }
+        text.insert(&mut doc.transact_mut(), 0, "Server edit. ");
+        drop(sub);
+        drop(tx);
+
+        let mut sink = FlakySink {
+            failures: 0,
+            kind: io::ErrorKind::WouldBlock,
+            sent: Vec::new(),
+        };
+        let (_control_tx, mut control_rx) = mpsc::channel(1);
+        forward_broadcasts(
+            &mut sink,
+            &mut rx,
+            &mut control_rx,
+            &doc,
+            ConnId::next(),
+            &test_opts(),
+        )
+        .await;
+
+        // Only the AI's update is preceded by the marker
+        let kinds: Vec<_> = sink
+            .sent
+            .iter()
+            .map(|m| match m {
+                Message::Text(text) => text.as_str(),
+                Message::Binary(_) => "binary",
+                _ => "other",
+            })
+            .collect();
+        assert_eq!(kinds, [AI_UPDATE_FRAME, "binary", "binary"]);
+    }
+
+    #[tokio::test]
+    async fn test_awareness_is_relayed_to_others_but_not_its_sender() {
+        let doc = Doc::new();
+        let (tx, mut rx_a) = broadcast::channel(16);
+        let mut rx_b = tx.subscribe();
+        let (conn_a, conn_b) = (ConnId::next(), ConnId::next());
+
+        // Client A moves its cursor; the server relays the update it announced
+        let awareness = yrs::sync::Awareness::new(Doc::new());
+        awareness
+            .set_local_state(serde_json::json!({ "cursor": 3 }))
+            .unwrap();
+        let update = yrs::updates::encoder::Encode::encode_v1(&awareness.update().unwrap());
+        tx.send(MessageStructure::Awareness {
+            update: update.clone(),
+            origin: Some(conn_a),
+        })
+        .unwrap();
+        drop(tx);
+
+        let mut sent = Vec::new();
+        for (rx, conn_id) in [(&mut rx_a, conn_a), (&mut rx_b, conn_b)] {
+            let mut sink = FlakySink {
+                failures: 0,
+                kind: io::ErrorKind::WouldBlock,
+                sent: Vec::new(),
+            };
+            let (_control_tx, mut control_rx) = mpsc::channel(1);
+            forward_broadcasts(&mut sink, rx, &mut control_rx, &doc, conn_id, &test_opts()).await;
+            sent.push(sink.sent);
+        }
+
+        assert!(sent[0].is_empty());
+        match sent[1].as_slice() {
+            [Message::Binary(frame)] => {
+                assert_eq!(presence::parse_awareness_frame(frame), Some(update));
+            }
+            other => panic!("expected one binary awareness frame, got {other:?}"),
+        }
+    }
 }
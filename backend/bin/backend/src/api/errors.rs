@@ -1,71 +1,380 @@
-use axum::{Json, http::StatusCode, response::IntoResponse};
+use crate::api::claims::AuthError;
+
+use axum::{
+    Json,
+    extract::rejection::JsonRejection,
+    http::{HeaderValue, StatusCode, header},
+    response::IntoResponse,
+};
+use backend_core::llm::tools::linter::DocumentChanged;
+use backend_core::refiner::error::RefineError;
 use serde::Serialize;
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
-    #[error("invalid input {0}")]
-    Custom(String),
+    /// Well-formed request that fails validation; `field` names the offending input
+    #[error("Validation {field}: {message}")]
+    Validation {
+        field: &'static str,
+        message: String,
+    },
 
     #[error("Validation Error {0}")]
     Validations(#[from] validator::ValidationErrors),
 
-    #[error("invalid input {0}")]
-    InvalidInput(String),
+    /// Body over the configured limit, whether caught by the limit layer or while buffering
+    #[error("Request body too large")]
+    PayloadTooLarge,
+
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+
+    /// The document changed under a request that rewrites it; retry on the current content
+    #[error("Conflict: {0}")]
+    Conflict(String),
+
+    #[error("Too many requests, retry in {0}s")]
+    RateLimited(u64),
+
+    /// OpenAI failed or could not be reached; `provider_status` is its HTTP status, if it answered
+    #[error("AI provider error ({provider_status:?}): {message}")]
+    UpstreamAi {
+        provider_status: Option<u16>,
+        message: String,
+    },
 
     #[error("Database Error {0}")]
     Sqlx(#[from] sqlx::Error),
 
-    #[error("Unauthorized")]
-    Unauthorized(String),
+    /// Anything else; the details are logged, never sent to the client
+    #[error("Internal error: {0}")]
+    Internal(anyhow::Error),
+}
 
-    #[error("Invalid IP")]
-    InvalidIp,
+impl From<JsonRejection> for Error {
+    fn from(rejection: JsonRejection) -> Self {
+        if rejection.status() == StatusCode::PAYLOAD_TOO_LARGE {
+            return Self::PayloadTooLarge;
+        }
+        Self::Validation {
+            field: "body",
+            message: rejection.body_text(),
+        }
+    }
+}
+
+impl From<AuthError> for Error {
+    fn from(e: AuthError) -> Self {
+        match e {
+            AuthError::MissingCredentials => Self::Unauthorized("Missing credentials".to_string()),
+            AuthError::InvalidToken => Self::Unauthorized("Invalid token".to_string()),
+            AuthError::Forbidden => Self::Forbidden("Forbidden".to_string()),
+        }
+    }
+}
+
+impl From<RefineError> for Error {
+    fn from(e: RefineError) -> Self {
+        Self::from_refine(&e)
+    }
 }
 
 impl Error {
-    fn info(&self) -> (i16, StatusCode, Option<String>, Option<serde_json::Value>) {
-        tracing::info!("api request error: {}", self);
-        match self {
-            Self::Custom(_) => (1000, StatusCode::INTERNAL_SERVER_ERROR, None, None),
-            Self::Validations(e) => (1001, StatusCode::BAD_REQUEST, Some(e.to_string()), None),
-            Self::InvalidInput(s) => (1009, StatusCode::BAD_REQUEST, Some(s.to_string()), None),
-            Self::Sqlx(err) => match err {
-                sqlx::Error::RowNotFound => (1002, StatusCode::NOT_FOUND, None, None),
-                _ => (1002, StatusCode::INTERNAL_SERVER_ERROR, None, None),
+    /// Classify a failed AI call: refiner errors keep their meaning, a stale lint
+    /// is a conflict, transport failures are upstream, and anything else is internal.
+    pub fn from_ai(e: &anyhow::Error) -> Self {
+        if let Some(refine) = e.downcast_ref::<RefineError>() {
+            return Self::from_refine(refine);
+        }
+        if let Some(changed) = e.downcast_ref::<DocumentChanged>() {
+            return Self::Conflict(changed.to_string());
+        }
+        if let Some(request) = e.downcast_ref::<reqwest::Error>() {
+            return Self::UpstreamAi {
+                provider_status: request.status().map(|s| s.as_u16()),
+                message: request.to_string(),
+            };
+        }
+        Self::Internal(anyhow::anyhow!("{e:?}"))
+    }
+
+    fn from_refine(e: &RefineError) -> Self {
+        let message = e.to_string();
+        match e {
+            RefineError::NoContentStructure => Self::Validation {
+                field: "text",
+                message,
+            },
+            RefineError::InvalidInstruction(_) => Self::Validation {
+                field: "instruction",
+                message,
+            },
+            RefineError::UnsupportedLanguage(_) => Self::Validation {
+                field: "language",
+                message,
+            },
+            RefineError::RateLimited => Self::UpstreamAi {
+                provider_status: Some(StatusCode::TOO_MANY_REQUESTS.as_u16()),
+                message,
+            },
+            RefineError::OpenAiStatus(status, _) => Self::UpstreamAi {
+                provider_status: Some(status.as_u16()),
+                message,
             },
-            Self::Unauthorized(s) => (1008, StatusCode::UNAUTHORIZED, Some(s.to_string()), None),
-            Self::InvalidIp => (2007, StatusCode::FORBIDDEN, None, None),
+            RefineError::Parse(_) | RefineError::Request(_) => Self::UpstreamAi {
+                provider_status: None,
+                message,
+            },
+            RefineError::DirectiveNotFound | RefineError::Other(_) => {
+                Self::Internal(anyhow::anyhow!(message))
+            }
+        }
+    }
+
+    /// Machine-readable code and the message safe to show, for transports other than JSON over HTTP
+    pub fn client_error(&self) -> (&'static str, String) {
+        let (_, code, message, _) = self.info();
+        (code, message)
+    }
+
+    /// Status code, machine-readable code, the message safe to show, and extra fields
+    fn info(&self) -> (StatusCode, &'static str, String, Option<serde_json::Value>) {
+        match self {
+            Self::Validation { field, message } => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "VALIDATION",
+                message.clone(),
+                Some(serde_json::json!({ "field": field })),
+            ),
+            Self::Validations(e) => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "VALIDATION",
+                e.to_string(),
+                None,
+            ),
+            Self::PayloadTooLarge => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "PAYLOAD_TOO_LARGE",
+                self.to_string(),
+                None,
+            ),
+            Self::Unauthorized(message) => (
+                StatusCode::UNAUTHORIZED,
+                "UNAUTHORIZED",
+                message.clone(),
+                None,
+            ),
+            Self::Forbidden(message) => (StatusCode::FORBIDDEN, "FORBIDDEN", message.clone(), None),
+            Self::Conflict(message) => (StatusCode::CONFLICT, "CONFLICT", message.clone(), None),
+            Self::RateLimited(retry_after) => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "RATE_LIMITED",
+                format!("Too many requests, retry in {retry_after}s"),
+                Some(serde_json::json!({ "retry_after": retry_after })),
+            ),
+            Self::UpstreamAi {
+                provider_status, ..
+            } => (
+                StatusCode::BAD_GATEWAY,
+                "UPSTREAM_AI",
+                match provider_status {
+                    Some(status) => format!("The AI service returned an error ({status})."),
+                    None => "Could not reach the AI service.".to_string(),
+                },
+                provider_status.map(|status| serde_json::json!({ "provider_status": status })),
+            ),
+            Self::Sqlx(sqlx::Error::RowNotFound) => (
+                StatusCode::NOT_FOUND,
+                "NOT_FOUND",
+                "Not found".to_string(),
+                None,
+            ),
+            Self::Sqlx(_) | Self::Internal(_) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "INTERNAL",
+                "Internal server error".to_string(),
+                None,
+            ),
         }
     }
 }
 
 impl IntoResponse for Error {
     fn into_response(self) -> axum::response::Response {
-        let (code, status_code, context, data) = self.info();
-        (status_code, Json(ErrorResponse::new(code, context, data))).into_response()
+        let (status_code, code, message, extra) = self.info();
+        if status_code.is_server_error() {
+            tracing::error!("api request error: {}", self);
+        } else {
+            tracing::info!("api request error: {}", self);
+        }
+        let mut response =
+            (status_code, Json(ErrorResponse::new(code, message, extra))).into_response();
+        if let Self::RateLimited(retry_after) = self {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+        }
+        response
     }
 }
 
-// response structure
+/// `{ "error": { "code", "message", "request_id", ... } }`; variant-specific
+/// fields such as `field` or `retry_after` sit next to `code`
 #[derive(Serialize)]
-struct ErrorDetails {
-    code: i16,
+struct ErrorBody {
+    code: &'static str,
+    message: String,
     #[serde(skip_serializing_if = "Option::is_none")]
-    context: Option<String>,
+    request_id: Option<String>,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    extra: Option<serde_json::Value>,
 }
 
 #[derive(Serialize)]
 struct ErrorResponse {
-    details: ErrorDetails,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    data: Option<serde_json::Value>,
+    error: ErrorBody,
 }
 
 impl ErrorResponse {
-    pub fn new(code: i16, context: Option<String>, data: Option<serde_json::Value>) -> Self {
+    pub fn new(code: &'static str, message: String, extra: Option<serde_json::Value>) -> Self {
         Self {
-            details: ErrorDetails { code, context },
-            data,
+            error: ErrorBody {
+                code,
+                message,
+                request_id: backend_core::llm::openai::current_request_id(),
+                extra,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn error_body(e: Error) -> (StatusCode, serde_json::Value) {
+        let response = e.into_response();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_every_variant_has_a_status_and_envelope() {
+        let cases = [
+            (
+                Error::Validation {
+                    field: "text",
+                    message: "text must not be empty".to_string(),
+                },
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "VALIDATION",
+            ),
+            (
+                Error::PayloadTooLarge,
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "PAYLOAD_TOO_LARGE",
+            ),
+            (
+                AuthError::InvalidToken.into(),
+                StatusCode::UNAUTHORIZED,
+                "UNAUTHORIZED",
+            ),
+            (
+                AuthError::Forbidden.into(),
+                StatusCode::FORBIDDEN,
+                "FORBIDDEN",
+            ),
+            (
+                Error::Conflict("stale".to_string()),
+                StatusCode::CONFLICT,
+                "CONFLICT",
+            ),
+            (
+                Error::RateLimited(7),
+                StatusCode::TOO_MANY_REQUESTS,
+                "RATE_LIMITED",
+            ),
+            (
+                RefineError::OpenAiStatus(reqwest::StatusCode::SERVICE_UNAVAILABLE, "".into())
+                    .into(),
+                StatusCode::BAD_GATEWAY,
+                "UPSTREAM_AI",
+            ),
+            (
+                Error::Sqlx(sqlx::Error::RowNotFound),
+                StatusCode::NOT_FOUND,
+                "NOT_FOUND",
+            ),
+            (
+                Error::Internal(anyhow::anyhow!("boom")),
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "INTERNAL",
+            ),
+        ];
+        for (e, status, code) in cases {
+            let (got, body) = error_body(e).await;
+            assert_eq!(got, status);
+            assert_eq!(body["error"]["code"], code);
+            assert!(body["error"]["message"].is_string());
+            assert_eq!(body.as_object().unwrap().len(), 1, "only the envelope");
         }
     }
+
+    #[tokio::test]
+    async fn test_request_id_is_in_the_envelope() {
+        let (_, body) = backend_core::llm::openai::with_request_id("req-7".to_string(), async {
+            error_body(Error::RateLimited(3)).await
+        })
+        .await;
+        assert_eq!(body["error"]["request_id"], "req-7");
+        assert_eq!(body["error"]["retry_after"], 3);
+    }
+
+    #[tokio::test]
+    async fn test_internal_details_are_not_leaked() {
+        let secret = "connection to 10.0.0.5:5432 refused for user backend_admin";
+        let (status, body) = error_body(Error::Internal(anyhow::anyhow!(secret))).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(body["error"]["message"], "Internal server error");
+        assert!(!body.to_string().contains("10.0.0.5"));
+
+        let (_, body) = error_body(Error::Sqlx(sqlx::Error::PoolTimedOut)).await;
+        assert_eq!(body["error"]["code"], "INTERNAL");
+        assert_eq!(body["error"]["message"], "Internal server error");
+    }
+
+    #[tokio::test]
+    async fn test_upstream_failures_are_bad_gateway_without_the_provider_body() {
+        let e = anyhow::Error::from(RefineError::OpenAiStatus(
+            reqwest::StatusCode::INTERNAL_SERVER_ERROR,
+            "sk-proj-leaky organization org-123".into(),
+        ));
+        let (status, body) = error_body(Error::from_ai(&e)).await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        assert_eq!(body["error"]["code"], "UPSTREAM_AI");
+        assert_eq!(body["error"]["provider_status"], 500);
+        assert!(!body.to_string().contains("org-123"));
+
+        // A refiner validation failure is still the client's fault
+        let e = anyhow::Error::from(RefineError::UnsupportedLanguage("xx".into()));
+        let (status, body) = error_body(Error::from_ai(&e)).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["error"]["field"], "language");
+
+        // The document moved on while the linter ran: retry, don't report a server error
+        let e = anyhow::Error::from(DocumentChanged);
+        let (status, body) = error_body(Error::from_ai(&e)).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["error"]["code"], "CONFLICT");
+
+        let e = anyhow::anyhow!("lint task panicked");
+        let (status, _) = error_body(Error::from_ai(&e)).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    }
 }
@@ -1,11 +1,15 @@
-use super::claims::Claims;
-use crate::{graphql::AppSchema, opts::Encoder};
+use super::claims::{Claims, decode_token};
+use crate::{
+    graphql::AppSchema,
+    opts::{Decoder, Encoder, WebSocketOpts},
+};
 
 use async_graphql::{
     Data, Response as GResponse, ServerError,
     http::{ALL_WEBSOCKET_PROTOCOLS, GraphiQLSource},
 };
 use async_graphql_axum::{GraphQLProtocol, GraphQLRequest, GraphQLResponse, GraphQLWebSocket};
+use atb_types::prelude::NoCustom;
 use axum::{
     Router,
     extract::{State, WebSocketUpgrade},
@@ -13,6 +17,7 @@ use axum::{
     response::{Html, IntoResponse, Response},
     routing::{get, post},
 };
+use axum_client_ip::ClientIp;
 use tracing::instrument;
 
 pub fn routes() -> Router<crate::api::state::AppState> {
@@ -20,7 +25,9 @@ pub fn routes() -> Router<crate::api::state::AppState> {
 }
 
 pub fn base_routes() -> Router<crate::api::state::AppState> {
-    Router::new().route("/graphql", post(graphql_handler))
+    Router::new()
+        .route("/graphql", post(graphql_handler))
+        .route("/graphql/ws", get(graphql_ws_handler))
 }
 
 /// Only debug builds have graphiql and a noauth path
@@ -37,7 +44,7 @@ pub fn dev_routes() -> Router<crate::api::state::AppState> {
 }
 
 #[instrument(
-      skip(schema, claims, req),
+      skip(schema, ip, claims, req),
       fields(
           user_id = tracing::field::Empty,
           gql_operation = tracing::field::Empty,
@@ -46,6 +53,7 @@ pub fn dev_routes() -> Router<crate::api::state::AppState> {
   )]
 async fn graphql_handler(
     State(schema): State<AppSchema>,
+    ClientIp(ip): ClientIp,
     claims: Claims,
     req: GraphQLRequest,
 ) -> GraphQLResponse {
@@ -75,6 +83,8 @@ async fn graphql_handler(
 
     req = req.data(claims);
     req = req.data(subject);
+    // AI mutations are rate limited per client IP, like the REST endpoints
+    req = req.data(ip);
 
     let resp = schema.execute(req).await;
     if !resp.errors.is_empty() {
@@ -87,9 +97,13 @@ async fn graphql_handler(
     resp.into()
 }
 
-#[allow(unused)]
+/// Subscriptions over `graphql-ws` / `graphql-transport-ws`; the client authenticates
+/// in its `connection_init` payload since browsers can't set websocket headers
 async fn graphql_ws_handler(
     State(schema): State<AppSchema>,
+    State(decoder): State<Decoder>,
+    State(opts): State<WebSocketOpts>,
+    ClientIp(ip): ClientIp,
     protocol: GraphQLProtocol,
     websocket: WebSocketUpgrade,
 ) -> Response {
@@ -97,15 +111,43 @@ async fn graphql_ws_handler(
         .protocols(ALL_WEBSOCKET_PROTOCOLS)
         .on_upgrade(move |stream| {
             GraphQLWebSocket::new(stream, schema.clone(), protocol)
-                .on_connection_init(|v| async move {
-                    //#NOTE Verification / auth handled here
-                    tracing::info!("{v}");
-                    Ok(Data::default())
+                .on_connection_init(move |params| async move {
+                    let mut data = connection_data(&params, &decoder, &opts)?;
+                    data.insert(ip);
+                    Ok(data)
                 })
                 .serve()
         })
 }
 
+/// Session data for a subscription connection: the caller's claims and subject
+/// from `{"Authorization": "Bearer <jwt>"}`, or nothing when websocket auth is disabled
+fn connection_data(
+    params: &serde_json::Value,
+    decoder: &Decoder,
+    opts: &WebSocketOpts,
+) -> async_graphql::Result<Data> {
+    let token = ["Authorization", "authorization"]
+        .iter()
+        .find_map(|key| params.get(key)?.as_str())
+        .map(|value| value.strip_prefix("Bearer ").unwrap_or(value));
+
+    let mut data = Data::default();
+    let Some(token) = token else {
+        if opts.ws_auth_disabled {
+            return Ok(data);
+        }
+        return Err(async_graphql::Error::new("Missing credentials"));
+    };
+    let claims = decode_token::<NoCustom>(token, decoder)
+        .map_err(|_| async_graphql::Error::new("Invalid token"))?;
+    if let Ok(subject) = claims.subject_as_uuid() {
+        data.insert(subject);
+    }
+    data.insert(claims);
+    Ok(data)
+}
+
 async fn graphiql(State(encoder): State<Encoder>) -> impl IntoResponse {
     use atb::fixtures::ALICE;
     use atb_types::Duration;
@@ -125,8 +167,9 @@ async fn graphiql(State(encoder): State<Encoder>) -> impl IntoResponse {
 
 async fn graphql_handler_no_auth(
     State(schema): State<AppSchema>,
+    ClientIp(ip): ClientIp,
     req: GraphQLRequest,
 ) -> GraphQLResponse {
     tracing::info!("graphql no auth operation: {:?}", req.0.operation_name);
-    schema.execute(req.into_inner()).await.into()
+    schema.execute(req.into_inner().data(ip)).await.into()
 }
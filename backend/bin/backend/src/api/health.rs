@@ -0,0 +1,155 @@
+use crate::api::state::AppState;
+
+use axum::{
+    Json, Router,
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use backend_core::llm::{openai::succeeded_within, provider};
+use serde::Serialize;
+use sqlx::PgPool;
+use std::{fmt::Display, future::Future, time::Duration};
+
+/// Budget for each dependency; the checks run concurrently, so `/readyz`
+/// answers within this even when every dependency hangs
+pub const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A completed AI call this recent proves the key works, so no probe is sent
+pub const LLM_FRESHNESS: Duration = Duration::from_secs(60);
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/healthz", get(|| async { StatusCode::OK }))
+        .route("/readyz", get(readyz_handler))
+}
+
+#[derive(Debug, Serialize)]
+pub struct DependencyStatus {
+    pub name: &'static str,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Run one dependency check, failing it if it errors or outlives `timeout`
+async fn check<F, E>(name: &'static str, timeout: Duration, probe: F) -> DependencyStatus
+where
+    F: Future<Output = Result<(), E>>,
+    E: Display,
+{
+    let error = match tokio::time::timeout(timeout, probe).await {
+        Ok(Ok(())) => None,
+        Ok(Err(e)) => Some(e.to_string()),
+        Err(_) => Some(format!("timed out after {timeout:?}")),
+    };
+    if let Some(error) = &error {
+        tracing::warn!("🩺 {} is not ready: {}", name, error);
+    }
+    DependencyStatus {
+        name,
+        ok: error.is_none(),
+        error,
+    }
+}
+
+/// `200` when every dependency answered, `503` otherwise; the body lists each one
+#[derive(Debug, Serialize)]
+pub struct Readiness {
+    pub ready: bool,
+    pub dependencies: Vec<DependencyStatus>,
+}
+
+impl Readiness {
+    fn new(dependencies: Vec<DependencyStatus>) -> Self {
+        Self {
+            ready: dependencies.iter().all(|d| d.ok),
+            dependencies,
+        }
+    }
+}
+
+impl IntoResponse for Readiness {
+    fn into_response(self) -> Response {
+        let status = if self.ready {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        };
+        (status, Json(self)).into_response()
+    }
+}
+
+/// The configured provider is ready if a call just succeeded; otherwise it must at
+/// least answer a HEAD, which needs no key
+async fn llm_ready() -> reqwest::Result<()> {
+    if succeeded_within(LLM_FRESHNESS) {
+        return Ok(());
+    }
+    provider::configured("").reachable().await
+}
+
+/// Postgres is ready when a pooled connection runs a query
+async fn postgres_ready(pool: &PgPool) -> sqlx::Result<()> {
+    sqlx::query("SELECT 1").execute(pool).await.map(drop)
+}
+
+/// Readiness: Postgres and Temporal must answer, and the AI provider too with
+/// `--readyz-check-openai`.
+/// `/healthz` stays a cheap liveness check that never touches a dependency.
+pub async fn readyz_handler(State(state): State<AppState>) -> Readiness {
+    let postgres = check("postgres", CHECK_TIMEOUT, postgres_ready(&state.pg_pool));
+    let temporal = check("temporal", CHECK_TIMEOUT, state.wf_engine.ping());
+    let llm = async {
+        if state.http_opts.readyz_check_openai {
+            Some(check("llm", CHECK_TIMEOUT, llm_ready()).await)
+        } else {
+            None
+        }
+    };
+
+    let (postgres, temporal, llm) = tokio::join!(postgres, temporal, llm);
+    Readiness::new([postgres, temporal].into_iter().chain(llm).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::postgres::PgPoolOptions;
+
+    #[tokio::test]
+    async fn test_closed_pool_fails_readiness() {
+        let pool = PgPoolOptions::new()
+            .connect_lazy("postgres://backend@127.0.0.1:1/backend")
+            .unwrap();
+        pool.close().await;
+
+        let postgres = check("postgres", CHECK_TIMEOUT, postgres_ready(&pool)).await;
+        let temporal = check("temporal", CHECK_TIMEOUT, async { Ok::<_, String>(()) }).await;
+        let response = Readiness::new(vec![postgres, temporal]).into_response();
+
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["ready"], false);
+        assert_eq!(body["dependencies"][0]["name"], "postgres");
+        assert_eq!(body["dependencies"][0]["ok"], false);
+        assert!(body["dependencies"][0]["error"].is_string());
+        assert_eq!(body["dependencies"][1]["ok"], true);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_slow_dependency_times_out() {
+        let status = check("temporal", CHECK_TIMEOUT, async {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            Ok::<_, String>(())
+        })
+        .await;
+
+        assert!(!status.ok);
+        assert_eq!(status.error.as_deref(), Some("timed out after 2s"));
+    }
+}
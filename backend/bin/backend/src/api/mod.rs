@@ -1,10 +1,18 @@
+pub mod admin;
 pub mod ai;
 pub mod auth;
 pub mod claims;
+pub mod debug;
+pub mod documents;
 pub mod editor;
 pub mod errors;
 pub mod graphql;
+pub mod health;
+pub mod presence;
+pub mod prometheus;
+pub mod rate_limit;
 pub mod state;
+pub mod tools;
 
 use crate::opts::HttpOpts;
 
@@ -13,66 +21,267 @@ use std::time::Duration;
 use axum::{
     Router,
     extract::{self, FromRequestParts},
-    http::{HeaderValue, Method, Request, StatusCode, header},
+    http::{HeaderName, HeaderValue, Method, Request, header},
     middleware::{self, Next},
     routing::get,
 };
 use axum_client_ip::ClientIp;
-use tower_http::{cors::CorsLayer, trace::TraceLayer};
+use backend_core::llm::{openai::with_request_id, usage};
+use tower_http::{
+    cors::CorsLayer,
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, RequestId, SetRequestIdLayer},
+    trace::TraceLayer,
+};
 
 pub fn build_app(opts: &HttpOpts, state: state::AppState) -> anyhow::Result<Router> {
-    let service_info: &'static str = Box::leak(
-        serde_json::to_string_pretty(atb_cli_utils::process_info())
-            .expect("serialize success. qed")
-            .into_boxed_str(),
-    );
+    let service_info: &'static serde_json::Value = Box::leak(Box::new(
+        serde_json::to_value(atb_cli_utils::process_info()).expect("serialize success. qed"),
+    ));
 
     let allowed_origins = opts
         .origins
         .iter()
         .map(|v| v.parse::<HeaderValue>().unwrap())
         .collect::<Vec<HeaderValue>>();
+    let request_id_header = HeaderName::try_from(opts.request_id_header.as_str())?;
+    // Install the recorder before any route can record into it
+    prometheus::handle();
 
-    Ok(Router::new()
-        .route("/infoz", get(move || async move { service_info }))
-        .route("/healthz", get(|| async { StatusCode::OK }))
+    let router = Router::new()
+        .route("/infoz", get(move || async move { infoz(service_info) }))
+        .route("/metricz", get(prometheus::render))
+        .merge(health::routes())
         .nest("/auth", auth::routes())
-        .merge(ai::routes())
+        .merge(
+            ai::routes()
+                .layer(ai::body_limit(opts))
+                .layer(middleware::map_response(ai::oversized_body_as_json))
+                .route_layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    rate_limit::limit_ai,
+                )),
+        )
         .merge(graphql::routes())
+        .merge(debug::routes())
+        .merge(admin::routes())
         .merge(editor::routes())
+        .merge(documents::routes())
         .layer(
             CorsLayer::new()
                 .allow_origin(allowed_origins)
                 .allow_methods([Method::GET, Method::POST])
-                .allow_headers([header::AUTHORIZATION, header::ACCEPT, header::CONTENT_TYPE])
+                .allow_headers([
+                    header::AUTHORIZATION,
+                    header::ACCEPT,
+                    header::CONTENT_TYPE,
+                    request_id_header.clone(),
+                ])
+                .expose_headers([request_id_header.clone(), header::RETRY_AFTER])
                 .allow_credentials(true)
                 .max_age(Duration::from_secs(3600)),
-        )
-        .layer(
-            tower::ServiceBuilder::new()
-                .layer(opts.client_ip_source.clone().into_extension())
-                .layer(
-                    TraceLayer::new_for_http().make_span_with(|request: &Request<_>| {
-                        tracing::info_span!(
-                            "http_request",
-                            method = %request.method(),
-                            uri = %request.uri(),
-                            ip = tracing::field::Empty
-                        )
-                    }),
-                )
-                .layer(middleware::from_fn(
-                    async |request: extract::Request, next: Next| {
-                        let (mut parts, body) = request.into_parts();
-                        if let Ok(ip) = ClientIp::from_request_parts(&mut parts, &()).await {
-                            let span = tracing::Span::current();
-                            span.record("ip", ip.0.to_string());
-                        } else {
-                            tracing::info!("WTF");
-                        }
-                        next.run(extract::Request::from_parts(parts, body)).await
-                    },
-                )),
-        )
-        .with_state(state))
+        );
+
+    Ok(with_observability(router, opts, request_id_header).with_state(state))
+}
+
+/// Request id, client ip and trace span layers shared by every route
+fn with_observability<S>(
+    router: Router<S>,
+    opts: &HttpOpts,
+    request_id_header: HeaderName,
+) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    router.layer(
+        tower::ServiceBuilder::new()
+            .layer(SetRequestIdLayer::new(
+                request_id_header.clone(),
+                MakeRequestUuid,
+            ))
+            .layer(PropagateRequestIdLayer::new(request_id_header))
+            .layer(opts.client_ip_source.clone().into_extension())
+            .layer(
+                TraceLayer::new_for_http().make_span_with(|request: &Request<_>| {
+                    let request_id = request
+                        .extensions()
+                        .get::<RequestId>()
+                        .and_then(|id| id.header_value().to_str().ok())
+                        .unwrap_or_default();
+                    tracing::info_span!(
+                        "http_request",
+                        method = %request.method(),
+                        // The query can carry the WebSocket `?token=`, so only the path is logged
+                        uri = %request.uri().path(),
+                        request_id = %request_id,
+                        ip = tracing::field::Empty,
+                        user_id = tracing::field::Empty
+                    )
+                }),
+            )
+            .layer(middleware::from_fn(
+                async |request: extract::Request, next: Next| {
+                    let (mut parts, body) = request.into_parts();
+                    if let Ok(ip) = ClientIp::from_request_parts(&mut parts, &()).await {
+                        let span = tracing::Span::current();
+                        span.record("ip", ip.0.to_string());
+                    }
+                    let request_id = parts
+                        .extensions
+                        .get::<RequestId>()
+                        .and_then(|id| id.header_value().to_str().ok())
+                        .unwrap_or_default()
+                        .to_string();
+                    // Make the id visible to error responses and outgoing OpenAI calls
+                    with_request_id(
+                        request_id,
+                        next.run(extract::Request::from_parts(parts, body)),
+                    )
+                    .await
+                },
+            )),
+    )
+}
+
+/// Process info plus the AI token usage and cost since start-up
+fn infoz(service_info: &serde_json::Value) -> String {
+    let mut info = service_info.clone();
+    if let Some(fields) = info.as_object_mut() {
+        fields.insert(
+            "token_usage".to_string(),
+            serde_json::json!(usage::totals()),
+        );
+    }
+    serde_json::to_string_pretty(&info).expect("serialize success. qed")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use atb_cli_utils::clap::Parser;
+    use axum::body::Body;
+    use backend_core::llm::openai::current_request_id;
+    use std::sync::{Arc, Mutex};
+    use tower::ServiceExt;
+    use tracing::{
+        Event, Id, Metadata, Subscriber,
+        field::{Field, Visit},
+        span::{Attributes, Record},
+    };
+
+    /// Minimal subscriber that remembers every span field value it is given
+    #[derive(Clone, Default)]
+    struct SpanFields(Arc<Mutex<Vec<(String, String)>>>);
+
+    struct Collect<'a>(&'a Mutex<Vec<(String, String)>>);
+
+    impl Visit for Collect<'_> {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            self.0
+                .lock()
+                .unwrap()
+                .push((field.name().to_string(), format!("{value:?}")));
+        }
+    }
+
+    impl Subscriber for SpanFields {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+        fn new_span(&self, attrs: &Attributes<'_>) -> Id {
+            attrs.record(&mut Collect(&self.0));
+            Id::from_u64(1)
+        }
+        fn record(&self, _: &Id, values: &Record<'_>) {
+            values.record(&mut Collect(&self.0));
+        }
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+        fn event(&self, _: &Event<'_>) {}
+        fn enter(&self, _: &Id) {}
+        fn exit(&self, _: &Id) {}
+    }
+
+    fn test_app() -> Router {
+        let opts = HttpOpts::parse_from(["backend"]);
+        let header = HeaderName::try_from(opts.request_id_header.as_str()).unwrap();
+        let router = Router::new().route(
+            "/echo",
+            get(|| async { current_request_id().unwrap_or_default() }),
+        );
+        with_observability(router, &opts, header)
+    }
+
+    #[tokio::test]
+    async fn test_request_id_propagates_to_span_and_response() {
+        let fields = SpanFields::default();
+        let _guard = tracing::subscriber::set_default(fields.clone());
+
+        let response = test_app()
+            .oneshot(
+                Request::builder()
+                    .uri("/echo")
+                    .header("x-request-id", "abc-123")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.headers()["x-request-id"], "abc-123");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"abc-123");
+        assert!(
+            fields
+                .0
+                .lock()
+                .unwrap()
+                .contains(&("request_id".to_string(), "abc-123".to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_query_tokens_stay_out_of_the_span() {
+        let fields = SpanFields::default();
+        let _guard = tracing::subscriber::set_default(fields.clone());
+
+        test_app()
+            .oneshot(
+                Request::builder()
+                    .uri("/echo?token=secret-jwt")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let fields = fields.0.lock().unwrap();
+        assert!(fields.contains(&("uri".to_string(), "/echo".to_string())));
+        assert!(!fields.iter().any(|(_, value)| value.contains("secret-jwt")));
+    }
+
+    #[tokio::test]
+    async fn test_request_id_is_generated_when_missing() {
+        let response = test_app()
+            .oneshot(Request::builder().uri("/echo").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        let id = response.headers()["x-request-id"].to_str().unwrap().to_string();
+        assert!(!id.is_empty());
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], id.as_bytes());
+    }
+
+    #[test]
+    fn test_infoz_reports_token_usage() {
+        let info: serde_json::Value =
+            serde_json::from_str(&infoz(&serde_json::json!({ "name": "backend" }))).unwrap();
+        assert_eq!(info["name"], "backend");
+        assert!(info["token_usage"]["prompt_tokens"].is_u64());
+        assert!(info["token_usage"]["cost_usd"].is_number());
+    }
 }
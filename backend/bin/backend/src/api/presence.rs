@@ -0,0 +1,221 @@
+use crate::api::state::ConnId;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use yrs::Doc;
+use yrs::block::ClientID;
+use yrs::encoding::read::{Cursor, Read};
+use yrs::encoding::write::Write;
+use yrs::sync::{Awareness, AwarenessUpdate};
+use yrs::updates::decoder::Decode;
+use yrs::updates::encoder::Encode;
+
+/// y-protocols' message type for awareness, as y-websocket frames it
+const MSG_AWARENESS: u32 = 1;
+
+/// Presence rides the binary lane as a y-protocols awareness message: the
+/// message type, then the awareness update as a length-prefixed buffer.
+/// Document updates stay raw Yjs updates on the same lane.
+pub fn awareness_frame(update: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(update.len() + 6);
+    frame.write_var(MSG_AWARENESS);
+    frame.write_buf(update);
+    frame
+}
+
+/// The awareness update in a client's binary frame, or `None` for a document
+/// update. A frame is presence only when it is exactly one awareness message
+/// whose update decodes, so a raw update that happens to start the same way
+/// still reaches the document.
+pub fn parse_awareness_frame(frame: &[u8]) -> Option<Vec<u8>> {
+    let mut cursor = Cursor::new(frame);
+    if cursor.read_var::<u32>().ok()? != MSG_AWARENESS {
+        return None;
+    }
+    let update = cursor.read_buf().ok()?;
+    if cursor.next != frame.len() {
+        return None;
+    }
+    AwarenessUpdate::decode_v1(update).ok()?;
+    Some(update.to_vec())
+}
+
+struct PresenceState {
+    awareness: Awareness,
+    /// The connection that first announced each awareness client; only it may
+    /// update the client, and its leaving clears it
+    owners: HashMap<ClientID, ConnId>,
+}
+
+impl PresenceState {
+    fn clients_of(&self, conn: ConnId) -> Vec<ClientID> {
+        self.owners
+            .iter()
+            .filter(|(_, owner)| **owner == conn)
+            .map(|(client, _)| *client)
+            .collect()
+    }
+}
+
+/// Who is connected and where their cursors are.
+///
+/// The server only relays: it keeps every client's latest state so a new
+/// connection sees who is already there, and announces a connection's clients
+/// as gone when its socket closes, even if the tab never said goodbye.
+#[derive(Clone)]
+pub struct Presence(Arc<Mutex<PresenceState>>);
+
+impl Presence {
+    pub fn new(doc: &Doc) -> Self {
+        Self(Arc::new(Mutex::new(PresenceState {
+            awareness: Awareness::new(doc.clone()),
+            owners: HashMap::new(),
+        })))
+    }
+
+    /// Apply an awareness update `conn` sent; `false` when it does not decode or
+    /// names a client another connection announced first
+    pub fn apply(&self, conn: ConnId, update: &[u8]) -> bool {
+        let update = match AwarenessUpdate::decode_v1(update) {
+            Ok(update) => update,
+            Err(e) => {
+                tracing::warn!(
+                    "👥 undecodable awareness update from conn {}: {:?}",
+                    conn,
+                    e
+                );
+                return false;
+            }
+        };
+        let mut state = self.0.lock().unwrap();
+        let announced: Vec<ClientID> = update.clients.keys().copied().collect();
+        let taken = announced
+            .iter()
+            .find(|client| state.owners.get(client).is_some_and(|owner| *owner != conn));
+        if let Some(client) = taken {
+            tracing::warn!(
+                "👥 conn {} announced client {}, which another connection owns",
+                conn,
+                client
+            );
+            return false;
+        }
+        if let Err(e) = state.awareness.apply_update(update) {
+            tracing::warn!("👥 could not apply awareness from conn {}: {:?}", conn, e);
+            return false;
+        }
+        for client in announced {
+            state.owners.insert(client, conn);
+        }
+        true
+    }
+
+    /// Everyone's current state, for a connection that just joined; `None`
+    /// while nobody has announced one
+    pub fn snapshot(&self) -> Option<Vec<u8>> {
+        let state = self.0.lock().unwrap();
+        let clients: Vec<ClientID> = state.owners.keys().copied().collect();
+        if clients.is_empty() {
+            return None;
+        }
+        let update = state.awareness.update_with_clients(clients).ok()?;
+        Some(update.encode_v1())
+    }
+
+    /// Forget `conn`'s clients; the update telling everyone else they left
+    pub fn disconnect(&self, conn: ConnId) -> Option<Vec<u8>> {
+        let mut state = self.0.lock().unwrap();
+        let clients = state.clients_of(conn);
+        if clients.is_empty() {
+            return None;
+        }
+        for client in &clients {
+            state.owners.remove(client);
+            state.awareness.remove_state(*client);
+        }
+        let update = state.awareness.update_with_clients(clients).ok()?;
+        Some(update.encode_v1())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yrs::{ReadTxn, Text, Transact};
+
+    /// A client's awareness with `state` set, as it would send it
+    fn client_update(doc: Doc, state: serde_json::Value) -> Vec<u8> {
+        let awareness = Awareness::new(doc);
+        awareness.set_local_state(state).unwrap();
+        awareness.update().unwrap().encode_v1()
+    }
+
+    #[test]
+    fn test_frames_round_trip() {
+        let update = client_update(Doc::new(), serde_json::json!({ "user": "alice" }));
+        let frame = awareness_frame(&update);
+        assert_eq!(frame[0], 1, "y-protocols messageAwareness");
+        assert_eq!(parse_awareness_frame(&frame), Some(update));
+
+        // Document updates on the same lane are not presence
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("content");
+        text.insert(&mut doc.transact_mut(), 0, "hi");
+        let doc_update = doc
+            .transact()
+            .encode_state_as_update_v1(&yrs::StateVector::default());
+        assert_eq!(parse_awareness_frame(&doc_update), None);
+        assert_eq!(parse_awareness_frame(&awareness_frame(&[0xff, 0xff])), None);
+    }
+
+    #[test]
+    fn test_join_and_leave() {
+        let server = Doc::new();
+        let presence = Presence::new(&server);
+        assert_eq!(presence.snapshot(), None);
+
+        let alice = Doc::new();
+        let (alice_conn, bob_conn) = (ConnId::next(), ConnId::next());
+        assert!(presence.apply(
+            alice_conn,
+            &client_update(alice.clone(), serde_json::json!({ "user": "alice" }))
+        ));
+        assert!(!presence.apply(bob_conn, &[0xff, 0xff]));
+
+        // A newcomer learns who is already here
+        let snapshot = AwarenessUpdate::decode_v1(&presence.snapshot().unwrap()).unwrap();
+        assert!(snapshot.clients[&alice.client_id()].json.contains("alice"));
+
+        // Bob never announced anything, so there is nothing to clear for him
+        assert_eq!(presence.disconnect(bob_conn), None);
+
+        // Alice's socket closes: everyone hears she left
+        let left = presence.disconnect(alice_conn).unwrap();
+        let left = AwarenessUpdate::decode_v1(&left).unwrap();
+        assert_eq!(&*left.clients[&alice.client_id()].json, "null");
+        assert_eq!(presence.snapshot(), None);
+        assert_eq!(presence.disconnect(alice_conn), None);
+    }
+
+    #[test]
+    fn test_clients_belong_to_the_connection_that_announced_them() {
+        let presence = Presence::new(&Doc::new());
+        let alice = Doc::new();
+        let (alice_conn, mallory_conn) = (ConnId::next(), ConnId::next());
+        let update = client_update(alice.clone(), serde_json::json!({ "user": "alice" }));
+        assert!(presence.apply(alice_conn, &update));
+
+        // Another connection can neither overwrite Alice's state nor clear it by leaving
+        let forged = client_update(alice.clone(), serde_json::json!({ "user": "mallory" }));
+        assert!(!presence.apply(mallory_conn, &forged));
+        assert_eq!(presence.disconnect(mallory_conn), None);
+        let snapshot = AwarenessUpdate::decode_v1(&presence.snapshot().unwrap()).unwrap();
+        assert!(snapshot.clients[&alice.client_id()].json.contains("alice"));
+
+        // Alice herself still updates it
+        let moved = client_update(
+            alice.clone(),
+            serde_json::json!({ "user": "alice", "cursor": 3 }),
+        );
+        assert!(presence.apply(alice_conn, &moved));
+    }
+}
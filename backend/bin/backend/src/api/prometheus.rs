@@ -0,0 +1,80 @@
+use axum::{http::header, response::IntoResponse};
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
+use std::sync::OnceLock;
+
+static HANDLE: OnceLock<PrometheusHandle> = OnceLock::new();
+
+/// The process-wide Prometheus recorder, installed on first use.
+/// Metrics recorded before it is installed are dropped.
+pub fn handle() -> &'static PrometheusHandle {
+    HANDLE.get_or_init(|| {
+        PrometheusBuilder::new()
+            .install_recorder()
+            .expect("no other metrics recorder is installed. qed")
+    })
+}
+
+/// `GET /metricz`: every metric in the Prometheus text exposition format
+pub async fn render() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        handle().render(),
+    )
+}
+
+/// Counts one websocket connection in `ws_connections_active` until dropped
+pub struct ActiveConnection(());
+
+impl ActiveConnection {
+    pub fn open() -> Self {
+        metrics::gauge!("ws_connections_active").increment(1.0);
+        Self(())
+    }
+}
+
+impl Drop for ActiveConnection {
+    fn drop(&mut self) {
+        metrics::gauge!("ws_connections_active").decrement(1.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, body::Body, http::Request, routing::get};
+    use backend_core::llm::openai;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_exposition_lists_recorded_metrics() {
+        handle();
+        let _conn = ActiveConnection::open();
+        // Nothing listens on port 1, so the call is counted as an error
+        let request = reqwest::Client::new().post("http://127.0.0.1:1/v1/chat/completions");
+        assert!(openai::send(request, "linter").await.is_err());
+
+        let response = Router::new()
+            .route("/metricz", get(render))
+            .oneshot(
+                Request::builder()
+                    .uri("/metricz")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/plain; version=0.0.4"
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let text = String::from_utf8(body.to_vec()).unwrap();
+        assert!(text.contains("ws_connections_active"));
+        assert!(text.contains("ai_calls_total{tool=\"linter\"}"));
+        assert!(text.contains("ai_call_errors_total{tool=\"linter\"}"));
+        assert!(text.contains("ai_call_duration_seconds"));
+    }
+}
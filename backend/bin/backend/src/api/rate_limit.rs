@@ -0,0 +1,274 @@
+use crate::api::{
+    claims::{AuthError, Claims},
+    errors::Error,
+};
+use crate::opts::HttpOpts;
+
+use atb_types::Uuid;
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use axum_client_ip::ClientIp;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Buckets untouched for this long are full again and can be forgotten
+const IDLE_EVICTION: Duration = Duration::from_secs(120);
+
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// Token bucket per key (client IP, subject, ...): `per_minute` requests of burst,
+/// refilled continuously. A limit of 0 disables throttling.
+pub struct RateLimiter<K = IpAddr> {
+    per_minute: u32,
+    buckets: Mutex<HashMap<K, Bucket>>,
+}
+
+impl<K: Hash + Eq> RateLimiter<K> {
+    pub fn new(per_minute: u32) -> Self {
+        Self {
+            per_minute,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Take one token for `key`, or return how long until one is available.
+    pub fn check(&self, key: K, now: Instant) -> Result<(), Duration> {
+        if self.per_minute == 0 {
+            return Ok(());
+        }
+        let capacity = self.per_minute as f64;
+        let per_sec = capacity / 60.0;
+
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() > 10_000 {
+            buckets.retain(|_, b| now.duration_since(b.last_refill) < IDLE_EVICTION);
+        }
+
+        let bucket = buckets.entry(key).or_insert(Bucket {
+            tokens: capacity,
+            last_refill: now,
+        });
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * per_sec).min(capacity);
+        bucket.last_refill = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / per_sec))
+        }
+    }
+}
+
+/// Budgets for everything that costs OpenAI tokens
+pub struct AiRateLimits {
+    /// REST calls from anonymous clients
+    pub by_ip: RateLimiter<IpAddr>,
+    /// REST calls from signed-in users, so a shared office IP doesn't throttle everyone
+    pub by_subject: RateLimiter<Uuid>,
+    /// `AI_COMMAND`s over the WebSocket, per client IP
+    pub ws_commands: RateLimiter<IpAddr>,
+}
+
+impl AiRateLimits {
+    pub fn new(opts: &HttpOpts) -> Self {
+        Self {
+            by_ip: RateLimiter::new(opts.rate_limit_per_minute),
+            by_subject: RateLimiter::new(opts.rate_limit_subject_per_minute),
+            ws_commands: RateLimiter::new(opts.ws_ai_commands_per_minute),
+        }
+    }
+
+    /// Take a token from `ip`'s bucket and, for a signed-in caller, from `subject`'s
+    /// too, so a token doesn't lift the per-IP limit
+    pub fn admit(&self, ip: IpAddr, subject: Option<Uuid>, now: Instant) -> Result<(), Duration> {
+        self.by_ip.check(ip, now).map_err(|wait| {
+            tracing::warn!(%ip, "🚦 rate limited, retry in {:?}", wait);
+            wait
+        })?;
+        if let Some(subject) = subject {
+            self.by_subject.check(subject, now).map_err(|wait| {
+                tracing::warn!(%subject, "🚦 rate limited, retry in {:?}", wait);
+                wait
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// Seconds to report in `Retry-After`, rounded up so a client that waits that long
+/// finds a token; never 0, which would invite an immediate retry
+pub fn retry_after_secs(wait: Duration) -> u64 {
+    let secs = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+    secs.max(1)
+}
+
+/// Middleware for the AI endpoints, see `AiRateLimits::admit`. WebSocket commands
+/// are checked in `handle_socket`, GraphQL mutations in their resolvers.
+pub async fn limit_ai(
+    State(limits): State<Arc<AiRateLimits>>,
+    ClientIp(ip): ClientIp,
+    claims: Result<Claims, AuthError>,
+    request: Request,
+    next: Next,
+) -> Result<Response, Error> {
+    let subject = claims.ok().and_then(|c| c.subject_as_uuid().ok());
+    if let Err(wait) = limits.admit(ip, subject, Instant::now()) {
+        return Err(Error::RateLimited(retry_after_secs(wait)));
+    }
+    Ok(next.run(request).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opts::Decoder;
+    use atb_cli_utils::clap::Parser;
+    use axum::{
+        Router,
+        body::Body,
+        extract::{ConnectInfo, FromRef},
+        http::{self, StatusCode, header},
+        middleware,
+        routing::post,
+    };
+    use axum_client_ip::ClientIpSource;
+    use std::net::SocketAddr;
+    use tower::ServiceExt;
+
+    #[derive(Clone, FromRef)]
+    struct TestState {
+        limits: Arc<AiRateLimits>,
+        decoder: Decoder,
+    }
+
+    fn test_app(limits: Arc<AiRateLimits>) -> Router {
+        let state = TestState {
+            limits,
+            decoder: Decoder(atb::fixtures::jwt::JWT_DECODING_KEY.clone()),
+        };
+        Router::new()
+            .route("/improve", post(|| async { StatusCode::OK }))
+            .route_layer(middleware::from_fn_with_state(state.clone(), limit_ai))
+            .layer(ClientIpSource::ConnectInfo.into_extension())
+            .with_state(state)
+    }
+
+    fn improve(bearer: Option<&str>, addr: &str) -> http::Request<Body> {
+        let mut request = http::Request::post("/improve");
+        if let Some(token) = bearer {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {token}"));
+        }
+        let mut request = request.body(Body::empty()).unwrap();
+        let addr: SocketAddr = addr.parse().unwrap();
+        request.extensions_mut().insert(ConnectInfo(addr));
+        request
+    }
+
+    fn alice_token() -> String {
+        atb_types::prelude::Builder::with_custom("tt", atb_types::Duration::minutes(5), None::<()>)
+            .subject(*atb::fixtures::ALICE)
+            .audience(vec![])
+            .build_fingerprinted()
+            .0
+            .encode(
+                &atb_types::jwt::HEADER_RS256,
+                &atb::fixtures::jwt::JWT_ENCODING_KEY,
+            )
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_ai_routes_answer_429_past_the_limit() {
+        let opts = HttpOpts::parse_from([
+            "backend",
+            "--rate-limit-per-minute",
+            "2",
+            "--rate-limit-subject-per-minute",
+            "1",
+        ]);
+        let app = test_app(Arc::new(AiRateLimits::new(&opts)));
+
+        let office = "10.0.0.1:4000";
+        for _ in 0..2 {
+            let response = app.clone().oneshot(improve(None, office)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        let response = app.clone().oneshot(improve(None, office)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "30");
+
+        // A token doesn't get around the exhausted IP...
+        let token = alice_token();
+        let response = app
+            .clone()
+            .oneshot(improve(Some(&token), office))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        // ...and from a fresh IP the subject's own bucket still applies
+        let home = "10.0.0.2:4000";
+        let response = app
+            .clone()
+            .oneshot(improve(Some(&token), home))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = app
+            .clone()
+            .oneshot(improve(Some(&token), home))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "60");
+    }
+
+    #[test]
+    fn test_bucket_allows_burst_then_limits() {
+        let limiter = RateLimiter::new(3);
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            assert!(limiter.check(ip, now).is_ok());
+        }
+        let wait = limiter.check(ip, now).unwrap_err();
+        assert!((wait.as_secs_f64() - 20.0).abs() < 0.01);
+
+        // Other clients have their own bucket
+        assert!(limiter.check("10.0.0.2".parse().unwrap(), now).is_ok());
+
+        // One token refills every 60s / 3
+        let later = now + Duration::from_secs(21);
+        assert!(limiter.check(ip, later).is_ok());
+        assert!(limiter.check(ip, later).is_err());
+    }
+
+    #[test]
+    fn test_retry_after_rounds_up() {
+        assert_eq!(retry_after_secs(Duration::from_millis(29_001)), 30);
+        assert_eq!(retry_after_secs(Duration::from_secs(30)), 30);
+        assert_eq!(retry_after_secs(Duration::from_millis(1)), 1);
+        assert_eq!(retry_after_secs(Duration::ZERO), 1);
+    }
+
+    #[test]
+    fn test_zero_disables_limit() {
+        let limiter = RateLimiter::new(0);
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        for _ in 0..100 {
+            assert!(limiter.check(ip, Instant::now()).is_ok());
+        }
+    }
+}
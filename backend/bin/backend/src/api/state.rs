@@ -1,15 +1,39 @@
 use crate::{
+    api::{editor::DocSize, presence::Presence, rate_limit::AiRateLimits},
     graphql::AppSchema,
-    opts::{Decoder, Encoder},
+    linter_task::AutoLinterHandle,
+    opts::{Decoder, EditorOpts, Encoder, HttpOpts, WebSocketOpts},
+    shutdown::ShutdownTrigger,
 };
 
+use atb_types::Uuid;
 use axum::extract::FromRef;
-use backend_core::{editor, temporal::WorkflowEngine};
-use serde::Deserialize;
+use backend_core::{
+    editor::{self, MarkSpan},
+    llm::{
+        coalesce::Coalescer,
+        tools::{
+            extender::Conversation,
+            linter::{LintCorrection, LintDiff},
+            readability::Readability,
+            summarizer::SummaryStyle,
+            tone::TonePreset,
+        },
+    },
+    temporal::WorkflowEngine,
+};
+use serde::{Deserialize, Serialize, Serializer, ser::SerializeMap};
 use sqlx::PgPool;
-use std::sync::Arc;
-use tokio::sync::broadcast;
-use yrs::Doc;
+use std::{
+    collections::BTreeMap,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+use tokio::sync::{broadcast, watch};
+use yrs::{Doc, Origin, TransactionMut};
 
 #[derive(Clone, FromRef)]
 pub struct AppState {
@@ -18,10 +42,26 @@ pub struct AppState {
     pub pg_pool: PgPool,
     pub jwt_encoder: Encoder,
     pub jwt_decoder: Decoder,
-    pub api_key: String,
+    pub api_key: Arc<str>,
+    /// Debounce, writing-timeout and word-stream timings
+    pub editor_opts: EditorOpts,
     pub editor_doc: Arc<Doc>,
+    /// Encoded size of `editor_doc`, kept current by `track_doc_size`
+    pub doc_size: DocSize,
     pub editor_broadcast_tx: broadcast::Sender<MessageStructure>,
     pub user_writing_state: Option<Arc<editor::UserWritingState>>,
+    pub ws_opts: WebSocketOpts,
+    pub auto_agents: AutoAgentToggles,
+    pub auto_linter: AutoLinterHandle,
+    pub coalescer: Arc<Coalescer<String>>,
+    /// Linter runs report their corrections, so they share calls separately
+    pub lint_coalescer: Arc<Coalescer<Vec<LintCorrection>>>,
+    pub pending_edits: PendingEdits,
+    pub agent_cache: AgentCache,
+    pub presence: Presence,
+    pub rate_limits: Arc<AiRateLimits>,
+    pub http_opts: Arc<HttpOpts>,
+    pub shutdown: ShutdownTrigger,
 }
 
 impl AppState {
@@ -31,11 +71,21 @@ impl AppState {
         pg_pool: PgPool,
         jwt_encoder: Encoder,
         jwt_decoder: Decoder,
-        api_key: String,
+        api_key: Arc<str>,
+        editor_opts: EditorOpts,
         editor_doc: Arc<Doc>,
         editor_broadcast_tx: broadcast::Sender<MessageStructure>,
         user_writing_state: Option<Arc<editor::UserWritingState>>,
+        ws_opts: WebSocketOpts,
+        auto_agents: AutoAgentToggles,
+        auto_linter: AutoLinterHandle,
+        rate_limits: Arc<AiRateLimits>,
+        http_opts: Arc<HttpOpts>,
+        shutdown: ShutdownTrigger,
     ) -> Self {
+        let agent_cache = AgentCache::new(editor_opts.agent_session_idle());
+        let presence = Presence::new(&editor_doc);
+        let doc_size = DocSize::measure(&editor_doc);
         Self {
             schema,
             wf_engine,
@@ -43,40 +93,1279 @@ impl AppState {
             jwt_encoder,
             jwt_decoder,
             api_key,
+            editor_opts,
             editor_doc,
+            doc_size,
             editor_broadcast_tx,
             user_writing_state,
+            ws_opts,
+            auto_agents,
+            auto_linter,
+            coalescer: Arc::new(Coalescer::new()),
+            lint_coalescer: Arc::new(Coalescer::new()),
+            pending_edits: PendingEdits::new(),
+            agent_cache,
+            presence,
+            rate_limits,
+            http_opts,
+            shutdown,
+        }
+    }
+}
+
+/// Composer sessions, by the `session_id` of their AGENT commands.
+///
+/// A session is forgotten once it has been idle for the configured time, so a
+/// later command with its id starts a fresh conversation.
+#[derive(Clone)]
+pub struct AgentCache {
+    sessions: mini_moka::sync::Cache<Uuid, Session>,
+    /// Held while a session is created, so two first commands share one
+    creating: Arc<std::sync::Mutex<()>>,
+}
+
+/// One composer session's conversation. A turn holds the lock until its text is
+/// recorded, so concurrent commands in a session take turns instead of starting
+/// from the same history and losing one of the two.
+pub type Session = Arc<tokio::sync::Mutex<Conversation>>;
+
+impl AgentCache {
+    pub fn new(idle: Duration) -> Self {
+        Self {
+            sessions: mini_moka::sync::Cache::builder()
+                .max_capacity(10_000)
+                .time_to_idle(idle)
+                .build(),
+            creating: Arc::new(std::sync::Mutex::new(())),
         }
     }
+
+    /// The session with `session_id`; a new or expired one starts empty
+    pub fn session(&self, session_id: Uuid) -> Session {
+        if let Some(session) = self.sessions.get(&session_id) {
+            return session;
+        }
+        let _creating = self.creating.lock().unwrap();
+        if let Some(session) = self.sessions.get(&session_id) {
+            return session;
+        }
+        let session = Session::default();
+        self.sessions.insert(session_id, session.clone());
+        session
+    }
+}
+
+/// How long a previewed lint edit can still be accepted
+const PENDING_EDIT_TTL: Duration = Duration::from_secs(600);
+
+/// Lint edits offered by a preview and not yet accepted or rejected, by id.
+///
+/// An id is forgotten once it has been decided on or after `PENDING_EDIT_TTL`,
+/// so a stale id is simply not found.
+#[derive(Clone)]
+pub struct PendingEdits(mini_moka::sync::Cache<Uuid, LintDiff>);
+
+impl PendingEdits {
+    pub fn new() -> Self {
+        Self(
+            mini_moka::sync::Cache::builder()
+                .max_capacity(10_000)
+                .time_to_live(PENDING_EDIT_TTL)
+                .build(),
+        )
+    }
+
+    /// Remember `diffs` and give each one an id
+    pub fn offer(&self, diffs: Vec<LintDiff>) -> Vec<SuggestedEdit> {
+        diffs
+            .into_iter()
+            .map(|diff| {
+                let id = Uuid::new_v4();
+                self.0.insert(id, diff.clone());
+                SuggestedEdit { id, diff }
+            })
+            .collect()
+    }
+
+    /// The edit with `id`, removed so it is decided on only once
+    pub fn take(&self, id: Uuid) -> Option<LintDiff> {
+        let diff = self.0.get(&id)?;
+        self.0.invalidate(&id);
+        Some(diff)
+    }
+}
+
+impl Default for PendingEdits {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A previewed lint change and the id `ACCEPT_EDIT` and `REJECT_EDIT` refer to it by
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SuggestedEdit {
+    pub id: Uuid,
+    #[serde(flatten)]
+    pub diff: LintDiff,
+}
+
+/// Runtime switches for the background auto-agents.
+///
+/// The debounce loop reads these on every pass, so toggling one from any
+/// connection takes effect without restarting the task.
+#[derive(Clone)]
+pub struct AutoAgentToggles {
+    pub linter: watch::Sender<bool>,
+    pub emoji_replacer: watch::Sender<bool>,
+    pub backseater: watch::Sender<bool>,
+    /// Paragraph index each connection in focus mode is working on. The auto-linter
+    /// is limited to these, and lints the whole document while the map is empty.
+    pub focus: watch::Sender<BTreeMap<ConnId, u32>>,
+    /// Held for the whole of a linter pass or a document-targeted refine, so the
+    /// auto-linter, `POST /linter` and refines never rewrite the fragment at the same time
+    pub lint_running: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl AutoAgentToggles {
+    pub fn new() -> Self {
+        Self {
+            linter: watch::Sender::new(false),
+            emoji_replacer: watch::Sender::new(false),
+            backseater: watch::Sender::new(false),
+            focus: watch::Sender::new(BTreeMap::new()),
+            lint_running: Arc::new(tokio::sync::Mutex::new(())),
+        }
+    }
+
+    /// Flip the linter and return whether it is now enabled
+    pub fn toggle_linter(&self) -> bool {
+        Self::flip(&self.linter)
+    }
+
+    /// Flip the emoji replacer and return whether it is now enabled
+    pub fn toggle_emoji_replacer(&self) -> bool {
+        Self::flip(&self.emoji_replacer)
+    }
+
+    /// Focus `conn` on one paragraph, or `None` to take it out of focus mode
+    pub fn set_focus(&self, conn: ConnId, paragraph: Option<u32>) {
+        self.focus.send_modify(|focus| match paragraph {
+            Some(index) => {
+                focus.insert(conn, index);
+            }
+            None => {
+                focus.remove(&conn);
+            }
+        });
+    }
+
+    /// The paragraphs any connection is focused on, in document order
+    pub fn focused_paragraphs(&self) -> Vec<u32> {
+        let mut paragraphs: Vec<u32> = self.focus.borrow().values().copied().collect();
+        paragraphs.sort_unstable();
+        paragraphs.dedup();
+        paragraphs
+    }
+
+    fn flip(flag: &watch::Sender<bool>) -> bool {
+        let mut enabled = false;
+        flag.send_modify(|value| {
+            *value = !*value;
+            enabled = *value;
+        });
+        enabled
+    }
+}
+
+impl Default for AutoAgentToggles {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Identifies one WebSocket connection so its own updates are not echoed back to it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ConnId(u64);
+
+impl ConnId {
+    const ORIGIN_PREFIX: &'static str = "conn:";
+
+    pub fn next() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(1);
+        Self(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Transaction origin used when applying this connection's updates
+    pub fn origin(self) -> Origin {
+        Origin::from(format!("{}{}", Self::ORIGIN_PREFIX, self.0).as_str())
+    }
+
+    pub fn from_origin(origin: Option<&Origin>) -> Option<Self> {
+        let origin = std::str::from_utf8(origin?.as_ref()).ok()?;
+        origin.strip_prefix(Self::ORIGIN_PREFIX)?.parse().ok().map(Self)
+    }
+}
+
+impl std::fmt::Display for ConnId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
 }
 
 #[derive(Clone, Debug)]
 pub enum MessageStructure {
-    // Lane A: The Y.js binary update
-    YjsUpdate(Vec<u8>),
+    // Lane A: The Y.js binary update; `origin` is None for server-side edits,
+    // and `is_ai` marks those written by the AI tools
+    YjsUpdate {
+        data: Vec<u8>,
+        origin: Option<ConnId>,
+        is_ai: bool,
+    },
     // Lane B: A JSON string for UI commands (Comments, Toasts, etc)
     AiCommand(String),
+    // Presence: a y-protocols awareness update, sent to clients as a binary awareness
+    // message; `origin` is the connection that announced it, None for departures
+    Awareness {
+        update: Vec<u8>,
+        origin: Option<ConnId>,
+    },
+}
+
+impl MessageStructure {
+    /// Broadcast for a committed doc update, tagged with the connection that made it
+    pub fn from_update(txn: &TransactionMut, update: &[u8]) -> Self {
+        Self::YjsUpdate {
+            data: update.to_vec(),
+            origin: ConnId::from_origin(txn.origin()),
+            is_ai: editor::is_ai_origin(txn.origin()),
+        }
+    }
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct AiCommand {
     pub r#type: String,
-    pub action: String,
+    pub action: AiAction,
     pub payload: Option<AiCommandPayload>,
+    /// Client-chosen id echoed on every `AiEvent` for this command; legacy clients omit it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<Uuid>,
+    /// BCP-47 tag refine-style tools write their result in; the input's language when omitted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+}
+
+/// Which editor tool an `AiCommand` asks for
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum AiAction {
+    Improve,
+    Fix,
+    Longer,
+    Shorter,
+    Agent,
+    Toggle,
+    Focus,
+    Stats,
+    Custom,
+    Highlight,
+    Translate,
+    Summarize,
+    /// Rewrite the payload's text in one of the preset tones
+    Tone,
+    /// Title candidates for the document, sent back as an `AI_RESULT` list
+    SuggestTitle,
+    /// Make the payload's title the document's first heading
+    ApplyTitle,
+    /// What the linter would change, sent back as `AI_COMMENT` diffs; nothing is applied
+    LintPreview,
+    /// Apply one edit from an earlier `LINT_PREVIEW`
+    AcceptEdit,
+    /// Drop one edit from an earlier `LINT_PREVIEW` without applying it
+    RejectEdit,
+    /// Abort the sender's in-flight command with this command's `request_id`
+    Cancel,
+    /// Undo the sender's last edit, leaving AI and other writers' edits alone
+    Undo,
+    /// Redo the sender's last undone edit
+    Redo,
+    /// Kept rather than rejected so the client still gets an `UNKNOWN_COMMAND` error back
+    #[serde(untagged)]
+    Unknown(String),
+}
+
+impl std::fmt::Display for AiAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Improve => "IMPROVE",
+            Self::Fix => "FIX",
+            Self::Longer => "LONGER",
+            Self::Shorter => "SHORTER",
+            Self::Agent => "AGENT",
+            Self::Toggle => "TOGGLE",
+            Self::Focus => "FOCUS",
+            Self::Stats => "STATS",
+            Self::Custom => "CUSTOM",
+            Self::Highlight => "HIGHLIGHT",
+            Self::Translate => "TRANSLATE",
+            Self::Summarize => "SUMMARIZE",
+            Self::Tone => "TONE",
+            Self::SuggestTitle => "SUGGEST_TITLE",
+            Self::ApplyTitle => "APPLY_TITLE",
+            Self::LintPreview => "LINT_PREVIEW",
+            Self::AcceptEdit => "ACCEPT_EDIT",
+            Self::RejectEdit => "REJECT_EDIT",
+            Self::Cancel => "CANCEL",
+            Self::Undo => "UNDO",
+            Self::Redo => "REDO",
+            Self::Unknown(name) => name,
+        };
+        f.write_str(name)
+    }
 }
 
-#[derive(Clone, Debug, Deserialize)]
+impl AiAction {
+    /// Whether running the action spends OpenAI tokens, and so counts against the rate limit
+    pub fn calls_openai(&self) -> bool {
+        !matches!(
+            self,
+            Self::Toggle
+                | Self::Focus
+                | Self::Stats
+                | Self::Highlight
+                | Self::ApplyTitle
+                | Self::AcceptEdit
+                | Self::RejectEdit
+                | Self::Cancel
+                | Self::Undo
+                | Self::Redo
+                | Self::Unknown(_)
+        )
+    }
+}
+
+/// Machine-readable reason attached to `AiEvent::Error`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum AiErrorCode {
+    InvalidPayload,
+    MissingPayload,
+    UnknownCommand,
+    NoContent,
+    DirectiveNotFound,
+    RateLimited,
+    Upstream,
+    Unavailable,
+    Internal,
+}
+
+/// Everything the server tells clients about an AI command (Lane B).
+///
+/// Status events keep the `{"type": "AI_STATUS", "status": ...}` shape the
+/// frontend already understands; every event carries the command's `request_id`,
+/// except `DocStats` pushed because the document changed, `LintReport` and
+/// `Readability`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AiEvent {
+    Thinking {
+        request_id: Uuid,
+        message: String,
+    },
+    Progress {
+        request_id: Uuid,
+        message: String,
+    },
+    /// The agent's work was handed to a Temporal workflow, pollable at
+    /// `GET /editor/agent/{workflow_id}/status`
+    WorkflowStarted {
+        request_id: Uuid,
+        workflow_id: String,
+    },
+    /// How far a streamed agent write has got, for the client's progress bar
+    StreamProgress {
+        request_id: Uuid,
+        done: usize,
+        total: usize,
+    },
+    Complete {
+        request_id: Uuid,
+        message: String,
+    },
+    Error {
+        request_id: Uuid,
+        code: AiErrorCode,
+        message: String,
+    },
+    /// The command was aborted by a `CANCEL` or by its sender starting to type
+    Cancelled {
+        request_id: Uuid,
+    },
+    /// A `RATE_LIMITED` error that also says when the client may send again
+    RateLimited {
+        request_id: Uuid,
+        retry_after: u64,
+    },
+    /// Refined text for the client to apply, with the formatting that survived
+    Result {
+        request_id: Uuid,
+        content: String,
+        marks: Vec<MarkSpan>,
+    },
+    /// Title candidates, best first, for the writer to pick one to apply
+    Titles {
+        request_id: Uuid,
+        titles: Vec<String>,
+    },
+    /// Changes the linter suggests, for the client to show as comments on the nodes
+    LintPreview {
+        request_id: Uuid,
+        diffs: Vec<SuggestedEdit>,
+    },
+    /// New state of an auto-agent toggle
+    ToggleState {
+        request_id: Uuid,
+        target: String,
+        enabled: bool,
+    },
+    /// Word count and reading time; `request_id` is `None` when sent because the doc changed
+    DocStats {
+        request_id: Option<Uuid>,
+        stats: editor::DocStats,
+    },
+    /// What the auto-linter changed, for the client's changelog
+    LintReport {
+        corrections: Vec<LintCorrection>,
+    },
+    /// Readability scores, recomputed after each auto-lint pass
+    Readability {
+        readability: Readability,
+    },
+}
+
+impl AiEvent {
+    pub fn into_message(self) -> MessageStructure {
+        MessageStructure::AiCommand(self.to_json())
+    }
+
+    /// The event as the text frame one client receives
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("AiEvent serializes to JSON. qed")
+    }
+}
+
+impl Serialize for AiEvent {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        match self {
+            Self::Thinking {
+                request_id,
+                message,
+            }
+            | Self::Progress {
+                request_id,
+                message,
+            }
+            | Self::Complete {
+                request_id,
+                message,
+            } => {
+                let status = match self {
+                    Self::Thinking { .. } => "thinking",
+                    Self::Progress { .. } => "progress",
+                    _ => "complete",
+                };
+                map.serialize_entry("type", "AI_STATUS")?;
+                map.serialize_entry("status", status)?;
+                map.serialize_entry("request_id", request_id)?;
+                map.serialize_entry("message", message)?;
+            }
+            Self::Error {
+                request_id,
+                code,
+                message,
+            } => {
+                map.serialize_entry("type", "AI_STATUS")?;
+                map.serialize_entry("status", "error")?;
+                map.serialize_entry("request_id", request_id)?;
+                map.serialize_entry("code", code)?;
+                map.serialize_entry("message", message)?;
+            }
+            Self::WorkflowStarted {
+                request_id,
+                workflow_id,
+            } => {
+                map.serialize_entry("type", "AI_STATUS")?;
+                map.serialize_entry("status", "thinking")?;
+                map.serialize_entry("request_id", request_id)?;
+                map.serialize_entry("message", "Writing...")?;
+                map.serialize_entry("workflow_id", workflow_id)?;
+            }
+            Self::StreamProgress {
+                request_id,
+                done,
+                total,
+            } => {
+                map.serialize_entry("type", "AI_PROGRESS")?;
+                map.serialize_entry("request_id", request_id)?;
+                map.serialize_entry("done", done)?;
+                map.serialize_entry("total", total)?;
+            }
+            Self::Cancelled { request_id } => {
+                map.serialize_entry("type", "AI_STATUS")?;
+                map.serialize_entry("status", "cancelled")?;
+                map.serialize_entry("request_id", request_id)?;
+                map.serialize_entry("message", "Cancelled")?;
+            }
+            Self::RateLimited {
+                request_id,
+                retry_after,
+            } => {
+                map.serialize_entry("type", "AI_STATUS")?;
+                map.serialize_entry("status", "error")?;
+                map.serialize_entry("request_id", request_id)?;
+                map.serialize_entry("code", &AiErrorCode::RateLimited)?;
+                map.serialize_entry(
+                    "message",
+                    &format!("Too many AI requests. Please try again in {retry_after}s."),
+                )?;
+                map.serialize_entry("retry_after", retry_after)?;
+            }
+            Self::Result {
+                request_id,
+                content,
+                marks,
+            } => {
+                map.serialize_entry("type", "AI_RESULT")?;
+                map.serialize_entry("status", "complete")?;
+                map.serialize_entry("request_id", request_id)?;
+                map.serialize_entry("message", content)?;
+                map.serialize_entry("marks", marks)?;
+            }
+            Self::Titles { request_id, titles } => {
+                map.serialize_entry("type", "AI_RESULT")?;
+                map.serialize_entry("status", "complete")?;
+                map.serialize_entry("request_id", request_id)?;
+                // Clients that only read `message` still show the candidates, one per line
+                map.serialize_entry("message", &titles.join("\n"))?;
+                map.serialize_entry("titles", titles)?;
+            }
+            Self::LintPreview { request_id, diffs } => {
+                map.serialize_entry("type", "AI_COMMENT")?;
+                map.serialize_entry("status", "complete")?;
+                map.serialize_entry("request_id", request_id)?;
+                map.serialize_entry("message", &format!("{} suggested changes", diffs.len()))?;
+                map.serialize_entry("diffs", diffs)?;
+            }
+            Self::ToggleState {
+                request_id,
+                target,
+                enabled,
+            } => {
+                map.serialize_entry("type", "TOGGLE_STATE")?;
+                map.serialize_entry("request_id", request_id)?;
+                map.serialize_entry("target", target)?;
+                map.serialize_entry("enabled", enabled)?;
+            }
+            Self::DocStats { request_id, stats } => {
+                map.serialize_entry("type", "DOC_STATS")?;
+                if let Some(request_id) = request_id {
+                    map.serialize_entry("request_id", request_id)?;
+                }
+                map.serialize_entry("stats", stats)?;
+            }
+            Self::LintReport { corrections } => {
+                map.serialize_entry("type", "AI_LINT_REPORT")?;
+                map.serialize_entry("corrections", corrections)?;
+            }
+            Self::Readability { readability } => {
+                map.serialize_entry("type", "READABILITY")?;
+                map.serialize_entry("readability", readability)?;
+            }
+        }
+        map.end()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct AgentPayload {
-    pub role : String,
+    pub role: String,
+    /// Text the writer highlighted; a `[bracketed]` selection is treated as an instruction
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub selection: Option<String>,
+    /// Which occurrence of `selection` in the document was highlighted, counting
+    /// from 0 in document order; the first when omitted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub occurrence: Option<usize>,
+    /// How multi-line output becomes paragraphs; `single_paragraph` when omitted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub paragraph_mode: Option<editor::ParagraphMode>,
+    /// Milliseconds between streamed words; 0 applies the text at once, omitted uses the server default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream_delay_ms: Option<u64>,
+    /// Commands with the same id continue one conversation with the composer
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<Uuid>,
+}
+
+/// Selected text plus the writer's own rewrite instruction
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CustomPayload {
+    pub text: String,
+    pub instruction: String,
+}
+
+/// The BCP-47 tag to translate into, and the selected text; without a
+/// selection the whole document is translated in place
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TranslatePayload {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    pub target_lang: String,
+}
+
+/// How the `SUMMARIZE` result should read
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SummarizePayload {
+    pub style: SummaryStyle,
+}
+
+/// Selected text and the preset tone to rewrite it in
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TonePayload {
+    pub text: String,
+    pub preset: TonePreset,
+}
+
+/// The previewed edit an `ACCEPT_EDIT` or `REJECT_EDIT` command decides on
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EditPayload {
+    pub edit_id: Uuid,
+}
+
+/// The title an `APPLY_TITLE` command puts at the top of the document
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TitlePayload {
+    pub title: String,
+}
+
+/// A word to format everywhere in the document, and the mark to give it
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HighlightPayload {
+    pub word: String,
+    pub mark: HighlightMark,
+    /// CSS color; required by the `color` mark and ignored by the others
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HighlightMark {
+    Bold,
+    Italic,
+    Color,
 }
 
 pub struct RefinerPayload {
     pub text: String,
 }
 
-#[derive(Clone, Debug, Deserialize)]
+/// Untagged on the wire: refine commands send the selected text as a bare string,
+/// agent commands an object with `role`, custom commands one with `text` and
+/// `instruction`, highlight commands one with `word` and `mark`, translate
+/// commands one with `target_lang` and maybe `text`, summarize commands one with
+/// `style`, apply-title commands one with `title`, tone commands one with `text`
+/// and `preset`, accept/reject commands one with `edit_id`, so the JSON shape
+/// alone picks the variant.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum AiCommandPayload {
     Refiner(String),
     Agent(AgentPayload),
-}
\ No newline at end of file
+    Custom(CustomPayload),
+    Highlight(HighlightPayload),
+    Translate(TranslatePayload),
+    Summarize(SummarizePayload),
+    Title(TitlePayload),
+    Tone(TonePayload),
+    Edit(EditPayload),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn round_trip(value: serde_json::Value) -> AiCommand {
+        let cmd: AiCommand = serde_json::from_value(value.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&cmd).unwrap(), value);
+        cmd
+    }
+
+    #[test]
+    fn test_legacy_command_without_request_id() {
+        let cmd: AiCommand = serde_json::from_value(json!({
+            "type": "AI_COMMAND",
+            "action": "FIX",
+            "payload": "teh text"
+        }))
+        .unwrap();
+        assert_eq!(cmd.request_id, None);
+        assert_eq!(cmd.language, None);
+    }
+
+    #[test]
+    fn test_command_carries_target_language() {
+        let cmd = round_trip(json!({
+            "type": "AI_COMMAND",
+            "action": "IMPROVE",
+            "payload": "teh text",
+            "language": "ja"
+        }));
+        assert_eq!(cmd.language.as_deref(), Some("ja"));
+    }
+
+    #[test]
+    fn test_ai_event_json_shapes() {
+        let id: Uuid = "6f1c2b9e-2a4d-4c1e-9b7a-0d8e5f3a1c22".parse().unwrap();
+        let shape = |event: AiEvent| serde_json::to_value(event).unwrap();
+
+        assert_eq!(
+            shape(AiEvent::Thinking {
+                request_id: id,
+                message: "Polishing your text...".into()
+            }),
+            json!({
+                "type": "AI_STATUS",
+                "status": "thinking",
+                "request_id": "6f1c2b9e-2a4d-4c1e-9b7a-0d8e5f3a1c22",
+                "message": "Polishing your text..."
+            })
+        );
+        assert_eq!(
+            shape(AiEvent::Progress {
+                request_id: id,
+                message: "Writing...".into()
+            }),
+            json!({
+                "type": "AI_STATUS",
+                "status": "progress",
+                "request_id": "6f1c2b9e-2a4d-4c1e-9b7a-0d8e5f3a1c22",
+                "message": "Writing..."
+            })
+        );
+        assert_eq!(
+            shape(AiEvent::Complete {
+                request_id: id,
+                message: "Applied FIX".into()
+            }),
+            json!({
+                "type": "AI_STATUS",
+                "status": "complete",
+                "request_id": "6f1c2b9e-2a4d-4c1e-9b7a-0d8e5f3a1c22",
+                "message": "Applied FIX"
+            })
+        );
+        assert_eq!(
+            shape(AiEvent::Error {
+                request_id: id,
+                code: AiErrorCode::RateLimited,
+                message: "busy".into()
+            }),
+            json!({
+                "type": "AI_STATUS",
+                "status": "error",
+                "request_id": "6f1c2b9e-2a4d-4c1e-9b7a-0d8e5f3a1c22",
+                "code": "RATE_LIMITED",
+                "message": "busy"
+            })
+        );
+        assert_eq!(
+            shape(AiEvent::RateLimited {
+                request_id: id,
+                retry_after: 12
+            }),
+            json!({
+                "type": "AI_STATUS",
+                "status": "error",
+                "request_id": "6f1c2b9e-2a4d-4c1e-9b7a-0d8e5f3a1c22",
+                "code": "RATE_LIMITED",
+                "message": "Too many AI requests. Please try again in 12s.",
+                "retry_after": 12
+            })
+        );
+        assert_eq!(
+            shape(AiEvent::WorkflowStarted {
+                request_id: id,
+                workflow_id: "compose_0192".into()
+            }),
+            json!({
+                "type": "AI_STATUS",
+                "status": "thinking",
+                "request_id": "6f1c2b9e-2a4d-4c1e-9b7a-0d8e5f3a1c22",
+                "message": "Writing...",
+                "workflow_id": "compose_0192"
+            })
+        );
+        assert_eq!(
+            shape(AiEvent::StreamProgress {
+                request_id: id,
+                done: 5,
+                total: 12
+            }),
+            json!({
+                "type": "AI_PROGRESS",
+                "request_id": "6f1c2b9e-2a4d-4c1e-9b7a-0d8e5f3a1c22",
+                "done": 5,
+                "total": 12
+            })
+        );
+        assert_eq!(
+            shape(AiEvent::Cancelled { request_id: id }),
+            json!({
+                "type": "AI_STATUS",
+                "status": "cancelled",
+                "request_id": "6f1c2b9e-2a4d-4c1e-9b7a-0d8e5f3a1c22",
+                "message": "Cancelled"
+            })
+        );
+        assert_eq!(
+            shape(AiEvent::Result {
+                request_id: id,
+                content: "the text".into(),
+                marks: vec![MarkSpan {
+                    start: 4,
+                    end: 8,
+                    mark: "bold".into()
+                }]
+            }),
+            json!({
+                "type": "AI_RESULT",
+                "status": "complete",
+                "request_id": "6f1c2b9e-2a4d-4c1e-9b7a-0d8e5f3a1c22",
+                "message": "the text",
+                "marks": [{ "start": 4, "end": 8, "mark": "bold" }]
+            })
+        );
+        assert_eq!(
+            shape(AiEvent::Titles {
+                request_id: id,
+                titles: vec!["Shipping the Editor".into(), "Launch Notes".into()]
+            }),
+            json!({
+                "type": "AI_RESULT",
+                "status": "complete",
+                "request_id": "6f1c2b9e-2a4d-4c1e-9b7a-0d8e5f3a1c22",
+                "message": "Shipping the Editor\nLaunch Notes",
+                "titles": ["Shipping the Editor", "Launch Notes"]
+            })
+        );
+        assert_eq!(
+            shape(AiEvent::LintPreview {
+                request_id: id,
+                diffs: vec![SuggestedEdit {
+                    id,
+                    diff: LintDiff {
+                        node_path: vec![1, 0],
+                        before: "Fix teh line.".into(),
+                        after: "Fix the line.".into(),
+                    }
+                }]
+            }),
+            json!({
+                "type": "AI_COMMENT",
+                "status": "complete",
+                "request_id": "6f1c2b9e-2a4d-4c1e-9b7a-0d8e5f3a1c22",
+                "message": "1 suggested changes",
+                "diffs": [{
+                    "id": "6f1c2b9e-2a4d-4c1e-9b7a-0d8e5f3a1c22",
+                    "node_path": [1, 0],
+                    "before": "Fix teh line.",
+                    "after": "Fix the line."
+                }]
+            })
+        );
+        assert_eq!(
+            shape(AiEvent::ToggleState {
+                request_id: id,
+                target: "LINTER".into(),
+                enabled: true
+            }),
+            json!({
+                "type": "TOGGLE_STATE",
+                "request_id": "6f1c2b9e-2a4d-4c1e-9b7a-0d8e5f3a1c22",
+                "target": "LINTER",
+                "enabled": true
+            })
+        );
+        assert_eq!(
+            shape(AiEvent::DocStats {
+                request_id: None,
+                stats: editor::DocStats {
+                    characters: 11,
+                    words: 2,
+                    paragraphs: 1,
+                    reading_minutes: 1
+                }
+            }),
+            json!({
+                "type": "DOC_STATS",
+                "stats": { "characters": 11, "words": 2, "paragraphs": 1, "reading_minutes": 1 }
+            })
+        );
+        assert_eq!(
+            shape(AiEvent::LintReport {
+                corrections: vec![LintCorrection {
+                    paragraph: 1,
+                    original: "teh".into(),
+                    corrected: "the".into(),
+                }]
+            }),
+            json!({
+                "type": "AI_LINT_REPORT",
+                "corrections": [{ "paragraph": 1, "original": "teh", "corrected": "the" }]
+            })
+        );
+        assert_eq!(
+            shape(AiEvent::Readability {
+                readability: Readability::default()
+            }),
+            json!({
+                "type": "READABILITY",
+                "readability": {
+                    "reading_ease": null,
+                    "grade_level": null,
+                    "sentences": 0,
+                    "avg_sentence_length": 0.0,
+                    "passive_sentences": 0,
+                    "long_sentences": []
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn test_action_names() {
+        let cmd = round_trip(json!({
+            "type": "AI_COMMAND",
+            "action": "SHORTER",
+            "payload": "a long sentence"
+        }));
+        assert_eq!(cmd.action, AiAction::Shorter);
+
+        let cmd = round_trip(json!({
+            "type": "AI_COMMAND",
+            "action": "SUMMON",
+            "payload": "x"
+        }));
+        assert_eq!(cmd.action, AiAction::Unknown("SUMMON".to_string()));
+        assert_eq!(cmd.action.to_string(), "SUMMON");
+
+        let cmd = round_trip(json!({
+            "type": "AI_COMMAND",
+            "action": "LINT_PREVIEW",
+            "payload": null
+        }));
+        assert_eq!(cmd.action, AiAction::LintPreview);
+        assert_eq!(cmd.action.to_string(), "LINT_PREVIEW");
+        assert!(cmd.action.calls_openai());
+
+        let cmd = round_trip(json!({
+            "type": "AI_COMMAND",
+            "action": "UNDO",
+            "payload": null
+        }));
+        assert_eq!(cmd.action, AiAction::Undo);
+        assert!(!cmd.action.calls_openai());
+    }
+
+    #[test]
+    fn test_refiner_payload_round_trip() {
+        let cmd = round_trip(json!({
+            "type": "command",
+            "action": "IMPROVE",
+            "payload": "make this better"
+        }));
+        assert_eq!(
+            cmd.payload,
+            Some(AiCommandPayload::Refiner("make this better".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_agent_payload_round_trip() {
+        let cmd = round_trip(json!({
+            "type": "command",
+            "action": "AGENT",
+            "payload": { "role": "writer" }
+        }));
+        assert_eq!(
+            cmd.payload,
+            Some(AiCommandPayload::Agent(AgentPayload {
+                role: "writer".to_string(),
+                selection: None,
+                occurrence: None,
+                paragraph_mode: None,
+                stream_delay_ms: None,
+                session_id: None,
+            }))
+        );
+
+        let cmd = round_trip(json!({
+            "type": "command",
+            "action": "AGENT",
+            "payload": { "role": "writer", "selection": "[add a conclusion]", "occurrence": 1 }
+        }));
+        assert!(matches!(
+            cmd.payload,
+            Some(AiCommandPayload::Agent(AgentPayload {
+                selection: Some(_),
+                occurrence: Some(1),
+                ..
+            }))
+        ));
+
+        let cmd = round_trip(json!({
+            "type": "command",
+            "action": "AGENT",
+            "payload": { "role": "writer", "paragraph_mode": "split_on_blank_lines" }
+        }));
+        assert!(matches!(
+            cmd.payload,
+            Some(AiCommandPayload::Agent(AgentPayload {
+                paragraph_mode: Some(editor::ParagraphMode::SplitOnBlankLines),
+                ..
+            }))
+        ));
+
+        let cmd = round_trip(json!({
+            "type": "command",
+            "action": "AGENT",
+            "payload": { "role": "writer", "stream_delay_ms": 0 }
+        }));
+        assert!(matches!(
+            cmd.payload,
+            Some(AiCommandPayload::Agent(AgentPayload {
+                stream_delay_ms: Some(0),
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn test_custom_payload_round_trip() {
+        let cmd = round_trip(json!({
+            "type": "AI_COMMAND",
+            "action": "CUSTOM",
+            "payload": { "text": "we shipped", "instruction": "make it a press release" }
+        }));
+        assert_eq!(cmd.action, AiAction::Custom);
+        assert_eq!(
+            cmd.payload,
+            Some(AiCommandPayload::Custom(CustomPayload {
+                text: "we shipped".to_string(),
+                instruction: "make it a press release".to_string(),
+            }))
+        );
+    }
+
+    #[test]
+    fn test_highlight_payload_round_trip() {
+        let cmd = round_trip(json!({
+            "type": "AI_COMMAND",
+            "action": "HIGHLIGHT",
+            "payload": { "word": "Rust", "mark": "color", "color": "#e11d48" }
+        }));
+        assert_eq!(cmd.action, AiAction::Highlight);
+        assert_eq!(
+            cmd.payload,
+            Some(AiCommandPayload::Highlight(HighlightPayload {
+                word: "Rust".to_string(),
+                mark: HighlightMark::Color,
+                color: Some("#e11d48".to_string()),
+            }))
+        );
+    }
+
+    #[test]
+    fn test_translate_payload_round_trip() {
+        let cmd = round_trip(json!({
+            "type": "AI_COMMAND",
+            "action": "TRANSLATE",
+            "payload": { "text": "Hello, world", "target_lang": "ja" }
+        }));
+        assert_eq!(cmd.action, AiAction::Translate);
+        assert_eq!(
+            cmd.payload,
+            Some(AiCommandPayload::Translate(TranslatePayload {
+                text: Some("Hello, world".to_string()),
+                target_lang: "ja".to_string(),
+            }))
+        );
+
+        let cmd = round_trip(json!({
+            "type": "AI_COMMAND",
+            "action": "TRANSLATE",
+            "payload": { "target_lang": "ja" }
+        }));
+        assert_eq!(
+            cmd.payload,
+            Some(AiCommandPayload::Translate(TranslatePayload {
+                text: None,
+                target_lang: "ja".to_string(),
+            }))
+        );
+    }
+
+    #[test]
+    fn test_summarize_payload_round_trip() {
+        let cmd = round_trip(json!({
+            "type": "AI_COMMAND",
+            "action": "SUMMARIZE",
+            "payload": { "style": "tl_dr" }
+        }));
+        assert_eq!(cmd.action, AiAction::Summarize);
+        assert_eq!(
+            cmd.payload,
+            Some(AiCommandPayload::Summarize(SummarizePayload {
+                style: SummaryStyle::TlDr,
+            }))
+        );
+    }
+
+    #[test]
+    fn test_tone_payload_round_trip() {
+        let cmd = round_trip(json!({
+            "type": "AI_COMMAND",
+            "action": "TONE",
+            "payload": { "text": "we shipped it", "preset": "marketing" }
+        }));
+        assert_eq!(cmd.action, AiAction::Tone);
+        assert_eq!(
+            cmd.payload,
+            Some(AiCommandPayload::Tone(TonePayload {
+                text: "we shipped it".to_string(),
+                preset: TonePreset::Marketing,
+            }))
+        );
+        assert!(cmd.action.calls_openai());
+    }
+
+    #[test]
+    fn test_edit_payload_round_trip() {
+        let cmd = round_trip(json!({
+            "type": "AI_COMMAND",
+            "action": "ACCEPT_EDIT",
+            "payload": { "edit_id": "6f1c2b9e-2a4d-4c1e-9b7a-0d8e5f3a1c22" }
+        }));
+        assert_eq!(cmd.action, AiAction::AcceptEdit);
+        assert!(matches!(cmd.payload, Some(AiCommandPayload::Edit(_))));
+        assert!(!cmd.action.calls_openai());
+    }
+
+    #[test]
+    fn test_pending_edits_are_taken_once() {
+        let pending = PendingEdits::new();
+        let diff = |text: &str| LintDiff {
+            node_path: vec![0],
+            before: text.to_string(),
+            after: text.replace("teh", "the"),
+        };
+        let offered = pending.offer(vec![diff("teh first"), diff("teh second")]);
+        assert_ne!(offered[0].id, offered[1].id);
+
+        assert_eq!(pending.take(offered[1].id), Some(diff("teh second")));
+        assert_eq!(pending.take(offered[1].id), None);
+        assert_eq!(pending.take(Uuid::new_v4()), None);
+        assert_eq!(pending.take(offered[0].id), Some(diff("teh first")));
+    }
+
+    #[test]
+    fn test_title_payload_round_trip() {
+        let cmd = round_trip(json!({
+            "type": "AI_COMMAND",
+            "action": "APPLY_TITLE",
+            "payload": { "title": "Shipping the Editor" }
+        }));
+        assert_eq!(cmd.action, AiAction::ApplyTitle);
+        assert_eq!(
+            cmd.payload,
+            Some(AiCommandPayload::Title(TitlePayload {
+                title: "Shipping the Editor".to_string(),
+            }))
+        );
+        assert!(!cmd.action.calls_openai());
+        assert!(AiAction::SuggestTitle.calls_openai());
+    }
+
+    #[test]
+    fn test_updates_are_tagged_with_their_origin() {
+        use yrs::{Text, Transact};
+
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("t");
+        let broadcast = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = broadcast.clone();
+        let _sub = doc
+            .observe_update_v1(move |txn, e| {
+                sink.lock()
+                    .unwrap()
+                    .push(MessageStructure::from_update(txn, &e.update))
+            })
+            .unwrap();
+
+        let conn = ConnId::next();
+        text.insert(&mut doc.transact_mut_with(conn.origin()), 0, "typed ");
+        text.insert(&mut doc.transact_mut_with(editor::AI_ORIGIN), 6, "written");
+
+        let tags: Vec<_> = broadcast
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|message| match message {
+                MessageStructure::YjsUpdate { origin, is_ai, .. } => (*origin, *is_ai),
+                MessageStructure::AiCommand(_) | MessageStructure::Awareness { .. } => {
+                    panic!("not an update")
+                }
+            })
+            .collect();
+        assert_eq!(tags, [(Some(conn), false), (None, true)]);
+    }
+
+    #[test]
+    fn test_agent_sessions_keep_their_conversation() {
+        let session = Uuid::new_v4();
+        let cmd = round_trip(json!({
+            "type": "command",
+            "action": "AGENT",
+            "payload": { "role": "writer", "session_id": session }
+        }));
+        assert!(matches!(
+            cmd.payload,
+            Some(AiCommandPayload::Agent(AgentPayload {
+                session_id: Some(id),
+                ..
+            })) if id == session
+        ));
+
+        let sessions = AgentCache::new(Duration::from_secs(60));
+        let mut conversation = Conversation::default();
+        conversation.record(Some("open with the harvest"), "The harvest failed.");
+        {
+            let shared = sessions.session(session);
+            let mut stored = shared.try_lock().unwrap();
+            assert!(stored.messages().is_empty());
+            stored.record(Some("open with the harvest"), "The harvest failed.");
+        }
+
+        assert_eq!(*sessions.session(session).try_lock().unwrap(), conversation);
+        assert!(
+            sessions
+                .session(Uuid::new_v4())
+                .try_lock()
+                .unwrap()
+                .messages()
+                .is_empty()
+        );
+    }
+}
@@ -0,0 +1,434 @@
+use super::{EditorTool, ToolContext, ToolError, ToolOutcome};
+use crate::api::state::{
+    AiCommandPayload, AiErrorCode, AiEvent, AppState, MessageStructure, Session,
+};
+use backend_core::editor::{get_doc_content, has_content_structure};
+use backend_core::llm::{
+    apply_composition, provider::ChatMessage, tools::extender::parse_directive,
+};
+use backend_core::refiner::error::RefineError;
+use backend_core::sqlx_postgres::{
+    PgPool,
+    compositions::{self, PendingComposition},
+};
+use backend_core::temporal::{
+    WorkflowExecution,
+    compose::{ComposeInput, compose_timeout},
+};
+use futures::future::BoxFuture;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use yrs::Doc;
+
+/// Slowest per-word delay a client may ask for; the auto-linter waits while
+/// the agent streams, so a crawl would hold it off
+pub const MAX_STREAM_DELAY_MS: u64 = 1000;
+
+/// How long a composition resumed after a restart waits for clients to bring
+/// the document back before it gives up
+pub const RESUME_CONTENT_WAIT: Duration = Duration::from_secs(300);
+
+/// Runs the writing agent as a Temporal workflow, then applies its text to the
+/// document directly through the CRDT.
+pub struct Composer;
+
+impl EditorTool for Composer {
+    fn run<'a>(&'a self, ctx: &'a ToolContext) -> BoxFuture<'a, Result<ToolOutcome, ToolError>> {
+        Box::pin(async move {
+            // #TODO: This should be matching the payload's role to determine which agent to run. We only have one right now.
+            let agent_payload = match &ctx.payload {
+                Some(AiCommandPayload::Agent(agent_payload)) => agent_payload,
+                Some(_) => {
+                    return Err(ToolError::new(
+                        AiErrorCode::InvalidPayload,
+                        "Invalid payload type for agent command",
+                    ));
+                }
+                None => return Err(ToolError::missing_payload()),
+            };
+
+            let word_delay_ms = match agent_payload.stream_delay_ms {
+                Some(ms) if ms > MAX_STREAM_DELAY_MS => {
+                    return Err(ToolError::new(
+                        AiErrorCode::InvalidPayload,
+                        format!("stream_delay_ms must be at most {MAX_STREAM_DELAY_MS}"),
+                    ));
+                }
+                Some(ms) => ms,
+                None => ctx.state.editor_opts.ai_word_delay_ms,
+            };
+
+            // 獲取共享的 UserWritingState
+            let Some(user_state) = &ctx.state.user_writing_state else {
+                return Err(ToolError::new(
+                    AiErrorCode::Unavailable,
+                    "User writing state not available",
+                ));
+            };
+
+            // Reject an empty document before starting a workflow
+            let doc = &ctx.state.editor_doc;
+            if !has_content_structure(doc) {
+                return Err(RefineError::NoContentStructure.into());
+            }
+            // A highlighted "[instruction]" is a directive, not text to continue verbatim
+            let directive = agent_payload.selection.as_deref().and_then(parse_directive);
+            let instruction = directive.as_ref().map(|d| d.instruction);
+            let directive = directive.map(|d| (d.raw, agent_payload.occurrence.unwrap_or(0)));
+            let paragraph_mode = agent_payload.paragraph_mode.unwrap_or_default();
+            // A follow-up in the same session sees what was asked and written before
+            let session = agent_payload
+                .session_id
+                .map(|id| ctx.state.agent_cache.session(id));
+            compose_turn(session, instruction, |history| async move {
+                let input = ComposeInput {
+                    article_draft: get_doc_content(doc),
+                    role: agent_payload.role.clone(),
+                    instruction: instruction.map(str::to_string),
+                    history,
+                };
+
+                let engine = &ctx.state.wf_engine;
+                let execution = engine.start_compose(&input).await.map_err(|e| {
+                    tracing::error!("❌ could not start compose workflow: {:?}", e);
+                    ToolError::new(
+                        AiErrorCode::Unavailable,
+                        "The writing agent is unavailable right now. Please try again shortly.",
+                    )
+                })?;
+                tracing::info!("🧭 composing in workflow {}", execution.workflow_id);
+                ctx.emit(AiEvent::WorkflowStarted {
+                    request_id: ctx.request_id,
+                    workflow_id: execution.workflow_id.clone(),
+                });
+
+                // A restart while the worker writes resumes from this row instead of losing the text
+                if let Err(e) = compositions::record_pending(
+                    &ctx.state.pg_pool,
+                    &execution,
+                    directive,
+                    paragraph_mode,
+                )
+                .await
+                {
+                    tracing::warn!(
+                        "📝 could not record composition {}: {:?}",
+                        execution.workflow_id,
+                        e
+                    );
+                }
+                let guard = ComposeGuard {
+                    state: ctx.state.clone(),
+                    execution: execution.clone(),
+                    done: false,
+                };
+
+                let progress = |done: usize, total: usize| {
+                    ctx.emit(AiEvent::StreamProgress {
+                        request_id: ctx.request_id,
+                        done,
+                        total,
+                    })
+                };
+                let composed = async {
+                    let text = engine.compose_result(&execution).await.map_err(|e| {
+                        tracing::error!("❌ compose workflow failed: {:?}", e);
+                        ToolError::new(AiErrorCode::Upstream, "The writing agent could not finish.")
+                    })?;
+                    apply_composition(
+                        doc,
+                        user_state,
+                        directive,
+                        &text,
+                        paragraph_mode,
+                        word_delay_ms,
+                        Some(&progress),
+                    )
+                    .await?;
+                    Ok::<_, ToolError>(text)
+                }
+                .await;
+                guard.finish().await;
+                composed
+            })
+            .await?;
+
+            tracing::info!("✅ Applied AI changes via CRDT");
+            Ok(ToolOutcome::Applied {
+                message: "AI agent finished successfully".to_string(),
+            })
+        })
+    }
+}
+
+/// One turn of a composer session: `compose` writes from the session's history,
+/// and its text is recorded as the next turn. The session stays locked until
+/// then, so a second command in the same session waits for this one and sees it.
+pub async fn compose_turn<F, Fut, E>(
+    session: Option<Session>,
+    instruction: Option<&str>,
+    compose: F,
+) -> Result<String, E>
+where
+    F: FnOnce(Vec<ChatMessage>) -> Fut,
+    Fut: Future<Output = Result<String, E>>,
+{
+    let Some(session) = session else {
+        return compose(Vec::new()).await;
+    };
+    let mut conversation = session.lock().await;
+    let text = compose(conversation.messages().to_vec()).await?;
+    conversation.record(instruction, &text);
+    Ok(text)
+}
+
+/// Owns a started compose workflow until its text is applied. Dropped before
+/// that because the client cancelled or typed over the agent, it terminates the
+/// workflow; dropped by shutdown, it leaves the workflow for `resume_pending`.
+struct ComposeGuard {
+    state: AppState,
+    execution: WorkflowExecution,
+    done: bool,
+}
+
+impl ComposeGuard {
+    /// The composition was applied or failed; there is nothing to resume or stop
+    async fn finish(mut self) {
+        self.done = true;
+        forget(&self.state.pg_pool, &self.execution.workflow_id).await;
+    }
+}
+
+impl Drop for ComposeGuard {
+    fn drop(&mut self) {
+        if self.done || self.state.shutdown.is_triggered() {
+            return;
+        }
+        let state = self.state.clone();
+        let execution = self.execution.clone();
+        tokio::spawn(async move {
+            tracing::info!("✋ terminating compose workflow {}", execution.workflow_id);
+            if let Err(e) = state.wf_engine.terminate(&execution).await {
+                tracing::warn!(
+                    "❌ could not terminate compose workflow {}: {:?}",
+                    execution.workflow_id,
+                    e
+                );
+            }
+            forget(&state.pg_pool, &execution.workflow_id).await;
+        });
+    }
+}
+
+async fn forget(pool: &PgPool, workflow_id: &str) {
+    if let Err(e) = compositions::finish_pending(pool, workflow_id).await {
+        tracing::warn!("📝 could not clear composition {}: {:?}", workflow_id, e);
+    }
+}
+
+/// Apply the compositions whose workflows outlived the process that started
+/// them. Called before the server listens; each one finishes in the background.
+pub async fn resume_pending(state: AppState) {
+    // Their workflows timed out while no process was waiting on them
+    match compositions::clear_expired(&state.pg_pool, compose_timeout()).await {
+        Ok(0) => {}
+        Ok(cleared) => tracing::info!("📝 dropped {cleared} expired compositions"),
+        Err(e) => tracing::warn!("📝 could not drop expired compositions: {:?}", e),
+    }
+    let pending = match compositions::list_pending(&state.pg_pool).await {
+        Ok(pending) => pending,
+        Err(e) => {
+            tracing::warn!("📝 could not load pending compositions: {:?}", e);
+            return;
+        }
+    };
+    for composition in pending {
+        tracing::info!("🧭 resuming composition {}", composition.workflow_id);
+        tokio::spawn(resume(state.clone(), composition));
+    }
+}
+
+async fn resume(state: AppState, composition: PendingComposition) {
+    let applied = async {
+        let Some(user_state) = &state.user_writing_state else {
+            anyhow::bail!("user writing state not available");
+        };
+        let text = state
+            .wf_engine
+            .compose_result(&composition.execution())
+            .await?;
+        // The document lives in memory, so it is empty until clients reconnect and resync
+        if !wait_for_content(
+            &state.editor_doc,
+            &state.editor_broadcast_tx,
+            RESUME_CONTENT_WAIT,
+        )
+        .await
+        {
+            anyhow::bail!("no client brought the document back");
+        }
+        apply_composition(
+            &state.editor_doc,
+            user_state,
+            composition.directive(),
+            &text,
+            composition.paragraph_mode(),
+            0,
+            None,
+        )
+        .await?;
+        Ok(())
+    }
+    .await;
+    match applied {
+        Ok(()) => tracing::info!("✅ applied resumed composition {}", composition.workflow_id),
+        Err(e) => tracing::warn!(
+            "❌ could not apply resumed composition {}: {:?}",
+            composition.workflow_id,
+            e
+        ),
+    }
+    forget(&state.pg_pool, &composition.workflow_id).await;
+}
+
+/// Wait until the document has content, for at most `wait`; false when it stayed empty
+async fn wait_for_content(
+    doc: &Arc<Doc>,
+    tx: &broadcast::Sender<MessageStructure>,
+    wait: Duration,
+) -> bool {
+    // Subscribe before looking, so an update between the two isn't missed
+    let mut updates = tx.subscribe();
+    let filled = async {
+        while !has_content_structure(doc) {
+            if let Err(broadcast::error::RecvError::Closed) = updates.recv().await {
+                return false;
+            }
+        }
+        true
+    };
+    tokio::time::timeout(wait, filled).await.unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::state::AgentCache;
+    use atb_types::Uuid;
+    use backend_core::llm::provider::{ChatCompletion, ChatReply, LlmProvider};
+    use backend_core::temporal::compose::compose_at;
+    use std::sync::Mutex;
+    use yrs::{Transact, XmlFragment, XmlTextPrelim};
+
+    /// Stands in for the worker's model: answers each call with the next reply
+    /// and keeps the messages it was sent
+    struct ScriptedLlm {
+        replies: Mutex<Vec<&'static str>>,
+        received: Mutex<Vec<Vec<ChatMessage>>>,
+    }
+
+    impl LlmProvider for ScriptedLlm {
+        fn chat_completion<'a>(
+            &'a self,
+            _tool: &'static str,
+            request: &'a ChatCompletion,
+        ) -> BoxFuture<'a, Result<ChatReply, RefineError>> {
+            Box::pin(async move {
+                self.received.lock().unwrap().push(request.messages.clone());
+                // Give a concurrent turn the chance to start meanwhile
+                tokio::task::yield_now().await;
+                Ok(ChatReply {
+                    content: Some(self.replies.lock().unwrap().remove(0).to_string()),
+                    ..Default::default()
+                })
+            })
+        }
+
+        fn describe(&self, _tool: &'static str, model: &str) -> String {
+            model.to_string()
+        }
+
+        fn reachable(&self) -> BoxFuture<'_, reqwest::Result<()>> {
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_agent_commands_in_a_session_take_turns() {
+        let llm = ScriptedLlm {
+            replies: Mutex::new(vec!["The harvest failed.", "Prices doubled."]),
+            received: Mutex::new(Vec::new()),
+        };
+        let sessions = AgentCache::new(Duration::from_secs(60));
+        let session_id = Uuid::new_v4();
+        let turn = |instruction: &'static str| {
+            compose_turn(
+                Some(sessions.session(session_id)),
+                Some(instruction),
+                |history| {
+                    let input = ComposeInput {
+                        article_draft: "Autumn came.".to_string(),
+                        role: "writer".to_string(),
+                        instruction: Some(instruction.to_string()),
+                        history,
+                    };
+                    let llm = &llm;
+                    async move { compose_at(llm, &input).await }
+                },
+            )
+        };
+
+        let (first, second) = tokio::join!(
+            turn("open with the harvest"),
+            turn("say what it did to prices")
+        );
+        assert_eq!(first.unwrap(), "The harvest failed.");
+        assert_eq!(second.unwrap(), "Prices doubled.");
+
+        // The second command reached the model with the first one's turn...
+        let received = llm.received.lock().unwrap();
+        let first_turn = [
+            ChatMessage::user("Instruction: open with the harvest"),
+            ChatMessage::assistant("The harvest failed."),
+        ];
+        assert!(!received[0].contains(&first_turn[1]));
+        assert!(first_turn.iter().all(|m| received[1].contains(m)));
+
+        // ...and the session kept both
+        let session = sessions.session(session_id);
+        let conversation = session.try_lock().unwrap();
+        assert_eq!(conversation.messages().len(), 4);
+        assert_eq!(
+            conversation.messages()[3],
+            ChatMessage::assistant("Prices doubled.")
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_resumed_composition_waits_for_clients_to_fill_the_document() {
+        let doc = Arc::new(Doc::new());
+        let (tx, _) = broadcast::channel(16);
+        assert!(
+            !wait_for_content(&doc, &tx, Duration::from_secs(1)).await,
+            "an empty document times out"
+        );
+
+        let waiting = tokio::spawn({
+            let (doc, tx) = (doc.clone(), tx.clone());
+            async move { wait_for_content(&doc, &tx, RESUME_CONTENT_WAIT).await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!waiting.is_finished());
+
+        let fragment = doc.get_or_insert_xml_fragment("content");
+        fragment.insert(&mut doc.transact_mut(), 0, XmlTextPrelim::new("Back again"));
+        let _ = tx.send(MessageStructure::YjsUpdate {
+            data: vec![],
+            origin: None,
+            is_ai: false,
+        });
+        assert!(waiting.await.unwrap());
+    }
+}
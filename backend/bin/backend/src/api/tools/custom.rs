@@ -0,0 +1,31 @@
+use super::refine::rewrite_selection;
+use super::{EditorTool, ToolContext, ToolError, ToolOutcome};
+use crate::api::state::{AiCommandPayload, AiErrorCode};
+use backend_core::refiner::processor::{call_custom_api, check_instruction};
+use futures::future::BoxFuture;
+
+/// Rewrites the selection following the writer's own instruction.
+pub struct Custom;
+
+impl EditorTool for Custom {
+    fn run<'a>(&'a self, ctx: &'a ToolContext) -> BoxFuture<'a, Result<ToolOutcome, ToolError>> {
+        Box::pin(async move {
+            let payload = match &ctx.payload {
+                Some(AiCommandPayload::Custom(payload)) => payload,
+                Some(_) => {
+                    return Err(ToolError::new(
+                        AiErrorCode::InvalidPayload,
+                        "Invalid payload type for custom command",
+                    ));
+                }
+                None => return Err(ToolError::missing_payload()),
+            };
+            let instruction = check_instruction(&payload.instruction)?.to_string();
+
+            rewrite_selection(ctx, &payload.text, "CUSTOM", move |input, key| {
+                Box::pin(async move { call_custom_api(input, &instruction, &key).await })
+            })
+            .await
+        })
+    }
+}
@@ -0,0 +1,56 @@
+use super::{EditorTool, ToolContext, ToolError, ToolOutcome};
+use crate::api::state::AiErrorCode;
+use futures::future::BoxFuture;
+
+/// Sets the sending connection's focused paragraph for distraction-free editing.
+/// The payload is the paragraph index, or `OFF` to leave focus mode.
+pub struct Focus;
+
+impl EditorTool for Focus {
+    fn thinking_message(&self) -> &'static str {
+        "Updating focus..."
+    }
+
+    fn run<'a>(&'a self, ctx: &'a ToolContext) -> BoxFuture<'a, Result<ToolOutcome, ToolError>> {
+        Box::pin(async move {
+            let paragraph = parse_focus(ctx.text_payload()?)?;
+            ctx.state.auto_agents.set_focus(ctx.conn_id, paragraph);
+
+            let message = match paragraph {
+                Some(index) => format!("Focused on paragraph {}", index + 1),
+                None => "Focus mode off".to_string(),
+            };
+            tracing::info!("🎯 {}", message);
+            Ok(ToolOutcome::Applied { message })
+        })
+    }
+}
+
+fn parse_focus(payload: &str) -> Result<Option<u32>, ToolError> {
+    let payload = payload.trim();
+    if payload.is_empty() || payload.eq_ignore_ascii_case("off") {
+        return Ok(None);
+    }
+    payload.parse().map(Some).map_err(|_| {
+        ToolError::new(
+            AiErrorCode::InvalidPayload,
+            format!("Invalid paragraph index: {}", payload),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_focus() {
+        assert_eq!(parse_focus("2"), Ok(Some(2)));
+        assert_eq!(parse_focus(" off "), Ok(None));
+        assert_eq!(parse_focus(""), Ok(None));
+        assert_eq!(
+            parse_focus("-1").unwrap_err().code,
+            AiErrorCode::InvalidPayload
+        );
+    }
+}
@@ -0,0 +1,107 @@
+use super::{EditorTool, ToolContext, ToolError, ToolOutcome};
+use crate::api::state::{AiCommandPayload, AiErrorCode, HighlightMark, HighlightPayload};
+use backend_core::editor::format_all_occurrences;
+use futures::future::BoxFuture;
+use std::collections::HashMap;
+use std::sync::Arc;
+use yrs::Any;
+use yrs::types::Attrs;
+
+/// Formats every occurrence of a word across the document; clients receive the
+/// change over the Yjs lane like any other edit.
+pub struct Highlight;
+
+impl EditorTool for Highlight {
+    fn thinking_message(&self) -> &'static str {
+        "Highlighting..."
+    }
+
+    fn run<'a>(&'a self, ctx: &'a ToolContext) -> BoxFuture<'a, Result<ToolOutcome, ToolError>> {
+        Box::pin(async move {
+            let payload = match &ctx.payload {
+                Some(AiCommandPayload::Highlight(payload)) => payload,
+                Some(_) => {
+                    return Err(ToolError::new(
+                        AiErrorCode::InvalidPayload,
+                        "Invalid payload type for highlight command",
+                    ));
+                }
+                None => return Err(ToolError::missing_payload()),
+            };
+            if payload.word.trim().is_empty() {
+                return Err(ToolError::new(
+                    AiErrorCode::InvalidPayload,
+                    "Nothing to highlight: the word is empty",
+                ));
+            }
+
+            let attrs = mark_attrs(payload)?;
+            let count = format_all_occurrences(&ctx.state.editor_doc, &payload.word, attrs)
+                .map_err(|e| ToolError::new(AiErrorCode::Internal, e.to_string()))?;
+
+            tracing::info!("🖍️ highlighted {} occurrences of {:?}", count, payload.word);
+            Ok(ToolOutcome::Applied {
+                message: format!("Highlighted {} occurrences of \"{}\"", count, payload.word),
+            })
+        })
+    }
+}
+
+/// The formatting attributes the editor stores for the requested mark
+fn mark_attrs(payload: &HighlightPayload) -> Result<Attrs, ToolError> {
+    let (name, value) = match payload.mark {
+        HighlightMark::Bold => ("bold", Any::Bool(true)),
+        HighlightMark::Italic => ("italic", Any::Bool(true)),
+        HighlightMark::Color => {
+            let color = payload
+                .color
+                .as_deref()
+                .map(str::trim)
+                .filter(|color| !color.is_empty())
+                .ok_or_else(|| {
+                    ToolError::new(AiErrorCode::InvalidPayload, "The color mark needs a color")
+                })?;
+            // Tiptap keeps text color as an attribute of its `textStyle` mark
+            let style = HashMap::from([("color".to_string(), Any::from(color))]);
+            ("textStyle", Any::from(style))
+        }
+    };
+    Ok(Attrs::from([(Arc::from(name), value)]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payload(mark: HighlightMark, color: Option<&str>) -> HighlightPayload {
+        HighlightPayload {
+            word: "Rust".to_string(),
+            mark,
+            color: color.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_mark_attrs_for_each_mark() {
+        let attrs = mark_attrs(&payload(HighlightMark::Bold, None)).unwrap();
+        assert_eq!(attrs.get("bold"), Some(&Any::Bool(true)));
+
+        let attrs = mark_attrs(&payload(HighlightMark::Italic, Some("#fff"))).unwrap();
+        assert_eq!(attrs.len(), 1);
+        assert_eq!(attrs.get("italic"), Some(&Any::Bool(true)));
+
+        let attrs = mark_attrs(&payload(HighlightMark::Color, Some("#e11d48"))).unwrap();
+        let Some(Any::Map(style)) = attrs.get("textStyle") else {
+            panic!("expected a textStyle map, got {attrs:?}");
+        };
+        assert_eq!(style.get("color"), Some(&Any::from("#e11d48")));
+    }
+
+    #[test]
+    fn test_color_mark_requires_a_color() {
+        for color in [None, Some("  ")] {
+            let e = mark_attrs(&payload(HighlightMark::Color, color)).unwrap_err();
+            assert_eq!(e.code, AiErrorCode::InvalidPayload);
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use super::{EditorTool, ToolContext, ToolError, ToolOutcome};
+use crate::api::state::{AiCommandPayload, AiErrorCode, EditPayload};
+use backend_core::editor::get_doc_content;
+use backend_core::llm::lint_preview;
+use backend_core::llm::tools::linter::accept_diff;
+use backend_core::refiner::error::RefineError;
+use backend_core::refiner::language::Language;
+use futures::future::BoxFuture;
+
+/// Lints the whole document without applying anything; the writer gets the
+/// suggested changes back as comments and accepts or rejects each one.
+pub struct LintPreview;
+
+impl EditorTool for LintPreview {
+    fn thinking_message(&self) -> &'static str {
+        "Checking your document..."
+    }
+
+    fn run<'a>(&'a self, ctx: &'a ToolContext) -> BoxFuture<'a, Result<ToolOutcome, ToolError>> {
+        Box::pin(async move {
+            let language = Language::parse_optional(ctx.language.as_deref())?;
+            let doc = &ctx.state.editor_doc;
+            if get_doc_content(doc).trim().is_empty() {
+                return Err(RefineError::NoContentStructure.into());
+            }
+
+            let diffs = lint_preview(&ctx.state.api_key, doc.clone(), language)
+                .await
+                .map_err(|e| match e.downcast::<RefineError>() {
+                    Ok(e) => ToolError::from(e),
+                    Err(e) => ToolError::new(AiErrorCode::Internal, e.to_string()),
+                })?;
+            tracing::info!("🔍 lint preview suggests {} changes", diffs.len());
+            Ok(ToolOutcome::LintPreview(
+                ctx.state.pending_edits.offer(diffs),
+            ))
+        })
+    }
+}
+
+/// Applies one previewed edit; clients receive the change over the Yjs lane.
+pub struct AcceptEdit;
+
+impl EditorTool for AcceptEdit {
+    fn thinking_message(&self) -> &'static str {
+        "Applying the suggestion..."
+    }
+
+    fn run<'a>(&'a self, ctx: &'a ToolContext) -> BoxFuture<'a, Result<ToolOutcome, ToolError>> {
+        Box::pin(async move {
+            let payload = edit_payload(ctx, "accept edit")?;
+            let Some(diff) = ctx.state.pending_edits.take(payload.edit_id) else {
+                return Err(expired_edit());
+            };
+            let applied = accept_diff(&ctx.state.editor_doc, &diff)
+                .map_err(|e| ToolError::new(AiErrorCode::Internal, e.to_string()))?;
+            if !applied {
+                return Err(ToolError::new(
+                    AiErrorCode::InvalidPayload,
+                    "The text changed after this suggestion was made. Preview again to get a new one.",
+                ));
+            }
+            tracing::info!("✅ accepted edit {}", payload.edit_id);
+            Ok(ToolOutcome::Applied {
+                message: format!("Changed \"{}\" to \"{}\"", diff.before, diff.after),
+            })
+        })
+    }
+}
+
+/// Drops one previewed edit; the document is untouched.
+pub struct RejectEdit;
+
+impl EditorTool for RejectEdit {
+    fn thinking_message(&self) -> &'static str {
+        "Dismissing the suggestion..."
+    }
+
+    fn run<'a>(&'a self, ctx: &'a ToolContext) -> BoxFuture<'a, Result<ToolOutcome, ToolError>> {
+        Box::pin(async move {
+            let payload = edit_payload(ctx, "reject edit")?;
+            if ctx.state.pending_edits.take(payload.edit_id).is_none() {
+                return Err(expired_edit());
+            }
+            tracing::info!("🗑️ rejected edit {}", payload.edit_id);
+            Ok(ToolOutcome::Applied {
+                message: "Dismissed the suggestion".to_string(),
+            })
+        })
+    }
+}
+
+fn edit_payload<'a>(ctx: &'a ToolContext, command: &str) -> Result<&'a EditPayload, ToolError> {
+    match &ctx.payload {
+        Some(AiCommandPayload::Edit(payload)) => Ok(payload),
+        Some(_) => Err(ToolError::new(
+            AiErrorCode::InvalidPayload,
+            format!("Invalid payload type for {command} command"),
+        )),
+        None => Err(ToolError::missing_payload()),
+    }
+}
+
+/// The id was never offered, was already decided on, or outlived its TTL
+fn expired_edit() -> ToolError {
+    ToolError::new(
+        AiErrorCode::InvalidPayload,
+        "This suggestion has expired or was already handled.",
+    )
+}
@@ -0,0 +1,436 @@
+//! Tools behind the WebSocket `AI_COMMAND` lane.
+//!
+//! Each tool only does its own work; `run_command` owns the lifecycle broadcast
+//! (thinking -> complete/result, or error) so every tool reports the same way.
+
+pub mod composer;
+pub mod custom;
+pub mod focus;
+pub mod highlight;
+pub mod lint_preview;
+pub mod refine;
+pub mod stats;
+pub mod summarize;
+pub mod title;
+pub mod toggle;
+pub mod tone;
+pub mod translate;
+
+use crate::api::state::{
+    AiAction, AiCommand, AiCommandPayload, AiErrorCode, AiEvent, AppState, ConnId, SuggestedEdit,
+};
+use atb_types::Uuid;
+use backend_core::editor::{DocStats, MarkSpan};
+use backend_core::refiner::error::RefineError;
+use backend_core::sqlx_postgres::ai_events::{self, AiEventStatus, NewAiEvent};
+use futures::future::BoxFuture;
+use std::time::Instant;
+
+pub const NO_CONTENT_MESSAGE: &str =
+    "Please start typing in the editor first. The AI agent needs existing content to work with.";
+
+/// Everything a tool gets for one command
+pub struct ToolContext {
+    pub state: AppState,
+    pub request_id: Uuid,
+    pub payload: Option<AiCommandPayload>,
+    pub language: Option<String>,
+    /// The connection that sent the command
+    pub conn_id: ConnId,
+}
+
+impl ToolContext {
+    /// Broadcast an AI event to every connected client
+    pub fn emit(&self, event: AiEvent) {
+        let _ = self.state.editor_broadcast_tx.send(event.into_message());
+    }
+
+    pub fn progress(&self, message: impl Into<String>) {
+        self.emit(AiEvent::Progress {
+            request_id: self.request_id,
+            message: message.into(),
+        });
+    }
+
+    /// The text the command works on, as the suggestion history shows it
+    fn input_preview(&self) -> &str {
+        match &self.payload {
+            Some(AiCommandPayload::Refiner(text)) => text,
+            Some(AiCommandPayload::Agent(agent)) => {
+                agent.selection.as_deref().unwrap_or(&agent.role)
+            }
+            Some(AiCommandPayload::Custom(custom)) => &custom.text,
+            Some(AiCommandPayload::Highlight(highlight)) => &highlight.word,
+            Some(AiCommandPayload::Translate(translate)) => {
+                translate.text.as_deref().unwrap_or(&translate.target_lang)
+            }
+            Some(AiCommandPayload::Title(title)) => &title.title,
+            Some(AiCommandPayload::Tone(tone)) => &tone.text,
+            // The summary is of the whole document, which the payload doesn't carry,
+            // and an edit id points at a previewed change rather than carrying text
+            Some(AiCommandPayload::Summarize(_) | AiCommandPayload::Edit(_)) | None => "",
+        }
+    }
+
+    /// Add the finished command to the suggestion history without waiting on the write
+    fn record(&self, action: &AiAction, output: &str, status: AiEventStatus, started: Instant) {
+        let tool = action.to_string().to_lowercase();
+        let event = NewAiEvent::new(
+            tool,
+            self.input_preview(),
+            output,
+            status,
+            started.elapsed(),
+        );
+        ai_events::spawn_record(&self.state.pg_pool, event);
+    }
+
+    /// The selected text of a refine-style command
+    pub fn text_payload(&self) -> Result<&str, ToolError> {
+        match &self.payload {
+            Some(AiCommandPayload::Refiner(text)) => Ok(text),
+            Some(_) => Err(ToolError::new(
+                AiErrorCode::InvalidPayload,
+                "Invalid payload type for refiner command",
+            )),
+            None => Err(ToolError::missing_payload()),
+        }
+    }
+}
+
+/// What a successful tool run produced
+#[derive(Debug, PartialEq)]
+pub enum ToolOutcome {
+    /// The document was edited in place; clients receive it over the Yjs lane
+    Applied { message: String },
+    /// Text for the client to put in place of its selection
+    Refined {
+        message: String,
+        content: String,
+        marks: Vec<MarkSpan>,
+    },
+    /// An auto-agent was switched on or off
+    Toggled {
+        target: &'static str,
+        enabled: bool,
+        message: String,
+    },
+    /// Current document statistics, for the requesting client's stats panel
+    Stats(DocStats),
+    /// Title candidates for the writer to choose from, best first
+    Titles(Vec<String>),
+    /// What the linter would change, each edit under the id that accepts or rejects it
+    LintPreview(Vec<SuggestedEdit>),
+}
+
+impl ToolOutcome {
+    /// What the writer got back: the suggested text, or the status message
+    fn output(&self) -> String {
+        match self {
+            Self::Applied { message } | Self::Toggled { message, .. } => message.clone(),
+            Self::Refined { content, .. } => content.clone(),
+            Self::Stats(stats) => format!("{} words", stats.words),
+            Self::Titles(titles) => titles.join("\n"),
+            Self::LintPreview(diffs) => diffs
+                .iter()
+                .map(|edit| format!("{} -> {}", edit.diff.before, edit.diff.after))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+
+    fn into_events(self, request_id: Uuid) -> Vec<AiEvent> {
+        match self {
+            Self::Applied { message } => vec![AiEvent::Complete {
+                request_id,
+                message,
+            }],
+            Self::Refined {
+                message,
+                content,
+                marks,
+            } => vec![
+                AiEvent::Complete {
+                    request_id,
+                    message,
+                },
+                AiEvent::Result {
+                    request_id,
+                    content,
+                    marks,
+                },
+            ],
+            Self::Toggled {
+                target,
+                enabled,
+                message,
+            } => vec![
+                AiEvent::ToggleState {
+                    request_id,
+                    target: target.to_string(),
+                    enabled,
+                },
+                AiEvent::Complete {
+                    request_id,
+                    message,
+                },
+            ],
+            Self::Stats(stats) => vec![
+                AiEvent::DocStats {
+                    request_id: Some(request_id),
+                    stats,
+                },
+                AiEvent::Complete {
+                    request_id,
+                    message: format!("{} words", stats.words),
+                },
+            ],
+            Self::Titles(titles) => vec![
+                AiEvent::Complete {
+                    request_id,
+                    message: format!("Suggested {} titles", titles.len()),
+                },
+                AiEvent::Titles { request_id, titles },
+            ],
+            Self::LintPreview(diffs) => vec![
+                AiEvent::Complete {
+                    request_id,
+                    message: format!("Found {} suggested changes", diffs.len()),
+                },
+                AiEvent::LintPreview { request_id, diffs },
+            ],
+        }
+    }
+}
+
+/// A failed tool run, as the writer will see it
+#[derive(Debug, PartialEq)]
+pub struct ToolError {
+    pub code: AiErrorCode,
+    pub message: String,
+}
+
+impl ToolError {
+    pub fn new(code: AiErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    pub fn missing_payload() -> Self {
+        Self::new(AiErrorCode::MissingPayload, "No payload found for command")
+    }
+}
+
+impl From<RefineError> for ToolError {
+    fn from(e: RefineError) -> Self {
+        let (code, message) = match &e {
+            RefineError::NoContentStructure => {
+                (AiErrorCode::NoContent, NO_CONTENT_MESSAGE.to_string())
+            }
+            RefineError::DirectiveNotFound => (
+                AiErrorCode::DirectiveNotFound,
+                "The highlighted instruction was edited before the AI finished.".to_string(),
+            ),
+            RefineError::InvalidInstruction(_) | RefineError::UnsupportedLanguage(_) => {
+                (AiErrorCode::InvalidPayload, e.to_string())
+            }
+            RefineError::RateLimited => (
+                AiErrorCode::RateLimited,
+                "The AI is busy right now. Please try again in a moment.".to_string(),
+            ),
+            RefineError::OpenAiStatus(status, _) => (
+                AiErrorCode::Upstream,
+                format!("The AI service returned an error ({status})."),
+            ),
+            RefineError::Parse(_) => (
+                AiErrorCode::Upstream,
+                "The AI returned an unexpected response.".to_string(),
+            ),
+            RefineError::Request(_) => (
+                AiErrorCode::Upstream,
+                "Could not reach the AI service.".to_string(),
+            ),
+            RefineError::Other(e) => (AiErrorCode::Internal, e.to_string()),
+        };
+        Self { code, message }
+    }
+}
+
+pub trait EditorTool: Send + Sync {
+    /// Status shown to the writer while the tool runs
+    fn thinking_message(&self) -> &'static str {
+        "Polishing your text..."
+    }
+
+    fn run<'a>(&'a self, ctx: &'a ToolContext) -> BoxFuture<'a, Result<ToolOutcome, ToolError>>;
+}
+
+/// The tool that handles `action`; adding a tool means adding an arm here
+pub fn tool_for(action: &AiAction) -> Option<&'static dyn EditorTool> {
+    match action {
+        AiAction::Improve => Some(&refine::IMPROVE),
+        AiAction::Fix => Some(&refine::FIX),
+        AiAction::Longer => Some(&refine::LONGER),
+        AiAction::Shorter => Some(&refine::SHORTER),
+        AiAction::Agent => Some(&composer::Composer),
+        AiAction::Toggle => Some(&toggle::Toggle),
+        AiAction::Focus => Some(&focus::Focus),
+        AiAction::Stats => Some(&stats::Stats),
+        AiAction::Custom => Some(&custom::Custom),
+        AiAction::Highlight => Some(&highlight::Highlight),
+        AiAction::Translate => Some(&translate::Translate),
+        AiAction::Summarize => Some(&summarize::Summarize),
+        AiAction::Tone => Some(&tone::Tone),
+        AiAction::SuggestTitle => Some(&title::SuggestTitle),
+        AiAction::ApplyTitle => Some(&title::ApplyTitle),
+        AiAction::LintPreview => Some(&lint_preview::LintPreview),
+        AiAction::AcceptEdit => Some(&lint_preview::AcceptEdit),
+        AiAction::RejectEdit => Some(&lint_preview::RejectEdit),
+        // Handled by the connection itself, which owns the in-flight tasks and undo stack
+        AiAction::Cancel | AiAction::Undo | AiAction::Redo | AiAction::Unknown(_) => None,
+    }
+}
+
+/// Run one AI command and broadcast its lifecycle to every client
+pub async fn run_command(state: AppState, cmd: AiCommand, conn_id: ConnId) {
+    // Legacy clients don't send an id; give the command one so events still correlate
+    let ctx = ToolContext {
+        state,
+        request_id: cmd.request_id.unwrap_or_else(Uuid::new_v4),
+        payload: cmd.payload,
+        language: cmd.language,
+        conn_id,
+    };
+
+    let Some(tool) = tool_for(&cmd.action) else {
+        tracing::error!("Unknown command: {}", cmd.action);
+        ctx.emit(AiEvent::Error {
+            request_id: ctx.request_id,
+            code: AiErrorCode::UnknownCommand,
+            message: format!("Unknown command: {}", cmd.action),
+        });
+        return;
+    };
+
+    tracing::info!("🤖 processing {}...", cmd.action);
+    ctx.emit(AiEvent::Thinking {
+        request_id: ctx.request_id,
+        message: tool.thinking_message().to_string(),
+    });
+
+    // Only calls to OpenAI are suggestions worth reviewing later
+    let recorded = cmd.action.calls_openai();
+    let started = Instant::now();
+    // Shutting down drops the tool mid-call rather than waiting on OpenAI
+    let Some(result) = ctx.state.shutdown.run_until(tool.run(&ctx)).await else {
+        tracing::info!("🛑 {} cancelled by shutdown", cmd.action);
+        if recorded {
+            ctx.record(&cmd.action, "", AiEventStatus::Cancelled, started);
+        }
+        ctx.emit(AiEvent::Error {
+            request_id: ctx.request_id,
+            code: AiErrorCode::Unavailable,
+            message: "The server is shutting down. Please try again shortly.".to_string(),
+        });
+        return;
+    };
+
+    match result {
+        Ok(outcome) => {
+            if recorded {
+                ctx.record(&cmd.action, &outcome.output(), AiEventStatus::Ok, started);
+            }
+            for event in outcome.into_events(ctx.request_id) {
+                ctx.emit(event);
+            }
+        }
+        Err(e) => {
+            tracing::warn!("❌ {} failed: {:?}", cmd.action, e);
+            if recorded {
+                ctx.record(&cmd.action, &e.message, AiEventStatus::Error, started);
+            }
+            ctx.emit(AiEvent::Error {
+                request_id: ctx.request_id,
+                code: e.code,
+                message: e.message,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_known_action_has_a_tool() {
+        for action in [
+            AiAction::Improve,
+            AiAction::Fix,
+            AiAction::Longer,
+            AiAction::Shorter,
+            AiAction::Agent,
+            AiAction::Toggle,
+            AiAction::Focus,
+            AiAction::Stats,
+            AiAction::Custom,
+            AiAction::Highlight,
+            AiAction::Translate,
+            AiAction::Summarize,
+            AiAction::Tone,
+            AiAction::SuggestTitle,
+            AiAction::ApplyTitle,
+            AiAction::LintPreview,
+            AiAction::AcceptEdit,
+            AiAction::RejectEdit,
+        ] {
+            assert!(tool_for(&action).is_some(), "no tool for {action}");
+        }
+        assert!(tool_for(&AiAction::Unknown("SUMMON".into())).is_none());
+    }
+
+    #[test]
+    fn test_refine_errors_map_to_codes() {
+        let e = ToolError::from(RefineError::NoContentStructure);
+        assert_eq!(e.code, AiErrorCode::NoContent);
+        assert_eq!(e.message, NO_CONTENT_MESSAGE);
+
+        let e = ToolError::from(RefineError::Parse("not json".into()));
+        assert_eq!(e.code, AiErrorCode::Upstream);
+        // Upstream details stay in the logs, not in front of the writer
+        assert!(!e.message.contains("not json"));
+    }
+
+    #[test]
+    fn test_refined_outcome_completes_then_sends_result() {
+        let id = Uuid::new_v4();
+        let events = ToolOutcome::Refined {
+            message: "Applied FIX".into(),
+            content: "the text".into(),
+            marks: vec![],
+        }
+        .into_events(id);
+        assert!(matches!(events[0], AiEvent::Complete { .. }));
+        assert!(matches!(events[1], AiEvent::Result { .. }));
+    }
+
+    #[test]
+    fn test_titles_outcome_completes_then_sends_the_list() {
+        let id = Uuid::new_v4();
+        let titles = vec![
+            "Shipping the Editor".to_string(),
+            "Launch Notes".to_string(),
+        ];
+        let events = ToolOutcome::Titles(titles.clone()).into_events(id);
+        assert!(matches!(events[0], AiEvent::Complete { .. }));
+        assert_eq!(
+            events[1],
+            AiEvent::Titles {
+                request_id: id,
+                titles
+            }
+        );
+    }
+}
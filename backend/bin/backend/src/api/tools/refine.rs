@@ -0,0 +1,76 @@
+use super::{EditorTool, ToolContext, ToolError, ToolOutcome};
+use backend_core::editor::{marks_for_text, realign_marks};
+use backend_core::refiner::error::RefineError;
+use backend_core::refiner::processor::{
+    call_fix_api, call_improve_api, call_longer_api, call_shorter_api,
+};
+use backend_core::refiner::types::{RefineInput, RefineOutput};
+use futures::future::BoxFuture;
+use std::sync::Arc;
+
+type RefineFn = fn(RefineInput, Arc<str>) -> BoxFuture<'static, Result<RefineOutput, RefineError>>;
+
+/// Rewrites the selected text and hands it back to the client, keeping the
+/// selection's bold/italic marks on the words that survived.
+pub struct Refine {
+    pub action: &'static str,
+    call: RefineFn,
+}
+
+pub static IMPROVE: Refine = Refine {
+    action: "IMPROVE",
+    call: |input, key| Box::pin(async move { call_improve_api(input, &key).await }),
+};
+
+pub static FIX: Refine = Refine {
+    action: "FIX",
+    call: |input, key| Box::pin(async move { call_fix_api(input, &key).await }),
+};
+
+pub static LONGER: Refine = Refine {
+    action: "LONGER",
+    call: |input, key| Box::pin(async move { call_longer_api(input, &key).await }),
+};
+
+pub static SHORTER: Refine = Refine {
+    action: "SHORTER",
+    call: |input, key| Box::pin(async move { call_shorter_api(input, &key).await }),
+};
+
+impl EditorTool for Refine {
+    fn run<'a>(&'a self, ctx: &'a ToolContext) -> BoxFuture<'a, Result<ToolOutcome, ToolError>> {
+        Box::pin(async move {
+            let original = ctx.text_payload()?;
+            rewrite_selection(ctx, original, self.action, self.call).await
+        })
+    }
+}
+
+/// Rewrite `original` with `call`, carrying the selection's formatting over to the result
+pub async fn rewrite_selection<F>(
+    ctx: &ToolContext,
+    original: &str,
+    action: &str,
+    call: F,
+) -> Result<ToolOutcome, ToolError>
+where
+    F: FnOnce(RefineInput, Arc<str>) -> BoxFuture<'static, Result<RefineOutput, RefineError>>,
+{
+    // Remember the selection's formatting; the refiner only returns plain text
+    let original_marks = marks_for_text(&ctx.state.editor_doc, original);
+
+    let input = RefineInput {
+        content: original.to_string(),
+        language: ctx.language.clone(),
+        tone: None,
+        audience: None,
+    };
+    let output = call(input, ctx.state.api_key.clone()).await?;
+
+    let marks = realign_marks(original, &original_marks, &output.content);
+    Ok(ToolOutcome::Refined {
+        message: format!("Applied {}", action),
+        content: output.content,
+        marks,
+    })
+}
@@ -0,0 +1,133 @@
+use super::{EditorTool, ToolContext, ToolError, ToolOutcome};
+use crate::api::state::{AiEvent, MessageStructure};
+use backend_core::editor::get_doc_stats;
+use futures::future::BoxFuture;
+use std::{sync::Arc, time::Duration};
+use tokio::sync::broadcast::{
+    self,
+    error::{RecvError, TryRecvError},
+};
+use tokio::time::Instant;
+use yrs::Doc;
+
+/// Stats are pushed at most this often while the document is being edited
+pub const STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Reports word count and reading time for the whole document.
+pub struct Stats;
+
+impl EditorTool for Stats {
+    fn thinking_message(&self) -> &'static str {
+        "Counting words..."
+    }
+
+    fn run<'a>(&'a self, ctx: &'a ToolContext) -> BoxFuture<'a, Result<ToolOutcome, ToolError>> {
+        Box::pin(async move { Ok(ToolOutcome::Stats(get_doc_stats(&ctx.state.editor_doc))) })
+    }
+}
+
+/// Push `DOC_STATS` to every client after edits, at most once per `interval`.
+///
+/// The first edit after a quiet spell is reported straight away; edits within
+/// `interval` of the last push are folded into one push once it elapses, so the
+/// final count always goes out. Listens on the same broadcast channel the
+/// WebSocket lanes use, so it sees user and AI edits alike and stops when the
+/// channel closes.
+pub async fn broadcast_on_change(
+    doc: Arc<Doc>,
+    tx: broadcast::Sender<MessageStructure>,
+    interval: Duration,
+) {
+    let mut rx = tx.subscribe();
+    let mut last_push: Option<Instant> = None;
+    loop {
+        // Wait for an edit
+        match rx.recv().await {
+            Ok(MessageStructure::YjsUpdate { .. }) | Err(RecvError::Lagged(_)) => {}
+            Ok(MessageStructure::AiCommand(_) | MessageStructure::Awareness { .. }) => continue,
+            Err(RecvError::Closed) => return,
+        }
+
+        // Hold it back until a full interval has passed since the last push...
+        if let Some(last_push) = last_push {
+            tokio::time::sleep_until(last_push + interval).await;
+        }
+        // ...and fold in everything that arrived meanwhile, since the push counts it
+        loop {
+            match rx.try_recv() {
+                Ok(_) | Err(TryRecvError::Lagged(_)) => {}
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Closed) => return,
+            }
+        }
+
+        let event = AiEvent::DocStats {
+            request_id: None,
+            stats: get_doc_stats(&doc),
+        };
+        let _ = tx.send(event.into_message());
+        last_push = Some(Instant::now());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yrs::{Transact, XmlFragment, XmlTextPrelim};
+
+    fn type_word(doc: &Doc, tx: &broadcast::Sender<MessageStructure>, word: &str) {
+        let fragment = doc.get_or_insert_xml_fragment("content");
+        let mut txn = doc.transact_mut();
+        let len = fragment.len(&txn);
+        fragment.insert(&mut txn, len, XmlTextPrelim::new(word));
+        drop(txn);
+        let _ = tx.send(MessageStructure::YjsUpdate {
+            data: vec![],
+            origin: None,
+            is_ai: false,
+        });
+    }
+
+    /// Word counts of the `DOC_STATS` events the client has received so far
+    fn pushed_words(client: &mut broadcast::Receiver<MessageStructure>) -> Vec<u64> {
+        let mut words = Vec::new();
+        while let Ok(message) = client.try_recv() {
+            if let MessageStructure::AiCommand(json) = message {
+                let event: serde_json::Value = serde_json::from_str(&json).unwrap();
+                assert_eq!(event["type"], "DOC_STATS");
+                assert!(event.get("request_id").is_none());
+                words.push(event["stats"]["words"].as_u64().unwrap());
+            }
+        }
+        words
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stats_are_pushed_at_most_once_per_interval() {
+        let doc = Arc::new(Doc::new());
+        let (tx, mut client) = broadcast::channel(16);
+        tokio::spawn(broadcast_on_change(
+            doc.clone(),
+            tx.clone(),
+            Duration::from_secs(1),
+        ));
+        tokio::task::yield_now().await;
+
+        // The first edit is reported straight away
+        type_word(&doc, &tx, "one ");
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(pushed_words(&mut client), [1]);
+
+        // A burst within the interval waits for it, then goes out as one push
+        type_word(&doc, &tx, "two ");
+        type_word(&doc, &tx, "three");
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert_eq!(pushed_words(&mut client), Vec::<u64>::new());
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert_eq!(pushed_words(&mut client), [3]);
+
+        // Nothing more without another edit
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        assert_eq!(pushed_words(&mut client), Vec::<u64>::new());
+    }
+}
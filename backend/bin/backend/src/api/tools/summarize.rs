@@ -0,0 +1,40 @@
+use super::{EditorTool, ToolContext, ToolError, ToolOutcome};
+use crate::api::state::{AiCommandPayload, AiErrorCode};
+use backend_core::llm::new_summarizer;
+use backend_core::llm::tools::summarizer::SummaryStyle;
+use backend_core::refiner::language::Language;
+use futures::future::BoxFuture;
+
+/// Summarizes the whole document; the summary goes back as a result, the document is untouched.
+pub struct Summarize;
+
+impl EditorTool for Summarize {
+    fn thinking_message(&self) -> &'static str {
+        "Summarizing your document..."
+    }
+
+    fn run<'a>(&'a self, ctx: &'a ToolContext) -> BoxFuture<'a, Result<ToolOutcome, ToolError>> {
+        Box::pin(async move {
+            // Without a payload the summary is a bulleted list, as before styles existed
+            let style = match &ctx.payload {
+                Some(AiCommandPayload::Summarize(payload)) => payload.style,
+                Some(_) => {
+                    return Err(ToolError::new(
+                        AiErrorCode::InvalidPayload,
+                        "Invalid payload type for summarize command",
+                    ));
+                }
+                None => SummaryStyle::default(),
+            };
+            let language = Language::parse_optional(ctx.language.as_deref())?;
+
+            let summary =
+                new_summarizer(&ctx.state.api_key, &ctx.state.editor_doc, style, language).await?;
+            Ok(ToolOutcome::Refined {
+                message: "Applied SUMMARIZE".to_string(),
+                content: summary,
+                marks: Vec::new(),
+            })
+        })
+    }
+}
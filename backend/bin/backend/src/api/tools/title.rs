@@ -0,0 +1,70 @@
+use super::{EditorTool, ToolContext, ToolError, ToolOutcome};
+use crate::api::documents::MAX_TITLE_CHARS;
+use crate::api::state::{AiCommandPayload, AiErrorCode};
+use backend_core::editor::set_document_title;
+use backend_core::llm::new_titler;
+use backend_core::refiner::language::Language;
+use futures::future::BoxFuture;
+
+/// Suggests titles for the whole document; the document is untouched until
+/// the writer picks one and sends it back with `APPLY_TITLE`.
+pub struct SuggestTitle;
+
+impl EditorTool for SuggestTitle {
+    fn thinking_message(&self) -> &'static str {
+        "Thinking of a title..."
+    }
+
+    fn run<'a>(&'a self, ctx: &'a ToolContext) -> BoxFuture<'a, Result<ToolOutcome, ToolError>> {
+        Box::pin(async move {
+            let language = Language::parse_optional(ctx.language.as_deref())?;
+            let titles = new_titler(&ctx.state.api_key, &ctx.state.editor_doc, language).await?;
+            Ok(ToolOutcome::Titles(titles))
+        })
+    }
+}
+
+/// Puts the chosen title at the top of the document as its level-1 heading;
+/// clients receive the change over the Yjs lane like any other edit.
+pub struct ApplyTitle;
+
+impl EditorTool for ApplyTitle {
+    fn thinking_message(&self) -> &'static str {
+        "Setting the title..."
+    }
+
+    fn run<'a>(&'a self, ctx: &'a ToolContext) -> BoxFuture<'a, Result<ToolOutcome, ToolError>> {
+        Box::pin(async move {
+            let payload = match &ctx.payload {
+                Some(AiCommandPayload::Title(payload)) => payload,
+                Some(_) => {
+                    return Err(ToolError::new(
+                        AiErrorCode::InvalidPayload,
+                        "Invalid payload type for apply title command",
+                    ));
+                }
+                None => return Err(ToolError::missing_payload()),
+            };
+            let title = payload.title.trim();
+            if title.is_empty() {
+                return Err(ToolError::new(
+                    AiErrorCode::InvalidPayload,
+                    "The title must not be empty",
+                ));
+            }
+            if title.chars().count() > MAX_TITLE_CHARS {
+                return Err(ToolError::new(
+                    AiErrorCode::InvalidPayload,
+                    format!("The title must be at most {MAX_TITLE_CHARS} characters"),
+                ));
+            }
+
+            set_document_title(&ctx.state.editor_doc, title)
+                .map_err(|e| ToolError::new(AiErrorCode::Internal, e.to_string()))?;
+            tracing::info!("🏷️ set the document title to {:?}", title);
+            Ok(ToolOutcome::Applied {
+                message: format!("Set the title to \"{}\"", title),
+            })
+        })
+    }
+}
@@ -0,0 +1,40 @@
+use super::{EditorTool, ToolContext, ToolError, ToolOutcome};
+use crate::api::state::AiErrorCode;
+use futures::future::BoxFuture;
+
+/// Switches an auto-agent on or off; the payload names the agent.
+pub struct Toggle;
+
+impl EditorTool for Toggle {
+    fn thinking_message(&self) -> &'static str {
+        "Updating auto-agents..."
+    }
+
+    fn run<'a>(&'a self, ctx: &'a ToolContext) -> BoxFuture<'a, Result<ToolOutcome, ToolError>> {
+        Box::pin(async move {
+            let toggles = &ctx.state.auto_agents;
+            let (target, label, enabled) = match ctx.text_payload()? {
+                "LINTER" => ("LINTER", "Linter", toggles.toggle_linter()),
+                "EMOJI_REPLACER" => (
+                    "EMOJI_REPLACER",
+                    "Emoji replacer",
+                    toggles.toggle_emoji_replacer(),
+                ),
+                other => {
+                    return Err(ToolError::new(
+                        AiErrorCode::InvalidPayload,
+                        format!("Unknown toggle target: {}", other),
+                    ));
+                }
+            };
+
+            let status = if enabled { "enabled" } else { "disabled" };
+            tracing::info!("🤖 {} {}", target, status);
+            Ok(ToolOutcome::Toggled {
+                target,
+                enabled,
+                message: format!("{} {}", label, status),
+            })
+        })
+    }
+}
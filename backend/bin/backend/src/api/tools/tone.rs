@@ -0,0 +1,35 @@
+use super::refine::rewrite_selection;
+use super::{EditorTool, ToolContext, ToolError, ToolOutcome};
+use crate::api::state::{AiCommandPayload, AiErrorCode};
+use backend_core::llm::tools::tone;
+use futures::future::BoxFuture;
+
+/// Rewrites the selection in the payload's preset tone.
+pub struct Tone;
+
+impl EditorTool for Tone {
+    fn thinking_message(&self) -> &'static str {
+        "Adjusting the tone..."
+    }
+
+    fn run<'a>(&'a self, ctx: &'a ToolContext) -> BoxFuture<'a, Result<ToolOutcome, ToolError>> {
+        Box::pin(async move {
+            let payload = match &ctx.payload {
+                Some(AiCommandPayload::Tone(payload)) => payload,
+                Some(_) => {
+                    return Err(ToolError::new(
+                        AiErrorCode::InvalidPayload,
+                        "Invalid payload type for tone command",
+                    ));
+                }
+                None => return Err(ToolError::missing_payload()),
+            };
+            let preset = payload.preset;
+
+            rewrite_selection(ctx, &payload.text, "TONE", move |input, key| {
+                Box::pin(async move { tone::execute_tool(input, preset, &key).await })
+            })
+            .await
+        })
+    }
+}
@@ -0,0 +1,50 @@
+use super::refine::rewrite_selection;
+use super::{EditorTool, ToolContext, ToolError, ToolOutcome};
+use crate::api::state::{AiCommandPayload, AiErrorCode};
+use backend_core::llm::tools::translator;
+use backend_core::refiner::language::Language;
+use backend_core::refiner::processor::call_translate_api;
+use futures::future::BoxFuture;
+
+/// Translates the selection into the requested language, or without a selection
+/// the whole document in place, paragraph by paragraph.
+pub struct Translate;
+
+impl EditorTool for Translate {
+    fn thinking_message(&self) -> &'static str {
+        "Translating your text..."
+    }
+
+    fn run<'a>(&'a self, ctx: &'a ToolContext) -> BoxFuture<'a, Result<ToolOutcome, ToolError>> {
+        Box::pin(async move {
+            let payload = match &ctx.payload {
+                Some(AiCommandPayload::Translate(payload)) => payload,
+                Some(_) => {
+                    return Err(ToolError::new(
+                        AiErrorCode::InvalidPayload,
+                        "Invalid payload type for translate command",
+                    ));
+                }
+                None => return Err(ToolError::missing_payload()),
+            };
+            // Reject an unknown target before any work is done
+            let target = Language::parse(&payload.target_lang)?;
+
+            let Some(text) = &payload.text else {
+                let changed = translator::translate_document(
+                    &ctx.state.editor_doc,
+                    target.tag,
+                    &ctx.state.api_key,
+                )
+                .await?;
+                return Ok(ToolOutcome::Applied {
+                    message: format!("Translated {} paragraphs into {}", changed, target.name),
+                });
+            };
+            rewrite_selection(ctx, text, "TRANSLATE", move |input, key| {
+                Box::pin(async move { call_translate_api(input, target.tag, &key).await })
+            })
+            .await
+        })
+    }
+}
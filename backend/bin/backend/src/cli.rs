@@ -3,7 +3,10 @@ pub use atb_cli_utils::{
     clap::{self, Parser},
 };
 
-use crate::opts::{DatabaseOpts, HttpOpts, Opts, TemporalOpts, WorkerOpts};
+use std::path::PathBuf;
+
+use crate::logging::LogFormat;
+use crate::opts::{DatabaseOpts, HttpOpts, LlmOpts, Opts, TemporalOpts, WorkerOpts};
 
 #[derive(Parser, Debug)]
 #[clap(
@@ -19,6 +22,13 @@ pub struct Cli {
     #[arg(env = "BACKEND_WORKER_THREADS")]
     pub worker_threads: Option<usize>,
 
+    /// Log line format: `pretty` for terminals, `json` for log aggregators
+    #[arg(long, env = "BACKEND_LOG_FORMAT", value_enum, default_value_t = LogFormat::Pretty)]
+    pub log_format: LogFormat,
+
+    #[clap(flatten)]
+    pub llm: LlmOpts,
+
     /// Subcommands
     #[clap(subcommand)]
     pub subcommand: Commands,
@@ -42,6 +52,10 @@ pub enum Commands {
     },
     /// Run Temporal worker only (no HTTP server)
     Worker {
+        // Where the worker stores the token usage of its activities
+        #[clap(flatten)]
+        db_opts: DatabaseOpts,
+
         #[clap(flatten)]
         worker: WorkerOpts,
     },
@@ -61,6 +75,16 @@ pub enum Commands {
     },
     /// Print the GraphQL schema SDL to stdout
     GenerateSchema,
+    /// Replay a `--record-updates` file into a fresh document and print its text
+    Replay {
+        /// Recording to replay
+        #[arg(value_hint = clap::ValueHint::FilePath)]
+        file: PathBuf,
+
+        /// Keep the original gaps between updates instead of applying them at once
+        #[arg(long)]
+        realtime: bool,
+    },
 }
 
 impl Cli {
@@ -1,12 +1,53 @@
-use async_graphql::{Context, EmptySubscription, Object, Result, Schema, SchemaBuilder};
+use async_graphql::{
+    Context, ErrorExtensions, Object, Result, Schema, SchemaBuilder, SimpleObject, Subscription,
+};
 
-use backend_core::temporal::WorkflowEngine;
+use crate::api::ai::{refine_call, validate_text};
+use crate::api::documents::validate_title;
+use crate::api::editor::history_page;
+use crate::api::errors::Error;
+use crate::api::rate_limit::{AiRateLimits, retry_after_secs};
+use crate::api::state::MessageStructure;
+use crate::model::{AiEventResponse, AiHistoryResponse, RefineAction};
+use atb_types::{DateTime, Utc, Uuid};
+use backend_core::{
+    editor,
+    refiner::types::RefineInput,
+    sqlx_postgres::{
+        ai_events::SHARED_DOC,
+        documents::{self, DocumentRecord},
+    },
+    temporal::WorkflowEngine,
+};
+use futures::{
+    Stream, StreamExt,
+    stream::{self, BoxStream},
+};
+use serde::Deserialize;
 use sqlx::PgPool;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::broadcast::{self, error::RecvError};
+use yrs::Doc;
 
-pub type AppSchema = Schema<QueryRoot, MutationRoot, EmptySubscription>;
+pub type AppSchema = Schema<QueryRoot, MutationRoot, SubscriptionRoot>;
 
-pub fn schema() -> SchemaBuilder<QueryRoot, MutationRoot, EmptySubscription> {
-    Schema::build(QueryRoot, MutationRoot, EmptySubscription)
+pub fn schema() -> SchemaBuilder<QueryRoot, MutationRoot, SubscriptionRoot> {
+    Schema::build(QueryRoot, MutationRoot, SubscriptionRoot)
+}
+
+/// The OpenAI key and text limit the AI mutations share with the REST endpoints
+#[derive(Clone)]
+pub struct RefineConfig {
+    pub api_key: Arc<str>,
+    pub max_text_chars: usize,
+}
+
+/// An API error as a GraphQL error: the client-safe message, with the code under `extensions`
+fn client_error(e: Error) -> async_graphql::Error {
+    let (code, message) = e.client_error();
+    async_graphql::Error::new(message).extend_with(|_, ext| ext.set("code", code))
 }
 
 #[derive(Default)]
@@ -23,6 +64,128 @@ impl QueryRoot {
 
         Ok(format!("{} | {}", env!("CARGO_PKG_VERSION"), pg_version))
     }
+
+    /// Word count and reading time of the shared document
+    async fn doc_stats(&self, ctx: &Context<'_>) -> Result<DocStats> {
+        let doc = ctx.data::<Arc<Doc>>()?;
+        Ok(editor::get_doc_stats(doc).into())
+    }
+
+    /// Stored documents, most recently updated first
+    async fn documents(&self, ctx: &Context<'_>) -> Result<Vec<Document>> {
+        let pool = ctx.data::<PgPool>()?;
+        let records = documents::list_documents(pool).await?;
+        Ok(records.into_iter().map(Into::into).collect())
+    }
+
+    /// `null` when there is no such document
+    async fn document(&self, ctx: &Context<'_>, id: Uuid) -> Result<Option<Document>> {
+        let pool = ctx.data::<PgPool>()?;
+        match documents::get_document(pool, id).await {
+            Ok(record) => Ok(Some(record.into())),
+            Err(sqlx::Error::RowNotFound) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Recorded AI suggestions, newest first; pass `nextBefore` and `nextBeforeId`
+    /// back as `before` and `beforeId` for the next page
+    async fn ai_history(
+        &self,
+        ctx: &Context<'_>,
+        limit: Option<i64>,
+        before: Option<DateTime<Utc>>,
+        before_id: Option<Uuid>,
+    ) -> Result<AiHistory> {
+        let pool = ctx.data::<PgPool>()?;
+        Ok(history_page(pool, limit, before, before_id).await?.into())
+    }
+}
+
+/// One AI call on the shared document and how it went
+#[derive(SimpleObject)]
+pub struct AiHistoryEvent {
+    pub id: Uuid,
+    pub doc_id: String,
+    pub tool: String,
+    pub input_preview: String,
+    pub output_preview: String,
+    pub status: String,
+    pub latency_ms: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<AiEventResponse> for AiHistoryEvent {
+    fn from(event: AiEventResponse) -> Self {
+        Self {
+            id: event.id,
+            doc_id: event.doc_id,
+            tool: event.tool,
+            input_preview: event.input_preview,
+            output_preview: event.output_preview,
+            status: event.status,
+            latency_ms: event.latency_ms,
+            created_at: event.created_at,
+        }
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct AiHistory {
+    pub events: Vec<AiHistoryEvent>,
+    pub next_before: Option<DateTime<Utc>>,
+    pub next_before_id: Option<Uuid>,
+}
+
+impl From<AiHistoryResponse> for AiHistory {
+    fn from(page: AiHistoryResponse) -> Self {
+        Self {
+            events: page.events.into_iter().map(Into::into).collect(),
+            next_before: page.next_before,
+            next_before_id: page.next_before_id,
+        }
+    }
+}
+
+/// A stored document: metadata plus the start of its plain text
+#[derive(SimpleObject)]
+pub struct Document {
+    pub id: Uuid,
+    pub title: String,
+    pub created_by: Option<Uuid>,
+    pub updated_at: DateTime<Utc>,
+    pub preview: String,
+}
+
+impl From<DocumentRecord> for Document {
+    fn from(record: DocumentRecord) -> Self {
+        Self {
+            preview: record.preview(),
+            id: record.id,
+            title: record.title,
+            created_by: record.created_by,
+            updated_at: record.updated_at,
+        }
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct DocStats {
+    pub characters: usize,
+    pub words: usize,
+    pub paragraphs: usize,
+    pub reading_minutes: u64,
+}
+
+impl From<editor::DocStats> for DocStats {
+    fn from(stats: editor::DocStats) -> Self {
+        Self {
+            characters: stats.characters,
+            words: stats.words,
+            paragraphs: stats.paragraphs,
+            reading_minutes: stats.reading_minutes,
+        }
+    }
 }
 
 #[derive(Default)]
@@ -35,4 +198,259 @@ impl MutationRoot {
         wf_engine.start_health_check().await?;
         Ok("ok")
     }
+
+    /// Create an empty document owned by the caller
+    async fn create_document(&self, ctx: &Context<'_>, title: String) -> Result<Document> {
+        let pool = ctx.data::<PgPool>()?;
+        let title = validate_title(&title).map_err(|e| async_graphql::Error::new(e.to_string()))?;
+        let created_by = ctx.data_opt::<Uuid>().copied();
+        Ok(documents::create_document(pool, title, created_by)
+            .await?
+            .into())
+    }
+
+    /// `false` when there was no such document
+    async fn delete_document(&self, ctx: &Context<'_>, id: Uuid) -> Result<bool> {
+        let pool = ctx.data::<PgPool>()?;
+        match documents::delete_document(pool, id).await {
+            Ok(()) => Ok(true),
+            Err(sqlx::Error::RowNotFound) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Rewrite `text` the way `POST /refine` does and return the result
+    async fn refine_text(
+        &self,
+        ctx: &Context<'_>,
+        text: String,
+        action: RefineAction,
+        tone: Option<String>,
+    ) -> Result<String> {
+        // The REST endpoints' budget: the client IP's bucket, and the subject's when signed in
+        let limits = ctx.data::<Arc<AiRateLimits>>()?;
+        let ip = *ctx.data::<IpAddr>()?;
+        limits
+            .admit(ip, ctx.data_opt::<Uuid>().copied(), Instant::now())
+            .map_err(|wait| client_error(Error::RateLimited(retry_after_secs(wait))))?;
+
+        let config = ctx.data::<RefineConfig>()?;
+        validate_text(&text, config.max_text_chars).map_err(client_error)?;
+        let input = RefineInput {
+            content: text,
+            language: None,
+            tone,
+            audience: None,
+        };
+        let output = refine_call(action)(input, config.api_key.clone())
+            .await
+            .map_err(|e| {
+                tracing::error!("Refine failed: {:?}", e);
+                client_error(e.into())
+            })?;
+        Ok(output.content)
+    }
+}
+
+#[derive(Default)]
+pub struct SubscriptionRoot;
+
+#[Subscription]
+impl SubscriptionRoot {
+    /// AI status, result and lint report events on a document, as the editor websocket sends them
+    async fn ai_events(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default_with = "SHARED_DOC.to_string()")] doc_id: String,
+    ) -> Result<BoxStream<'static, AiStatusEvent>> {
+        if doc_id != SHARED_DOC {
+            return Err(async_graphql::Error::new(format!(
+                "unknown live document: {doc_id}"
+            )));
+        }
+        let rx = ctx
+            .data::<broadcast::Sender<MessageStructure>>()?
+            .subscribe();
+        Ok(ai_event_stream(rx).boxed())
+    }
+}
+
+/// One message of the AI lane; fields an event type doesn't carry are `null`
+#[derive(Debug, Clone, PartialEq, Deserialize, SimpleObject)]
+pub struct AiStatusEvent {
+    #[serde(rename = "type")]
+    #[graphql(name = "type")]
+    pub kind: String,
+    pub status: Option<String>,
+    pub message: Option<String>,
+    pub request_id: Option<Uuid>,
+}
+
+/// The AI lane of the editor broadcast; Yjs updates and messages that aren't
+/// events are skipped, and a lagging subscriber just misses what it dropped
+fn ai_event_stream(
+    rx: broadcast::Receiver<MessageStructure>,
+) -> impl Stream<Item = AiStatusEvent> + Send {
+    stream::unfold(rx, |mut rx| async move {
+        loop {
+            match rx.recv().await {
+                Ok(MessageStructure::AiCommand(json)) => {
+                    if let Ok(event) = serde_json::from_str(&json) {
+                        return Some((event, rx));
+                    }
+                }
+                Ok(MessageStructure::YjsUpdate { .. } | MessageStructure::Awareness { .. }) => {}
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!("📡 AI event subscriber lagged, skipped {}", skipped);
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::rate_limit::RateLimiter;
+    use crate::api::state::AiEvent;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_subscription_receives_ai_events() {
+        let (tx, _) = broadcast::channel(16);
+        let schema = schema().data(tx.clone()).finish();
+        let stream = schema.execute_stream(
+            "subscription { aiEvents(docId: \"shared\") { type status message requestId } }",
+        );
+        let events = tokio::spawn(stream.take(2).collect::<Vec<_>>());
+        // The resolver subscribes once the stream is first polled
+        while tx.receiver_count() == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        let request_id = Uuid::new_v4();
+        tx.send(MessageStructure::YjsUpdate {
+            data: vec![0, 0],
+            origin: None,
+            is_ai: false,
+        })
+        .unwrap();
+        tx.send(
+            AiEvent::Thinking {
+                request_id,
+                message: "Polishing your text...".into(),
+            }
+            .into_message(),
+        )
+        .unwrap();
+        tx.send(
+            AiEvent::LintReport {
+                corrections: Vec::new(),
+            }
+            .into_message(),
+        )
+        .unwrap();
+
+        let events = tokio::time::timeout(Duration::from_secs(1), events)
+            .await
+            .expect("events arrive")
+            .unwrap();
+        let data: Vec<_> = events
+            .into_iter()
+            .map(|response| response.data.into_json().unwrap()["aiEvents"].clone())
+            .collect();
+        assert_eq!(
+            data[0],
+            serde_json::json!({
+                "type": "AI_STATUS",
+                "status": "thinking",
+                "message": "Polishing your text...",
+                "requestId": request_id.to_string(),
+            })
+        );
+        assert_eq!(data[1]["type"], "AI_LINT_REPORT");
+        assert!(data[1]["requestId"].is_null());
+    }
+
+    /// AI budgets of `per_minute` calls, 0 for unlimited
+    fn rate_limits(per_minute: u32) -> Arc<AiRateLimits> {
+        Arc::new(AiRateLimits {
+            by_ip: RateLimiter::new(per_minute),
+            by_subject: RateLimiter::new(per_minute),
+            ws_commands: RateLimiter::new(per_minute),
+        })
+    }
+
+    fn client_ip() -> IpAddr {
+        "10.0.0.1".parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_refine_text_rejects_bad_input_before_calling_openai() {
+        let schema = schema()
+            .data(rate_limits(0))
+            .data(client_ip())
+            .data(RefineConfig {
+                api_key: "test-key".into(),
+                max_text_chars: 10,
+            })
+            .finish();
+
+        let response = schema
+            .execute("mutation { refineText(text: \"   \", action: IMPROVE) }")
+            .await;
+        assert_eq!(response.errors.len(), 1);
+        assert_eq!(response.errors[0].message, "text must not be empty");
+        let extensions = serde_json::to_value(&response.errors[0].extensions).unwrap();
+        assert_eq!(extensions["code"], "VALIDATION");
+
+        let response = schema
+            .execute(
+                "mutation { refineText(text: \"far too long here\", action: FIX, tone: \"formal\") }",
+            )
+            .await;
+        assert!(response.errors[0].message.contains("the limit is 10"));
+
+        let response = schema
+            .execute("mutation { refineText(text: \"hi\", action: LOUDER) }")
+            .await;
+        assert!(!response.errors.is_empty(), "unknown action");
+        assert_eq!(response.data, async_graphql::Value::Null);
+    }
+
+    #[tokio::test]
+    async fn test_refine_text_is_rate_limited() {
+        let schema = schema()
+            .data(rate_limits(1))
+            .data(client_ip())
+            .data(RefineConfig {
+                api_key: "test-key".into(),
+                max_text_chars: 10,
+            })
+            .finish();
+        let mutation = "mutation { refineText(text: \"   \", action: IMPROVE) }";
+
+        // The first call takes the only token, then fails validation
+        let response = schema.execute(mutation).await;
+        assert_eq!(response.errors[0].message, "text must not be empty");
+
+        let response = schema.execute(mutation).await;
+        assert_eq!(response.errors.len(), 1);
+        assert!(response.errors[0].message.starts_with("Too many requests"));
+        let extensions = serde_json::to_value(&response.errors[0].extensions).unwrap();
+        assert_eq!(extensions["code"], "RATE_LIMITED");
+    }
+
+    #[tokio::test]
+    async fn test_subscription_rejects_other_documents() {
+        let (tx, _) = broadcast::channel::<MessageStructure>(16);
+        let schema = schema().data(tx).finish();
+        let response = schema
+            .execute_stream("subscription { aiEvents(docId: \"draft\") { type } }")
+            .next()
+            .await
+            .unwrap();
+        assert!(!response.errors.is_empty());
+    }
 }
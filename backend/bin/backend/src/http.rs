@@ -1,19 +1,28 @@
-use crate::{api, opts::*};
+use crate::{
+    api,
+    linter_task::{self, AutoAgentContext, AutoLinterHandle},
+    opts::*,
+    shutdown::ShutdownTrigger,
+};
 
+use std::time::Instant;
 use std::{sync::Arc, time::Duration};
 
-use crate::api::state::MessageStructure;
+use crate::api::rate_limit::AiRateLimits;
+use crate::api::state::{AutoAgentToggles, MessageStructure};
 use atb_cli_utils::AtbCli;
-use atb_tokio_ext::shutdown_signal;
 use backend_core::{editor, sqlx_postgres, temporal};
 use sqlx::PgPool;
 use tokio::net::TcpListener;
+use tokio::sync::watch;
+
 pub async fn run(
     db_opts: DatabaseOpts,
     http_opts: HttpOpts,
     temporal_opts: TemporalOpts,
     opts: Opts,
 ) -> anyhow::Result<()> {
+    opts.configure_search();
     let client_id = crate::Cli::client_id();
     let pg_pool = sqlx_postgres::connect_pg(&db_opts.postgres, 30, Some(&client_id)).await?;
     let client = temporal::try_connect_temporal(
@@ -23,28 +32,60 @@ pub async fn run(
     )
     .await?;
 
-    // Create minimal editor state for Http mode (not used, but required by AppState)
+    // Editor state for Http mode; same document lanes and auto-agents as mono, without the worker
     let doc = std::sync::Arc::new(yrs::Doc::new());
-    let (broadcast_tx, _) = tokio::sync::broadcast::channel(100);
+    let (broadcast_tx, _) = tokio::sync::broadcast::channel(http_opts.ws.ws_broadcast_capacity);
+    let user_writing_state = Arc::new(opts.editor.user_writing_state());
+    let (notify_tx, notify_rx) = watch::channel(Instant::now());
 
     // Setup Observer: When Yrs changes, broadcast the delta
     let tx_clone = broadcast_tx.clone();
-    let _sub = doc.observe_update_v1(move |_txn, update_event| {
-        let update = update_event.update.to_vec();
-        let _ = tx_clone.send(MessageStructure::YjsUpdate(update));
+    let recorder = http_opts.update_recorder()?;
+    let _sub = doc.observe_update_v1(move |txn, update_event| {
+        let _ = tx_clone.send(MessageStructure::from_update(txn, &update_event.update));
+        let _ = notify_tx.send(Instant::now());
+        if let Some(recorder) = &recorder {
+            if let Err(e) = recorder.record(&update_event.update) {
+                tracing::warn!("❌ failed to record update: {e:?}");
+            }
+        }
     });
 
-    start_http(
+    let auto_agents = AutoAgentToggles::new();
+    let ctx = AutoAgentContext {
+        doc: doc.clone(),
+        api_key: opts.openai_api_key.clone(),
+        broadcast_tx: broadcast_tx.clone(),
+        user_state: user_writing_state.clone(),
+        toggles: auto_agents.clone(),
+        debounce: opts.editor.debounce(),
+        lint: linter_task::recorded(
+            pg_pool.clone(),
+            linter_task::temporal_lint(
+                temporal::WorkflowEngine::new(client.clone(), temporal_opts.task_queue.clone()),
+                opts.editor.lint_mode(),
+            ),
+        ),
+    };
+    let auto_linter = linter_task::spawn(ctx, notify_rx);
+
+    let served = start_http(
         pg_pool,
         client,
         http_opts,
         temporal_opts.task_queue,
         opts.openai_api_key,
+        opts.editor,
         doc,
         broadcast_tx,
-        None, // user_writing_state: None for http mode
+        Some(user_writing_state),
+        auto_agents,
+        auto_linter.clone(),
+        ShutdownTrigger::new(),
     )
-    .await
+    .await;
+    auto_linter.shutdown().await;
+    served
 }
 
 pub async fn start_http(
@@ -53,16 +94,40 @@ pub async fn start_http(
     http_opts: HttpOpts,
     task_queue: String,
     api_key: String,
+    editor_opts: EditorOpts,
     editor_doc: std::sync::Arc<yrs::Doc>,
     editor_broadcast_tx: tokio::sync::broadcast::Sender<MessageStructure>,
     user_writing_state: Option<Arc<editor::UserWritingState>>,
+    auto_agents: AutoAgentToggles,
+    auto_linter: AutoLinterHandle,
+    shutdown: ShutdownTrigger,
 ) -> anyhow::Result<()> {
     let wf_engine = temporal::WorkflowEngine::new(client, task_queue);
+    let api_key: Arc<str> = api_key.into();
+    let rate_limits = Arc::new(AiRateLimits::new(&http_opts));
     let schema = crate::graphql::schema()
         .data(wf_engine.clone())
         .data(pg_pool.clone())
+        .data(editor_doc.clone())
+        .data(editor_broadcast_tx.clone())
+        .data(rate_limits.clone())
+        .data(crate::graphql::RefineConfig {
+            api_key: api_key.clone(),
+            max_text_chars: http_opts.max_text_chars,
+        })
         .finish();
     let (jwt_encoder, jwt_decoder) = http_opts.load_jwt()?;
+
+    // Store the tokens of every AI call this process makes, the worker's too in mono mode
+    sqlx_postgres::ai_usage::store_usage(&pg_pool);
+
+    // Live word count for every client, pushed at most once a second while editing
+    tokio::spawn(api::tools::stats::broadcast_on_change(
+        editor_doc.clone(),
+        editor_broadcast_tx.clone(),
+        api::tools::stats::STATS_INTERVAL,
+    ));
+
     let app_state = api::state::AppState::new(
         schema,
         wf_engine,
@@ -70,10 +135,25 @@ pub async fn start_http(
         jwt_encoder,
         jwt_decoder,
         api_key,
+        editor_opts,
         editor_doc,
         editor_broadcast_tx,
         user_writing_state,
+        http_opts.ws.clone(),
+        auto_agents,
+        auto_linter,
+        rate_limits,
+        Arc::new(http_opts.clone()),
+        shutdown.clone(),
     );
+    tokio::spawn(api::editor::track_doc_size(
+        app_state.editor_doc.clone(),
+        app_state.editor_broadcast_tx.clone(),
+        app_state.doc_size.clone(),
+        api::editor::DOC_SIZE_RECALIBRATION,
+    ));
+    // Compose workflows a previous run started still write into the document
+    api::tools::composer::resume_pending(app_state.clone()).await;
 
     tracing::info!("http listening on {}", http_opts.host);
     let app = api::build_app(&http_opts, app_state)?;
@@ -82,7 +162,7 @@ pub async fn start_http(
         listener,
         app.into_make_service_with_connect_info::<std::net::SocketAddr>(),
     )
-    .with_graceful_shutdown(shutdown_signal())
+    .with_graceful_shutdown(shutdown.wait())
     .await?;
 
     Ok(())
@@ -0,0 +1,861 @@
+use crate::api::state::{AiEvent, AutoAgentToggles, MessageStructure};
+use backend_core::{
+    editor,
+    llm::tools::{
+        linter::{self, DocumentChanged, LintCorrection, LintMode},
+        readability::document_readability,
+    },
+    sqlx_postgres::ai_events::{self, AiEventStatus, NewAiEvent},
+    temporal::{WorkflowEngine, lint::LintInput},
+};
+use futures::future::BoxFuture;
+use sqlx::PgPool;
+use std::time::Instant;
+use std::{sync::Arc, time::Duration};
+use tokio::sync::broadcast;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use yrs::Doc;
+
+/// Lint step of the auto-agent loop, injectable so the loop can be tested without OpenAI
+/// The second argument is the focused paragraph, when focus mode is on
+pub type LintFn = Arc<
+    dyn Fn(Arc<Doc>, Option<u32>) -> BoxFuture<'static, anyhow::Result<Vec<LintCorrection>>>
+        + Send
+        + Sync,
+>;
+
+/// Everything the debounced auto-agent loop needs
+pub struct AutoAgentContext {
+    pub doc: Arc<Doc>,
+    pub api_key: String,
+    pub broadcast_tx: broadcast::Sender<MessageStructure>,
+    pub user_state: Arc<editor::UserWritingState>,
+    pub toggles: AutoAgentToggles,
+    pub debounce: Duration,
+    pub lint: LintFn,
+}
+
+/// Mono's lint step: one OpenAI linter call per pass, in process, since the
+/// worker sits next to the document anyway and a workflow would only add a round trip
+pub fn openai_lint(api_key: String, mode: LintMode) -> LintFn {
+    Arc::new(move |doc, focus| {
+        let api_key = api_key.clone();
+        Box::pin(
+            async move { backend_core::llm::new_linter(&api_key, doc, focus, None, mode).await },
+        )
+    })
+}
+
+/// HTTP mode's lint step: one lint workflow per pass, so a worker makes the OpenAI
+/// call under Temporal's retry policy; the corrections are applied here, where the
+/// live document is
+pub fn temporal_lint(wf_engine: WorkflowEngine, mode: LintMode) -> LintFn {
+    Arc::new(move |doc, focus| {
+        let wf_engine = wf_engine.clone();
+        Box::pin(async move {
+            let scope = linter::lint_scope(&doc, focus, mode)?;
+            let input = LintInput {
+                xml: scope.xml.clone(),
+                language: None,
+                mode,
+            };
+            let linted = wf_engine.lint(&input).await?;
+            linter::apply_lint(&doc, &scope, &linted)
+        })
+    })
+}
+
+/// `lint` with every pass added to the suggestion history, like the commands
+/// writers run themselves; a pass cut short by an edit is recorded as cancelled
+pub fn recorded(pool: PgPool, lint: LintFn) -> LintFn {
+    Arc::new(move |doc, focus| {
+        let (pool, lint) = (pool.clone(), lint.clone());
+        Box::pin(async move {
+            let started = Instant::now();
+            let input = editor::get_doc_content(&doc);
+            let result = lint(doc, focus).await;
+            let (output, status) = match &result {
+                Ok(corrections) => (corrections_preview(corrections), AiEventStatus::Ok),
+                Err(e) if e.is::<DocumentChanged>() => (String::new(), AiEventStatus::Cancelled),
+                Err(_) => (String::new(), AiEventStatus::Error),
+            };
+            let event = NewAiEvent::new("linter", &input, &output, status, started.elapsed());
+            ai_events::spawn_record(&pool, event);
+            result
+        })
+    })
+}
+
+/// The corrections as the history shows them, e.g. `teh → the; recieve → receive`
+pub fn corrections_preview(corrections: &[LintCorrection]) -> String {
+    corrections
+        .iter()
+        .map(|c| format!("{} → {}", c.original, c.corrected))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// Control over a running auto-agent loop.
+///
+/// Clones share the same loop, so the HTTP state can pause it while the caller
+/// that spawned it keeps the handle for shutdown. Dropping every clone stops the loop.
+#[derive(Clone)]
+pub struct AutoLinterHandle {
+    paused: watch::Sender<bool>,
+    stop: watch::Sender<bool>,
+    task: Arc<tokio::sync::Mutex<Option<JoinHandle<()>>>>,
+}
+
+impl AutoLinterHandle {
+    /// Hold every auto-agent until `resume`; a pass already running finishes first
+    pub fn pause(&self) {
+        self.paused.send_replace(true);
+    }
+
+    pub fn resume(&self) {
+        self.paused.send_replace(false);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.paused.borrow()
+    }
+
+    /// Stop the loop and wait for it to exit.
+    ///
+    /// A pass in flight is dropped at its next await point, which cancels the
+    /// pending OpenAI request instead of letting it finish after the server is gone.
+    pub async fn shutdown(&self) {
+        self.stop.send_replace(true);
+        if let Some(task) = self.task.lock().await.take() {
+            if let Err(e) = task.await {
+                tracing::error!("❌ Auto-linter task failed: {:?}", e);
+            }
+        }
+    }
+}
+
+/// Start the auto-agent loop on its own task
+pub fn spawn(ctx: AutoAgentContext, notify_rx: watch::Receiver<Instant>) -> AutoLinterHandle {
+    let paused = watch::Sender::new(false);
+    let stop = watch::Sender::new(false);
+    let loop_paused = paused.subscribe();
+    let mut stop_rx = stop.subscribe();
+    let task = tokio::spawn(async move {
+        tokio::select! {
+            _ = auto_agent_loop(ctx, notify_rx, loop_paused) => {}
+            _ = stop_rx.wait_for(|stop| *stop) => {
+                tracing::info!("🛑 Auto-linter stopping");
+            }
+        }
+    });
+    AutoLinterHandle {
+        paused,
+        stop,
+        task: Arc::new(tokio::sync::Mutex::new(Some(task))),
+    }
+}
+
+/// Debounced loop running the enabled auto-agents after the document settles.
+///
+/// Toggles are read on every pass, so enabling or disabling an agent at runtime
+/// takes effect on the next debounce.
+async fn auto_agent_loop(
+    ctx: AutoAgentContext,
+    mut notify_rx: watch::Receiver<Instant>,
+    mut paused: watch::Receiver<bool>,
+) {
+    tracing::info!(
+        "🚀 Smart Auto-linter started (Debounce: {}s)",
+        ctx.debounce.as_secs()
+    );
+    let mut before_content = "".to_string();
+    // Set when a pass was cut short; the next one starts without waiting for another edit
+    let mut rearm = false;
+    // 核心邏輯：等待變動 -> 觸發冷卻 -> 執行
+    loop {
+        if !std::mem::take(&mut rearm) && notify_rx.changed().await.is_err() {
+            tracing::error!("🔍 Notify RX changed error");
+            break;
+        }
+
+        if !wait_for_quiet(&mut notify_rx, ctx.debounce, &ctx.user_state).await {
+            break;
+        }
+
+        if *paused.borrow() {
+            tracing::info!("⏸️ Auto-linter paused, waiting for resume");
+            if paused.wait_for(|paused| !*paused).await.is_err() {
+                break;
+            }
+            // Whatever was written while paused gets checked once it settles
+            rearm = true;
+            continue;
+        }
+
+        let linter_enabled = *ctx.toggles.linter.borrow();
+        let emoji_replacer_enabled = *ctx.toggles.emoji_replacer.borrow();
+        let backseater_enabled = *ctx.toggles.backseater.borrow();
+
+        let current_content = editor::get_doc_content(&ctx.doc);
+        if current_content.is_empty() || current_content == before_content {
+            tracing::info!("🔍 Doc is empty or not changed, skipping checks");
+            continue;
+        }
+
+        if linter_enabled {
+            // Another pass (e.g. `POST /linter`) is still rewriting the fragment
+            let Ok(_lint_guard) = ctx.toggles.lint_running.clone().try_lock_owned() else {
+                tracing::info!("⏳ Linter already running, re-arming debounce");
+                rearm = true;
+                continue;
+            };
+            tracing::info!("🤖 Calling AI Linter...");
+            metrics::counter!("linter_runs_total").increment(1);
+            let focused = ctx.toggles.focused_paragraphs();
+            match lint_focused(&ctx, focused).await {
+                Ok(corrections) => {
+                    tracing::info!("✅ AI check successful, {} corrections", corrections.len());
+                    // Only report when something changed; an empty changelog tells the writer nothing
+                    if !corrections.is_empty() {
+                        let report = AiEvent::LintReport { corrections };
+                        let _ = ctx.broadcast_tx.send(report.into_message());
+                    }
+                    let readability = document_readability(&ctx.doc);
+                    let _ = ctx
+                        .broadcast_tx
+                        .send(AiEvent::Readability { readability }.into_message());
+                }
+                Err(e) if e.is::<DocumentChanged>() => {
+                    // The stale result was discarded; lint the new content once it settles
+                    tracing::info!("✏️ Document changed during lint, re-arming debounce");
+                    rearm = true;
+                    continue;
+                }
+                Err(e) => tracing::error!("❌ AI check failed: {:?}", e),
+            }
+        }
+
+        if emoji_replacer_enabled {
+            tracing::info!("🤖 Calling AI Emoji Replacer...");
+            match backend_core::llm::new_emoji_replacer(&ctx.api_key, &ctx.doc).await {
+                Ok(_) => {
+                    tracing::info!("✅ AI emoji replacer successful");
+                }
+                Err(e) => tracing::error!("❌ AI emoji replacer failed: {:?}", e),
+            }
+        }
+
+        if backseater_enabled {
+            tracing::info!("💬 Calling AI Backseater...");
+            match backend_core::llm::new_backseating_agent(&ctx.api_key, &ctx.doc).await {
+                Ok(comments) => {
+                    if !comments.is_empty() {
+                        tracing::info!("✅ Generated {} comments from backseater", comments.len());
+                        // Send each comment to frontend via broadcast channel
+                        for comment in comments {
+                            let comment_json = serde_json::json!({
+                                "type": "COMMENT",
+                                "comment_on": comment.comment_on,
+                                "comment": comment.comment,
+                                "color_hex": comment.color_hex
+                            });
+                            if let Err(e) = ctx
+                                .broadcast_tx
+                                .send(MessageStructure::AiCommand(comment_json.to_string()))
+                            {
+                                tracing::warn!("Failed to broadcast backseater comment: {:?}", e);
+                            }
+                        }
+                    } else {
+                        tracing::info!("⚠️ No comments generated by backseater");
+                    }
+                }
+                Err(e) => tracing::error!("❌ AI backseater failed: {:?}", e),
+            }
+        }
+
+        // Update before_content AFTER all tools have run (or been skipped)
+        before_content = editor::get_doc_content(&ctx.doc);
+    }
+    tracing::info!("🔌 Linter task exiting");
+}
+
+/// One lint call per paragraph a connection is focused on, or a single call over the
+/// whole document when nobody is in focus mode
+async fn lint_focused(
+    ctx: &AutoAgentContext,
+    focused: Vec<u32>,
+) -> anyhow::Result<Vec<LintCorrection>> {
+    if focused.is_empty() {
+        return (ctx.lint)(ctx.doc.clone(), None).await;
+    }
+    let mut corrections = Vec::new();
+    for paragraph in focused {
+        corrections.extend((ctx.lint)(ctx.doc.clone(), Some(paragraph)).await?);
+    }
+    Ok(corrections)
+}
+
+/// Wait until the document has been quiet for `debounce` and neither the user nor an AI
+/// stream is writing.
+///
+/// A pause mid-sentence can outlast the debounce while the user's edits are still
+/// in flight, so the timer is re-armed whenever `is_user_writing()` is set when it fires.
+/// The same goes for a composer pausing between streamed words (`is_ai_writing()`).
+/// Returns `false` once the notify channel is closed.
+async fn wait_for_quiet(
+    notify_rx: &mut watch::Receiver<Instant>,
+    debounce: Duration,
+    user_state: &editor::UserWritingState,
+) -> bool {
+    loop {
+        let delay = tokio::time::sleep(debounce);
+        tokio::pin!(delay);
+
+        tokio::select! {
+            changed = notify_rx.changed() => {
+                if changed.is_err() { return false; }
+                tracing::debug!("⌨️ User still typing, skipping checks");
+                continue;
+            }
+            _ = &mut delay => {
+                if user_state.is_user_writing() {
+                    tracing::debug!("⌨️ User is writing, re-arming linter debounce");
+                    continue;
+                }
+                if user_state.is_ai_writing() {
+                    tracing::debug!("🤖 AI is still appending, re-arming linter debounce");
+                    continue;
+                }
+                return true;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::state::ConnId;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use yrs::{Transact, XmlFragment, XmlTextPrelim};
+
+    fn counting_lint(calls: Arc<AtomicUsize>) -> LintFn {
+        Arc::new(move |_doc, _focus| {
+            let calls = calls.clone();
+            Box::pin(async move {
+                calls.fetch_add(1, Ordering::SeqCst);
+                Ok(Vec::new())
+            })
+        })
+    }
+
+    fn type_into(doc: &Doc, notify_tx: &watch::Sender<Instant>, text: &str) {
+        let fragment = doc.get_or_insert_xml_fragment("content");
+        {
+            let mut txn = doc.transact_mut();
+            fragment.insert(&mut txn, 0, XmlTextPrelim::new(text));
+        }
+        notify_tx.send(Instant::now()).unwrap();
+    }
+
+    /// Move paused time forward by `by` a millisecond at a time, letting the loop
+    /// react to each step as it would in real time
+    async fn advance(by: Duration) {
+        for _ in 0..by.as_millis() {
+            tokio::time::advance(Duration::from_millis(1)).await;
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[test]
+    fn test_history_shows_each_correction() {
+        let correction = |original: &str, corrected: &str| LintCorrection {
+            paragraph: 0,
+            original: original.to_string(),
+            corrected: corrected.to_string(),
+        };
+        let corrections = [correction("teh", "the"), correction("recieve", "receive")];
+        assert_eq!(
+            corrections_preview(&corrections),
+            "teh → the; recieve → receive"
+        );
+        assert_eq!(corrections_preview(&[]), "");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_disabled_linter_is_not_called() {
+        let doc = Arc::new(Doc::new());
+        let (broadcast_tx, _) = broadcast::channel(16);
+        let (notify_tx, notify_rx) = watch::channel(Instant::now());
+        let toggles = AutoAgentToggles::new();
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let ctx = AutoAgentContext {
+            doc: doc.clone(),
+            api_key: String::new(),
+            broadcast_tx,
+            user_state: Arc::new(editor::UserWritingState::new(2000)),
+            toggles: toggles.clone(),
+            debounce: Duration::from_millis(20),
+            lint: counting_lint(calls.clone()),
+        };
+        let task = spawn(ctx, notify_rx);
+
+        // Enabled: a change past the debounce triggers exactly one lint
+        assert!(toggles.toggle_linter());
+        type_into(&doc, &notify_tx, "first draft");
+        advance(Duration::from_millis(100)).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // Disabled at runtime: the running loop must stop linting
+        assert!(!toggles.toggle_linter());
+        type_into(&doc, &notify_tx, "second draft ");
+        advance(Duration::from_millis(100)).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        task.shutdown().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_focus_mode_scopes_the_linter() {
+        let doc = Arc::new(Doc::new());
+        let (broadcast_tx, _) = broadcast::channel(16);
+        let (notify_tx, notify_rx) = watch::channel(Instant::now());
+        let toggles = AutoAgentToggles::new();
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+        let recorded = seen.clone();
+        let lint: LintFn = Arc::new(move |_doc, focus| {
+            recorded.lock().unwrap().push(focus);
+            Box::pin(async { Ok(Vec::new()) })
+        });
+        let ctx = AutoAgentContext {
+            doc: doc.clone(),
+            api_key: String::new(),
+            broadcast_tx,
+            user_state: Arc::new(editor::UserWritingState::new(2000)),
+            toggles: toggles.clone(),
+            debounce: Duration::from_millis(20),
+            lint,
+        };
+        let task = spawn(ctx, notify_rx);
+
+        let (alice, bob) = (ConnId::next(), ConnId::next());
+        toggles.toggle_linter();
+        toggles.set_focus(alice, Some(2));
+        type_into(&doc, &notify_tx, "focused ");
+        advance(Duration::from_millis(100)).await;
+        assert_eq!(*seen.lock().unwrap(), vec![Some(2)]);
+
+        // A second writer's focus adds to the first one's rather than replacing it
+        toggles.set_focus(bob, Some(0));
+        type_into(&doc, &notify_tx, "both ");
+        advance(Duration::from_millis(100)).await;
+        assert_eq!(seen.lock().unwrap()[1..], [Some(0), Some(2)]);
+
+        // Leaving focus mode only drops that writer's paragraph
+        toggles.set_focus(alice, None);
+        type_into(&doc, &notify_tx, "bob only ");
+        advance(Duration::from_millis(100)).await;
+        assert_eq!(seen.lock().unwrap()[3..], [Some(0)]);
+
+        toggles.set_focus(bob, None);
+        type_into(&doc, &notify_tx, "unfocused ");
+        advance(Duration::from_millis(100)).await;
+        assert_eq!(seen.lock().unwrap()[4..], [None]);
+        task.shutdown().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_lint_corrections_are_broadcast_as_a_report() {
+        let doc = Arc::new(Doc::new());
+        let (broadcast_tx, mut broadcast_rx) = broadcast::channel(16);
+        let (notify_tx, notify_rx) = watch::channel(Instant::now());
+        let toggles = AutoAgentToggles::new();
+
+        let lint: LintFn = Arc::new(|_doc, _focus| {
+            Box::pin(async {
+                Ok(vec![LintCorrection {
+                    paragraph: 0,
+                    original: "teh".to_string(),
+                    corrected: "the".to_string(),
+                }])
+            })
+        });
+        let ctx = AutoAgentContext {
+            doc: doc.clone(),
+            api_key: String::new(),
+            broadcast_tx,
+            user_state: Arc::new(editor::UserWritingState::new(2000)),
+            toggles: toggles.clone(),
+            debounce: Duration::from_millis(20),
+            lint,
+        };
+        let task = spawn(ctx, notify_rx);
+
+        toggles.toggle_linter();
+        type_into(&doc, &notify_tx, "teh draft");
+        let message = tokio::time::timeout(Duration::from_secs(1), broadcast_rx.recv())
+            .await
+            .expect("lint report is broadcast")
+            .unwrap();
+        let MessageStructure::AiCommand(json) = message else {
+            panic!("expected an AI message, got {message:?}");
+        };
+        let report: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(report["type"], "AI_LINT_REPORT");
+        assert_eq!(report["corrections"][0]["corrected"], "the");
+
+        // Readability follows each pass, scored on the text as it now stands
+        let MessageStructure::AiCommand(json) = broadcast_rx.recv().await.unwrap() else {
+            panic!("expected readability scores");
+        };
+        let scores: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(scores["type"], "READABILITY");
+        assert_eq!(scores["readability"]["sentences"], 1);
+        assert_eq!(scores["readability"]["avg_sentence_length"], 2.0);
+
+        task.shutdown().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_lint_is_skipped_while_another_pass_runs() {
+        let doc = Arc::new(Doc::new());
+        let (broadcast_tx, _) = broadcast::channel(16);
+        let (notify_tx, notify_rx) = watch::channel(Instant::now());
+        let toggles = AutoAgentToggles::new();
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let ctx = AutoAgentContext {
+            doc: doc.clone(),
+            api_key: String::new(),
+            broadcast_tx,
+            user_state: Arc::new(editor::UserWritingState::new(2000)),
+            toggles: toggles.clone(),
+            debounce: Duration::from_millis(20),
+            lint: counting_lint(calls.clone()),
+        };
+        let task = spawn(ctx, notify_rx);
+
+        // A REST lint is in flight
+        let running = toggles.lint_running.clone().try_lock_owned().unwrap();
+        toggles.toggle_linter();
+        type_into(&doc, &notify_tx, "draft");
+        advance(Duration::from_millis(100)).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+        // Once it finishes, the re-armed pass runs without another edit
+        drop(running);
+        advance(Duration::from_millis(100)).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        task.shutdown().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stale_lint_result_is_rescheduled() {
+        let doc = Arc::new(Doc::new());
+        let (broadcast_tx, mut broadcast_rx) = broadcast::channel(16);
+        let (notify_tx, notify_rx) = watch::channel(Instant::now());
+        let toggles = AutoAgentToggles::new();
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        // A slow lint whose first result goes stale because the writer kept typing
+        let counter = calls.clone();
+        let lint: LintFn = Arc::new(move |_doc, _focus| {
+            let call = counter.fetch_add(1, Ordering::SeqCst);
+            Box::pin(async move {
+                tokio::time::sleep(Duration::from_millis(30)).await;
+                if call == 0 {
+                    return Err(DocumentChanged.into());
+                }
+                Ok(vec![LintCorrection {
+                    paragraph: 0,
+                    original: "teh".to_string(),
+                    corrected: "the".to_string(),
+                }])
+            })
+        });
+        let ctx = AutoAgentContext {
+            doc: doc.clone(),
+            api_key: String::new(),
+            broadcast_tx,
+            user_state: Arc::new(editor::UserWritingState::new(2000)),
+            toggles: toggles.clone(),
+            debounce: Duration::from_millis(20),
+            lint,
+        };
+        let task = spawn(ctx, notify_rx);
+
+        toggles.toggle_linter();
+        type_into(&doc, &notify_tx, "teh draft");
+        advance(Duration::from_millis(250)).await;
+
+        // The discarded run reported nothing; the rescheduled one did
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        let MessageStructure::AiCommand(json) = broadcast_rx.try_recv().unwrap() else {
+            panic!("expected a lint report");
+        };
+        assert!(json.contains("AI_LINT_REPORT"));
+        let MessageStructure::AiCommand(json) = broadcast_rx.try_recv().unwrap() else {
+            panic!("expected readability scores");
+        };
+        assert!(json.contains("READABILITY"));
+        assert!(broadcast_rx.try_recv().is_err());
+
+        task.shutdown().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_wait_for_quiet_rearms_while_user_writing() {
+        let (_notify_tx, mut notify_rx) = watch::channel(Instant::now());
+        let user_state = Arc::new(editor::UserWritingState::new(2000));
+        user_state.mark_user_writing();
+
+        let state = user_state.clone();
+        let wait = tokio::spawn(async move {
+            wait_for_quiet(&mut notify_rx, Duration::from_millis(20), &state).await
+        });
+
+        // Several debounce windows elapse while the user is still typing
+        advance(Duration::from_millis(100)).await;
+        assert!(!wait.is_finished());
+
+        user_state.clear_user_writing();
+        let quiet = tokio::time::timeout(Duration::from_secs(1), wait)
+            .await
+            .expect("debounce completes once the user stops typing")
+            .unwrap();
+        assert!(quiet);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_linter_skips_when_user_types_right_before_expiry() {
+        let doc = Arc::new(Doc::new());
+        let (broadcast_tx, _) = broadcast::channel(16);
+        let (notify_tx, notify_rx) = watch::channel(Instant::now());
+        let toggles = AutoAgentToggles::new();
+        let user_state = Arc::new(editor::UserWritingState::new(2000));
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let ctx = AutoAgentContext {
+            doc: doc.clone(),
+            api_key: String::new(),
+            broadcast_tx,
+            user_state: user_state.clone(),
+            toggles: toggles.clone(),
+            debounce: Duration::from_millis(50),
+            lint: counting_lint(calls.clone()),
+        };
+        let task = spawn(ctx, notify_rx);
+
+        toggles.toggle_linter();
+        type_into(&doc, &notify_tx, "half a sent");
+        // The user's next keystrokes are marked just before the debounce fires
+        advance(Duration::from_millis(40)).await;
+        user_state.mark_user_writing();
+        advance(Duration::from_millis(150)).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+        user_state.clear_user_writing();
+        advance(Duration::from_millis(150)).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        task.shutdown().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_wait_for_quiet_rearms_while_ai_is_appending() {
+        let (_notify_tx, mut notify_rx) = watch::channel(Instant::now());
+        let user_state = Arc::new(editor::UserWritingState::new(2000));
+        let ai_writing = user_state.start_ai_writing();
+
+        let state = user_state.clone();
+        let wait = tokio::spawn(async move {
+            wait_for_quiet(&mut notify_rx, Duration::from_millis(20), &state).await
+        });
+        advance(Duration::from_millis(100)).await;
+        assert!(!wait.is_finished());
+
+        drop(ai_writing);
+        let quiet = tokio::time::timeout(Duration::from_secs(1), wait)
+            .await
+            .expect("debounce completes once the AI stops appending")
+            .unwrap();
+        assert!(quiet);
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_quiet_stops_when_channel_closes() {
+        let (notify_tx, mut notify_rx) = watch::channel(Instant::now());
+        let user_state = editor::UserWritingState::new(2000);
+        drop(notify_tx);
+
+        assert!(!wait_for_quiet(&mut notify_rx, Duration::from_secs(5), &user_state).await);
+    }
+
+    fn parse_opts(args: &[&str]) -> crate::opts::Opts {
+        use atb_cli_utils::clap::Parser;
+        crate::opts::Opts::try_parse_from(
+            ["backend", "--openai-api-key", "test-key"]
+                .iter()
+                .chain(args),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_editor_opts_keep_the_old_timings_by_default() {
+        let opts = parse_opts(&[]);
+        assert_eq!(opts.editor.debounce(), Duration::from_secs(5));
+        assert_eq!(opts.editor.user_writing_state().writing_timeout_ms, 2000);
+        assert_eq!(opts.editor.ai_word_delay_ms, 100);
+    }
+
+    #[test]
+    fn test_xml_lint_mode_is_opt_in() {
+        assert_eq!(parse_opts(&[]).editor.lint_mode(), LintMode::Structured);
+        assert_eq!(
+            parse_opts(&["--lint-xml-mode"]).editor.lint_mode(),
+            LintMode::Xml
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_editor_opts_reach_the_writing_state_and_lint_loop() {
+        let opts = parse_opts(&[
+            "--debounce-secs",
+            "1",
+            "--writing-timeout-ms",
+            "250",
+            "--ai-word-delay-ms",
+            "5",
+        ]);
+        assert_eq!(opts.editor.ai_word_delay_ms, 5);
+
+        let user_state = Arc::new(opts.editor.user_writing_state());
+        assert_eq!(user_state.writing_timeout_ms, 250);
+
+        let doc = Arc::new(Doc::new());
+        let (broadcast_tx, _) = broadcast::channel(16);
+        let (notify_tx, notify_rx) = watch::channel(Instant::now());
+        let toggles = AutoAgentToggles::new();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let ctx = AutoAgentContext {
+            doc: doc.clone(),
+            api_key: String::new(),
+            broadcast_tx,
+            user_state,
+            toggles: toggles.clone(),
+            debounce: opts.editor.debounce(),
+            lint: counting_lint(calls.clone()),
+        };
+        let task = spawn(ctx, notify_rx);
+
+        toggles.toggle_linter();
+        type_into(&doc, &notify_tx, "first draft");
+        // The configured one-second debounce, not the old five, decides when the lint runs
+        advance(Duration::from_millis(500)).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+        advance(Duration::from_millis(1000)).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        task.shutdown().await;
+    }
+
+    fn context(doc: &Arc<Doc>, toggles: &AutoAgentToggles, lint: LintFn) -> AutoAgentContext {
+        AutoAgentContext {
+            doc: doc.clone(),
+            api_key: String::new(),
+            broadcast_tx: broadcast::channel(16).0,
+            user_state: Arc::new(editor::UserWritingState::new(2000)),
+            toggles: toggles.clone(),
+            debounce: Duration::from_secs(5),
+            lint,
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_lint_runs_once_the_debounce_elapses() {
+        let doc = Arc::new(Doc::new());
+        let (notify_tx, notify_rx) = watch::channel(Instant::now());
+        let toggles = AutoAgentToggles::new();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let linter = spawn(
+            context(&doc, &toggles, counting_lint(calls.clone())),
+            notify_rx,
+        );
+
+        toggles.toggle_linter();
+        type_into(&doc, &notify_tx, "first draft");
+        advance(Duration::from_secs(4)).await;
+        // Another keystroke inside the window pushes the pass back a full debounce
+        notify_tx.send(Instant::now()).unwrap();
+        advance(Duration::from_secs(4)).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+        advance(Duration::from_secs(2)).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        linter.shutdown().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_paused_linter_catches_up_after_resume() {
+        let doc = Arc::new(Doc::new());
+        let (notify_tx, notify_rx) = watch::channel(Instant::now());
+        let toggles = AutoAgentToggles::new();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let linter = spawn(
+            context(&doc, &toggles, counting_lint(calls.clone())),
+            notify_rx,
+        );
+
+        toggles.toggle_linter();
+        linter.pause();
+        assert!(linter.is_paused());
+        type_into(&doc, &notify_tx, "written while paused");
+        advance(Duration::from_secs(30)).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+        // No further edit needed: the paused changes are linted once the debounce passes
+        linter.resume();
+        advance(Duration::from_secs(6)).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        linter.shutdown().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_shutdown_cancels_an_in_flight_lint() {
+        let doc = Arc::new(Doc::new());
+        let (notify_tx, notify_rx) = watch::channel(Instant::now());
+        let toggles = AutoAgentToggles::new();
+        let started = Arc::new(AtomicUsize::new(0));
+        let started_in_lint = started.clone();
+        // Stands in for an OpenAI call that has not answered yet
+        let hanging: LintFn = Arc::new(move |_doc, _focus| {
+            let started = started_in_lint.clone();
+            Box::pin(async move {
+                started.fetch_add(1, Ordering::SeqCst);
+                std::future::pending().await
+            })
+        });
+        let linter = spawn(context(&doc, &toggles, hanging), notify_rx);
+
+        toggles.toggle_linter();
+        type_into(&doc, &notify_tx, "first draft");
+        advance(Duration::from_secs(6)).await;
+        assert_eq!(started.load(Ordering::SeqCst), 1);
+
+        tokio::time::timeout(Duration::from_secs(1), linter.shutdown())
+            .await
+            .expect("shutdown does not wait for the pending call");
+        // The cancelled pass released the lint lock on its way out
+        assert!(toggles.lint_running.try_lock().is_ok());
+        // A second shutdown, e.g. from another clone, returns at once
+        linter.shutdown().await;
+    }
+}
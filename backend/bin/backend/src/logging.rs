@@ -0,0 +1,93 @@
+use atb::logging::init_tracer;
+use tracing::Subscriber;
+use tracing_subscriber::{EnvFilter, fmt::MakeWriter, util::SubscriberInitExt};
+
+/// How log lines are written to stdout
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human-readable lines, for a terminal
+    #[default]
+    Pretty,
+    /// One JSON object per line, with the fields of every enclosing span
+    Json,
+}
+
+/// Install the tracer for `format`, then `run`; the tracer lives until `run` returns
+pub fn with_tracer<R>(format: LogFormat, run: impl FnOnce() -> R) -> R {
+    match format {
+        LogFormat::Pretty => {
+            let _guard = init_tracer(Default::default()).expect("tracer setup succeeds. qed");
+            run()
+        }
+        LogFormat::Json => {
+            json_subscriber(std::io::stdout)
+                .try_init()
+                .expect("tracer setup succeeds. qed");
+            run()
+        }
+    }
+}
+
+/// JSON lines filtered by `RUST_LOG` (default `info`); `span` is the innermost
+/// span and `spans` the whole stack, so request fields such as `request_id`
+/// and `user_id` are on every line logged while handling the request
+fn json_subscriber<W>(writer: W) -> impl Subscriber + Send + Sync + 'static
+where
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    tracing_subscriber::fmt()
+        .json()
+        .with_current_span(true)
+        .with_span_list(true)
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .with_writer(writer)
+        .finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_json_lines_carry_span_fields() {
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = json_subscriber(move || writer.clone());
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!(
+                "http_request",
+                method = "POST",
+                uri = "/api/ai/improve",
+                request_id = "req-7",
+            );
+            let _entered = span.enter();
+            tracing::info!("📝 improved");
+        });
+
+        let bytes = captured.0.lock().unwrap().clone();
+        let line: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(line["fields"]["message"], "📝 improved");
+        assert_eq!(line["span"]["name"], "http_request");
+        assert_eq!(line["span"]["method"], "POST");
+        assert_eq!(line["spans"][0]["request_id"], "req-7");
+    }
+}
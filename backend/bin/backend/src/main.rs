@@ -3,14 +3,19 @@ pub mod cli;
 pub mod graphql;
 
 pub mod http;
+pub mod linter_task;
+pub mod logging;
 pub mod model;
 pub mod mono;
 pub mod opts;
+pub mod shutdown;
 pub mod worker;
 
 use anyhow::Result;
-use atb::logging::init_tracer;
 use atb_cli_utils::AtbCli;
+use backend_core::editor;
+use std::sync::Arc;
+use yrs::Doc;
 
 use crate::cli::{Cli, Commands};
 
@@ -23,30 +28,49 @@ fn main() -> Result<()> {
             println!("{}", graphql::schema().finish().sdl());
             Ok(())
         }
-        Commands::Worker { worker } => {
-            let _guard = init_tracer(Default::default()).expect("tracer setup succeeds. qed");
+        Commands::Replay { file, realtime } => logging::with_tracer(cli.log_format, || {
             let runtime = Cli::create_runtime(cli.worker_threads)?;
-            runtime.block_on(async move { worker::run(worker).await })
-        }
+            runtime.block_on(async move {
+                let updates = editor::read_recording(&file)?;
+                tracing::info!(
+                    "🎞️ replaying {} updates from {}",
+                    updates.len(),
+                    file.display()
+                );
+                let doc = Arc::new(Doc::new());
+                editor::replay_updates(&doc, &updates, realtime).await?;
+                println!("{}", editor::get_doc_content(&doc));
+                Ok(())
+            })
+        }),
+        Commands::Worker { db_opts, worker } => logging::with_tracer(cli.log_format, || {
+            cli.llm.configure()?;
+            worker.configure_activities(&cli.llm)?;
+            let runtime = Cli::create_runtime(cli.worker_threads)?;
+            runtime.block_on(async move { worker::run(db_opts, worker).await })
+        }),
         Commands::Http {
             db_opts,
             http,
             temporal,
             opts,
-        } => {
-            let _guard = init_tracer(Default::default()).expect("tracer setup succeeds. qed");
+        } => logging::with_tracer(cli.log_format, || {
+            cli.llm.configure()?;
+            cli.llm.require_openai_key(&opts.openai_api_key)?;
             let runtime = Cli::create_runtime(cli.worker_threads)?;
             runtime.block_on(async move { http::run(db_opts, http, temporal, opts).await })
-        }
+        }),
         Commands::Mono {
             db_opts,
             http,
             worker,
             opts,
-        } => {
-            let _guard = init_tracer(Default::default()).expect("tracer setup succeeds. qed");
+        } => logging::with_tracer(cli.log_format, || {
+            cli.llm.configure()?;
+            cli.llm.require_openai_key(&opts.openai_api_key)?;
+            worker.configure_activities(&cli.llm)?;
             let runtime = Cli::create_runtime(cli.worker_threads)?;
             runtime.block_on(async move { mono::run(db_opts, http, worker, opts).await })
-        }
+        }),
     }
 }
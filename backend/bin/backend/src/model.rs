@@ -1,11 +1,284 @@
+use crate::api::state::SuggestedEdit;
+use atb_types::{DateTime, Utc, Uuid};
+use backend_core::editor::ChunkGranularity;
+use backend_core::llm::tools::linter::LintCorrection;
+use backend_core::llm::tools::summarizer::SummaryStyle;
+use backend_core::llm::tools::tone::TonePreset;
+use backend_core::llm::usage::UsageTotals;
+use backend_core::sqlx_postgres::ai_events::AiEventRecord;
+use backend_core::sqlx_postgres::ai_usage::{self, ToolUsageRecord};
+use backend_core::sqlx_postgres::documents::DocumentRecord;
+use backend_core::temporal::WorkflowStatus;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RefineRequest {
+    /// The text to refine; leave empty and set `target` to refine the shared document instead
+    #[serde(default)]
     pub text: String,
+    /// Read the text from the shared document and write the result back into it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<DocTarget>,
+    /// The live document `target` points into; only the shared document exists so far
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub doc_id: Option<String>,
+    /// Required by `POST /refine`; the per-action routes fill it in themselves
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub action: Option<RefineAction>,
+    /// BCP-47 tag to write the result in, e.g. `zh-TW`; the input's language when omitted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// Free-form tone for the rewrite, e.g. `formal` or `playful`; unknown tones are passed through
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tone: Option<String>,
+    /// Who the rewrite is written for, e.g. `executives`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audience: Option<String>,
+}
+
+/// The part of the shared document a refine reads from and writes its result back to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "scope", rename_all = "snake_case")]
+pub enum DocTarget {
+    /// Every paragraph of the document, each refined and rewritten in place
+    Document,
+    /// Characters `start..end` of the document's plain text, within one paragraph
+    Selection { start: usize, end: usize },
+}
+
+/// Which rewrite `POST /refine` and the `refineText` mutation perform
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, async_graphql::Enum)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum RefineAction {
+    Improve,
+    Fix,
+    Longer,
+    Shorter,
+}
+
+impl RefineAction {
+    /// Name the action is coalesced and recorded in the suggestion history under.
+    /// Every action calls OpenAI as the `refiner` tool, so they share its settings.
+    pub fn tool(self) -> &'static str {
+        match self {
+            Self::Improve => "improve",
+            Self::Fix => "fix",
+            Self::Longer => "longer",
+            Self::Shorter => "shorter",
+        }
+    }
+}
+
+/// Body of `POST /refine/custom`: rewrite `text` following the writer's `instruction`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CustomRefineRequest {
+    pub text: String,
+    pub instruction: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+}
+
+/// Body of `POST /refine/tone`: rewrite `text` in the voice of `preset`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ToneRequest {
+    pub text: String,
+    /// `formal`, `casual`, `academic`, `marketing` or `empathetic`; anything else is a 422
+    pub preset: TonePreset,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+}
+
+/// Body of `POST /translate`: translate `text` into `target_lang`, e.g. `ja` or `zh-TW`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TranslateRequest {
+    pub text: String,
+    pub target_lang: String,
+}
+
+/// Body of `POST /summarize`; without `text` the shared document is summarized
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SummarizeRequest {
+    /// Raw text to summarize, for callers outside the editor
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    /// `bullet` (the default), `abstract` or `tl_dr`
+    #[serde(default)]
+    pub style: SummaryStyle,
+    /// BCP-47 tag to write the summary in; the document's language when omitted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RefineResponse {
     pub text: String,
 }
+
+/// What `POST /linter` changed; empty when the text needed no corrections
+#[derive(Debug, Serialize)]
+pub struct LinterResponse {
+    pub corrections: Vec<LintCorrection>,
+}
+
+/// Body of `POST /editor/lint/preview`
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct LintPreviewRequest {
+    /// BCP-47 tag the linter corrects towards; the document's language when omitted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+}
+
+/// What linting the shared document would change; nothing has been applied.
+/// Each edit's `id` can be accepted or rejected over the WebSocket.
+#[derive(Debug, Serialize)]
+pub struct LintPreviewResponse {
+    pub diffs: Vec<SuggestedEdit>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChunkPreviewRequest {
+    pub text: String,
+    #[serde(default)]
+    pub granularity: ChunkGranularity,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ImportRequest {
+    pub markdown: String,
+}
+
+/// Body of `GET /editor/content`: the document as plain text
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DocContent {
+    pub text: String,
+}
+
+/// Body of `POST /documents`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateDocumentRequest {
+    pub title: String,
+}
+
+/// A stored document's metadata and the start of its text
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DocumentResponse {
+    pub id: Uuid,
+    pub title: String,
+    pub created_by: Option<Uuid>,
+    pub updated_at: DateTime<Utc>,
+    pub preview: String,
+}
+
+impl From<DocumentRecord> for DocumentResponse {
+    fn from(record: DocumentRecord) -> Self {
+        Self {
+            preview: record.preview(),
+            id: record.id,
+            title: record.title,
+            created_by: record.created_by,
+            updated_at: record.updated_at,
+        }
+    }
+}
+
+/// Query of `GET /editor/history`
+#[derive(Debug, Default, Deserialize)]
+pub struct HistoryQuery {
+    pub limit: Option<i64>,
+    /// Only events older than this; pass the previous page's `next_before`
+    pub before: Option<DateTime<Utc>>,
+    /// Breaks ties at `before`; pass the previous page's `next_before_id`
+    pub before_id: Option<Uuid>,
+}
+
+/// One recorded AI suggestion
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AiEventResponse {
+    pub id: Uuid,
+    pub doc_id: String,
+    pub tool: String,
+    pub input_preview: String,
+    pub output_preview: String,
+    pub status: String,
+    pub latency_ms: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<AiEventRecord> for AiEventResponse {
+    fn from(record: AiEventRecord) -> Self {
+        Self {
+            id: record.id,
+            doc_id: record.doc_id,
+            tool: record.tool,
+            input_preview: record.input_preview,
+            output_preview: record.output_preview,
+            status: record.status,
+            latency_ms: record.latency_ms,
+            created_at: record.created_at,
+        }
+    }
+}
+
+/// Body of `GET /editor/history`: a page of events, newest first.
+/// `next_before` and `next_before_id` are absent on the last page
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AiHistoryResponse {
+    pub events: Vec<AiEventResponse>,
+    pub next_before: Option<DateTime<Utc>>,
+    pub next_before_id: Option<Uuid>,
+}
+
+/// Query of `GET /editor/usage`
+#[derive(Debug, Default, Deserialize)]
+pub struct UsageQuery {
+    /// Start of the period; the last 30 days when omitted
+    pub since: Option<DateTime<Utc>>,
+}
+
+/// Tokens and estimated cost of one tool's AI calls
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ToolUsageResponse {
+    pub tool: String,
+    pub calls: i64,
+    pub prompt_tokens: i64,
+    pub completion_tokens: i64,
+    pub cost_usd: f64,
+}
+
+impl From<ToolUsageRecord> for ToolUsageResponse {
+    fn from(record: ToolUsageRecord) -> Self {
+        Self {
+            tool: record.tool,
+            calls: record.calls,
+            prompt_tokens: record.prompt_tokens,
+            completion_tokens: record.completion_tokens,
+            cost_usd: record.cost_usd,
+        }
+    }
+}
+
+/// Body of `GET /editor/usage`: usage per tool since `since`, most expensive
+/// first, and the sum of them all
+#[derive(Debug, Serialize)]
+pub struct UsageResponse {
+    pub since: DateTime<Utc>,
+    pub tools: Vec<ToolUsageResponse>,
+    pub total: UsageTotals,
+}
+
+impl UsageResponse {
+    pub fn new(since: DateTime<Utc>, tools: Vec<ToolUsageRecord>) -> Self {
+        Self {
+            since,
+            total: ai_usage::totals(&tools),
+            tools: tools.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+/// Body of `GET /editor/agent/{workflow_id}/status`
+#[derive(Debug, Serialize)]
+pub struct AgentStatusResponse {
+    pub workflow_id: String,
+    pub status: WorkflowStatus,
+}
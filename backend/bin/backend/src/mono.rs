@@ -1,20 +1,22 @@
-use crate::{api::state::MessageStructure, http, opts::*};
+use crate::{
+    api::state::{AutoAgentToggles, MessageStructure},
+    http,
+    linter_task::{self, AutoAgentContext},
+    opts::*,
+    shutdown::ShutdownTrigger,
+};
 use atb_cli_utils::AtbCli;
-use backend_core::{editor, sqlx_postgres, temporal};
+use backend_core::llm::{provider, tools::linter::LINTER_MODEL};
+use backend_core::{sqlx_postgres, temporal};
 use std::time::Instant;
-use std::{
-    sync::Arc,
-    sync::atomic::{AtomicBool, Ordering},
-    time::Duration,
-};
+use std::{sync::Arc, time::Duration};
 use tokio::sync::broadcast;
 use tokio::sync::watch;
 use yrs::Doc;
 // Doc 讀寫操作已移至 backend_core::editor 模組
-// Use AtomicBool for thread-safe flag access (no unsafe blocks needed)
-pub static LINTER_FLAG: AtomicBool = AtomicBool::new(false);
-pub static EMOJI_REPLACER_FLAG: AtomicBool = AtomicBool::new(false);
-pub static BACKSEATER_FLAG: AtomicBool = AtomicBool::new(false);
+
+/// How long shutdown waits for the Temporal worker to finish its in-flight tasks
+const WORKER_STOP_TIMEOUT: Duration = Duration::from_secs(10);
 
 pub async fn run(
     db_opts: DatabaseOpts,
@@ -22,6 +24,11 @@ pub async fn run(
     worker_opts: WorkerOpts,
     opts: Opts,
 ) -> anyhow::Result<()> {
+    opts.configure_search();
+    tracing::info!(
+        "🧹 auto-linter calls {}",
+        provider::configured(&opts.openai_api_key).describe("linter", LINTER_MODEL)
+    );
     let client_id = crate::Cli::client_id();
     let pg_pool = sqlx_postgres::connect_pg(&db_opts.postgres, 30, Some(&client_id)).await?;
     let client = temporal::try_connect_temporal(
@@ -34,8 +41,12 @@ pub async fn run(
 
     let task_queue = worker_opts.temporal.task_queue.clone();
     let worker_config = crate::worker::worker_config(&worker_opts)?;
-    let worker_handle =
-        std::thread::spawn(move || crate::worker::start_worker(client, worker_config));
+    // One trigger for both, so /admin/shutdown stops the worker along with HTTP
+    let shutdown = ShutdownTrigger::new();
+    let worker_shutdown = shutdown.clone();
+    let worker_handle = std::thread::spawn(move || {
+        crate::worker::start_worker(client, worker_config, worker_shutdown)
+    });
 
     // Initialize the Yrs Document for collaborative editing
     // Start with empty fragment - y-prosemirror will handle structure automatically
@@ -43,132 +54,115 @@ pub async fn run(
     let _xml_fragment = doc.get_or_insert_xml_fragment("content");
 
     // Create Broadcast Channel (Server -> All Clients)
-    let (broadcast_tx, _) = broadcast::channel::<MessageStructure>(100);
+    let (broadcast_tx, _) =
+        broadcast::channel::<MessageStructure>(http_opts.ws.ws_broadcast_capacity);
 
     // Create User Writing State for user writing detection
-    let user_writing_state = Arc::new(editor::UserWritingState::new(2000)); // 2 second timeout
-    let (notify_tx, mut notify_rx) = watch::channel(Instant::now());
+    let user_writing_state = Arc::new(opts.editor.user_writing_state());
+    let (notify_tx, notify_rx) = watch::channel(Instant::now());
 
     // Setup Observer: When Yrs changes (by User OR AI), broadcast the delta
     let tx_clone = broadcast_tx.clone();
-    let _sub = doc.observe_update_v1(move |_txn, update_event| {
-        let update = update_event.update.to_vec();
+    let recorder = http_opts.update_recorder()?;
+    let _sub = doc.observe_update_v1(move |txn, update_event| {
         // Send binary update to all connected clients
-        let _ = tx_clone.send(MessageStructure::YjsUpdate(update));
+        let _ = tx_clone.send(MessageStructure::from_update(txn, &update_event.update));
         let _ = notify_tx.send(Instant::now());
-    });
-
-    // Clone values before moving into the async task
-    let api_key_for_task = opts.openai_api_key.clone();
-    let doc_for_task = doc.clone();
-    let broadcast_tx_for_task = broadcast_tx.clone();
-
-    
-    tokio::spawn(async move {
-        tracing::info!("🚀 Smart Auto-linter started (Debounce: 5s)");
-        let mut before_content = "".to_string();
-        // 核心邏輯：等待變動 -> 觸發 5 秒冷卻 -> 執行
-        loop {
-            if notify_rx.changed().await.is_err() {
-                tracing::error!("🔍 Notify RX changed error");
-                break;
-            }
-
-            loop {
-                let delay = tokio::time::sleep(std::time::Duration::from_secs(5));
-                tokio::pin!(delay);
-
-                tokio::select! {
-                    changed = notify_rx.changed() => {
-                        if changed.is_err() { return; }
-                        tracing::debug!("⌨️ User still typing, skipping checks");
-                        continue;
-                    }
-                    _ = &mut delay => {
-                        break;
-                    }
-                }
-            }
-
-            let linter_enabled = LINTER_FLAG.load(Ordering::Relaxed);
-            let emoji_replacer_enabled = EMOJI_REPLACER_FLAG.load(Ordering::Relaxed);
-            let backseater_enabled = BACKSEATER_FLAG.load(Ordering::Relaxed);
-
-            let current_content = editor::get_doc_content(&doc_for_task);
-            if current_content.is_empty() || current_content == before_content {
-                tracing::info!("🔍 Doc is empty or not changed, skipping checks");
-                continue;
+        if let Some(recorder) = &recorder {
+            if let Err(e) = recorder.record(&update_event.update) {
+                tracing::warn!("❌ failed to record update: {e:?}");
             }
-
-            if linter_enabled {
-                tracing::info!("🤖 Calling AI Linter...");
-                match backend_core::llm::new_linter(&api_key_for_task, doc_for_task.clone()).await {
-                    Ok(_) => {
-                        tracing::info!("✅ AI check successful");
-                    }
-                    Err(e) => tracing::error!("❌ AI check failed: {:?}", e),
-                }
-            }
-
-            if emoji_replacer_enabled {
-                tracing::info!("🤖 Calling AI Emoji Replacer...");
-                match backend_core::llm::new_emoji_replacer(&api_key_for_task, &doc_for_task).await {
-                    Ok(_) => {
-                        tracing::info!("✅ AI emoji replacer successful");
-                    }
-                    Err(e) => tracing::error!("❌ AI emoji replacer failed: {:?}", e),
-                }
-            }
-
-            if backseater_enabled {
-                tracing::info!("💬 Calling AI Backseater...");
-                match backend_core::llm::new_backseating_agent(&api_key_for_task, &doc_for_task).await {
-                    Ok(comments) => {
-                        if !comments.is_empty() {
-                            tracing::info!("✅ Generated {} comments from backseater", comments.len());
-                            // Send each comment to frontend via broadcast channel
-                            for comment in comments {
-                                let comment_json = serde_json::json!({
-                                    "type": "COMMENT",
-                                    "comment_on": comment.comment_on,
-                                    "comment": comment.comment,
-                                    "color_hex": comment.color_hex
-                                });
-                                if let Err(e) = broadcast_tx_for_task.send(MessageStructure::AiCommand(comment_json.to_string())) {
-                                    tracing::warn!("Failed to broadcast backseater comment: {:?}", e);
-                                }
-                            }
-                        } else {
-                            tracing::info!("⚠️ No comments generated by backseater");
-                        }
-                    }
-                    Err(e) => tracing::error!("❌ AI backseater failed: {:?}", e),
-                }
-            }
-
-            // Update before_content AFTER all tools have run (or been skipped)
-            before_content = editor::get_doc_content(&doc_for_task);
         }
-        tracing::info!("🔌 Linter task exiting");
     });
 
-    http::start_http(
+    // Toggles are shared with AppState so TOGGLE commands reach the running loop
+    let auto_agents = AutoAgentToggles::new();
+
+    let ctx = AutoAgentContext {
+        doc: doc.clone(),
+        api_key: opts.openai_api_key.clone(),
+        broadcast_tx: broadcast_tx.clone(),
+        user_state: user_writing_state.clone(),
+        toggles: auto_agents.clone(),
+        debounce: opts.editor.debounce(),
+        lint: linter_task::recorded(
+            pg_pool.clone(),
+            linter_task::openai_lint(opts.openai_api_key.clone(), opts.editor.lint_mode()),
+        ),
+    };
+    let auto_linter = linter_task::spawn(ctx, notify_rx);
+
+    let served = http::start_http(
         pg_pool,
         http_client,
         http_opts,
         task_queue,
         opts.openai_api_key,
+        opts.editor,
         doc,
         broadcast_tx,
         Some(user_writing_state),
+        auto_agents,
+        auto_linter.clone(),
+        shutdown.clone(),
     )
-    .await?;
+    .await;
+    // The server may also stop on an error; the worker must hear about it either way
+    shutdown.trigger();
+    // Stop the loop before the worker, so no OpenAI call outlives the server
+    auto_linter.shutdown().await;
+    join_worker(worker_handle, WORKER_STOP_TIMEOUT).await?;
+    served
+}
 
-    worker_handle
+/// Join the worker thread once it stops, giving up after `timeout` so a stuck
+/// poll cannot hold the process open; the thread is dropped with the process.
+async fn join_worker(
+    handle: std::thread::JoinHandle<anyhow::Result<()>>,
+    timeout: Duration,
+) -> anyhow::Result<()> {
+    let deadline = Instant::now() + timeout;
+    while !handle.is_finished() {
+        if Instant::now() >= deadline {
+            tracing::warn!(
+                "⏱️ worker did not stop within {:?}, exiting without it",
+                timeout
+            );
+            return Ok(());
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    handle
         .join()
-        .map_err(|e| anyhow::anyhow!("worker thread panicked: {:?}", e))??;
-
-    Ok(())
+        .map_err(|e| anyhow::anyhow!("worker thread panicked: {:?}", e))?
 }
 
-// 測試已移至 backend_core::editor 模組
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_join_worker_returns_the_worker_result() {
+        let handle = std::thread::spawn(|| Err(anyhow::anyhow!("poller failed")));
+        let e = join_worker(handle, Duration::from_secs(1))
+            .await
+            .unwrap_err();
+        assert_eq!(e.to_string(), "poller failed");
+    }
+
+    #[tokio::test]
+    async fn test_join_worker_gives_up_on_a_stuck_worker() {
+        let (release_tx, release_rx) = std::sync::mpsc::channel::<()>();
+        let handle = std::thread::spawn(move || {
+            let _ = release_rx.recv();
+            Ok(())
+        });
+
+        let started = Instant::now();
+        join_worker(handle, Duration::from_millis(100))
+            .await
+            .unwrap();
+        assert!(started.elapsed() < Duration::from_secs(1));
+        release_tx.send(()).unwrap();
+    }
+}
@@ -1,8 +1,8 @@
-use std::{fs, io::Read, path::PathBuf};
+use std::{fs, io::Read, path::PathBuf, sync::Arc};
 
 use atb_cli_utils::clap::{self, Parser, ValueHint};
 use atb_types::{
-    Duration,
+    Duration, Uuid,
     jwt::HEADER_RS256,
     prelude::{
         Builder, Claims,
@@ -10,6 +10,16 @@ use atb_types::{
     },
 };
 use axum_client_ip::ClientIpSource;
+use backend_core::editor::{UpdateRecorder, UserWritingState};
+use backend_core::llm::{
+    anthropic::{self, Anthropic},
+    cache::{self, ResponseCache},
+    openai::{DEFAULT_BASE_URL, DEFAULT_TIMEOUT, ModelOverrides, OpenAiExtras},
+    provider,
+    tools::{linter::LintMode, researcher},
+    usage::{self, ModelPrice},
+};
+use backend_core::temporal::openai;
 use serde::{Serialize, de::DeserializeOwned};
 
 #[derive(Debug, Clone, Parser)]
@@ -45,6 +55,138 @@ pub struct HttpOpts {
         value_hint = ValueHint::FilePath,
     )]
     pub jwt_pub_key: Option<PathBuf>,
+
+    /// Requests per minute each client IP may make to the AI endpoints (0 = unlimited)
+    #[arg(long, default_value = "30", env = "BACKEND_RATE_LIMIT_PER_MINUTE")]
+    pub rate_limit_per_minute: u32,
+
+    /// Requests per minute each signed-in subject may make to the AI endpoints (0 = unlimited)
+    #[arg(
+        long,
+        default_value = "60",
+        env = "BACKEND_RATE_LIMIT_SUBJECT_PER_MINUTE"
+    )]
+    pub rate_limit_subject_per_minute: u32,
+
+    /// AI commands per minute each client IP may send over the WebSocket (0 = unlimited)
+    #[arg(long, default_value = "30", env = "BACKEND_WS_AI_COMMANDS_PER_MINUTE")]
+    pub ws_ai_commands_per_minute: u32,
+
+    /// Header used to read (or assign) the request id for cross-service tracing
+    #[arg(long, default_value = "x-request-id", env = "BACKEND_REQUEST_ID_HEADER")]
+    pub request_id_header: String,
+
+    /// JWT subjects allowed to call the /admin endpoints (none by default)
+    #[arg(long, value_delimiter = ';', env = "BACKEND_ADMIN_SUBJECTS")]
+    pub admin_subjects: Vec<Uuid>,
+
+    /// Record every broadcast Yjs update to this file (JSON lines) for `backend replay`
+    #[arg(
+        long,
+        env = "BACKEND_RECORD_UPDATES",
+        value_hint = ValueHint::FilePath,
+    )]
+    pub record_updates: Option<PathBuf>,
+
+    /// Close a WebSocket client once its update grows the document past this many bytes (0 = unlimited)
+    #[arg(long, default_value = "2097152", env = "BACKEND_MAX_DOC_BYTES")]
+    pub max_doc_bytes: usize,
+
+    /// Drop (without closing the client) any single WebSocket Yjs update larger than this (0 = unlimited)
+    #[arg(long, default_value = "1048576", env = "BACKEND_MAX_WS_UPDATE_BYTES")]
+    pub max_ws_update_bytes: usize,
+
+    /// Interval between server pings to each WebSocket client (milliseconds, 0 = off)
+    #[arg(long, default_value = "15000", env = "BACKEND_WS_PING_INTERVAL_MS")]
+    pub ws_ping_interval_ms: u64,
+
+    /// Close a WebSocket client after this many ping intervals without any frame from it
+    #[arg(long, default_value = "3", env = "BACKEND_WS_MAX_MISSED_PONGS")]
+    pub ws_max_missed_pongs: u32,
+
+    /// Longest text, in characters, the refine endpoints accept
+    #[arg(long, default_value = "20000", env = "BACKEND_MAX_TEXT_CHARS")]
+    pub max_text_chars: usize,
+
+    /// Largest request body the AI endpoints accept; bigger ones get 413
+    #[arg(long, default_value = "262144", env = "BACKEND_MAX_BODY_BYTES")]
+    pub max_body_bytes: usize,
+
+    /// Also require the configured AI provider to answer a HEAD request in /readyz
+    #[arg(long, default_value = "false", env = "BACKEND_READYZ_CHECK_OPENAI")]
+    pub readyz_check_openai: bool,
+
+    #[clap(flatten)]
+    pub ws: WebSocketOpts,
+}
+
+#[derive(Debug, Clone, Parser, Serialize)]
+pub struct WebSocketOpts {
+    /// Retries for a transient WebSocket send failure before dropping the client
+    #[arg(long, default_value = "3", env = "BACKEND_WS_SEND_RETRIES")]
+    pub ws_send_retries: u32,
+
+    /// Delay between WebSocket send retries (milliseconds)
+    #[arg(long, default_value = "50", env = "BACKEND_WS_SEND_RETRY_DELAY_MS")]
+    pub ws_send_retry_delay_ms: u64,
+
+    /// Editor broadcast channel capacity; clients that fall further behind are resynced
+    #[arg(long, default_value = "100", env = "BACKEND_WS_BROADCAST_CAPACITY")]
+    pub ws_broadcast_capacity: usize,
+
+    /// Close a WebSocket client that has sent no edit or command for this long, even
+    /// one still answering pings (seconds, 0 = never)
+    #[arg(long, default_value = "1800", env = "BACKEND_WS_IDLE_TIMEOUT_SECS")]
+    pub ws_idle_timeout_secs: u64,
+
+    /// Accept WebSocket connections without a JWT (local development only)
+    #[arg(long, default_value = "false", env = "BACKEND_WS_AUTH_DISABLED")]
+    pub ws_auth_disabled: bool,
+}
+
+#[derive(Debug, Clone, Parser, Serialize)]
+pub struct EditorOpts {
+    /// Quiet period after the last edit before the auto-agents run (seconds)
+    #[arg(long, default_value = "5", env = "BACKEND_DEBOUNCE_SECS")]
+    pub debounce_secs: u64,
+
+    /// How long after a writer's last keystroke they still count as writing (milliseconds)
+    #[arg(long, default_value = "2000", env = "BACKEND_WRITING_TIMEOUT_MS")]
+    pub writing_timeout_ms: u64,
+
+    /// Delay between words when the composer streams text into the document (milliseconds)
+    #[arg(long, default_value = "100", env = "BACKEND_AI_WORD_DELAY_MS")]
+    pub ai_word_delay_ms: u64,
+
+    /// Have the linter echo the whole document XML instead of listing corrections (rollback only)
+    #[arg(long, default_value = "false", env = "BACKEND_LINT_XML_MODE")]
+    pub lint_xml_mode: bool,
+
+    /// Forget a composer session after this long without an AGENT command (seconds)
+    #[arg(long, default_value = "1800", env = "BACKEND_AGENT_SESSION_IDLE_SECS")]
+    pub agent_session_idle_secs: u64,
+}
+
+impl EditorOpts {
+    pub fn debounce(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.debounce_secs)
+    }
+
+    pub fn agent_session_idle(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.agent_session_idle_secs)
+    }
+
+    pub fn user_writing_state(&self) -> UserWritingState {
+        UserWritingState::new(self.writing_timeout_ms)
+    }
+
+    pub fn lint_mode(&self) -> LintMode {
+        if self.lint_xml_mode {
+            LintMode::Xml
+        } else {
+            LintMode::Structured
+        }
+    }
 }
 
 impl HttpOpts {
@@ -67,6 +209,16 @@ impl HttpOpts {
             _ => return Err(anyhow::anyhow!("jwt cannot be disjoint")),
         })
     }
+
+    pub fn update_recorder(&self) -> anyhow::Result<Option<Arc<UpdateRecorder>>> {
+        let Some(path) = &self.record_updates else {
+            return Ok(None);
+        };
+        tracing::info!("🎞️ recording yjs updates to {}", path.display());
+        // The writer logs its own failure and ends once the recorder is dropped
+        let (recorder, _writer) = UpdateRecorder::create(path)?;
+        Ok(Some(Arc::new(recorder)))
+    }
 }
 
 #[derive(Clone)]
@@ -163,10 +315,267 @@ pub struct WorkerOpts {
         env = "BACKEND_TEMPORAL_MAX_CACHED_WORKFLOWS"
     )]
     pub max_cached_workflows: usize,
+
+    /// OpenAI key for compose and lint activities; required with `--llm-provider openai`
+    #[arg(long, env = "OPENAI_API_KEY")]
+    pub worker_openai_api_key: Option<String>,
+}
+
+impl WorkerOpts {
+    /// Hand the worker's activities the credentials `llm` needs
+    pub fn configure_activities(&self, llm: &LlmOpts) -> anyhow::Result<()> {
+        let key = self.worker_openai_api_key.clone().unwrap_or_default();
+        llm.require_openai_key(&key)?;
+        openai::configure_api_key(key);
+        Ok(())
+    }
 }
 
 #[derive(Clone, Debug, Parser)]
 pub struct Opts {
-    #[arg(long, env = "OPENAI_API_KEY")]
+    /// OpenAI key; required with `--llm-provider openai`, the other providers take none
+    #[arg(
+        long,
+        env = "OPENAI_API_KEY",
+        default_value = "",
+        hide_default_value = true
+    )]
     pub openai_api_key: String,
+
+    /// Brave Search API key for the researcher tool; without it research uses model knowledge only
+    #[arg(long, env = "SEARCH_API_KEY")]
+    pub search_api_key: Option<String>,
+
+    #[clap(flatten)]
+    pub editor: EditorOpts,
+}
+
+impl Opts {
+    /// Give the researcher web search when a key is set
+    pub fn configure_search(&self) {
+        if let Some(key) = &self.search_api_key {
+            researcher::configure_search(Box::new(researcher::WebSearch::new(key.clone())));
+        }
+    }
+}
+
+/// Which LLM API the tools call
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LlmProviderKind {
+    /// OpenAI chat completions, with the OpenAI key
+    #[default]
+    #[value(name = "openai")]
+    OpenAi,
+    /// Anthropic's messages API; needs `--anthropic-api-key`
+    Anthropic,
+    /// An OpenAI-compatible server on this machine (Ollama, vLLM, LM Studio), for
+    /// offline development; the OpenAI key may be empty
+    Local,
+}
+
+/// Where Ollama serves its OpenAI-compatible API
+pub const LOCAL_BASE_URL: &str = "http://localhost:11434/v1";
+
+/// Local models take a while, especially on a laptop
+const LOCAL_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(600);
+
+#[derive(Clone, Debug, Parser)]
+pub struct LlmOpts {
+    /// LLM backend for every AI tool
+    #[arg(long, env = "LLM_PROVIDER", value_enum, default_value_t = LlmProviderKind::OpenAi)]
+    pub llm_provider: LlmProviderKind,
+
+    /// Root of the OpenAI API, or of a compatible server such as vLLM, Ollama or LM Studio
+    /// [default: OpenAI's, or Ollama's on localhost for `local`]
+    #[arg(long, env = "BACKEND_OPENAI_BASE_URL")]
+    pub openai_base_url: Option<String>,
+
+    /// Models to call on OpenAI or a local server, as `model` for every tool or
+    /// `tool=model` for one
+    #[arg(long, value_delimiter = ';', env = "LLM_MODELS")]
+    pub llm_models: Vec<String>,
+
+    /// Give up on an OpenAI or local model call after this long (seconds)
+    /// [default: 120, or 600 for `local`]
+    #[arg(long, env = "LLM_TIMEOUT_SECS")]
+    pub llm_timeout_secs: Option<u64>,
+
+    /// Extra headers on AI provider requests, as `Name: value` or `tool/Name: value` for one tool
+    #[arg(long, value_delimiter = ';', env = "OPENAI_EXTRA_HEADERS")]
+    pub openai_extra_headers: Vec<String>,
+
+    /// Extra query parameters on AI provider requests, as `key=value` or `tool/key=value`
+    #[arg(long, value_delimiter = ';', env = "OPENAI_EXTRA_QUERY")]
+    pub openai_extra_query: Vec<String>,
+
+    /// Prices for the usage report, as `model=prompt/completion` in USD per million
+    /// tokens; they take precedence over the built-in OpenAI and Claude prices
+    #[arg(long, value_delimiter = ';', env = "LLM_MODEL_PRICES")]
+    pub llm_model_prices: Vec<String>,
+
+    /// Call the model for every refine and lint request instead of answering
+    /// identical ones from the cache
+    #[arg(long, default_value = "false", env = "LLM_CACHE_DISABLED")]
+    pub llm_cache_disabled: bool,
+
+    /// Most refine and lint answers kept in the cache
+    #[arg(long, env = "LLM_CACHE_MAX_ENTRIES", default_value_t = 1000)]
+    pub llm_cache_max_entries: u64,
+
+    /// How long a cached answer is served (seconds)
+    #[arg(long, env = "LLM_CACHE_TTL_SECS", default_value_t = 600)]
+    pub llm_cache_ttl_secs: u64,
+
+    /// Anthropic API key, used when the provider is `anthropic`
+    #[arg(long, env = "ANTHROPIC_API_KEY")]
+    pub anthropic_api_key: Option<String>,
+
+    /// Claude model for the tools written for `gpt-4o`
+    #[arg(long, env = "ANTHROPIC_MODEL", default_value = anthropic::MODEL)]
+    pub anthropic_model: String,
+
+    /// Claude model for the tools written for `gpt-4o-mini`
+    #[arg(long, env = "ANTHROPIC_FAST_MODEL", default_value = anthropic::FAST_MODEL)]
+    pub anthropic_fast_model: String,
+}
+
+impl LlmOpts {
+    /// Install the chosen provider; OpenAI and local servers need nothing
+    /// installed, as OpenAI's API is the default
+    pub fn configure(&self) -> anyhow::Result<()> {
+        let local = self.llm_provider == LlmProviderKind::Local;
+        let base_url = match &self.openai_base_url {
+            Some(base_url) => base_url,
+            None if local => LOCAL_BASE_URL,
+            None => DEFAULT_BASE_URL,
+        };
+        backend_core::llm::openai::configure_base_url(base_url)?;
+        backend_core::llm::openai::configure_models(ModelOverrides::parse(&self.llm_models)?);
+        let timeout = match self.llm_timeout_secs {
+            Some(secs) => std::time::Duration::from_secs(secs),
+            None if local => LOCAL_TIMEOUT,
+            None => DEFAULT_TIMEOUT,
+        };
+        backend_core::llm::openai::configure_timeout(timeout);
+        let extras = OpenAiExtras::parse(&self.openai_extra_headers, &self.openai_extra_query)?;
+        backend_core::llm::openai::configure_extras(extras);
+        let prices = self.llm_model_prices.iter().map(|p| ModelPrice::parse(p));
+        usage::configure_prices(prices.collect::<anyhow::Result<_>>()?);
+        if !self.llm_cache_disabled {
+            cache::configure_cache(ResponseCache::new(
+                self.llm_cache_max_entries,
+                std::time::Duration::from_secs(self.llm_cache_ttl_secs),
+            ));
+        }
+        match self.llm_provider {
+            LlmProviderKind::OpenAi => {}
+            LlmProviderKind::Local => {
+                if self.llm_models.is_empty() {
+                    tracing::warn!(
+                        "🏠 no --llm-models for the local server, the tools will ask it for OpenAI models"
+                    );
+                }
+                tracing::info!("🏠 AI tools call the local server at {base_url}");
+            }
+            LlmProviderKind::Anthropic => {
+                let key = self.anthropic_api_key.as_ref().ok_or_else(|| {
+                    anyhow::anyhow!("--llm-provider anthropic needs --anthropic-api-key")
+                })?;
+                let anthropic = Anthropic::new(key.clone())
+                    .with_models(&self.anthropic_model, &self.anthropic_fast_model);
+                provider::configure_provider(Arc::new(anthropic));
+                tracing::info!(
+                    "🤖 AI tools call Anthropic ({}, {})",
+                    self.anthropic_model,
+                    self.anthropic_fast_model
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Refuse to start without an OpenAI key when the tools call OpenAI
+    pub fn require_openai_key(&self, key: &str) -> anyhow::Result<()> {
+        if key.is_empty() && self.llm_provider == LlmProviderKind::OpenAi {
+            anyhow::bail!("OPENAI_API_KEY is required with --llm-provider openai");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extras_for_an_unknown_tool_fail_startup() {
+        // Every command installs the extras through `LlmOpts`, the worker included
+        let opts =
+            LlmOpts::try_parse_from(["backend", "--openai-extra-headers", "lintr/X-Route: cheap"])
+                .unwrap();
+        assert!(opts.configure().is_err());
+    }
+
+    #[test]
+    fn test_openai_is_the_default_llm_provider() {
+        let opts = LlmOpts::try_parse_from(["backend"]).unwrap();
+        assert_eq!(opts.llm_provider, LlmProviderKind::OpenAi);
+        assert!(opts.configure().is_ok());
+
+        let opts = LlmOpts::try_parse_from(["backend", "--llm-provider", "anthropic"]).unwrap();
+        assert_eq!(opts.llm_provider, LlmProviderKind::Anthropic);
+        assert_eq!(opts.anthropic_model, anthropic::MODEL);
+        // Anthropic without its key is a startup error
+        assert!(opts.configure().is_err());
+
+        // No scheme: rejected at startup rather than on the first AI call
+        let opts = LlmOpts::try_parse_from(["backend", "--openai-base-url", "api.example.com/v1"])
+            .unwrap();
+        assert!(opts.configure().is_err());
+
+        // Malformed model overrides and prices too
+        let opts = LlmOpts::try_parse_from(["backend", "--llm-models", "linter="]).unwrap();
+        assert!(opts.configure().is_err());
+        let opts =
+            LlmOpts::try_parse_from(["backend", "--llm-model-prices", "llama3.1=free"]).unwrap();
+        assert!(opts.configure().is_err());
+    }
+
+    #[test]
+    fn test_openai_key_is_required_only_for_openai() {
+        let opts = LlmOpts::try_parse_from(["backend"]).unwrap();
+        assert!(opts.require_openai_key("").is_err());
+        assert!(opts.require_openai_key("sk-test").is_ok());
+
+        for provider in ["local", "anthropic"] {
+            let opts = LlmOpts::try_parse_from(["backend", "--llm-provider", provider]).unwrap();
+            assert!(opts.require_openai_key("").is_ok());
+        }
+    }
+
+    #[test]
+    fn test_local_provider_options() {
+        let opts = LlmOpts::try_parse_from([
+            "backend",
+            "--llm-provider",
+            "local",
+            "--llm-models",
+            "llama3.1:8b;linter=qwen2.5:7b",
+        ])
+        .unwrap();
+        assert_eq!(opts.llm_provider, LlmProviderKind::Local);
+        assert_eq!(opts.openai_base_url, None);
+        assert_eq!(opts.llm_models, ["llama3.1:8b", "linter=qwen2.5:7b"]);
+    }
+
+    #[test]
+    fn test_response_cache_options() {
+        let opts = LlmOpts::try_parse_from(["backend"]).unwrap();
+        assert!(!opts.llm_cache_disabled);
+        assert_eq!(opts.llm_cache_max_entries, 1000);
+        assert_eq!(opts.llm_cache_ttl_secs, 600);
+
+        let opts = LlmOpts::try_parse_from(["backend", "--llm-cache-disabled"]).unwrap();
+        assert!(opts.llm_cache_disabled);
+    }
 }
@@ -0,0 +1,114 @@
+use std::future::Future;
+use std::sync::Arc;
+
+use atb_tokio_ext::shutdown_signal;
+use tokio::sync::watch;
+
+/// Starts the graceful shutdown from inside the process, e.g. `POST /admin/shutdown`.
+///
+/// Everything waiting on `wait()` stops on either this trigger or a signal, so
+/// both paths drain the same way.
+#[derive(Clone)]
+pub struct ShutdownTrigger(Arc<watch::Sender<bool>>);
+
+impl Default for ShutdownTrigger {
+    fn default() -> Self {
+        Self(Arc::new(watch::Sender::new(false)))
+    }
+}
+
+impl ShutdownTrigger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Request shutdown. Returns `false` if it was already requested.
+    pub fn trigger(&self) -> bool {
+        !self.0.send_replace(true)
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Run `work` unless shutdown starts first; `None` means it was dropped mid-flight
+    pub async fn run_until<F: Future>(&self, work: F) -> Option<F::Output> {
+        tokio::select! {
+            output = work => Some(output),
+            _ = self.wait() => None,
+        }
+    }
+
+    /// Resolves on SIGINT/SIGTERM or once `trigger` is called; a signal also
+    /// marks the trigger, so `is_triggered` reports either path
+    pub fn wait(&self) -> impl Future<Output = ()> + Send + 'static {
+        let tx = self.0.clone();
+        let mut rx = self.0.subscribe();
+        async move {
+            tokio::select! {
+                _ = shutdown_signal() => {
+                    tx.send_replace(true);
+                }
+                _ = rx.wait_for(|requested| *requested) => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_trigger_is_idempotent() {
+        let trigger = ShutdownTrigger::new();
+        assert!(!trigger.is_triggered());
+        assert!(trigger.trigger());
+        assert!(!trigger.trigger());
+        assert!(trigger.is_triggered());
+
+        // Waiters that subscribe after the trigger still resolve
+        tokio::time::timeout(Duration::from_secs(1), trigger.wait())
+            .await
+            .expect("wait resolves once triggered");
+    }
+
+    #[tokio::test]
+    async fn test_run_until_drops_work_on_shutdown() {
+        let trigger = ShutdownTrigger::new();
+        assert_eq!(trigger.run_until(async { 7 }).await, Some(7));
+
+        // Stands in for an AI call that never answers
+        let slow = trigger.run_until(std::future::pending::<()>());
+        let trigger_later = async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            trigger.trigger();
+        };
+        let (cancelled, _) = tokio::time::timeout(Duration::from_secs(1), async {
+            tokio::join!(slow, trigger_later)
+        })
+        .await
+        .expect("shutdown cuts the pending work short");
+        assert_eq!(cancelled, None);
+    }
+
+    #[tokio::test]
+    async fn test_trigger_drains_server() {
+        let trigger = ShutdownTrigger::new();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server = tokio::spawn(
+            axum::serve(listener, axum::Router::new()).with_graceful_shutdown(trigger.wait()),
+        );
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!server.is_finished());
+
+        trigger.trigger();
+        tokio::time::timeout(Duration::from_secs(1), server)
+            .await
+            .expect("server drains after trigger")
+            .unwrap()
+            .unwrap();
+    }
+}
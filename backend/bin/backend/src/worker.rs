@@ -1,15 +1,21 @@
-use crate::opts::WorkerOpts;
+use crate::opts::{DatabaseOpts, WorkerOpts};
 use std::{sync::Arc, time::Duration};
 
+use backend_core::sqlx_postgres;
 use backend_core::temporal::{
     self, CoreRuntime, RuntimeOptions, TelemetryOptions, TemporalClient, TemporalWorker, Worker,
     WorkerConfig, WorkerTaskTypes, WorkerVersioningStrategy, init_worker,
 };
 
+use crate::shutdown::ShutdownTrigger;
 use atb_cli_utils::AtbCli;
-use atb_tokio_ext::shutdown_signal;
 
-pub async fn run(opts: WorkerOpts) -> anyhow::Result<()> {
+pub async fn run(db_opts: DatabaseOpts, opts: WorkerOpts) -> anyhow::Result<()> {
+    let client_id = crate::Cli::client_id();
+    let pg_pool = sqlx_postgres::connect_pg(&db_opts.postgres, 5, Some(&client_id)).await?;
+    // The activities' AI calls land in the same ai_usage table as the HTTP process's
+    sqlx_postgres::ai_usage::store_usage(&pg_pool);
+
     let client = temporal::try_connect_temporal(
         &opts.temporal.temporal,
         &opts.temporal.namespace,
@@ -19,7 +25,8 @@ pub async fn run(opts: WorkerOpts) -> anyhow::Result<()> {
 
     // Single worker entity per process; scale via pollers/outstanding task limits.
     let worker_config = worker_config(&opts)?;
-    let handle = std::thread::spawn(move || start_worker(client, worker_config));
+    let handle =
+        std::thread::spawn(move || start_worker(client, worker_config, ShutdownTrigger::new()));
 
     handle
         .join()
@@ -43,13 +50,17 @@ pub fn worker_config(opts: &WorkerOpts) -> anyhow::Result<WorkerConfig> {
         .map_err(|s| anyhow::anyhow!("{s}"))
 }
 
-pub fn start_worker(client: TemporalClient, worker_config: WorkerConfig) -> anyhow::Result<()> {
+pub fn start_worker(
+    client: TemporalClient,
+    worker_config: WorkerConfig,
+    shutdown: ShutdownTrigger,
+) -> anyhow::Result<()> {
     tokio::runtime::Builder::new_current_thread()
         .enable_all()
         .build()?
         .block_on(async move {
             let worker = new_worker(client, worker_config)?;
-            worker.run(shutdown_signal()).await?;
+            worker.run(shutdown.wait()).await?;
             Ok(())
         })
 }
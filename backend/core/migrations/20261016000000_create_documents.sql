@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS documents (
+    id UUID PRIMARY KEY,
+    title TEXT NOT NULL,
+    created_by UUID,
+    yjs_state BYTEA NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS documents_updated_at_idx ON documents (updated_at DESC);
@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS ai_events (
+    id UUID PRIMARY KEY,
+    doc_id TEXT NOT NULL,
+    tool TEXT NOT NULL,
+    input_preview TEXT NOT NULL,
+    output_preview TEXT NOT NULL,
+    status TEXT NOT NULL,
+    latency_ms BIGINT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS ai_events_created_at_idx ON ai_events (created_at DESC, id DESC);
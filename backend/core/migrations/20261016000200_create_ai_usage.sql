@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS ai_usage (
+    id UUID PRIMARY KEY,
+    tool TEXT NOT NULL,
+    model TEXT NOT NULL,
+    prompt_tokens BIGINT NOT NULL,
+    completion_tokens BIGINT NOT NULL,
+    cost_usd DOUBLE PRECISION,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS ai_usage_created_at_idx ON ai_usage (created_at);
@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS compositions (
+    workflow_id TEXT PRIMARY KEY,
+    run_id TEXT,
+    directive TEXT,
+    occurrence INTEGER NOT NULL DEFAULT 0,
+    paragraph_mode TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
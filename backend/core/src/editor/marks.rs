@@ -0,0 +1,232 @@
+use crate::editor::write::collect_text_nodes;
+use serde::Serialize;
+use yrs::types::text::{Diff, YChange};
+use yrs::{Any, Doc, Out, Text, Transact};
+
+// ============================================================================
+// Formatting Marks
+// ============================================================================
+
+/// 一段格式標記（bold、italic 等），以字元（Unicode scalar）為單位的半開區間
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MarkSpan {
+    pub start: usize,
+    pub end: usize,
+    pub mark: String,
+}
+
+/// 讀取文檔中 `target` 所在文字節點的格式，回傳相對於 `target` 的標記區間
+///
+/// 只處理 `target` 完整落在同一個文字節點內的情況；找不到則回傳空列表
+pub fn marks_for_text(doc: &Doc, target: &str) -> Vec<MarkSpan> {
+    if target.is_empty() {
+        return Vec::new();
+    }
+    let fragment = doc.get_or_insert_xml_fragment("content");
+    let txn = doc.transact();
+
+    let mut text_nodes = Vec::new();
+    collect_text_nodes(&txn, &fragment, &mut text_nodes);
+
+    for text_ref in text_nodes {
+        // 重建節點純文字，同時記錄每段格式的字元區間
+        let mut node_text = String::new();
+        let mut node_marks = Vec::new();
+        for chunk in text_ref.diff(&txn, YChange::identity) {
+            let chunk: Diff<YChange> = chunk;
+            let Out::Any(Any::String(s)) = chunk.insert else {
+                continue;
+            };
+            let start = node_text.chars().count();
+            node_text.push_str(&s);
+            let end = node_text.chars().count();
+            for (name, value) in chunk.attributes.iter().flat_map(|attrs| attrs.iter()) {
+                if !matches!(value, Any::Null | Any::Bool(false)) {
+                    node_marks.push(MarkSpan {
+                        start,
+                        end,
+                        mark: name.to_string(),
+                    });
+                }
+            }
+        }
+
+        let Some(byte_pos) = node_text.find(target) else {
+            continue;
+        };
+        let offset = node_text[..byte_pos].chars().count();
+        let len = target.chars().count();
+
+        return node_marks
+            .into_iter()
+            .filter_map(|m| {
+                let start = m.start.max(offset);
+                let end = m.end.min(offset + len);
+                (start < end).then(|| MarkSpan {
+                    start: start - offset,
+                    end: end - offset,
+                    mark: m.mark,
+                })
+            })
+            .collect();
+    }
+    Vec::new()
+}
+
+/// 盡力將原文的格式標記對應到改寫後的文字
+///
+/// 以詞為單位做最長公共子序列對齊；標記覆蓋的詞在新文字中全部原樣且連續出現時才保留，
+/// 否則丟棄該標記
+pub fn realign_marks(original: &str, marks: &[MarkSpan], improved: &str) -> Vec<MarkSpan> {
+    let old_tokens = tokenize(original);
+    let new_tokens = tokenize(improved);
+    let mapping = align(original, &old_tokens, improved, &new_tokens);
+
+    marks
+        .iter()
+        .filter_map(|m| {
+            let covered: Vec<usize> = old_tokens
+                .iter()
+                .enumerate()
+                .filter(|(_, t)| t.start < m.end && m.start < t.end)
+                .map(|(i, _)| i)
+                .collect();
+            let mapped = covered
+                .iter()
+                .map(|&i| mapping[i])
+                .collect::<Option<Vec<usize>>>()?;
+            let (&first, &last) = (mapped.first()?, mapped.last()?);
+            if last - first + 1 != mapped.len() {
+                return None;
+            }
+            Some(MarkSpan {
+                start: new_tokens[first].start,
+                end: new_tokens[last].end,
+                mark: m.mark.clone(),
+            })
+        })
+        .collect()
+}
+
+/// 詞或單一標點，字元區間
+struct Token {
+    start: usize,
+    end: usize,
+}
+
+fn tokenize(text: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut word_start = None;
+    for (i, c) in text.chars().enumerate() {
+        if c.is_alphanumeric() {
+            word_start.get_or_insert(i);
+            continue;
+        }
+        if let Some(start) = word_start.take() {
+            tokens.push(Token { start, end: i });
+        }
+        if !c.is_whitespace() {
+            tokens.push(Token { start: i, end: i + 1 });
+        }
+    }
+    if let Some(start) = word_start {
+        tokens.push(Token {
+            start,
+            end: text.chars().count(),
+        });
+    }
+    tokens
+}
+
+/// LCS 對齊：回傳每個原文詞在新文字中的位置
+fn align(original: &str, old: &[Token], improved: &str, new: &[Token]) -> Vec<Option<usize>> {
+    let old_chars: Vec<char> = original.chars().collect();
+    let new_chars: Vec<char> = improved.chars().collect();
+    let same = |i: usize, j: usize| {
+        old_chars[old[i].start..old[i].end] == new_chars[new[j].start..new[j].end]
+    };
+
+    let (n, m) = (old.len(), new.len());
+    let mut lcs = vec![vec![0u32; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if same(i, j) {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut mapping = vec![None; n];
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if same(i, j) {
+            mapping[i] = Some(j);
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    mapping
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use yrs::{XmlElementPrelim, XmlFragment, XmlTextPrelim};
+
+    fn bold(start: usize, end: usize) -> MarkSpan {
+        MarkSpan {
+            start,
+            end,
+            mark: "bold".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_bold_word_survives_unchanged() {
+        let original = "the quick fox jumped";
+        // "quick"
+        let marks = vec![bold(4, 9)];
+
+        let improved = "Then the quick brown fox leapt.";
+        let realigned = realign_marks(original, &marks, improved);
+        assert_eq!(realigned, vec![bold(9, 14)]);
+        assert_eq!(
+            improved.chars().skip(9).take(5).collect::<String>(),
+            "quick"
+        );
+    }
+
+    #[test]
+    fn test_bold_word_dropped_when_removed() {
+        let original = "the quick fox jumped";
+        let marks = vec![bold(4, 9)];
+
+        let realigned = realign_marks(original, &marks, "the fast fox jumped");
+        assert!(realigned.is_empty());
+    }
+
+    #[test]
+    fn test_marks_for_text_reads_doc_formatting() {
+        let doc = Doc::new();
+        let fragment = doc.get_or_insert_xml_fragment("content");
+        {
+            let mut txn = doc.transact_mut();
+            let para = fragment.insert(&mut txn, 0, XmlElementPrelim::empty("paragraph"));
+            let text = para.insert(&mut txn, 0, XmlTextPrelim::new("Intro. the quick fox"));
+            let attrs = HashMap::from([(Arc::from("bold"), Any::Bool(true))]);
+            text.format(&mut txn, 11, 5, attrs);
+        }
+
+        let marks = marks_for_text(&doc, "the quick fox");
+        assert_eq!(marks, vec![bold(4, 9)]);
+        assert!(marks_for_text(&doc, "not in the doc").is_empty());
+    }
+}
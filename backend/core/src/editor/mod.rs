@@ -1,7 +1,18 @@
+pub mod marks;
 pub mod read;
+pub mod replay;
+pub mod undo;
 pub mod write;
 
-pub use read::get_doc_content;
+pub use marks::{MarkSpan, marks_for_text, realign_marks};
+pub use read::{
+    DocStats, export_html, export_markdown, get_doc_content, get_doc_stats, get_doc_text,
+};
+pub use replay::{RecordedUpdate, UpdateRecorder, read_recording, replay_updates};
+pub use undo::UserUndo;
 pub use write::{
-    UserWritingState, append_ai_content_to_doc, append_ai_content_word_by_word, prepare_words, has_content_structure
+    ChunkGranularity, UserWritingState, append_ai_content_to_doc, append_ai_content_word_by_word, prepare_words, has_content_structure,
+    ParagraphMode, append_ai_paragraphs_word_by_word, prepare_chars, prepare_chunks,
+    prepare_sentences, replace_text_in_doc, replace_nth_text_in_doc, replace_text_in_node, replace_texts_in_nodes, NodeEdit, import_markdown, format_all_occurrences, patch_text_nodes, set_document_title, text_node_contents, TextPatch, split_paragraphs, start_ai_paragraph,
+    PROGRESS_EVERY_WORDS, WordProgress, AI_ORIGIN, SERVER_ORIGIN, is_ai_origin,
 };
\ No newline at end of file
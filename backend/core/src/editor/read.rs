@@ -1,5 +1,8 @@
+use serde::Serialize;
 use std::sync::Arc;
-use yrs::{Doc, GetString, Transact, XmlFragment};
+use yrs::types::text::{Diff, YChange};
+use yrs::types::xml::{XmlElementRef, XmlOut, XmlTextRef};
+use yrs::{Any, Doc, GetString, Out, Text, Transact, Xml, XmlFragment};
 
 // ============================================================================
 // Constants: Element Type Definitions
@@ -17,6 +20,9 @@ const BLOCK_ELEMENTS: &[&str] = &[
 /// 換行元素列表：這些元素本身代表換行
 const BREAK_ELEMENTS: &[&str] = &["hard_break", "br"];
 
+/// 閱讀速度（每分鐘字數），用於估算閱讀時間
+const READING_WORDS_PER_MINUTE: usize = 200;
+
 // ============================================================================
 // Public API
 // ============================================================================
@@ -44,7 +50,118 @@ const BREAK_ELEMENTS: &[&str] = &["hard_break", "br"];
 pub fn get_doc_content(doc: &Arc<Doc>) -> String {
     let xml_fragment = doc.get_or_insert_xml_fragment("content");
     let txn = doc.transact();
-    extract_text_from_fragment(&xml_fragment, &txn)
+    extract_text_from_fragment(&xml_fragment, &txn, false)
+}
+
+/// 與 `get_doc_content` 相同，但不含文字節點的格式標籤（如 `<bold>`）
+///
+/// 給不解析這些標籤的使用者，例如 `GET /editor/content`。
+pub fn get_doc_text(doc: &Arc<Doc>) -> String {
+    let xml_fragment = doc.get_or_insert_xml_fragment("content");
+    let txn = doc.transact();
+    extract_text_from_fragment(&xml_fragment, &txn, true)
+}
+
+/// 文件統計資訊
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct DocStats {
+    /// 字元數（不含段落間的換行）
+    pub characters: usize,
+    pub words: usize,
+    /// 非空白的區塊數（段落、標題等）
+    pub paragraphs: usize,
+    /// 以每分鐘 200 字估算，無條件進位至整分鐘
+    pub reading_minutes: u64,
+}
+
+/// 計算文件統計資訊
+///
+/// 與 `get_doc_content` 使用同一套文字提取邏輯，因此字數與 AI 看到的內容一致。
+pub fn get_doc_stats(doc: &Arc<Doc>) -> DocStats {
+    stats_for_text(&get_doc_content(doc))
+}
+
+fn stats_for_text(content: &str) -> DocStats {
+    let words = count_words(content);
+    DocStats {
+        characters: content.chars().filter(|c| *c != '\n').count(),
+        words,
+        paragraphs: content
+            .split('\n')
+            .filter(|line| !line.trim().is_empty())
+            .count(),
+        reading_minutes: words.div_ceil(READING_WORDS_PER_MINUTE) as u64,
+    }
+}
+
+/// 計算字數：以空白分隔的詞各算一個字，中日文字元（漢字、假名）則每個字元算一個字
+pub(crate) fn count_words(content: &str) -> usize {
+    let mut words = 0;
+    let mut in_word = false;
+    for c in content.chars() {
+        if is_cjk_word_char(c) {
+            words += 1;
+            in_word = false;
+        } else if c.is_whitespace() || is_cjk_punctuation(c) {
+            in_word = false;
+        } else if !in_word {
+            words += 1;
+            in_word = true;
+        }
+    }
+    words
+}
+
+/// 漢字與平假名、片假名；韓文以空白分詞，因此不在此列
+pub(crate) fn is_cjk_word_char(c: char) -> bool {
+    matches!(
+        c,
+        '\u{3040}'..='\u{30FF}'
+            | '\u{3400}'..='\u{4DBF}'
+            | '\u{4E00}'..='\u{9FFF}'
+            | '\u{F900}'..='\u{FAFF}'
+            | '\u{20000}'..='\u{2FA1F}'
+    )
+}
+
+/// 全形標點（如「，」「。」）不算字，但會斷開前後的詞
+fn is_cjk_punctuation(c: char) -> bool {
+    matches!(c, '\u{3000}'..='\u{303F}' | '\u{FF00}'..='\u{FFEF}')
+}
+
+/// 將文件匯出為 Markdown
+///
+/// 與 `get_doc_content` 不同，這裡保留區塊結構與行內格式：
+/// - `heading` 依 `level` 屬性輸出 `#` ~ `######`
+/// - `code_block` 輸出 fenced code block（保留 `language` 屬性）
+/// - `blockquote` 每行加上 `> ` 前綴
+/// - bold / italic / code / strike 標記轉為對應的 Markdown 語法
+///
+/// 空白區塊會被略過，區塊之間以空行分隔。
+pub fn export_markdown(doc: &Arc<Doc>) -> String {
+    let xml_fragment = doc.get_or_insert_xml_fragment("content");
+    let txn = doc.transact();
+    let blocks: Vec<XmlOut> = (0..xml_fragment.len(&txn))
+        .filter_map(|i| xml_fragment.get(&txn, i))
+        .collect();
+    markdown_blocks(&blocks, &txn)
+}
+
+/// 將文件匯出為語意化 HTML
+///
+/// `paragraph` → `<p>`、`heading` → `<h1>` ~ `<h6>`（依 `level` 屬性）、
+/// `code_block` → `<pre><code>`、`blockquote` → `<blockquote>`、換行元素 → `<br>`；
+/// bold / italic 等格式輸出為 `<strong>`、`<em>`，所有文字皆經過跳脫。
+pub fn export_html(doc: &Arc<Doc>) -> String {
+    let xml_fragment = doc.get_or_insert_xml_fragment("content");
+    let txn = doc.transact();
+    let mut output = String::new();
+    for i in 0..xml_fragment.len(&txn) {
+        if let Some(child) = xml_fragment.get(&txn, i) {
+            html_node(&child, &txn, &mut output);
+        }
+    }
+    output
 }
 
 // ============================================================================
@@ -54,9 +171,11 @@ pub fn get_doc_content(doc: &Arc<Doc>) -> String {
 /// 從 XML Fragment 中提取所有文字內容
 ///
 /// 遍歷 fragment 的所有子節點，遞迴提取文字，最後清理末尾多餘的換行符。
+/// `plain` 為 true 時略過文字節點的格式標籤。
 fn extract_text_from_fragment(
     fragment: &yrs::types::xml::XmlFragmentRef,
     txn: &yrs::Transaction,
+    plain: bool,
 ) -> String {
     let mut content = String::new();
     let child_count = fragment.len(txn);
@@ -64,7 +183,7 @@ fn extract_text_from_fragment(
     // 遍歷所有子節點並提取文字
     for i in 0..child_count {
         if let Some(child) = fragment.get(txn, i) {
-            extract_text_from_node(&child, txn, &mut content, false);
+            extract_text_from_node(&child, txn, &mut content, false, plain);
         }
     }
 
@@ -84,21 +203,23 @@ fn extract_text_from_fragment(
 /// * `txn` - 只讀事務
 /// * `output` - 輸出緩衝區，累積提取的文字
 /// * `is_inline` - 標記當前是否在 inline 上下文中（用於控制換行行為）
+/// * `plain` - 是否略過文字節點的格式標籤
 fn extract_text_from_node(
     node: &yrs::types::xml::XmlOut,
     txn: &yrs::Transaction,
     output: &mut String,
     is_inline: bool,
+    plain: bool,
 ) {
     match node {
         yrs::types::xml::XmlOut::Text(text_node) => {
-            handle_text_node(text_node, txn, output);
+            handle_text_node(text_node, txn, output, plain);
         }
         yrs::types::xml::XmlOut::Element(element_node) => {
-            handle_element_node(element_node, txn, output, is_inline);
+            handle_element_node(element_node, txn, output, is_inline, plain);
         }
         yrs::types::xml::XmlOut::Fragment(fragment_node) => {
-            handle_fragment_node(fragment_node, txn, output, is_inline);
+            handle_fragment_node(fragment_node, txn, output, is_inline, plain);
         }
     }
 }
@@ -112,8 +233,13 @@ fn handle_text_node(
     text_node: &yrs::types::xml::XmlTextRef,
     txn: &yrs::Transaction,
     output: &mut String,
+    plain: bool,
 ) {
-    let text = text_node.get_string(txn);
+    let text = if plain {
+        super::write::plain_text(txn, text_node)
+    } else {
+        text_node.get_string(txn)
+    };
     if !text.is_empty() {
         output.push_str(&text);
     }
@@ -125,6 +251,7 @@ fn handle_element_node(
     txn: &yrs::Transaction,
     output: &mut String,
     is_inline: bool,
+    plain: bool,
 ) {
     let tag_name = element_node.tag().as_ref();
     let child_count = element_node.len(txn);
@@ -137,7 +264,7 @@ fn handle_element_node(
     // 如果當前是區塊級元素，子節點會被標記為 inline（避免重複換行）
     for i in 0..child_count {
         if let Some(child) = element_node.get(txn, i) {
-            extract_text_from_node(&child, txn, output, !is_block_element);
+            extract_text_from_node(&child, txn, output, !is_block_element, plain);
         }
     }
 
@@ -157,13 +284,293 @@ fn handle_fragment_node(
     txn: &yrs::Transaction,
     output: &mut String,
     is_inline: bool,
+    plain: bool,
 ) {
     let child_count = fragment_node.len(txn);
     for i in 0..child_count {
         if let Some(child) = fragment_node.get(txn, i) {
-            extract_text_from_node(&child, txn, output, is_inline);
+            extract_text_from_node(&child, txn, output, is_inline, plain);
+        }
+    }
+}
+
+// ============================================================================
+// Markdown Export
+// ============================================================================
+
+/// 行內格式對應的 Markdown 標記，由內而外套用
+const MARKDOWN_MARKS: &[(&str, &str)] = &[
+    ("code", "`"),
+    ("bold", "**"),
+    ("italic", "*"),
+    ("strike", "~~"),
+];
+
+/// 將多個區塊節點轉為 Markdown，以空行分隔並略過空區塊
+fn markdown_blocks(nodes: &[XmlOut], txn: &yrs::Transaction) -> String {
+    nodes
+        .iter()
+        .map(|node| markdown_block(node, txn))
+        .filter(|block| !block.trim().is_empty())
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// 將單一區塊節點轉為 Markdown
+fn markdown_block(node: &XmlOut, txn: &yrs::Transaction) -> String {
+    let element = match node {
+        XmlOut::Text(text_node) => return markdown_text(text_node, txn),
+        XmlOut::Fragment(fragment_node) => {
+            let children: Vec<XmlOut> = (0..fragment_node.len(txn))
+                .filter_map(|i| fragment_node.get(txn, i))
+                .collect();
+            return markdown_blocks(&children, txn);
+        }
+        XmlOut::Element(element) => element,
+    };
+
+    match element.tag().as_ref() {
+        "paragraph" => markdown_inline(element, txn),
+        "heading" => format!(
+            "{} {}",
+            "#".repeat(heading_level(element, txn)),
+            markdown_inline(element, txn)
+        ),
+        "code_block" => {
+            let language = element_attribute(element, txn, "language").unwrap_or_default();
+            format!("```{}\n{}\n```", language, code_text(element, txn))
+        }
+        "blockquote" => {
+            let children: Vec<XmlOut> = (0..element.len(txn))
+                .filter_map(|i| element.get(txn, i))
+                .collect();
+            markdown_blocks(&children, txn)
+                .lines()
+                .map(|line| {
+                    if line.is_empty() {
+                        ">".to_string()
+                    } else {
+                        format!("> {}", line)
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        }
+        "horizontal_rule" => "---".to_string(),
+        tag if is_break_element(tag) => String::new(),
+        _ => {
+            // 未知的容器元素：子節點含區塊元素時逐一輸出，否則視為段落
+            let children: Vec<XmlOut> = (0..element.len(txn))
+                .filter_map(|i| element.get(txn, i))
+                .collect();
+            let has_blocks = children.iter().any(|child| {
+                matches!(child, XmlOut::Element(e) if is_block_level_element(e.tag().as_ref()))
+            });
+            if has_blocks {
+                markdown_blocks(&children, txn)
+            } else {
+                markdown_inline(element, txn)
+            }
+        }
+    }
+}
+
+/// 將元素的行內子節點轉為 Markdown；換行元素輸出為 Markdown 硬換行
+fn markdown_inline(element: &XmlElementRef, txn: &yrs::Transaction) -> String {
+    let mut output = String::new();
+    for i in 0..element.len(txn) {
+        match element.get(txn, i) {
+            Some(XmlOut::Text(text_node)) => output.push_str(&markdown_text(&text_node, txn)),
+            Some(XmlOut::Element(child)) if is_break_element(child.tag().as_ref()) => {
+                output.push_str("  \n");
+            }
+            Some(XmlOut::Element(child)) => output.push_str(&markdown_inline(&child, txn)),
+            Some(XmlOut::Fragment(_)) | None => {}
+        }
+    }
+    output
+}
+
+/// 依文字節點的格式屬性加上 Markdown 標記
+///
+/// 標記只包住文字本身，前後空白留在標記外（`** bold**` 不是合法的 Markdown）
+fn markdown_text(text_node: &XmlTextRef, txn: &yrs::Transaction) -> String {
+    let mut output = String::new();
+    for (text, marks) in formatted_chunks(text_node, txn) {
+        let core = text.trim();
+        if core.is_empty() {
+            output.push_str(&text);
+            continue;
+        }
+        let mut marked = core.to_string();
+        for (name, syntax) in MARKDOWN_MARKS {
+            if marks.iter().any(|mark| mark == name) {
+                marked = format!("{syntax}{marked}{syntax}");
+            }
+        }
+        let leading = &text[..text.len() - text.trim_start().len()];
+        let trailing = &text[text.trim_end().len()..];
+        output.push_str(leading);
+        output.push_str(&marked);
+        output.push_str(trailing);
+    }
+    output
+}
+
+// ============================================================================
+// HTML Export
+// ============================================================================
+
+/// 行內格式對應的 HTML 標籤，由內而外套用
+const HTML_MARKS: &[(&str, &str)] = &[
+    ("code", "code"),
+    ("bold", "strong"),
+    ("italic", "em"),
+    ("strike", "s"),
+];
+
+/// 只改變標籤名稱的容器元素
+const HTML_CONTAINERS: &[(&str, &str)] = &[
+    ("paragraph", "p"),
+    ("blockquote", "blockquote"),
+    ("bullet_list", "ul"),
+    ("ordered_list", "ol"),
+    ("list_item", "li"),
+];
+
+/// 將節點轉為 HTML 並寫入 `output`；區塊與行內節點使用同一套規則
+fn html_node(node: &XmlOut, txn: &yrs::Transaction, output: &mut String) {
+    let element = match node {
+        XmlOut::Text(text_node) => return html_text(text_node, txn, output),
+        XmlOut::Fragment(fragment_node) => {
+            for i in 0..fragment_node.len(txn) {
+                if let Some(child) = fragment_node.get(txn, i) {
+                    html_node(&child, txn, output);
+                }
+            }
+            return;
+        }
+        XmlOut::Element(element) => element,
+    };
+
+    let tag_name = element.tag().as_ref();
+    let tag = match tag_name {
+        "heading" => format!("h{}", heading_level(element, txn)),
+        "code_block" => {
+            match element_attribute(element, txn, "language").filter(|l| !l.is_empty()) {
+                Some(language) => output.push_str(&format!(
+                    "<pre><code class=\"language-{}\">",
+                    escape_html(&language)
+                )),
+                None => output.push_str("<pre><code>"),
+            }
+            output.push_str(&escape_html(&code_text(element, txn)));
+            output.push_str("</code></pre>");
+            return;
+        }
+        "horizontal_rule" => return output.push_str("<hr>"),
+        tag if is_break_element(tag) => return output.push_str("<br>"),
+        tag => match HTML_CONTAINERS.iter().find(|(name, _)| *name == tag) {
+            Some((_, html)) => html.to_string(),
+            // 未知元素：只輸出子節點，不猜測標籤
+            None => String::new(),
+        },
+    };
+
+    if !tag.is_empty() {
+        output.push_str(&format!("<{tag}>"));
+    }
+    for i in 0..element.len(txn) {
+        if let Some(child) = element.get(txn, i) {
+            html_node(&child, txn, output);
+        }
+    }
+    if !tag.is_empty() {
+        output.push_str(&format!("</{tag}>"));
+    }
+}
+
+/// 跳脫文字並依格式屬性包上 `<strong>`、`<em>` 等標籤
+fn html_text(text_node: &XmlTextRef, txn: &yrs::Transaction, output: &mut String) {
+    for (text, marks) in formatted_chunks(text_node, txn) {
+        let mut marked = escape_html(&text);
+        for (name, tag) in HTML_MARKS {
+            if marks.iter().any(|mark| mark == name) {
+                marked = format!("<{tag}>{marked}</{tag}>");
+            }
+        }
+        output.push_str(&marked);
+    }
+}
+
+/// 跳脫 HTML 特殊字元，文字與屬性值皆可使用
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// ============================================================================
+// Export Helpers
+// ============================================================================
+
+/// 將文字節點拆成格式一致的片段，回傳 (文字, 生效中的格式名稱)
+fn formatted_chunks(text_node: &XmlTextRef, txn: &yrs::Transaction) -> Vec<(String, Vec<String>)> {
+    text_node
+        .diff(txn, YChange::identity)
+        .into_iter()
+        .filter_map(|chunk: Diff<YChange>| {
+            let Out::Any(Any::String(text)) = chunk.insert else {
+                return None;
+            };
+            let marks = chunk
+                .attributes
+                .iter()
+                .flat_map(|attrs| attrs.iter())
+                .filter(|(_, value)| !matches!(value, Any::Null | Any::Bool(false)))
+                .map(|(name, _)| name.to_string())
+                .collect();
+            Some((text.to_string(), marks))
+        })
+        .collect()
+}
+
+/// 標題層級，取自 `level` 屬性並限制在 1 ~ 6；缺少時視為 1
+fn heading_level(element: &XmlElementRef, txn: &yrs::Transaction) -> usize {
+    element_attribute(element, txn, "level")
+        .and_then(|level| level.parse::<f64>().ok())
+        .map_or(1, |level| (level as usize).clamp(1, 6))
+}
+
+/// 程式碼區塊的原始文字，不套用行內格式
+fn code_text(element: &XmlElementRef, txn: &yrs::Transaction) -> String {
+    let mut code = String::new();
+    for i in 0..element.len(txn) {
+        if let Some(child) = element.get(txn, i) {
+            extract_text_from_node(&child, txn, &mut code, true, false);
         }
     }
+    code.trim_end_matches('\n').to_string()
+}
+
+/// 讀取元素屬性並轉為字串
+fn element_attribute(
+    element: &XmlElementRef,
+    txn: &yrs::Transaction,
+    name: &str,
+) -> Option<String> {
+    element
+        .get_attribute(txn, name)
+        .map(|value| value.to_string(txn))
 }
 
 // ============================================================================
@@ -191,7 +598,8 @@ fn is_break_element(tag_name: &str) -> bool {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use yrs::XmlTextPrelim;
+    use yrs::types::Attrs;
+    use yrs::{XmlElementPrelim, XmlTextPrelim};
 
     #[test]
     fn test_get_doc_content_empty() {
@@ -221,7 +629,7 @@ mod tests {
         let doc = Doc::new();
         let xml_fragment = doc.get_or_insert_xml_fragment("content");
         let txn = doc.transact();
-        let text = extract_text_from_fragment(&xml_fragment, &txn);
+        let text = extract_text_from_fragment(&xml_fragment, &txn, false);
         assert_eq!(text, "");
     }
 
@@ -238,10 +646,262 @@ mod tests {
 
         // 提取文字
         let txn = doc.transact();
-        let text = extract_text_from_fragment(&fragment, &txn);
+        let text = extract_text_from_fragment(&fragment, &txn, false);
         assert_eq!(text, "hello, world!");
     }
 
+    /// 依序插入 (標籤, 文字) 區塊
+    fn doc_with_blocks(blocks: &[(&str, &str)]) -> Arc<Doc> {
+        let doc = Arc::new(Doc::new());
+        let fragment = doc.get_or_insert_xml_fragment("content");
+        {
+            let mut txn = doc.transact_mut();
+            for (i, (tag, text)) in blocks.iter().enumerate() {
+                let block = fragment.insert(&mut txn, i as u32, XmlElementPrelim::empty(*tag));
+                block.insert(&mut txn, 0, XmlTextPrelim::new(*text));
+            }
+        }
+        doc
+    }
+
+    #[test]
+    fn test_get_doc_stats_empty() {
+        let doc = Arc::new(Doc::new());
+        assert_eq!(get_doc_stats(&doc), DocStats::default());
+    }
+
+    #[test]
+    fn test_get_doc_stats_multiple_paragraphs() {
+        let doc = doc_with_blocks(&[
+            ("paragraph", "The quick brown fox"),
+            ("paragraph", ""),
+            ("paragraph", "jumps over the lazy dog."),
+        ]);
+        let stats = get_doc_stats(&doc);
+        assert_eq!(stats.words, 9);
+        assert_eq!(stats.paragraphs, 2);
+        assert_eq!(
+            stats.characters,
+            "The quick brown fox".len() + "jumps over the lazy dog.".len()
+        );
+        // 9 字以每分鐘 200 字計算，進位為 1 分鐘
+        assert_eq!(stats.reading_minutes, 1);
+    }
+
+    #[test]
+    fn test_get_doc_stats_counts_headings_as_blocks() {
+        let doc = doc_with_blocks(&[("heading", "Intro"), ("paragraph", "Hello there")]);
+        let stats = get_doc_stats(&doc);
+        assert_eq!(stats.words, 3);
+        assert_eq!(stats.paragraphs, 2);
+        assert_eq!(stats.characters, 16);
+    }
+
+    #[test]
+    fn test_get_doc_stats_counts_chinese_by_character() {
+        let doc = doc_with_blocks(&[
+            ("paragraph", "我們用 Rust 寫編輯器。"),
+            ("paragraph", "Hello, 世界！"),
+        ]);
+        let stats = get_doc_stats(&doc);
+        // 我們用 (3) + Rust (1) + 寫編輯器 (4)；Hello, (1) + 世界 (2)
+        assert_eq!(stats.words, 11);
+        assert_eq!(stats.paragraphs, 2);
+        assert_eq!(
+            stats.characters,
+            "我們用 Rust 寫編輯器。Hello, 世界！".chars().count()
+        );
+    }
+
+    #[test]
+    fn test_get_doc_stats_headings_only() {
+        let doc = doc_with_blocks(&[("heading", "Chapter One"), ("heading", "第二章")]);
+        let stats = get_doc_stats(&doc);
+        assert_eq!(stats.words, 5);
+        assert_eq!(stats.paragraphs, 2);
+        assert_eq!(stats.reading_minutes, 1);
+    }
+
+    #[test]
+    fn test_reading_time_rounds_up_to_a_minute_per_200_words() {
+        let text = vec!["word"; 400].join(" ");
+        assert_eq!(stats_for_text(&text).reading_minutes, 2);
+        let text = vec!["word"; 401].join(" ");
+        assert_eq!(stats_for_text(&text).reading_minutes, 3);
+    }
+
+    /// 插入帶屬性的區塊元素，回傳其參照以便加入子節點
+    fn insert_block(
+        doc: &Doc,
+        tag: &str,
+        attrs: &[(&str, &str)],
+        text: &str,
+    ) -> yrs::types::xml::XmlElementRef {
+        let fragment = doc.get_or_insert_xml_fragment("content");
+        let mut txn = doc.transact_mut();
+        let len = fragment.len(&txn);
+        let block = fragment.insert(&mut txn, len, XmlElementPrelim::empty(tag));
+        for (key, value) in attrs {
+            block.insert_attribute(&mut txn, *key, *value);
+        }
+        if !text.is_empty() {
+            block.insert(&mut txn, 0, XmlTextPrelim::new(text));
+        }
+        block
+    }
+
+    #[test]
+    fn test_export_markdown_empty() {
+        let doc = Arc::new(Doc::new());
+        assert_eq!(export_markdown(&doc), "");
+    }
+
+    #[test]
+    fn test_export_markdown_headings_use_level() {
+        let doc = Arc::new(Doc::new());
+        insert_block(&doc, "heading", &[("level", "1")], "Title");
+        insert_block(&doc, "heading", &[("level", "2")], "Section");
+        insert_block(&doc, "heading", &[], "No level");
+        insert_block(&doc, "paragraph", &[], "Body text.");
+
+        assert_eq!(
+            export_markdown(&doc),
+            "# Title\n\n## Section\n\n# No level\n\nBody text."
+        );
+    }
+
+    #[test]
+    fn test_export_markdown_code_block_is_fenced() {
+        let doc = Arc::new(Doc::new());
+        insert_block(
+            &doc,
+            "code_block",
+            &[("language", "rust")],
+            "fn main() {}\nlet x = 1;",
+        );
+        insert_block(&doc, "code_block", &[], "plain");
+
+        assert_eq!(
+            export_markdown(&doc),
+            "```rust\nfn main() {}\nlet x = 1;\n```\n\n```\nplain\n```"
+        );
+    }
+
+    #[test]
+    fn test_export_markdown_blockquote_prefixes_every_line() {
+        let doc = Arc::new(Doc::new());
+        let quote = insert_block(&doc, "blockquote", &[], "");
+        {
+            let mut txn = doc.transact_mut();
+            for (i, text) in ["First line", "Second line"].iter().enumerate() {
+                let para = quote.insert(&mut txn, i as u32, XmlElementPrelim::empty("paragraph"));
+                para.insert(&mut txn, 0, XmlTextPrelim::new(*text));
+            }
+        }
+
+        assert_eq!(export_markdown(&doc), "> First line\n>\n> Second line");
+    }
+
+    #[test]
+    fn test_export_markdown_preserves_bold_and_italic() {
+        let doc = Arc::new(Doc::new());
+        let para = insert_block(&doc, "paragraph", &[], "");
+        {
+            let mut txn = doc.transact_mut();
+            let text = para.insert(&mut txn, 0, XmlTextPrelim::new("Make it bold and italic "));
+            let bold = Attrs::from([(Arc::from("bold"), Any::Bool(true))]);
+            let italic = Attrs::from([(Arc::from("italic"), Any::Bool(true))]);
+            // "bold " 含尾端空白，標記應只包住文字
+            text.format(&mut txn, 8, 5, bold);
+            text.format(&mut txn, 17, 6, italic);
+        }
+
+        assert_eq!(export_markdown(&doc), "Make it **bold** and *italic* ");
+    }
+
+    #[test]
+    fn test_export_markdown_horizontal_rule_and_hard_break() {
+        let doc = Arc::new(Doc::new());
+        let para = insert_block(&doc, "paragraph", &[], "");
+        {
+            let mut txn = doc.transact_mut();
+            para.insert(&mut txn, 0, XmlTextPrelim::new("line one"));
+            para.insert(&mut txn, 1, XmlElementPrelim::empty("hard_break"));
+            para.insert(&mut txn, 2, XmlTextPrelim::new("line two"));
+        }
+        insert_block(&doc, "horizontal_rule", &[], "");
+        insert_block(&doc, "paragraph", &[], "After");
+
+        assert_eq!(
+            export_markdown(&doc),
+            "line one  \nline two\n\n---\n\nAfter"
+        );
+    }
+
+    #[test]
+    fn test_export_html_block_elements() {
+        let doc = Arc::new(Doc::new());
+        insert_block(&doc, "heading", &[("level", "2")], "Section");
+        insert_block(&doc, "paragraph", &[], "Body");
+        insert_block(&doc, "code_block", &[("language", "rust")], "let x = 1;");
+        let quote = insert_block(&doc, "blockquote", &[], "");
+        {
+            let mut txn = doc.transact_mut();
+            let para = quote.insert(&mut txn, 0, XmlElementPrelim::empty("paragraph"));
+            para.insert(&mut txn, 0, XmlTextPrelim::new("Quoted"));
+            para.insert(&mut txn, 1, XmlElementPrelim::empty("hard_break"));
+            para.insert(&mut txn, 2, XmlTextPrelim::new("again"));
+        }
+        insert_block(&doc, "horizontal_rule", &[], "");
+
+        assert_eq!(
+            export_html(&doc),
+            "<h2>Section</h2><p>Body</p>\
+             <pre><code class=\"language-rust\">let x = 1;</code></pre>\
+             <blockquote><p>Quoted<br>again</p></blockquote><hr>"
+        );
+    }
+
+    #[test]
+    fn test_export_html_heading_level_defaults_and_clamps() {
+        let doc = Arc::new(Doc::new());
+        insert_block(&doc, "heading", &[], "Default");
+        insert_block(&doc, "heading", &[("level", "9")], "Deep");
+        assert_eq!(export_html(&doc), "<h1>Default</h1><h6>Deep</h6>");
+    }
+
+    #[test]
+    fn test_export_html_escapes_text_and_code() {
+        let doc = Arc::new(Doc::new());
+        insert_block(&doc, "paragraph", &[], "Tom & \"Jerry\" <script>");
+        insert_block(&doc, "code_block", &[], "if a < b && c > d {}");
+
+        assert_eq!(
+            export_html(&doc),
+            "<p>Tom &amp; &quot;Jerry&quot; &lt;script&gt;</p>\
+             <pre><code>if a &lt; b &amp;&amp; c &gt; d {}</code></pre>"
+        );
+    }
+
+    #[test]
+    fn test_export_html_nested_marks() {
+        let doc = Arc::new(Doc::new());
+        let para = insert_block(&doc, "paragraph", &[], "");
+        {
+            let mut txn = doc.transact_mut();
+            let text = para.insert(&mut txn, 0, XmlTextPrelim::new("plain both bold"));
+            let bold = Attrs::from([(Arc::from("bold"), Any::Bool(true))]);
+            let italic = Attrs::from([(Arc::from("italic"), Any::Bool(true))]);
+            text.format(&mut txn, 6, 9, bold);
+            text.format(&mut txn, 6, 4, italic);
+        }
+
+        assert_eq!(
+            export_html(&doc),
+            "<p>plain <em><strong>both</strong></em><strong> bold</strong></p>"
+        );
+    }
+
     #[test]
     fn test_is_block_level_element() {
         assert!(is_block_level_element("paragraph"));
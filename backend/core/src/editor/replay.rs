@@ -0,0 +1,160 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use yrs::updates::decoder::Decode;
+use yrs::{Doc, Transact, Update};
+
+// ============================================================================
+// Recording
+// ============================================================================
+
+/// 一筆錄製的 Yjs 更新（v1 編碼），`at_ms` 為距離開始錄製的毫秒數
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedUpdate {
+    pub at_ms: u64,
+    pub update: Vec<u8>,
+}
+
+/// 將廣播出去的每筆更新以 JSON Lines 寫入檔案，供重播使用
+///
+/// observer 只把更新送進 channel，實際寫檔在獨立的 blocking task 中進行，
+/// 不會在 Yjs 交易中等待磁碟。
+pub struct UpdateRecorder {
+    started: Instant,
+    updates: mpsc::Sender<RecordedUpdate>,
+}
+
+impl UpdateRecorder {
+    /// 建立錄製檔並啟動寫入 task；recorder 被丟棄後，task 寫完剩餘的更新即結束
+    pub fn create(path: &Path) -> Result<(Self, JoinHandle<Result<()>>)> {
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create recording {}", path.display()))?;
+        let (updates, pending) = mpsc::channel();
+        let writer = tokio::task::spawn_blocking(move || {
+            let written = write_recording(file, pending);
+            if let Err(e) = &written {
+                tracing::warn!("❌ recording stopped: {e:?}");
+            }
+            written
+        });
+        let recorder = Self {
+            started: Instant::now(),
+            updates,
+        };
+        Ok((recorder, writer))
+    }
+
+    /// 在 observer 中呼叫，不會阻塞；寫入 task 已因錯誤停止時回傳錯誤
+    pub fn record(&self, update: &[u8]) -> Result<()> {
+        let entry = RecordedUpdate {
+            at_ms: self.started.elapsed().as_millis() as u64,
+            update: update.to_vec(),
+        };
+        self.updates
+            .send(entry)
+            .map_err(|_| anyhow::anyhow!("Recording writer has stopped"))
+    }
+}
+
+/// 依序寫入收到的更新；每批寫完即 `sync_data` 落盤，程序中斷也不會遺失已寫入的內容
+fn write_recording(mut file: File, updates: mpsc::Receiver<RecordedUpdate>) -> Result<()> {
+    while let Ok(first) = updates.recv() {
+        for entry in std::iter::once(first).chain(updates.try_iter()) {
+            let mut line = serde_json::to_vec(&entry)?;
+            line.push(b'\n');
+            file.write_all(&line)?;
+        }
+        file.sync_data()?;
+    }
+    Ok(())
+}
+
+/// 讀取錄製檔，略過空行
+pub fn read_recording(path: &Path) -> Result<Vec<RecordedUpdate>> {
+    let file =
+        File::open(path).with_context(|| format!("Failed to open recording {}", path.display()))?;
+    BufReader::new(file)
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| line.as_ref().map_or(true, |l| !l.trim().is_empty()))
+        .map(|(index, line)| {
+            serde_json::from_str(&line?)
+                .with_context(|| format!("Invalid recording entry on line {}", index + 1))
+        })
+        .collect()
+}
+
+// ============================================================================
+// Replay
+// ============================================================================
+
+/// 依序將錄製的更新套用到 `doc`
+///
+/// `realtime` 為 true 時依原本的時間間隔播放（用於示範），否則立即套用全部更新。
+pub async fn replay_updates(doc: &Doc, updates: &[RecordedUpdate], realtime: bool) -> Result<()> {
+    let mut previous_ms = 0;
+    for (index, entry) in updates.iter().enumerate() {
+        if realtime && entry.at_ms > previous_ms {
+            tokio::time::sleep(Duration::from_millis(entry.at_ms - previous_ms)).await;
+        }
+        previous_ms = entry.at_ms;
+
+        let update = Update::decode_v1(&entry.update)
+            .with_context(|| format!("Failed to decode update #{}", index))?;
+        doc.transact_mut()
+            .apply_update(update)
+            .with_context(|| format!("Failed to apply update #{}", index))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::editor::{append_ai_content_to_doc, get_doc_content};
+    use std::sync::Arc;
+    use yrs::{XmlElementPrelim, XmlFragment, XmlTextPrelim};
+
+    #[tokio::test]
+    async fn test_replay_reconstructs_recorded_session() {
+        let path = std::env::temp_dir().join(format!("yjs-recording-{}.jsonl", std::process::id()));
+        let (recorder, writer) = UpdateRecorder::create(&path).unwrap();
+        let recorder = Arc::new(recorder);
+
+        let doc = Arc::new(Doc::new());
+        let observer_recorder = recorder.clone();
+        let _sub = doc
+            .observe_update_v1(move |_txn, event| {
+                observer_recorder.record(&event.update).unwrap();
+            })
+            .unwrap();
+
+        // 模擬一段協作：建立段落，之後再追加兩次
+        let fragment = doc.get_or_insert_xml_fragment("content");
+        {
+            let mut txn = doc.transact_mut();
+            let para = fragment.insert(&mut txn, 0, XmlElementPrelim::empty("paragraph"));
+            para.insert(&mut txn, 0, XmlTextPrelim::new("Hello"));
+        }
+        append_ai_content_to_doc(&doc, " collaborative").unwrap();
+        append_ai_content_to_doc(&doc, " world").unwrap();
+
+        // Once every recorder is gone, the writer finishes what it was sent
+        drop((_sub, recorder));
+        writer.await.unwrap().unwrap();
+        let updates = read_recording(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(updates.len(), 3);
+        assert!(updates.windows(2).all(|w| w[0].at_ms <= w[1].at_ms));
+
+        let replayed = Arc::new(Doc::new());
+        replay_updates(&replayed, &updates, false).await.unwrap();
+        assert_eq!(get_doc_content(&replayed), get_doc_content(&doc));
+        assert!(get_doc_content(&replayed).contains("world"));
+    }
+}
@@ -0,0 +1,109 @@
+use anyhow::Result;
+use yrs::undo::UndoManager;
+use yrs::{Doc, Origin};
+
+// ============================================================================
+// Per-User Undo
+// ============================================================================
+
+/// 單一使用者的復原／重做堆疊
+///
+/// 只追蹤來源為 `origin` 的交易，因此 AI（[`AI_ORIGIN`](super::write::AI_ORIGIN)）
+/// 與其他使用者的修改都不會被復原。復原本身也是一筆交易，observer 會照常廣播。
+pub struct UserUndo(UndoManager);
+
+impl UserUndo {
+    /// 從現在開始追蹤 `origin` 對文檔內容的修改
+    pub fn new(doc: &Doc, origin: Origin) -> Self {
+        let fragment = doc.get_or_insert_xml_fragment("content");
+        let mut manager = UndoManager::new(doc, &fragment);
+        manager.include_origin(origin);
+        Self(manager)
+    }
+
+    /// 復原最近一次修改；沒有可復原的修改時回傳 `false`
+    pub fn undo(&mut self) -> Result<bool> {
+        Ok(self.0.try_undo()?)
+    }
+
+    /// 重做最近一次復原；沒有可重做的修改時回傳 `false`
+    pub fn redo(&mut self) -> Result<bool> {
+        Ok(self.0.try_redo()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::editor::write::{append_ai_content_to_doc, text_node_contents};
+    use std::sync::Arc;
+    use yrs::types::xml::{XmlElementPrelim, XmlOut};
+    use yrs::{Text, Transact, XmlFragment, XmlTextPrelim};
+
+    fn doc_with_paragraphs(texts: &[&str]) -> Arc<Doc> {
+        let doc = Arc::new(Doc::new());
+        let fragment = doc.get_or_insert_xml_fragment("content");
+        let mut txn = doc.transact_mut();
+        for (i, text) in texts.iter().enumerate() {
+            let para = fragment.insert(&mut txn, i as u32, XmlElementPrelim::empty("paragraph"));
+            para.insert(&mut txn, 0, XmlTextPrelim::new(*text));
+        }
+        drop(txn);
+        doc
+    }
+
+    /// Type `text` at `index` of a paragraph's text, as the connection with `origin`
+    fn type_as(doc: &Doc, origin: &str, paragraph: u32, index: u32, text: &str) {
+        let fragment = doc.get_or_insert_xml_fragment("content");
+        let mut txn = doc.transact_mut_with(origin);
+        let Some(XmlOut::Element(para)) = fragment.get(&txn, paragraph) else {
+            panic!("no paragraph {paragraph}");
+        };
+        let Some(XmlOut::Text(text_ref)) = para.get(&txn, 0) else {
+            panic!("paragraph {paragraph} has no text");
+        };
+        text_ref.insert(&mut txn, index, text);
+    }
+
+    #[test]
+    fn test_undo_leaves_ai_and_other_users_edits() {
+        let doc = doc_with_paragraphs(&["First.", "Second."]);
+        let mut undo = UserUndo::new(&doc, Origin::from("conn:1"));
+
+        type_as(&doc, "conn:1", 0, 6, " Typed.");
+        append_ai_content_to_doc(&doc, "AI wrote this.").unwrap();
+        type_as(&doc, "conn:2", 1, 0, "Other ");
+        assert_eq!(
+            text_node_contents(&doc),
+            ["First. Typed.", "Other Second. AI wrote this."]
+        );
+
+        assert!(undo.undo().unwrap());
+        assert_eq!(
+            text_node_contents(&doc),
+            ["First.", "Other Second. AI wrote this."]
+        );
+        // The AI's and the other writer's edits are not on this stack
+        assert!(!undo.undo().unwrap());
+
+        assert!(undo.redo().unwrap());
+        assert_eq!(
+            text_node_contents(&doc),
+            ["First. Typed.", "Other Second. AI wrote this."]
+        );
+        assert!(!undo.redo().unwrap());
+    }
+
+    #[test]
+    fn test_nothing_to_undo_before_the_user_edits() {
+        let doc = doc_with_paragraphs(&["Written before connecting."]);
+        let mut undo = UserUndo::new(&doc, Origin::from("conn:1"));
+        append_ai_content_to_doc(&doc, "More.").unwrap();
+
+        assert!(!undo.undo().unwrap());
+        assert_eq!(
+            text_node_contents(&doc),
+            ["Written before connecting. More."]
+        );
+    }
+}
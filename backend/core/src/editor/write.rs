@@ -1,10 +1,33 @@
+use super::read::is_cjk_word_char;
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
 use std::sync::{
     Arc,
-    atomic::{AtomicBool, Ordering},
+    atomic::{AtomicBool, AtomicUsize, Ordering},
 };
 use std::time::Duration;
-use yrs::{Doc, GetString, Text, Transact, XmlFragment};
+use yrs::types::Attrs;
+use yrs::types::text::{Diff, YChange};
+use yrs::types::xml::{XmlElementPrelim, XmlElementRef};
+use yrs::{
+    Any, Doc, GetString, OffsetKind, Origin, Out, ReadTxn, Text, Transact, Xml, XmlFragment,
+    XmlTextPrelim, XmlTextRef,
+};
+
+/// AI 工具寫入文檔時的交易來源
+///
+/// 使用者的復原只追蹤自己連線的來源，因此不會撤銷這些修改
+pub const AI_ORIGIN: &str = "ai";
+
+/// 使用者透過 API 要求、由伺服器代為寫入（標題、匯入）時的交易來源
+///
+/// 與 [`AI_ORIGIN`] 分開，客戶端不會把這些修改標示為 AI 所寫
+pub const SERVER_ORIGIN: &str = "server";
+
+/// 交易是否由伺服器以 [`AI_ORIGIN`] 寫入
+pub fn is_ai_origin(origin: Option<&Origin>) -> bool {
+    origin.is_some_and(|origin| origin.as_ref() == AI_ORIGIN.as_bytes())
+}
 
 // ============================================================================
 // User Writing Detection Context
@@ -19,6 +42,19 @@ pub struct UserWritingState {
     pub user_writing_flag: Arc<AtomicBool>,
     /// 用戶停止寫入的閾值（毫秒），超過此時間後自動清除標記
     pub writing_timeout_ms: u64,
+    /// 正在逐字追加內容的 AI 任務數量（composer / refiner）
+    ai_writers: Arc<AtomicUsize>,
+}
+
+/// AI 逐字追加期間持有；drop 時自動清除 AI 寫入標記
+pub struct AiWritingGuard {
+    ai_writers: Arc<AtomicUsize>,
+}
+
+impl Drop for AiWritingGuard {
+    fn drop(&mut self) {
+        self.ai_writers.fetch_sub(1, Ordering::Relaxed);
+    }
 }
 
 impl UserWritingState {
@@ -30,6 +66,7 @@ impl UserWritingState {
         Self {
             user_writing_flag: Arc::new(AtomicBool::new(false)),
             writing_timeout_ms,
+            ai_writers: Arc::new(AtomicUsize::new(0)),
         }
     }
 
@@ -55,6 +92,21 @@ impl UserWritingState {
     pub fn clear_user_writing(&self) {
         self.user_writing_flag.store(false, Ordering::Relaxed);
     }
+
+    /// 標記 AI 開始逐字追加，直到回傳的 guard 被 drop
+    ///
+    /// 可巢狀或併發呼叫，所有 guard 都 drop 後才視為 AI 停止寫入
+    pub fn start_ai_writing(&self) -> AiWritingGuard {
+        self.ai_writers.fetch_add(1, Ordering::Relaxed);
+        AiWritingGuard {
+            ai_writers: self.ai_writers.clone(),
+        }
+    }
+
+    /// 檢查是否有 AI 任務正在逐字追加內容；自動 linter 應等待其完成
+    pub fn is_ai_writing(&self) -> bool {
+        self.ai_writers.load(Ordering::Relaxed) > 0
+    }
 }
 
 // ============================================================================
@@ -100,6 +152,75 @@ pub fn prepare_words(content: &str) -> Vec<String> {
         .collect()
 }
 
+/// 將文字分割為句子列表，句子之間保留一個空格，最後一句加換行符
+///
+/// 以 `.` `!` `?` 後接空白作為句尾；全形 `。` `！` `？` 不需空白
+pub fn prepare_sentences(content: &str) -> Vec<String> {
+    let mut sentences = Vec::new();
+    let mut current = String::new();
+    let mut chars = content.trim().chars().peekable();
+
+    while let Some(c) = chars.next() {
+        current.push(c);
+        let at_boundary = chars.peek().is_none_or(|next| next.is_whitespace());
+        let ends_sentence = match c {
+            '.' | '!' | '?' => at_boundary,
+            '。' | '！' | '？' => true,
+            _ => false,
+        };
+        if ends_sentence {
+            sentences.push(current.trim().to_string());
+            current.clear();
+        }
+    }
+    if !current.trim().is_empty() {
+        sentences.push(current.trim().to_string());
+    }
+
+    terminate_chunks(sentences, " ")
+}
+
+/// 將文字分割為單一字元列表（以 Unicode 字元為單位），最後一個字元加換行符
+pub fn prepare_chars(content: &str) -> Vec<String> {
+    let chars = content.trim().chars().map(String::from).collect();
+    terminate_chunks(chars, "")
+}
+
+/// 串流時的切分粒度
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChunkGranularity {
+    #[default]
+    Words,
+    Sentences,
+    Chars,
+}
+
+/// 依粒度切分文字，與串流寫入時使用的切分方式完全相同
+pub fn prepare_chunks(content: &str, granularity: ChunkGranularity) -> Vec<String> {
+    match granularity {
+        ChunkGranularity::Words => prepare_words(content),
+        ChunkGranularity::Sentences => prepare_sentences(content),
+        ChunkGranularity::Chars => prepare_chars(content),
+    }
+}
+
+/// 每個片段後加上分隔符，最後一個片段改加換行符
+fn terminate_chunks(chunks: Vec<String>, separator: &str) -> Vec<String> {
+    let last = chunks.len().saturating_sub(1);
+    chunks
+        .into_iter()
+        .enumerate()
+        .map(|(index, chunk)| {
+            if index == last {
+                format!("{}\n", chunk)
+            } else {
+                format!("{}{}", chunk, separator)
+            }
+        })
+        .collect()
+}
+
 /// Check if the document has content structure (at least one paragraph)
 pub fn has_content_structure(doc: &Arc<Doc>) -> bool {
     let xml_fragment = doc.get_or_insert_xml_fragment("content");
@@ -109,6 +230,10 @@ pub fn has_content_structure(doc: &Arc<Doc>) -> bool {
 
 /// 將 AI 生成的內容寫入 Doc 的最後一個段落
 ///
+/// 內容原樣寫入，不做 trim：`prepare_words` 產生的片段已自帶空格或換行符。
+/// 只有在既有文字不以空白結尾、且內容也不以空白開頭時才補一個空格，
+/// 避免與前文黏在一起，也不會與片段自帶的空格重複。
+///
 /// # Arguments
 /// * `doc` - 共享的 Yrs Doc 實例
 /// * `content` - 要寫入的文字內容
@@ -137,7 +262,7 @@ pub fn append_ai_content_to_doc(doc: &Arc<Doc>, content: &str) -> Result<()> {
     }
 
     let xml_fragment = doc.get_or_insert_xml_fragment("content");
-    let mut txn = doc.transact_mut();
+    let mut txn = doc.transact_mut_with(AI_ORIGIN);
 
     // 獲取 fragment 長度
     let len = xml_fragment.len(&txn);
@@ -180,11 +305,15 @@ pub fn append_ai_content_to_doc(doc: &Arc<Doc>, content: &str) -> Result<()> {
 
     // 在文字末尾插入 AI 生成的內容
     let current_len = text_ref.len(&txn);
-    // 如果已有文字，在前面加空格
-    let text_to_insert = if current_len > 0 {
-        format!(" {}", content.trim())
+    let ends_with_space = plain_text(&txn, &text_ref)
+        .chars()
+        .next_back()
+        .is_none_or(char::is_whitespace);
+    // 前文與內容之間都沒有空白時才補空格
+    let text_to_insert = if ends_with_space || content.starts_with(char::is_whitespace) {
+        content.to_string()
     } else {
-        content.trim().to_string()
+        format!(" {}", content)
     };
 
     text_ref.insert(&mut txn, current_len, &text_to_insert);
@@ -193,6 +322,12 @@ pub fn append_ai_content_to_doc(doc: &Arc<Doc>, content: &str) -> Result<()> {
     Ok(())
 }
 
+/// 逐字追加的進度回報，參數為 `(已寫入單詞數, 總單詞數)`
+pub type WordProgress<'a> = &'a (dyn Fn(usize, usize) + Send + Sync);
+
+/// 每追加多少個單詞回報一次進度（最後一個單詞一定回報）
+pub const PROGRESS_EVERY_WORDS: usize = 5;
+
 /// 逐字追加預處理的單詞列表到文檔
 ///
 /// **重要**：一旦檢測到用戶寫入，立即停止並拋棄剩餘單詞，不恢復
@@ -202,6 +337,7 @@ pub fn append_ai_content_to_doc(doc: &Arc<Doc>, content: &str) -> Result<()> {
 /// * `words` - 預處理的單詞列表（Vec<String>），每個單詞已包含空格或換行符
 /// * `delay_ms` - 每個單詞之間的延遲（毫秒），用於流式效果，預設100ms
 /// * `user_state` - 用戶寫入狀態，用於檢測用戶是否在寫入
+/// * `progress` - 可選的進度回報，每 `PROGRESS_EVERY_WORDS` 個單詞呼叫一次
 ///
 /// # Returns
 /// `Ok(())` 如果成功完成或中斷
@@ -209,26 +345,29 @@ pub fn append_ai_content_to_doc(doc: &Arc<Doc>, content: &str) -> Result<()> {
 ///
 /// # Behavior
 /// - 每次追加前檢查 `user_state.is_user_writing()`
-/// - 如果用戶開始寫入，立即返回 `Ok(())`，拋棄剩餘單詞
+/// - 如果用戶開始寫入，立即返回 `Ok(())`，拋棄剩餘單詞，之後不再回報進度
 /// - 不保留任何狀態，每次調用都是獨立的
 pub async fn append_ai_content_word_by_word(
     doc: &Arc<Doc>,
     words: Vec<String>,
     delay_ms: u64,
     user_state: &UserWritingState,
+    progress: Option<WordProgress<'_>>,
 ) -> Result<()> {
     if words.is_empty() {
         return Ok(());
     }
+    let total = words.len();
 
     // 在開始前檢查一次
     if user_state.is_user_writing() {
         tracing::info!("User is writing, skipping AI append");
         return Ok(()); // 直接拋棄所有單詞
     }
+    let _ai_writing = user_state.start_ai_writing();
 
     // 遍歷預處理的單詞列表
-    for word in words {
+    for (index, word) in words.into_iter().enumerate() {
         // 每次追加前再次檢查用戶是否開始寫入
         if user_state.is_user_writing() {
             tracing::info!(
@@ -240,6 +379,13 @@ pub async fn append_ai_content_word_by_word(
         // 追加單詞（已包含空格或換行符）
         append_ai_content_to_doc(doc, &word)?;
 
+        let done = index + 1;
+        if let Some(report) = progress {
+            if done % PROGRESS_EVERY_WORDS == 0 || done == total {
+                report(done, total);
+            }
+        }
+
         // 延遲以產生流式效果
         if delay_ms > 0 {
             tokio::time::sleep(Duration::from_millis(delay_ms)).await;
@@ -249,6 +395,174 @@ pub async fn append_ai_content_word_by_word(
     Ok(())
 }
 
+// ============================================================================
+// Paragraph Import
+// ============================================================================
+
+/// 多行 AI 輸出如何對應到段落
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ParagraphMode {
+    /// 全部接在最後一個段落（原本的行為）
+    #[default]
+    SingleParagraph,
+    /// 每個空行分隔的區塊成為一個段落，區塊內的換行視為空格
+    SplitOnBlankLines,
+    /// 每一行成為一個段落
+    SplitOnNewline,
+}
+
+/// 依模式將 AI 輸出切分為段落文字，空段落會被略過
+pub fn split_paragraphs(content: &str, mode: ParagraphMode) -> Vec<String> {
+    let paragraphs: Vec<String> = match mode {
+        ParagraphMode::SingleParagraph => vec![content.trim().to_string()],
+        ParagraphMode::SplitOnBlankLines => {
+            let mut blocks = vec![Vec::new()];
+            for line in content.lines().map(str::trim) {
+                if line.is_empty() {
+                    blocks.push(Vec::new());
+                } else if let Some(block) = blocks.last_mut() {
+                    block.push(line);
+                }
+            }
+            blocks.into_iter().map(|block| block.join(" ")).collect()
+        }
+        ParagraphMode::SplitOnNewline => content
+            .lines()
+            .map(|line| line.trim().to_string())
+            .collect(),
+    };
+    paragraphs.into_iter().filter(|p| !p.is_empty()).collect()
+}
+
+/// 在文檔末尾新增一個空段落（含空文字節點），之後的追加會寫進這個段落
+pub fn start_ai_paragraph(doc: &Arc<Doc>) -> Result<()> {
+    let xml_fragment = doc.get_or_insert_xml_fragment("content");
+    let mut txn = doc.transact_mut_with(AI_ORIGIN);
+    let len = xml_fragment.len(&txn);
+    let para = xml_fragment.insert(
+        &mut txn,
+        len,
+        yrs::types::xml::XmlElementPrelim::empty("paragraph"),
+    );
+    para.insert(&mut txn, 0, yrs::XmlTextPrelim::new(""));
+    Ok(())
+}
+
+/// 逐字追加多行 AI 輸出，依 `mode` 建立新段落
+///
+/// 第一段接在最後一個段落後面（與單段模式相同），其餘每段各自新增一個 `paragraph`。
+/// 用戶開始寫入時停止，不再新增段落。進度以整段輸出的單詞數計算。
+pub async fn append_ai_paragraphs_word_by_word(
+    doc: &Arc<Doc>,
+    content: &str,
+    mode: ParagraphMode,
+    delay_ms: u64,
+    user_state: &UserWritingState,
+    progress: Option<WordProgress<'_>>,
+) -> Result<()> {
+    let paragraphs: Vec<Vec<String>> = split_paragraphs(content, mode)
+        .iter()
+        .map(|paragraph| prepare_words(paragraph))
+        .collect();
+    let total = paragraphs.iter().map(Vec::len).sum();
+    let mut done = 0;
+    // 段落之間也維持 AI 寫入標記，避免 linter 在兩段之間插入
+    let _ai_writing = user_state.start_ai_writing();
+    for (index, words) in paragraphs.into_iter().enumerate() {
+        if index > 0 {
+            if user_state.is_user_writing() {
+                tracing::info!("User started writing, not starting another AI paragraph");
+                return Ok(());
+            }
+            start_ai_paragraph(doc)?;
+        }
+        let written_before = done;
+        done += words.len();
+        // 把段落內的進度換算成整體進度
+        let overall = |n: usize, _: usize| {
+            if let Some(report) = progress {
+                report(written_before + n, total);
+            }
+        };
+        let paragraph_progress = progress.map(|_| &overall as WordProgress<'_>);
+        append_ai_content_word_by_word(doc, words, delay_ms, user_state, paragraph_progress)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Replace the first occurrence of `target` in any text node with `replacement`
+///
+/// Used to swap a highlighted directive (e.g. `[expand on X]`) for the content
+/// generated from it, in a single transaction.
+///
+/// # Returns
+/// `Ok(true)` if the text was found and replaced, `Ok(false)` if it no longer exists
+pub fn replace_text_in_doc(doc: &Arc<Doc>, target: &str, replacement: &str) -> Result<bool> {
+    replace_nth_text_in_doc(doc, target, 0, replacement)
+}
+
+/// Replace the `nth` (0-based) occurrence of `target`, counting in document order
+///
+/// For callers that know which of several identical passages they mean, e.g. a
+/// selection given as an offset into `get_doc_content`.
+///
+/// # Returns
+/// `Ok(true)` if the text was found and replaced, `Ok(false)` if there is no such occurrence
+pub fn replace_nth_text_in_doc(
+    doc: &Arc<Doc>,
+    target: &str,
+    nth: usize,
+    replacement: &str,
+) -> Result<bool> {
+    if target.is_empty() {
+        return Ok(false);
+    }
+
+    let xml_fragment = doc.get_or_insert_xml_fragment("content");
+    let mut txn = doc.transact_mut_with(AI_ORIGIN);
+    let mut text_nodes = Vec::new();
+    collect_text_nodes(&txn, &xml_fragment, &mut text_nodes);
+
+    let mut skip = nth;
+    for text_ref in text_nodes {
+        let current_text = plain_text(&txn, &text_ref);
+        for (byte_index, _) in current_text.match_indices(target) {
+            if skip > 0 {
+                skip -= 1;
+                continue;
+            }
+            let index = text_len(doc, &current_text[..byte_index]);
+            text_ref.remove_range(&mut txn, index, text_len(doc, target));
+            text_ref.insert(&mut txn, index, replacement);
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Length of `s` in the offset unit the document is configured with
+pub(crate) fn text_len(doc: &Doc, s: &str) -> u32 {
+    match doc.options().offset_kind {
+        OffsetKind::Utf16 => s.encode_utf16().count() as u32,
+        _ => s.len() as u32,
+    }
+}
+
+/// Text content of a node without the formatting tags `get_string` renders
+pub(crate) fn plain_text(txn: &impl ReadTxn, text_ref: &XmlTextRef) -> String {
+    text_ref
+        .diff(txn, YChange::identity)
+        .into_iter()
+        .filter_map(|chunk: Diff<YChange>| match chunk.insert {
+            Out::Any(Any::String(s)) => Some(s.to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
 /// Apply text replacements to all text nodes in the document
 ///
 /// This function traverses the XML fragment, finds all text nodes,
@@ -274,7 +588,7 @@ pub fn apply_replacements(
     
     // CRITICAL: XmlTextRef references are tied to the transaction they were created in.
     // We MUST collect them within the write transaction, not before it.
-    let mut txn = doc.transact_mut();
+    let mut txn = doc.transact_mut_with(AI_ORIGIN);
     let mut text_nodes = Vec::new();
     collect_text_nodes(&txn, &xml_fragment, &mut text_nodes);
 
@@ -308,9 +622,320 @@ pub fn apply_replacements(
     Ok(())
 }
 
+/// Apply formatting `attrs` to every occurrence of `word` in the document
+///
+/// Matches are exact and case-sensitive, so the text itself never changes; only
+/// the matched ranges gain the attributes. A match must be a whole word, so "cat"
+/// leaves "concatenate" alone. All nodes are formatted in a single transaction,
+/// so clients receive one update.
+///
+/// # Returns
+/// The number of occurrences that were formatted
+pub fn format_all_occurrences(doc: &Arc<Doc>, word: &str, attrs: Attrs) -> Result<usize> {
+    if word.is_empty() {
+        anyhow::bail!("Cannot format an empty word");
+    }
+
+    let xml_fragment = doc.get_or_insert_xml_fragment("content");
+    let mut txn = doc.transact_mut_with(AI_ORIGIN);
+    let mut text_nodes = Vec::new();
+    collect_text_nodes(&txn, &xml_fragment, &mut text_nodes);
+
+    let word_len = text_len(doc, word);
+    let mut count = 0;
+    for text_ref in text_nodes {
+        let current_text = plain_text(&txn, &text_ref);
+        for (byte_index, _) in current_text.match_indices(word) {
+            let before = &current_text[..byte_index];
+            let after = &current_text[byte_index + word.len()..];
+            if !is_whole_word(before, word, after) {
+                continue;
+            }
+            let index = text_len(doc, before);
+            text_ref.format(&mut txn, index, word_len, attrs.clone());
+            count += 1;
+        }
+    }
+
+    Ok(count)
+}
+
+/// Whether `word`, found between `before` and `after`, stands on its own rather
+/// than inside a longer word. Han and kana have no spaces between words, so they
+/// never join one.
+fn is_whole_word(before: &str, word: &str, after: &str) -> bool {
+    let is_word_char = |c: char| (c.is_alphanumeric() || c == '_') && !is_cjk_word_char(c);
+    let joins = |outer: Option<char>, inner: Option<char>| {
+        outer.is_some_and(is_word_char) && inner.is_some_and(is_word_char)
+    };
+    !joins(before.chars().next_back(), word.chars().next())
+        && !joins(after.chars().next(), word.chars().next_back())
+}
+
+/// A rewrite of one text node, addressed by its position in [`text_node_contents`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextPatch {
+    pub node: usize,
+    /// The node's text when the rewrite was made
+    pub original: String,
+    pub replacement: String,
+}
+
+/// Plain text of every text node in the document, in document order
+pub fn text_node_contents(doc: &Arc<Doc>) -> Vec<String> {
+    let xml_fragment = doc.get_or_insert_xml_fragment("content");
+    let txn = doc.transact();
+    let mut text_nodes = Vec::new();
+    collect_text_nodes(&txn, &xml_fragment, &mut text_nodes);
+    text_nodes
+        .iter()
+        .map(|text_ref| plain_text(&txn, text_ref))
+        .collect()
+}
+
+/// Rewrite text nodes in place, leaving the elements around them alone
+///
+/// Only the part of a node that differs from its replacement is swapped, so an
+/// unchanged prefix or suffix keeps its marks. A node whose text no longer
+/// matches `original` was edited in the meantime and is skipped. All nodes are
+/// patched in a single transaction, so clients receive one update.
+///
+/// # Returns
+/// The number of nodes that were patched
+pub fn patch_text_nodes(doc: &Arc<Doc>, patches: &[TextPatch]) -> Result<usize> {
+    let xml_fragment = doc.get_or_insert_xml_fragment("content");
+    let mut txn = doc.transact_mut_with(AI_ORIGIN);
+    let mut text_nodes = Vec::new();
+    collect_text_nodes(&txn, &xml_fragment, &mut text_nodes);
+
+    let mut count = 0;
+    for patch in patches {
+        let Some(text_ref) = text_nodes.get(patch.node) else {
+            continue;
+        };
+        if plain_text(&txn, text_ref) != patch.original {
+            tracing::debug!(
+                "Text node {} changed since it was read, skipping",
+                patch.node
+            );
+            continue;
+        }
+        let Some((start, removed, inserted)) = changed_range(&patch.original, &patch.replacement)
+        else {
+            continue;
+        };
+        let index = text_len(doc, &patch.original[..start]);
+        if !removed.is_empty() {
+            text_ref.remove_range(&mut txn, index, text_len(doc, removed));
+        }
+        if !inserted.is_empty() {
+            text_ref.insert(&mut txn, index, inserted);
+        }
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+/// Byte offset at which `original` and `replacement` start to differ, and the
+/// differing middle of each; `None` when they are equal
+fn changed_range<'a>(original: &'a str, replacement: &'a str) -> Option<(usize, &'a str, &'a str)> {
+    if original == replacement {
+        return None;
+    }
+    let prefix: usize = original
+        .chars()
+        .zip(replacement.chars())
+        .take_while(|(a, b)| a == b)
+        .map(|(a, _)| a.len_utf8())
+        .sum();
+    let suffix: usize = original[prefix..]
+        .chars()
+        .rev()
+        .zip(replacement[prefix..].chars().rev())
+        .take_while(|(a, b)| a == b)
+        .map(|(a, _)| a.len_utf8())
+        .sum();
+    Some((
+        prefix,
+        &original[prefix..original.len() - suffix],
+        &replacement[prefix..replacement.len() - suffix],
+    ))
+}
+
+/// Rewrite `original` to `replacement` inside the top-level node at `index`
+///
+/// Only the differing characters are swapped, so the marks around them survive.
+/// `original` must occur exactly once in the node's text, read across all of its
+/// text nodes; otherwise the node was edited since the text was read, and nothing
+/// is changed.
+///
+/// # Returns
+/// `true` if the text was replaced
+pub fn replace_text_in_node(
+    doc: &Arc<Doc>,
+    index: u32,
+    original: &str,
+    replacement: &str,
+) -> Result<bool> {
+    let xml_fragment = doc.get_or_insert_xml_fragment("content");
+    let mut txn = doc.transact_mut_with(AI_ORIGIN);
+    Ok(replace_in_node(
+        doc,
+        &mut txn,
+        &xml_fragment,
+        index,
+        original,
+        replacement,
+    ))
+}
+
+/// A rewrite inside one top-level node, as [`replace_text_in_node`] takes it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeEdit {
+    pub index: u32,
+    pub original: String,
+    pub replacement: String,
+}
+
+/// [`replace_text_in_node`] for several edits in a single transaction, so
+/// clients receive one update
+///
+/// # Returns
+/// Whether each edit was applied, in the order given
+pub fn replace_texts_in_nodes(doc: &Arc<Doc>, edits: &[NodeEdit]) -> Result<Vec<bool>> {
+    let xml_fragment = doc.get_or_insert_xml_fragment("content");
+    let mut txn = doc.transact_mut_with(AI_ORIGIN);
+    Ok(edits
+        .iter()
+        .map(|edit| {
+            replace_in_node(
+                doc,
+                &mut txn,
+                &xml_fragment,
+                edit.index,
+                &edit.original,
+                &edit.replacement,
+            )
+        })
+        .collect())
+}
+
+fn replace_in_node(
+    doc: &Arc<Doc>,
+    txn: &mut yrs::TransactionMut,
+    xml_fragment: &yrs::XmlFragmentRef,
+    index: u32,
+    original: &str,
+    replacement: &str,
+) -> bool {
+    let (original, replacement) = (original.trim(), replacement.trim());
+    if original.is_empty() {
+        return false;
+    }
+
+    let mut text_nodes = Vec::new();
+    match xml_fragment.get(txn, index) {
+        Some(yrs::types::xml::XmlOut::Element(elem)) => {
+            collect_text_nodes_from_elem(txn, &elem, &mut text_nodes)
+        }
+        Some(yrs::types::xml::XmlOut::Text(text_ref)) => text_nodes.push(text_ref),
+        _ => return false,
+    }
+
+    // Text read from the node flattens its text nodes across marks and inline
+    // elements, so the match runs over all of them joined together
+    let texts: Vec<(yrs::XmlTextRef, String)> = text_nodes
+        .into_iter()
+        .map(|text_ref| {
+            let text = plain_text(txn, &text_ref);
+            (text_ref, text)
+        })
+        .collect();
+    let joined: String = texts.iter().map(|(_, text)| text.as_str()).collect();
+    let mut matches = joined.match_indices(original).map(|(at, _)| at);
+    let Some(at) = matches.next() else {
+        return false;
+    };
+    if matches.next().is_some() {
+        // Ambiguous: there is no telling which occurrence was meant
+        return false;
+    }
+    let Some((start, removed, inserted)) = changed_range(original, replacement) else {
+        return true;
+    };
+
+    // The changed range may span several text nodes: each loses its share of
+    // `removed`, and `inserted` goes where the change starts
+    let (from, to) = (at + start, at + start + removed.len());
+    let last = texts.len() - 1;
+    let mut node_start = 0;
+    for (i, (text_ref, text)) in texts.iter().enumerate() {
+        let node_end = node_start + text.len();
+        let (lo, hi) = (from.max(node_start), to.min(node_end));
+        if lo < hi {
+            text_ref.remove_range(
+                txn,
+                text_len(doc, &text[..lo - node_start]),
+                text_len(doc, &text[lo - node_start..hi - node_start]),
+            );
+        }
+        let holds_start = (node_start..node_end).contains(&from) || (i == last && from == node_end);
+        if holds_start && !inserted.is_empty() {
+            text_ref.insert(txn, text_len(doc, &text[..from - node_start]), inserted);
+        }
+        node_start = node_end;
+    }
+    true
+}
+
+/// Make `title` the document's level-1 heading, in a single transaction
+///
+/// When the document already starts with a heading, that heading becomes
+/// level 1 and its text is replaced in place; otherwise a new heading is
+/// inserted above the existing content, which is left as it was.
+pub fn set_document_title(doc: &Arc<Doc>, title: &str) -> Result<()> {
+    let title = title.trim();
+    if title.is_empty() {
+        anyhow::bail!("Cannot set an empty title");
+    }
+
+    let xml_fragment = doc.get_or_insert_xml_fragment("content");
+    let mut txn = doc.transact_mut_with(SERVER_ORIGIN);
+    let existing = match xml_fragment.get(&txn, 0) {
+        Some(yrs::types::xml::XmlOut::Element(element)) if element.tag().as_ref() == "heading" => {
+            Some(element)
+        }
+        _ => None,
+    };
+    let Some(heading) = existing else {
+        let heading = xml_fragment.insert(&mut txn, 0, XmlElementPrelim::empty("heading"));
+        heading.insert_attribute(&mut txn, "level", Any::Number(1.0));
+        heading.insert(&mut txn, 0, XmlTextPrelim::new(title));
+        return Ok(());
+    };
+
+    heading.insert_attribute(&mut txn, "level", Any::Number(1.0));
+    // Keep the heading's first text node so collaborators' cursors in it survive
+    let text_ref = match heading.get(&txn, 0) {
+        Some(yrs::types::xml::XmlOut::Text(text_ref)) => text_ref,
+        _ => heading.insert(&mut txn, 0, XmlTextPrelim::new("")),
+    };
+    let len = heading.len(&txn);
+    if len > 1 {
+        heading.remove_range(&mut txn, 1, len - 1);
+    }
+    let text_len = text_ref.len(&txn);
+    if text_len > 0 {
+        text_ref.remove_range(&mut txn, 0, text_len);
+    }
+    text_ref.insert(&mut txn, 0, title);
+    Ok(())
+}
+
 /// Helper: Recursively find all XmlTextRef nodes in a fragment
 /// Uses ReadTxn trait so it works with both Transaction and TransactionMut
-fn collect_text_nodes(
+pub(crate) fn collect_text_nodes(
     txn: &impl yrs::ReadTxn,
     fragment: &yrs::XmlFragmentRef,
     collector: &mut Vec<yrs::XmlTextRef>,
@@ -359,6 +984,230 @@ fn collect_text_nodes_from_elem(
     }
 }
 
+// ============================================================================
+// Markdown Import
+// ============================================================================
+
+/// Markdown 解析後的區塊
+#[derive(Debug, Clone, PartialEq)]
+enum MarkdownBlock {
+    Paragraph(String),
+    Heading(u8, String),
+    CodeBlock { language: String, code: String },
+    Blockquote(Vec<MarkdownBlock>),
+    HorizontalRule,
+}
+
+/// 以 Markdown 取代整份文件內容
+///
+/// 支援段落、`#` 標題、fenced code block、`>` 引言與分隔線；行內的
+/// `**bold**`、`*italic*`、`` `code` ``、`~~strike~~` 轉為文字格式。
+/// 所有變更在同一個事務中完成，連線中的客戶端只會收到一次更新。
+/// 匯入後文件即有段落結構，`append_ai_content_to_doc` 可直接追加。
+pub fn import_markdown(doc: &Arc<Doc>, markdown: &str) -> Result<()> {
+    let blocks = parse_markdown_blocks(markdown);
+    if blocks.is_empty() {
+        return Err(anyhow::anyhow!("Markdown has no content to import"));
+    }
+
+    let xml_fragment = doc.get_or_insert_xml_fragment("content");
+    let mut txn = doc.transact_mut_with(SERVER_ORIGIN);
+    let len = xml_fragment.len(&txn);
+    if len > 0 {
+        xml_fragment.remove_range(&mut txn, 0, len);
+    }
+    for (index, block) in blocks.iter().enumerate() {
+        insert_markdown_block(&mut txn, &xml_fragment, index as u32, block);
+    }
+    Ok(())
+}
+
+/// 逐行解析 Markdown 區塊；連續的非空白行合併為同一段落
+fn parse_markdown_blocks(markdown: &str) -> Vec<MarkdownBlock> {
+    let mut blocks = Vec::new();
+    let mut paragraph: Vec<&str> = Vec::new();
+    let mut lines = markdown.lines().peekable();
+
+    let flush = |paragraph: &mut Vec<&str>, blocks: &mut Vec<MarkdownBlock>| {
+        if !paragraph.is_empty() {
+            blocks.push(MarkdownBlock::Paragraph(paragraph.join(" ")));
+            paragraph.clear();
+        }
+    };
+
+    while let Some(line) = lines.next() {
+        let trimmed = line.trim();
+
+        if let Some(language) = trimmed.strip_prefix("```") {
+            flush(&mut paragraph, &mut blocks);
+            let mut code = Vec::new();
+            // 未閉合的 code block 延伸到文件結尾
+            for code_line in lines.by_ref() {
+                if code_line.trim_start().starts_with("```") {
+                    break;
+                }
+                code.push(code_line);
+            }
+            blocks.push(MarkdownBlock::CodeBlock {
+                language: language.trim().to_string(),
+                code: code.join("\n"),
+            });
+        } else if trimmed.is_empty() {
+            flush(&mut paragraph, &mut blocks);
+        } else if let Some((level, text)) = parse_heading(trimmed) {
+            flush(&mut paragraph, &mut blocks);
+            blocks.push(MarkdownBlock::Heading(level, text.to_string()));
+        } else if is_horizontal_rule(trimmed) {
+            flush(&mut paragraph, &mut blocks);
+            blocks.push(MarkdownBlock::HorizontalRule);
+        } else if trimmed.starts_with('>') {
+            flush(&mut paragraph, &mut blocks);
+            let mut quoted = vec![strip_quote_marker(trimmed)];
+            while let Some(next) = lines.peek().map(|l| l.trim()) {
+                if !next.starts_with('>') {
+                    break;
+                }
+                quoted.push(strip_quote_marker(next));
+                lines.next();
+            }
+            blocks.push(MarkdownBlock::Blockquote(parse_markdown_blocks(
+                &quoted.join("\n"),
+            )));
+        } else {
+            paragraph.push(trimmed);
+        }
+    }
+    flush(&mut paragraph, &mut blocks);
+    blocks
+}
+
+/// `# Title` ~ `###### Title`；`#` 後必須有空白（`#hashtag` 不是標題）
+fn parse_heading(line: &str) -> Option<(u8, &str)> {
+    let level = line.chars().take_while(|c| *c == '#').count();
+    if !(1..=6).contains(&level) {
+        return None;
+    }
+    let rest = &line[level..];
+    if !rest.is_empty() && !rest.starts_with(' ') {
+        return None;
+    }
+    Some((level as u8, rest.trim()))
+}
+
+/// `---`、`***`、`___`（至少三個，可夾空白）
+fn is_horizontal_rule(line: &str) -> bool {
+    let chars: Vec<char> = line.chars().filter(|c| !c.is_whitespace()).collect();
+    chars.len() >= 3 && ['-', '*', '_'].iter().any(|m| chars.iter().all(|c| c == m))
+}
+
+fn strip_quote_marker(line: &str) -> &str {
+    let rest = line.strip_prefix('>').unwrap_or(line);
+    rest.strip_prefix(' ').unwrap_or(rest)
+}
+
+fn insert_markdown_block<F: XmlFragment>(
+    txn: &mut yrs::TransactionMut,
+    parent: &F,
+    index: u32,
+    block: &MarkdownBlock,
+) {
+    match block {
+        MarkdownBlock::Paragraph(text) => {
+            let para = parent.insert(txn, index, XmlElementPrelim::empty("paragraph"));
+            insert_inline_markdown(txn, &para, text);
+        }
+        MarkdownBlock::Heading(level, text) => {
+            let heading = parent.insert(txn, index, XmlElementPrelim::empty("heading"));
+            heading.insert_attribute(txn, "level", Any::Number(*level as f64));
+            insert_inline_markdown(txn, &heading, text);
+        }
+        MarkdownBlock::CodeBlock { language, code } => {
+            let code_block = parent.insert(txn, index, XmlElementPrelim::empty("code_block"));
+            if !language.is_empty() {
+                code_block.insert_attribute(txn, "language", language.as_str());
+            }
+            if !code.is_empty() {
+                code_block.insert(txn, 0, XmlTextPrelim::new(code.as_str()));
+            }
+        }
+        MarkdownBlock::Blockquote(children) => {
+            let quote = parent.insert(txn, index, XmlElementPrelim::empty("blockquote"));
+            for (i, child) in children.iter().enumerate() {
+                insert_markdown_block(txn, &quote, i as u32, child);
+            }
+        }
+        MarkdownBlock::HorizontalRule => {
+            parent.insert(txn, index, XmlElementPrelim::empty("horizontal_rule"));
+        }
+    }
+}
+
+/// 將行內 Markdown 寫入元素的單一文字節點，格式以文字屬性表示
+fn insert_inline_markdown(txn: &mut yrs::TransactionMut, element: &XmlElementRef, text: &str) {
+    let segments = parse_inline_markdown(text);
+    if segments.is_empty() {
+        return;
+    }
+    let text_ref = element.insert(txn, 0, XmlTextPrelim::new(""));
+    for (segment, marks) in segments {
+        let attrs: Attrs = marks
+            .iter()
+            .map(|mark| (Arc::from(*mark), Any::Bool(true)))
+            .collect();
+        let offset = text_ref.len(&*txn);
+        text_ref.insert_with_attributes(txn, offset, &segment, attrs);
+    }
+}
+
+/// 行內格式的標記符號，較長的先比對（`**` 優先於 `*`）
+const INLINE_MARKDOWN_MARKS: &[(&str, &str)] = &[
+    ("**", "bold"),
+    ("~~", "strike"),
+    ("*", "italic"),
+    ("`", "code"),
+];
+
+/// 拆出格式一致的文字片段
+///
+/// 只有在後面找得到對應的結尾標記時才開啟格式，否則照字面保留；
+/// `code` 內不再解析其他標記。
+fn parse_inline_markdown(text: &str) -> Vec<(String, Vec<&'static str>)> {
+    let mut segments = Vec::new();
+    let mut current = String::new();
+    let mut active: Vec<&'static str> = Vec::new();
+    let mut rest = text;
+
+    'outer: while let Some(c) = rest.chars().next() {
+        let in_code = active.contains(&"code");
+        for (delimiter, mark) in INLINE_MARKDOWN_MARKS {
+            if !rest.starts_with(delimiter) || (in_code && *mark != "code") {
+                continue;
+            }
+            let after = &rest[delimiter.len()..];
+            let is_open = active.contains(mark);
+            if !is_open && !after.contains(delimiter) {
+                continue;
+            }
+            if !current.is_empty() {
+                segments.push((std::mem::take(&mut current), active.clone()));
+            }
+            if is_open {
+                active.retain(|m| m != mark);
+            } else {
+                active.push(*mark);
+            }
+            rest = after;
+            continue 'outer;
+        }
+        current.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    if !current.is_empty() {
+        segments.push((current, active));
+    }
+    segments
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -407,6 +1256,39 @@ mod tests {
         assert!(content.contains("AI content"));
     }
 
+    #[test]
+    fn test_ai_edits_are_tagged_in_update_events() {
+        use crate::llm::tools::emoji_replacer::Replacement;
+
+        let doc = Arc::new(Doc::new());
+        let fragment = doc.get_or_insert_xml_fragment("content");
+        let origins = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = origins.clone();
+        let _sub = doc
+            .observe_update_v1(move |txn, _event| {
+                sink.lock().unwrap().push(is_ai_origin(txn.origin()))
+            })
+            .unwrap();
+
+        // 使用者輸入
+        {
+            let mut txn = doc.transact_mut_with("conn:1");
+            let para = fragment.insert(&mut txn, 0, XmlElementPrelim::empty("paragraph"));
+            para.insert(&mut txn, 0, XmlTextPrelim::new("I love it"));
+        }
+        append_ai_content_to_doc(&doc, "so much.").unwrap();
+        let replacements = [Replacement {
+            replace: "love".to_string(),
+            with: "❤️".to_string(),
+        }];
+        apply_replacements(&doc, "content", &replacements).unwrap();
+        // 使用者要求的寫入由伺服器代勞，但不是 AI 所寫
+        set_document_title(&doc, "Love").unwrap();
+
+        assert_eq!(*origins.lock().unwrap(), [false, true, true, false]);
+        assert!(!is_ai_origin(None));
+    }
+
     #[test]
     fn test_append_empty_content() {
         let doc = Arc::new(Doc::new());
@@ -434,6 +1316,206 @@ mod tests {
         assert_eq!(content, "Existing");
     }
 
+    #[test]
+    fn test_replace_directive_with_generated_content() {
+        let doc = Arc::new(Doc::new());
+        let fragment = doc.get_or_insert_xml_fragment("content");
+
+        {
+            let mut txn = doc.transact_mut();
+            let para = fragment.insert(
+                &mut txn,
+                0,
+                yrs::types::xml::XmlElementPrelim::empty("paragraph"),
+            );
+            para.insert(
+                &mut txn,
+                0,
+                XmlTextPrelim::new("Growth slowed. [expand on the economic impact] The end."),
+            );
+        }
+
+        let directive =
+            crate::llm::tools::extender::parse_directive("[expand on the economic impact]")
+                .unwrap();
+        let replaced =
+            replace_text_in_doc(&doc, directive.raw, "Exports fell sharply.").unwrap();
+        assert!(replaced);
+
+        let content = crate::editor::read::get_doc_content(&doc);
+        assert_eq!(content, "Growth slowed. Exports fell sharply. The end.");
+
+        // The directive is gone, so a second replacement finds nothing
+        assert!(!replace_text_in_doc(&doc, directive.raw, "again").unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_composition_replaces_the_highlighted_directive() {
+        let doc = doc_with_paragraph("[add detail] Growth slowed. [add detail]");
+        let user_state = UserWritingState::new(2000);
+        crate::llm::apply_composition(
+            &doc,
+            &user_state,
+            Some(("[add detail]", 1)),
+            "Exports fell.",
+            ParagraphMode::SingleParagraph,
+            0,
+            None,
+        )
+        .await
+        .unwrap();
+
+        // The second directive was highlighted, so the first stays untouched
+        assert_eq!(
+            crate::editor::read::get_doc_content(&doc),
+            "[add detail] Growth slowed. Exports fell."
+        );
+    }
+
+    fn paragraph_texts(doc: &Arc<Doc>) -> Vec<String> {
+        let fragment = doc.get_or_insert_xml_fragment("content");
+        let txn = doc.transact();
+        (0..fragment.len(&txn))
+            .filter_map(|i| match fragment.get(&txn, i) {
+                Some(yrs::types::xml::XmlOut::Element(para)) => Some(
+                    (0..para.len(&txn))
+                        .filter_map(|j| match para.get(&txn, j) {
+                            Some(yrs::types::xml::XmlOut::Text(text)) => {
+                                Some(text.get_string(&txn))
+                            }
+                            _ => None,
+                        })
+                        .collect(),
+                ),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn doc_with_paragraph(text: &str) -> Arc<Doc> {
+        let doc = Arc::new(Doc::new());
+        let fragment = doc.get_or_insert_xml_fragment("content");
+        let mut txn = doc.transact_mut();
+        let para = fragment.insert(
+            &mut txn,
+            0,
+            yrs::types::xml::XmlElementPrelim::empty("paragraph"),
+        );
+        para.insert(&mut txn, 0, XmlTextPrelim::new(text));
+        drop(txn);
+        doc
+    }
+
+    const MULTI_LINE: &str = "First line\nstill first.\n\nSecond block.\n\n\nThird.";
+
+    #[test]
+    fn test_split_paragraphs_by_mode() {
+        assert_eq!(
+            split_paragraphs(MULTI_LINE, ParagraphMode::SingleParagraph),
+            vec![MULTI_LINE]
+        );
+        assert_eq!(
+            split_paragraphs(MULTI_LINE, ParagraphMode::SplitOnBlankLines),
+            vec!["First line still first.", "Second block.", "Third."]
+        );
+        assert_eq!(
+            split_paragraphs(MULTI_LINE, ParagraphMode::SplitOnNewline),
+            vec!["First line", "still first.", "Second block.", "Third."]
+        );
+        assert!(split_paragraphs(" \n\n ", ParagraphMode::SplitOnBlankLines).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_append_paragraphs_single_paragraph_mode() {
+        let doc = doc_with_paragraph("Existing");
+        let user_state = UserWritingState::new(2000);
+        append_ai_paragraphs_word_by_word(
+            &doc,
+            MULTI_LINE,
+            ParagraphMode::SingleParagraph,
+            0,
+            &user_state,
+            None,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            paragraph_texts(&doc),
+            vec!["Existing First line still first. Second block. Third.\n"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_append_paragraphs_split_on_blank_lines() {
+        let doc = doc_with_paragraph("Existing");
+        let user_state = UserWritingState::new(2000);
+        append_ai_paragraphs_word_by_word(
+            &doc,
+            MULTI_LINE,
+            ParagraphMode::SplitOnBlankLines,
+            0,
+            &user_state,
+            None,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            paragraph_texts(&doc),
+            vec![
+                "Existing First line still first.\n",
+                "Second block.\n",
+                "Third.\n"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_append_paragraphs_split_on_newline() {
+        let doc = doc_with_paragraph("Existing");
+        let user_state = UserWritingState::new(2000);
+        append_ai_paragraphs_word_by_word(
+            &doc,
+            MULTI_LINE,
+            ParagraphMode::SplitOnNewline,
+            0,
+            &user_state,
+            None,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            paragraph_texts(&doc),
+            vec![
+                "Existing First line\n",
+                "still first.\n",
+                "Second block.\n",
+                "Third.\n"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_no_new_paragraph_while_user_writing() {
+        let doc = doc_with_paragraph("Existing");
+        let user_state = UserWritingState::new(2000);
+        user_state.mark_user_writing();
+        append_ai_paragraphs_word_by_word(
+            &doc,
+            MULTI_LINE,
+            ParagraphMode::SplitOnNewline,
+            0,
+            &user_state,
+            None,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(paragraph_texts(&doc), vec!["Existing"]);
+    }
+
     #[test]
     fn test_prepare_words() {
         let words = prepare_words("Hello World");
@@ -452,6 +1534,21 @@ mod tests {
         assert!(whitespace.is_empty());
     }
 
+    #[test]
+    fn test_prepare_sentences_and_chars() {
+        let sentences = prepare_sentences("Hi there.  How are you? Fine 3.5 times");
+        assert_eq!(sentences, vec!["Hi there. ", "How are you? ", "Fine 3.5 times\n"]);
+
+        let cjk = prepare_sentences("你好。今天好嗎？");
+        assert_eq!(cjk, vec!["你好。 ", "今天好嗎？\n"]);
+
+        let chars = prepare_chars(" héllo ");
+        assert_eq!(chars, vec!["h", "é", "l", "l", "o\n"]);
+
+        assert!(prepare_sentences("  ").is_empty());
+        assert!(prepare_chars("").is_empty());
+    }
+
     #[tokio::test]
     async fn test_append_word_by_word_with_user_interruption() {
         let doc = Arc::new(Doc::new());
@@ -478,7 +1575,7 @@ mod tests {
         let doc_clone = doc.clone();
         let user_state_clone = user_state.clone();
         let append_task = tokio::spawn(async move {
-            append_ai_content_word_by_word(&doc_clone, words, 50, &user_state_clone).await
+            append_ai_content_word_by_word(&doc_clone, words, 50, &user_state_clone, None).await
         });
 
         // 模擬用戶開始寫入（在第一個單詞後）
@@ -518,7 +1615,7 @@ mod tests {
         let words = prepare_words("Test Word");
 
         // 完整追加（用戶未中斷）
-        let result = append_ai_content_word_by_word(&doc, words, 10, &user_state).await;
+        let result = append_ai_content_word_by_word(&doc, words, 10, &user_state, None).await;
         assert!(result.is_ok());
 
         let content = crate::editor::read::get_doc_content(&doc);
@@ -527,6 +1624,121 @@ mod tests {
         assert!(content.contains("Word"));
     }
 
+    #[tokio::test]
+    async fn test_streamed_words_are_single_spaced() {
+        let doc = doc_with_paragraph("Existing");
+        let user_state = UserWritingState::new(2000);
+
+        append_ai_content_word_by_word(&doc, prepare_words("Hello World"), 0, &user_state, None)
+            .await
+            .unwrap();
+
+        assert_eq!(paragraph_texts(&doc), vec!["Existing Hello World\n"]);
+    }
+
+    #[tokio::test]
+    async fn test_streaming_reports_progress_every_few_words() {
+        let doc = doc_with_paragraph("Existing");
+        let user_state = UserWritingState::new(2000);
+        let reports = std::sync::Mutex::new(Vec::new());
+        let record = |done: usize, total: usize| reports.lock().unwrap().push((done, total));
+
+        let words = prepare_words("one two three four five six seven eight nine ten eleven twelve");
+        append_ai_content_word_by_word(&doc, words, 0, &user_state, Some(&record))
+            .await
+            .unwrap();
+        assert_eq!(*reports.lock().unwrap(), vec![(5, 12), (10, 12), (12, 12)]);
+
+        // 多段輸出以整體單詞數回報
+        reports.lock().unwrap().clear();
+        append_ai_paragraphs_word_by_word(
+            &doc,
+            "a b c\n\nd e f g",
+            ParagraphMode::SplitOnBlankLines,
+            0,
+            &user_state,
+            Some(&record),
+        )
+        .await
+        .unwrap();
+        assert_eq!(*reports.lock().unwrap(), vec![(3, 7), (7, 7)]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_zero_delay_applies_without_sleeping() {
+        let doc = doc_with_paragraph("Existing");
+        let user_state = UserWritingState::new(2000);
+
+        let started = tokio::time::Instant::now();
+        append_ai_content_word_by_word(&doc, prepare_words("one two three"), 0, &user_state, None)
+            .await
+            .unwrap();
+        assert_eq!(started.elapsed(), Duration::ZERO);
+
+        append_ai_content_word_by_word(&doc, prepare_words("four five"), 10, &user_state, None)
+            .await
+            .unwrap();
+        assert_eq!(started.elapsed(), Duration::from_millis(20));
+    }
+
+    #[tokio::test]
+    async fn test_progress_stops_when_user_interrupts() {
+        let doc = doc_with_paragraph("Existing");
+        let user_state = UserWritingState::new(2000);
+        let reports = std::sync::Mutex::new(Vec::new());
+        // 第一次回報時用戶開始寫入
+        let record = |done: usize, total: usize| {
+            reports.lock().unwrap().push((done, total));
+            user_state.mark_user_writing();
+        };
+
+        let words = prepare_words("one two three four five six seven eight nine ten eleven twelve");
+        append_ai_content_word_by_word(&doc, words, 0, &user_state, Some(&record))
+            .await
+            .unwrap();
+        assert_eq!(*reports.lock().unwrap(), vec![(5, 12)]);
+        assert_eq!(
+            paragraph_texts(&doc),
+            vec!["Existing one two three four five "]
+        );
+    }
+
+    #[test]
+    fn test_append_does_not_double_existing_whitespace() {
+        let doc = doc_with_paragraph("Existing ");
+        append_ai_content_to_doc(&doc, "text").unwrap();
+        append_ai_content_to_doc(&doc, " more").unwrap();
+        append_ai_content_to_doc(&doc, "glued").unwrap();
+
+        assert_eq!(paragraph_texts(&doc), vec!["Existing text more glued"]);
+    }
+
+    #[tokio::test]
+    async fn test_ai_writing_flag_covers_the_whole_stream() {
+        let doc = doc_with_paragraph("Existing");
+        let user_state = UserWritingState::new(2000);
+        assert!(!user_state.is_ai_writing());
+
+        let state = user_state.clone();
+        let stream = tokio::spawn(async move {
+            append_ai_content_word_by_word(&doc, prepare_words("one two three"), 30, &state, None)
+                .await
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(user_state.is_ai_writing());
+
+        stream.await.unwrap().unwrap();
+        assert!(!user_state.is_ai_writing());
+
+        // Nested guards: the flag clears only when the last one is dropped
+        let outer = user_state.start_ai_writing();
+        let inner = user_state.start_ai_writing();
+        drop(inner);
+        assert!(user_state.is_ai_writing());
+        drop(outer);
+        assert!(!user_state.is_ai_writing());
+    }
+
     #[tokio::test]
     async fn test_append_word_by_word_skips_when_user_writing() {
         let doc = Arc::new(Doc::new());
@@ -554,10 +1766,274 @@ mod tests {
         let words = prepare_words("Should Not Append");
 
         // 嘗試追加，但應該被跳過
-        let result = append_ai_content_word_by_word(&doc, words, 10, &user_state).await;
+        let result = append_ai_content_word_by_word(&doc, words, 10, &user_state, None).await;
         assert!(result.is_ok()); // 返回 Ok，但沒有追加內容
 
         let content = crate::editor::read::get_doc_content(&doc);
         assert_eq!(content, "Existing"); // 內容未改變
     }
+
+    #[test]
+    fn test_import_markdown_headings_and_paragraphs() {
+        let doc = Arc::new(Doc::new());
+        import_markdown(
+            &doc,
+            "# Title\n\nFirst paragraph\nstill first.\n\n## Section\n\nSecond paragraph.",
+        )
+        .unwrap();
+
+        assert_eq!(
+            crate::editor::export_markdown(&doc),
+            "# Title\n\nFirst paragraph still first.\n\n## Section\n\nSecond paragraph."
+        );
+        assert_eq!(
+            crate::editor::get_doc_content(&doc),
+            "Title\nFirst paragraph still first.\nSection\nSecond paragraph."
+        );
+    }
+
+    #[test]
+    fn test_replace_nth_text_in_doc_counts_across_paragraphs() {
+        let doc = Arc::new(Doc::new());
+        import_markdown(&doc, "Hello world, hello.\n\nHello again").unwrap();
+
+        assert!(replace_nth_text_in_doc(&doc, "Hello", 1, "Hi").unwrap());
+        assert_eq!(
+            crate::editor::get_doc_content(&doc),
+            "Hello world, hello.\nHi again"
+        );
+        assert!(!replace_nth_text_in_doc(&doc, "Hello", 1, "Hi").unwrap());
+    }
+
+    #[test]
+    fn test_import_markdown_replaces_existing_content() {
+        let doc = Arc::new(Doc::new());
+        import_markdown(&doc, "Old text").unwrap();
+        import_markdown(&doc, "New text").unwrap();
+        assert_eq!(crate::editor::get_doc_content(&doc), "New text");
+    }
+
+    #[test]
+    fn test_import_markdown_code_blocks_quotes_and_rules() {
+        let doc = Arc::new(Doc::new());
+        let markdown = "```rust\nfn main() {\n    // **not bold**\n}\n```\n\n> Quoted *text*\n> continues\n\n---";
+        import_markdown(&doc, markdown).unwrap();
+
+        assert_eq!(
+            crate::editor::export_html(&doc),
+            "<pre><code class=\"language-rust\">fn main() {\n    // **not bold**\n}</code></pre>\
+             <blockquote><p>Quoted <em>text</em> continues</p></blockquote><hr>"
+        );
+    }
+
+    #[test]
+    fn test_import_markdown_inline_marks() {
+        let doc = Arc::new(Doc::new());
+        import_markdown(&doc, "Plain **bold *both*** `a*b` ~~gone~~ and 2 * 3").unwrap();
+
+        assert_eq!(
+            crate::editor::export_html(&doc),
+            "<p>Plain <strong>bold </strong><em><strong>both</strong></em> \
+             <code>a*b</code> <s>gone</s> and 2 * 3</p>"
+        );
+    }
+
+    #[test]
+    fn test_import_markdown_gives_ai_a_structure_to_append_to() {
+        let doc = Arc::new(Doc::new());
+        assert!(!has_content_structure(&doc));
+
+        import_markdown(&doc, "# Notes\n\nSeeded").unwrap();
+        assert!(has_content_structure(&doc));
+        append_ai_content_to_doc(&doc, "and extended").unwrap();
+        assert!(crate::editor::get_doc_content(&doc).contains("Seeded"));
+        assert!(crate::editor::get_doc_content(&doc).contains("and extended"));
+    }
+
+    #[test]
+    fn test_import_markdown_rejects_empty_input() {
+        let doc = Arc::new(Doc::new());
+        assert!(import_markdown(&doc, "  \n\n ").is_err());
+    }
+
+    fn formatted_runs(doc: &Doc) -> Vec<(String, bool)> {
+        let fragment = doc.get_or_insert_xml_fragment("content");
+        let txn = doc.transact();
+        let mut text_nodes = Vec::new();
+        collect_text_nodes(&txn, &fragment, &mut text_nodes);
+        text_nodes
+            .iter()
+            .flat_map(|text_ref| text_ref.diff(&txn, YChange::identity))
+            .filter_map(|chunk| match chunk.insert {
+                Out::Any(Any::String(s)) => {
+                    let bold = chunk
+                        .attributes
+                        .is_some_and(|attrs| attrs.get("bold") == Some(&Any::Bool(true)));
+                    Some((s.to_string(), bold))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_format_all_occurrences_marks_every_match() {
+        let doc = Arc::new(Doc::new());
+        import_markdown(&doc, "Rust is fast.\n\nWe like Rust and *Rust* likes us").unwrap();
+
+        let attrs = Attrs::from([(Arc::from("bold"), Any::Bool(true))]);
+        assert_eq!(format_all_occurrences(&doc, "Rust", attrs).unwrap(), 3);
+
+        let bold: Vec<_> = formatted_runs(&doc)
+            .into_iter()
+            .filter(|(_, bold)| *bold)
+            .map(|(text, _)| text)
+            .collect();
+        assert_eq!(bold, vec!["Rust", "Rust", "Rust"]);
+        assert_eq!(
+            crate::editor::export_markdown(&doc),
+            "**Rust** is fast.\n\nWe like **Rust** and ***Rust*** likes us"
+        );
+    }
+
+    #[test]
+    fn test_format_all_occurrences_leaves_non_matches_untouched() {
+        let doc = Arc::new(Doc::new());
+        import_markdown(&doc, "Nothing to see here").unwrap();
+        let before = crate::editor::get_doc_content(&doc);
+
+        let attrs = Attrs::from([(Arc::from("italic"), Any::Bool(true))]);
+        assert_eq!(
+            format_all_occurrences(&doc, "rust", attrs.clone()).unwrap(),
+            0
+        );
+        assert!(formatted_runs(&doc).iter().all(|(_, bold)| !bold));
+        assert_eq!(crate::editor::get_doc_content(&doc), before);
+        assert_eq!(crate::editor::export_markdown(&doc), "Nothing to see here");
+
+        assert!(format_all_occurrences(&doc, "", attrs).is_err());
+    }
+
+    #[test]
+    fn test_format_all_occurrences_matches_whole_words_only() {
+        let doc = Arc::new(Doc::new());
+        import_markdown(&doc, "The cat can concatenate; cat_food isn't a cat.").unwrap();
+
+        let attrs = Attrs::from([(Arc::from("bold"), Any::Bool(true))]);
+        assert_eq!(
+            format_all_occurrences(&doc, "cat", attrs.clone()).unwrap(),
+            2
+        );
+        assert_eq!(
+            crate::editor::export_markdown(&doc),
+            "The **cat** can concatenate; cat_food isn't a **cat**."
+        );
+
+        // Chinese has no spaces between words, so a match inside a sentence counts
+        let doc = Arc::new(Doc::new());
+        import_markdown(&doc, "我愛貓，貓也愛我").unwrap();
+        assert_eq!(format_all_occurrences(&doc, "貓", attrs).unwrap(), 2);
+        assert_eq!(
+            crate::editor::export_markdown(&doc),
+            "我愛**貓**，**貓**也愛我"
+        );
+    }
+
+    #[test]
+    fn test_patch_text_nodes_keeps_marks_and_skips_edited_nodes() {
+        let doc = Arc::new(Doc::new());
+        import_markdown(&doc, "**Rust** is fast.\n\nSecond paragraph").unwrap();
+        assert_eq!(
+            text_node_contents(&doc),
+            vec!["Rust is fast.", "Second paragraph"]
+        );
+
+        let patches = [
+            TextPatch {
+                node: 0,
+                original: "Rust is fast.".to_string(),
+                replacement: "Rust is quick.".to_string(),
+            },
+            TextPatch {
+                node: 1,
+                original: "What the writer replaced".to_string(),
+                replacement: "Stale rewrite".to_string(),
+            },
+        ];
+        assert_eq!(patch_text_nodes(&doc, &patches).unwrap(), 1);
+        assert_eq!(
+            crate::editor::export_markdown(&doc),
+            "**Rust** is quick.\n\nSecond paragraph"
+        );
+        assert_eq!(changed_range("日本語", "日本語"), None);
+        assert_eq!(
+            changed_range("日本語です", "日本人です"),
+            Some((6, "語", "人"))
+        );
+    }
+
+    #[test]
+    fn test_replace_text_in_node_applies_one_edit_and_keeps_marks() {
+        let doc = Arc::new(Doc::new());
+        import_markdown(&doc, "**Fix** teh first.\n\nAnd teh second.").unwrap();
+
+        assert!(replace_text_in_node(&doc, 1, "And teh second.", "And the second.").unwrap());
+        assert_eq!(
+            crate::editor::export_markdown(&doc),
+            "**Fix** teh first.\n\nAnd the second."
+        );
+
+        // Text that is no longer there, or a node that no longer exists, is left alone
+        assert!(!replace_text_in_node(&doc, 1, "And teh second.", "And the second.").unwrap());
+        assert!(!replace_text_in_node(&doc, 5, "Fix teh first.", "Fix the first.").unwrap());
+
+        assert!(replace_text_in_node(&doc, 0, " Fix teh first. ", "Fix the first.").unwrap());
+        assert_eq!(
+            crate::editor::export_markdown(&doc),
+            "**Fix** the first.\n\nAnd the second."
+        );
+    }
+
+    #[test]
+    fn test_set_document_title_inserts_one_heading_above_the_paragraphs() {
+        let doc = Arc::new(Doc::new());
+        import_markdown(&doc, "First **paragraph**.\n\nSecond paragraph").unwrap();
+
+        set_document_title(&doc, "  A Title  ").unwrap();
+        assert_eq!(
+            crate::editor::export_markdown(&doc),
+            "# A Title\n\nFirst **paragraph**.\n\nSecond paragraph"
+        );
+
+        // A second title replaces the first in place rather than stacking headings
+        set_document_title(&doc, "A Better Title").unwrap();
+        let fragment = doc.get_or_insert_xml_fragment("content");
+        let txn = doc.transact();
+        let tags: Vec<String> = (0..fragment.len(&txn))
+            .filter_map(|i| match fragment.get(&txn, i) {
+                Some(yrs::types::xml::XmlOut::Element(element)) => Some(element.tag().to_string()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(tags, vec!["heading", "paragraph", "paragraph"]);
+        drop(txn);
+        assert_eq!(
+            crate::editor::export_markdown(&doc),
+            "# A Better Title\n\nFirst **paragraph**.\n\nSecond paragraph"
+        );
+
+        assert!(set_document_title(&doc, " ").is_err());
+    }
+
+    #[test]
+    fn test_set_document_title_promotes_an_existing_heading() {
+        let doc = Arc::new(Doc::new());
+        import_markdown(&doc, "## Draft *notes*\n\nBody").unwrap();
+
+        set_document_title(&doc, "Launch plan").unwrap();
+        assert_eq!(
+            crate::editor::export_markdown(&doc),
+            "# Launch plan\n\nBody"
+        );
+    }
 }
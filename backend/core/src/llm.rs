@@ -1,9 +1,21 @@
 pub mod agent;
+pub mod anthropic;
+pub mod cache;
+pub mod coalesce;
+pub mod openai;
+pub mod provider;
 pub mod tools;
+pub mod truncate;
 pub mod types;
+pub mod usage;
 
+pub use agent::apply_composition;
+pub use agent::lint_preview;
 pub use agent::new_backseating_agent;
 pub use agent::new_composer;
 pub use agent::new_emoji_replacer;
 pub use agent::new_linter;
+pub use agent::new_summarizer;
+pub use agent::new_titler;
 pub use types::McpTool;
+pub use usage::TokenUsage;
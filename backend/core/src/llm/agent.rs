@@ -1,5 +1,9 @@
 use crate::llm::tools::extender;
 use crate::llm::tools::linter;
+use crate::llm::tools::summarizer::{self, SummaryStyle};
+use crate::llm::tools::titler;
+use crate::refiner::error::RefineError;
+use crate::refiner::language::Language;
 use anyhow::Result;
 use std::sync::Arc;
 use yrs::Doc;
@@ -9,25 +13,121 @@ pub async fn new_composer(
     role: &str,
     doc: &Arc<Doc>,
     user_state: &crate::editor::UserWritingState,
-) -> Result<()> {
-    let api_key = api_key.to_string();
+    selection: Option<&str>,
+    occurrence: usize,
+    paragraph_mode: crate::editor::ParagraphMode,
+    word_delay_ms: u64,
+    progress: Option<crate::editor::WordProgress<'_>>,
+) -> Result<(), RefineError> {
+    if !crate::editor::has_content_structure(doc) {
+        return Err(RefineError::NoContentStructure);
+    }
+
     let article_draft = crate::editor::get_doc_content(doc);
-    let result = extender::execute_tool(&article_draft, role, &api_key)
-        .await
-        .map_err(|e| anyhow::anyhow!("Failed to execute tool: {}", e))?;
-    println!("result: {}", result);
+    // A highlighted "[instruction]" is a directive, not text to continue verbatim
+    let directive = selection.and_then(extender::parse_directive);
+    if let Some(directive) = &directive {
+        tracing::info!("🧭 Composer following directive: {}", directive.instruction);
+    }
+    let result = extender::execute_tool(
+        &article_draft,
+        role,
+        api_key,
+        directive.as_ref().map(|d| d.instruction),
+        &[],
+    )
+    .await?;
 
-    // 使用 prepare_words 預處理單詞（添加空格和換行符）
-    let words = crate::editor::prepare_words(&result);
-    crate::editor::append_ai_content_word_by_word(doc, words, 100, user_state).await?;
-    Ok(())
+    apply_composition(
+        doc,
+        user_state,
+        directive.map(|d| (d.raw, occurrence)),
+        &result,
+        paragraph_mode,
+        word_delay_ms,
+        progress,
+    )
+    .await
 }
 
-pub async fn new_linter(api_key: &str, doc: Arc<Doc>) -> Result<()> {
-    let (_result, _updated_doc) = linter::execute_tool(doc, api_key).await?;
+/// Put composed text into the document: in place of the highlighted directive,
+/// given as its text and which occurrence of that text it is, otherwise streamed
+/// word by word after the existing text
+pub async fn apply_composition(
+    doc: &Arc<Doc>,
+    user_state: &crate::editor::UserWritingState,
+    directive: Option<(&str, usize)>,
+    text: &str,
+    paragraph_mode: crate::editor::ParagraphMode,
+    word_delay_ms: u64,
+    progress: Option<crate::editor::WordProgress<'_>>,
+) -> Result<(), RefineError> {
+    if let Some((directive, nth)) = directive {
+        if !crate::editor::replace_nth_text_in_doc(doc, directive, nth, text.trim())? {
+            return Err(RefineError::DirectiveNotFound);
+        }
+        return Ok(());
+    }
+
+    // 依段落模式切分後逐字追加
+    crate::editor::append_ai_paragraphs_word_by_word(
+        doc,
+        text,
+        paragraph_mode,
+        word_delay_ms,
+        user_state,
+        progress,
+    )
+    .await?;
     Ok(())
 }
 
+pub async fn new_linter(
+    api_key: &str,
+    doc: Arc<Doc>,
+    focus: Option<u32>,
+    language: Option<Language>,
+    mode: linter::LintMode,
+) -> Result<Vec<linter::LintCorrection>> {
+    linter::execute_tool(doc, api_key, focus, language, mode).await
+}
+
+/// What linting the whole document would change; nothing is written to it
+pub async fn lint_preview(
+    api_key: &str,
+    doc: Arc<Doc>,
+    language: Option<Language>,
+) -> Result<Vec<linter::LintDiff>> {
+    linter::preview(doc, api_key, language).await
+}
+
+/// Summarize the whole document; the document itself is left untouched
+pub async fn new_summarizer(
+    api_key: &str,
+    doc: &Arc<Doc>,
+    style: SummaryStyle,
+    language: Option<Language>,
+) -> Result<String, RefineError> {
+    let content = crate::editor::get_doc_content(doc);
+    if content.trim().is_empty() {
+        return Err(RefineError::NoContentStructure);
+    }
+    summarizer::execute_tool(&content, style, language, api_key).await
+}
+
+/// Title candidates for the whole document, best first; nothing is written to it
+pub async fn new_titler(
+    api_key: &str,
+    doc: &Arc<Doc>,
+    language: Option<Language>,
+) -> Result<Vec<String>, RefineError> {
+    let content = crate::editor::get_doc_content(doc);
+    if content.trim().is_empty() {
+        return Err(RefineError::NoContentStructure);
+    }
+    titler::execute_tool(&content, language, api_key).await
+}
+
 pub async fn new_backseating_agent(api_key: &str, doc: &Arc<Doc>) -> Result<Vec<crate::llm::tools::backseater::BackseaterArgs>> {
     let content = crate::editor::get_doc_content(doc);
     if content.trim().is_empty() {
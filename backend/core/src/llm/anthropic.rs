@@ -0,0 +1,323 @@
+use crate::llm::provider::{
+    ChatCompletion, ChatReply, LlmProvider, ResponseFormat, ToolCall, call_span,
+};
+use crate::llm::usage::TokenUsage;
+use crate::refiner::error::{RefineError, check_response};
+use futures::future::BoxFuture;
+use serde_json::json;
+use tracing::Instrument;
+
+pub const MESSAGES_URL: &str = "https://api.anthropic.com/v1/messages";
+
+const API_VERSION: &str = "2023-06-01";
+
+/// The default stand-in for the tools' `gpt-4o`...
+pub const MODEL: &str = "claude-sonnet-4-0";
+
+/// ...and for their `gpt-4o-mini`
+pub const FAST_MODEL: &str = "claude-3-5-haiku-latest";
+
+/// The name of the tool a JSON schema answer is delivered through
+const SCHEMA_TOOL: &str = "respond";
+
+/// The messages API requires a cap; no tool answers anywhere near it
+const MAX_TOKENS: u32 = 4096;
+
+/// Anthropic's messages API, behind the same interface as OpenAI
+pub struct Anthropic {
+    client: reqwest::Client,
+    url: String,
+    api_key: String,
+    model: String,
+    fast_model: String,
+}
+
+impl Anthropic {
+    pub fn new(api_key: impl Into<String>) -> Self {
+        Self::at(MESSAGES_URL, api_key)
+    }
+
+    /// `new` against another endpoint, e.g. a mock server in tests
+    pub fn at(url: impl Into<String>, api_key: impl Into<String>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            url: url.into(),
+            api_key: api_key.into(),
+            model: MODEL.to_string(),
+            fast_model: FAST_MODEL.to_string(),
+        }
+    }
+
+    /// Run the tools written for `gpt-4o` on `model`, and those written for
+    /// `gpt-4o-mini` on `fast_model`
+    pub fn with_models(mut self, model: impl Into<String>, fast_model: impl Into<String>) -> Self {
+        self.model = model.into();
+        self.fast_model = fast_model.into();
+        self
+    }
+
+    /// The Claude model for an OpenAI model name; Claude names pass through
+    fn claude_model<'a>(&'a self, model: &'a str) -> &'a str {
+        if model.starts_with("claude") {
+            model
+        } else if model.ends_with("-mini") {
+            &self.fast_model
+        } else {
+            &self.model
+        }
+    }
+}
+
+impl LlmProvider for Anthropic {
+    fn chat_completion<'a>(
+        &'a self,
+        tool: &'static str,
+        request: &'a ChatCompletion,
+    ) -> BoxFuture<'a, Result<ChatReply, RefineError>> {
+        let model = self.claude_model(&request.model);
+        let span = call_span(tool, model);
+        let call = async move {
+            let builder = self
+                .client
+                .post(&self.url)
+                .header("x-api-key", &self.api_key)
+                .header("anthropic-version", API_VERSION)
+                .json(&messages_body(model, request));
+            let request = crate::llm::openai::forward(builder, tool);
+            let response = crate::llm::openai::send(request, tool).await?;
+            let response = check_response(response).await?;
+
+            let body: serde_json::Value = response
+                .json()
+                .await
+                .map_err(|e| RefineError::Parse(e.to_string()))?;
+            if let Some(usage) = body.get("usage") {
+                let usage = TokenUsage {
+                    prompt_tokens: usage["input_tokens"].as_u64().unwrap_or_default(),
+                    completion_tokens: usage["output_tokens"].as_u64().unwrap_or_default(),
+                };
+                crate::llm::usage::record(tool, model, usage);
+            }
+            Ok(messages_reply(&body))
+        };
+        Box::pin(call.instrument(span))
+    }
+
+    fn describe(&self, _tool: &'static str, model: &str) -> String {
+        format!("{} at {}", self.claude_model(model), self.url)
+    }
+
+    fn reachable(&self) -> BoxFuture<'_, reqwest::Result<()>> {
+        Box::pin(crate::llm::openai::reachable(&self.url))
+    }
+}
+
+/// `request` as a messages API body.
+///
+/// System messages move to the top-level `system` field. Claude has no JSON
+/// mode: a JSON object answer is asked for in the system prompt, and a schema
+/// answer becomes a forced call of a tool taking that schema.
+fn messages_body(model: &str, request: &ChatCompletion) -> serde_json::Value {
+    let mut system: Vec<&str> = Vec::new();
+    let mut messages = Vec::new();
+    for message in &request.messages {
+        if message.role == "system" {
+            system.push(&message.content);
+        } else {
+            messages.push(json!({ "role": message.role, "content": message.content }));
+        }
+    }
+    if request.format == ResponseFormat::JsonObject {
+        system.push("Reply with a single JSON object and nothing else.");
+    }
+
+    let mut tools: Vec<_> = request
+        .tools
+        .iter()
+        .map(|tool| {
+            json!({
+                "name": tool.name,
+                "description": tool.description,
+                "input_schema": tool.input_schema
+            })
+        })
+        .collect();
+    let mut tool_choice = request.tool_choice.clone();
+    if let ResponseFormat::JsonSchema { name, schema } = &request.format {
+        tools.push(json!({
+            "name": SCHEMA_TOOL,
+            "description": format!("Deliver the answer as `{name}`."),
+            "input_schema": schema
+        }));
+        tool_choice = Some(SCHEMA_TOOL.to_string());
+    }
+
+    let mut body = json!({
+        "model": model,
+        "max_tokens": MAX_TOKENS,
+        "messages": messages,
+    });
+    if !system.is_empty() {
+        body["system"] = json!(system.join("\n\n"));
+    }
+    if let Some(temperature) = request.temperature {
+        body["temperature"] = json!(temperature);
+    }
+    if !tools.is_empty() {
+        body["tools"] = json!(tools);
+    }
+    if let Some(name) = tool_choice {
+        body["tool_choice"] = json!({ "type": "tool", "name": name });
+    }
+    body
+}
+
+/// Text blocks joined as the content, `tool_use` blocks as tool calls; a call
+/// of the schema tool is the content itself
+fn messages_reply(body: &serde_json::Value) -> ChatReply {
+    let mut text = Vec::new();
+    let mut tool_calls = Vec::new();
+    for block in body["content"].as_array().into_iter().flatten() {
+        match block["type"].as_str() {
+            Some("text") => text.extend(block["text"].as_str().map(str::to_string)),
+            Some("tool_use") if block["name"] == SCHEMA_TOOL => {
+                text.push(block["input"].to_string())
+            }
+            Some("tool_use") => {
+                if let Some(name) = block["name"].as_str() {
+                    tool_calls.push(ToolCall {
+                        name: name.to_string(),
+                        arguments: block["input"].clone(),
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    ChatReply {
+        content: (!text.is_empty()).then(|| text.concat()),
+        tool_calls,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::provider::ChatMessage;
+    use crate::llm::types::McpTool;
+
+    #[test]
+    fn test_system_messages_and_tools_are_translated() {
+        let request = ChatCompletion {
+            model: "gpt-4o".to_string(),
+            messages: vec![
+                ChatMessage::system("You are a researcher."),
+                ChatMessage::user("Look this up"),
+            ],
+            tools: vec![McpTool {
+                name: "report".to_string(),
+                description: "Deliver the report.".to_string(),
+                input_schema: json!({ "type": "object" }),
+            }],
+            tool_choice: Some("report".to_string()),
+            temperature: Some(0.3),
+            format: ResponseFormat::Text,
+        };
+        let anthropic = Anthropic::new("test-key");
+        let body = messages_body(anthropic.claude_model(&request.model), &request);
+        assert_eq!(body["model"], MODEL);
+        assert_eq!(body["system"], "You are a researcher.");
+        assert_eq!(
+            body["messages"],
+            json!([{ "role": "user", "content": "Look this up" }])
+        );
+        assert_eq!(
+            body["tools"][0]["input_schema"],
+            json!({ "type": "object" })
+        );
+        assert_eq!(
+            body["tool_choice"],
+            json!({ "type": "tool", "name": "report" })
+        );
+
+        assert_eq!(anthropic.claude_model("gpt-4o-mini"), FAST_MODEL);
+        assert_eq!(anthropic.claude_model("claude-opus-4-0"), "claude-opus-4-0");
+
+        let anthropic = anthropic.with_models("claude-opus-4-0", "claude-sonnet-4-0");
+        assert_eq!(anthropic.claude_model("gpt-4o"), "claude-opus-4-0");
+        assert_eq!(anthropic.claude_model("gpt-4o-mini"), "claude-sonnet-4-0");
+    }
+
+    #[test]
+    fn test_json_formats_without_json_mode() {
+        let mut request = ChatCompletion {
+            model: "gpt-4o-mini".to_string(),
+            messages: vec![ChatMessage::system("Title it."), ChatMessage::user("Text")],
+            format: ResponseFormat::JsonObject,
+            ..Default::default()
+        };
+        let body = messages_body(FAST_MODEL, &request);
+        assert!(body["system"].as_str().unwrap().ends_with("nothing else."));
+        assert!(body.get("tools").is_none());
+
+        request.format = ResponseFormat::JsonSchema {
+            name: "lint_corrections".to_string(),
+            schema: json!({ "type": "object" }),
+        };
+        let body = messages_body(FAST_MODEL, &request);
+        assert_eq!(body["tools"][0]["name"], SCHEMA_TOOL);
+        assert_eq!(body["tool_choice"]["name"], SCHEMA_TOOL);
+    }
+
+    #[tokio::test]
+    async fn test_reply_blocks_become_content_and_tool_calls() {
+        let (url, received) = crate::llm::openai::mock_reply(json!({
+            "content": [
+                { "type": "text", "text": "Found it." },
+                { "type": "tool_use", "id": "toolu_1", "name": "report", "input": { "summary": "Done." } },
+                { "type": "tool_use", "id": "toolu_2", "name": SCHEMA_TOOL, "input": { "corrections": [] } }
+            ],
+            "usage": { "input_tokens": 12, "output_tokens": 5 }
+        }));
+        let request = ChatCompletion {
+            model: "gpt-4o".to_string(),
+            messages: vec![ChatMessage::user("Go")],
+            ..Default::default()
+        };
+
+        let reply = Anthropic::at(&url, "test-key")
+            .chat_completion("researcher", &request)
+            .await
+            .unwrap();
+        assert_eq!(
+            reply.content.as_deref(),
+            Some(r#"Found it.{"corrections":[]}"#)
+        );
+        assert_eq!(
+            reply.tool_calls,
+            vec![ToolCall {
+                name: "report".to_string(),
+                arguments: json!({ "summary": "Done." }),
+            }]
+        );
+        assert_eq!(received.await.unwrap()["max_tokens"], MAX_TOKENS);
+    }
+
+    #[tokio::test]
+    async fn test_request_id_is_forwarded_upstream() {
+        let (url, head) = crate::llm::openai::mock_request_head(json!({ "content": [] }));
+        let request = ChatCompletion {
+            model: "gpt-4o".to_string(),
+            messages: vec![ChatMessage::user("Go")],
+            ..Default::default()
+        };
+
+        let anthropic = Anthropic::at(&url, "test-key");
+        let call = anthropic.chat_completion("researcher", &request);
+        crate::llm::openai::with_request_id("req-42".to_string(), call)
+            .await
+            .unwrap();
+        let head = head.await.unwrap().to_lowercase();
+        assert!(head.contains("x-client-request-id: req-42"));
+    }
+}
@@ -0,0 +1,223 @@
+use crate::llm::provider::{ChatCompletion, ChatReply, LlmProvider};
+use crate::refiner::error::RefineError;
+use mini_moka::sync::Cache;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// Answers to recent requests, so an identical request within the TTL is
+/// served without calling the model again.
+///
+/// Only successful replies are kept. A hit never reaches the provider, so it
+/// costs nothing and is left out of the usage accounting.
+pub struct ResponseCache {
+    replies: Cache<RequestKey, ChatReply>,
+}
+
+impl ResponseCache {
+    pub fn new(max_entries: u64, ttl: Duration) -> Self {
+        Self {
+            replies: Cache::builder()
+                .max_capacity(max_entries)
+                .time_to_live(ttl)
+                .build(),
+        }
+    }
+
+    /// `llm.chat_completion(tool, request)`, or the reply it gave last time
+    pub async fn chat_completion(
+        &self,
+        llm: &dyn LlmProvider,
+        tool: &'static str,
+        request: &ChatCompletion,
+    ) -> Result<ChatReply, RefineError> {
+        if let Some(reply) = self.get(llm, tool, request) {
+            return Ok(reply);
+        }
+        let reply = llm.chat_completion(tool, request).await?;
+        self.insert(llm, tool, request, reply.clone());
+        Ok(reply)
+    }
+
+    /// The reply cached for `request`, if any
+    pub fn get(
+        &self,
+        llm: &dyn LlmProvider,
+        tool: &'static str,
+        request: &ChatCompletion,
+    ) -> Option<ChatReply> {
+        let reply = self.replies.get(&RequestKey::new(llm, tool, request));
+        match reply {
+            Some(_) => {
+                metrics::counter!("ai_cache_hits_total", "tool" => tool).increment(1);
+                tracing::debug!("📦 Cached {} reply", tool);
+            }
+            None => metrics::counter!("ai_cache_misses_total", "tool" => tool).increment(1),
+        }
+        reply
+    }
+
+    /// Remember `reply` as the answer to `request`
+    pub fn insert(
+        &self,
+        llm: &dyn LlmProvider,
+        tool: &'static str,
+        request: &ChatCompletion,
+        reply: ChatReply,
+    ) {
+        self.replies
+            .insert(RequestKey::new(llm, tool, request), reply);
+    }
+}
+
+/// Identifies requests that get the same answer: the tool, the model and
+/// endpoint it ends up on, and everything sent to it. Message content is
+/// trimmed, as surrounding whitespace does not change the answer.
+///
+/// The whole normalized request is the key, so a hit is compared against it
+/// rather than trusting a hash that two requests may share.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct RequestKey {
+    tool: &'static str,
+    target: String,
+    messages: Vec<(String, String)>,
+    tools: String,
+    tool_choice: Option<String>,
+    temperature: Option<u64>,
+    format: String,
+}
+
+impl RequestKey {
+    fn new(llm: &dyn LlmProvider, tool: &'static str, request: &ChatCompletion) -> Self {
+        Self {
+            tool,
+            target: llm.describe(tool, &request.model),
+            messages: request
+                .messages
+                .iter()
+                .map(|message| (message.role.clone(), message.content.trim().to_string()))
+                .collect(),
+            tools: serde_json::to_string(&request.tools).unwrap_or_default(),
+            tool_choice: request.tool_choice.clone(),
+            temperature: request.temperature.map(f64::to_bits),
+            format: format!("{:?}", request.format),
+        }
+    }
+}
+
+static CACHE: OnceLock<ResponseCache> = OnceLock::new();
+
+/// Cache the refine and lint answers in `cache`; only the first call takes
+/// effect. Without one, every request calls the model.
+pub fn configure_cache(cache: ResponseCache) {
+    if CACHE.set(cache).is_err() {
+        tracing::warn!("AI response cache already configured, ignoring");
+    }
+}
+
+/// The configured cache, if any
+pub fn configured() -> Option<&'static ResponseCache> {
+    CACHE.get()
+}
+
+/// `llm.chat_completion(tool, request)` through the configured cache, if any
+pub async fn chat_completion(
+    llm: &dyn LlmProvider,
+    tool: &'static str,
+    request: &ChatCompletion,
+) -> Result<ChatReply, RefineError> {
+    match configured() {
+        Some(cache) => cache.chat_completion(llm, tool, request).await,
+        None => llm.chat_completion(tool, request).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::provider::{ChatMessage, FakeProvider, OpenAi};
+
+    fn request(text: &str) -> ChatCompletion {
+        ChatCompletion {
+            model: "gpt-4o".to_string(),
+            messages: vec![
+                ChatMessage::system("Improve the text."),
+                ChatMessage::user(format!("The existing text is: {text}")),
+            ],
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_identical_request_is_not_sent_again() {
+        // The mock server answers a single request
+        let (url, received) = crate::llm::openai::mock_openai("Improved.");
+        let llm = OpenAi::at(&url, "test-key");
+        let cache = ResponseCache::new(100, Duration::from_secs(60));
+
+        let first = cache
+            .chat_completion(&llm, "refiner", &request("Draft."))
+            .await
+            .unwrap();
+        received.await.unwrap();
+        let second = cache
+            .chat_completion(&llm, "refiner", &request("  Draft.\n"))
+            .await
+            .unwrap();
+        assert_eq!(first.content.as_deref(), Some("Improved."));
+        assert_eq!(second, first);
+    }
+
+    #[tokio::test]
+    async fn test_different_requests_are_sent() {
+        let llm = FakeProvider::new([
+            ChatReply {
+                content: Some("One.".to_string()),
+                tool_calls: Vec::new(),
+            },
+            ChatReply {
+                content: Some("Two.".to_string()),
+                tool_calls: Vec::new(),
+            },
+        ]);
+        let cache = ResponseCache::new(100, Duration::from_secs(60));
+
+        cache
+            .chat_completion(&llm, "refiner", &request("Draft."))
+            .await
+            .unwrap();
+        let mut warmer = request("Draft.");
+        warmer.temperature = Some(0.9);
+        let reply = cache
+            .chat_completion(&llm, "refiner", &warmer)
+            .await
+            .unwrap();
+        assert_eq!(reply.content.as_deref(), Some("Two."));
+        // Same request from another tool
+        assert!(
+            cache
+                .chat_completion(&llm, "linter", &request("Draft."))
+                .await
+                .is_err()
+        );
+        assert_eq!(llm.requests().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_failures_are_not_cached() {
+        let llm = FakeProvider::new([]);
+        let cache = ResponseCache::new(100, Duration::from_secs(60));
+        assert!(
+            cache
+                .chat_completion(&llm, "refiner", &request("Draft."))
+                .await
+                .is_err()
+        );
+        assert!(
+            cache
+                .chat_completion(&llm, "refiner", &request("Draft."))
+                .await
+                .is_err()
+        );
+        assert_eq!(llm.requests().len(), 2);
+    }
+}
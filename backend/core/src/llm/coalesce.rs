@@ -0,0 +1,193 @@
+use futures::future::{BoxFuture, FutureExt, Shared};
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::future::Future;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+
+type SharedCall<T> = Shared<BoxFuture<'static, Result<T, Arc<anyhow::Error>>>>;
+
+/// Identifies upstream calls that are guaranteed to produce the same answer
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CoalesceKey {
+    pub tool: &'static str,
+    pub content_hash: u64,
+    pub model: &'static str,
+}
+
+impl CoalesceKey {
+    pub fn new(tool: &'static str, content: &str, model: &'static str) -> Self {
+        let mut hasher = DefaultHasher::new();
+        content.hash(&mut hasher);
+        Self {
+            tool,
+            content_hash: hasher.finish(),
+            model,
+        }
+    }
+}
+
+/// Singleflight for AI calls: while a call for a key is in flight, identical
+/// requests wait on that same call instead of hitting OpenAI again.
+pub struct Coalescer<T> {
+    inflight: Mutex<HashMap<CoalesceKey, SharedCall<T>>>,
+}
+
+impl<T> Default for Coalescer<T> {
+    fn default() -> Self {
+        Self {
+            inflight: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<T> Coalescer<T>
+where
+    T: Clone + Send + Sync + 'static,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run `call` for `key`, or join the call already in flight for it.
+    /// `call` is only invoked when this request leads.
+    pub async fn run<F, Fut>(&self, key: CoalesceKey, call: F) -> anyhow::Result<T>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = anyhow::Result<T>> + Send + 'static,
+    {
+        let shared = {
+            let mut inflight = self.inflight.lock().unwrap();
+            match inflight.get(&key) {
+                Some(existing) => {
+                    tracing::debug!("🔗 Joining in-flight {} call", key.tool);
+                    existing.clone()
+                }
+                None => {
+                    let shared = call().map(|r| r.map_err(Arc::new)).boxed().shared();
+                    inflight.insert(key.clone(), shared.clone());
+                    shared
+                }
+            }
+        };
+
+        // Forget the call once we stop waiting on it, even when this request is
+        // dropped mid-flight; otherwise aborted waiters would leave it behind
+        let _forget = Forget {
+            inflight: &self.inflight,
+            key,
+            shared: shared.clone(),
+        };
+
+        shared.await.map_err(|e| anyhow::anyhow!("{:#}", e))
+    }
+}
+
+/// Removes an in-flight entry when dropped, if it is still the call we waited on
+struct Forget<'a, T> {
+    inflight: &'a Mutex<HashMap<CoalesceKey, SharedCall<T>>>,
+    key: CoalesceKey,
+    shared: SharedCall<T>,
+}
+
+impl<T> Drop for Forget<'_, T> {
+    fn drop(&mut self) {
+        // Only forget the call we waited on; a newer one may already be registered
+        let mut inflight = self.inflight.lock().unwrap_or_else(|e| e.into_inner());
+        if inflight
+            .get(&self.key)
+            .is_some_and(|current| current.ptr_eq(&self.shared))
+        {
+            inflight.remove(&self.key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_identical_requests_share_one_call() {
+        let coalescer = Arc::new(Coalescer::<String>::new());
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let waiters = (0..10).map(|_| {
+            let coalescer = coalescer.clone();
+            let calls = calls.clone();
+            async move {
+                coalescer
+                    .run(CoalesceKey::new("improve", "same text", "gpt-4o"), || {
+                        calls.fetch_add(1, Ordering::SeqCst);
+                        async {
+                            tokio::time::sleep(Duration::from_millis(50)).await;
+                            Ok("improved".to_string())
+                        }
+                    })
+                    .await
+            }
+        });
+        let results = futures::future::join_all(waiters).await;
+
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert!(
+            results
+                .iter()
+                .all(|r| r.as_deref().ok() == Some("improved"))
+        );
+        assert!(coalescer.inflight.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_aborted_waiters_do_not_leave_the_call_behind() {
+        let coalescer = Arc::new(Coalescer::<String>::new());
+        let key = CoalesceKey::new("fix", "same text", "gpt-4o");
+
+        let waiters: Vec<_> = (0..3)
+            .map(|_| {
+                let coalescer = coalescer.clone();
+                let key = key.clone();
+                tokio::spawn(async move {
+                    coalescer
+                        .run(key, futures::future::pending::<anyhow::Result<String>>)
+                        .await
+                })
+            })
+            .collect();
+        while coalescer.inflight.lock().unwrap().is_empty() {
+            tokio::task::yield_now().await;
+        }
+
+        for waiter in &waiters {
+            waiter.abort();
+        }
+        for waiter in waiters {
+            assert!(waiter.await.unwrap_err().is_cancelled());
+        }
+        assert!(coalescer.inflight.lock().unwrap().is_empty());
+
+        // The next identical request starts its own call instead of joining a dead one
+        let result = coalescer
+            .run(key, || async { Ok("fixed".to_string()) })
+            .await;
+        assert_eq!(result.unwrap(), "fixed");
+    }
+
+    #[tokio::test]
+    async fn test_different_keys_and_errors_are_not_merged() {
+        let coalescer = Coalescer::<String>::new();
+
+        let (a, b) = tokio::join!(
+            coalescer.run(CoalesceKey::new("fix", "a", "gpt-4o"), || async {
+                Ok("a".to_string())
+            }),
+            coalescer.run(CoalesceKey::new("fix", "b", "gpt-4o"), || async {
+                Err(anyhow::anyhow!("upstream down"))
+            }),
+        );
+        assert_eq!(a.unwrap(), "a");
+        assert!(b.unwrap_err().to_string().contains("upstream down"));
+    }
+}
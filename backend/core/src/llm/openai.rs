@@ -0,0 +1,631 @@
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// OpenAI's own API root; a compatible server (vLLM, Ollama, LM Studio) can stand in for it
+pub const DEFAULT_BASE_URL: &str = "https://api.openai.com/v1";
+
+pub const CHAT_COMPLETIONS_URL: &str = "https://api.openai.com/v1/chat/completions";
+
+/// Header OpenAI echoes back in its logs for a caller-supplied request id
+pub const CLIENT_REQUEST_ID_HEADER: &str = "X-Client-Request-Id";
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// Run `fut` with `request_id` as the current request id, so upstream calls made
+/// inside it can be correlated with the incoming HTTP request.
+pub async fn with_request_id<F: Future>(request_id: String, fut: F) -> F::Output {
+    REQUEST_ID.scope(request_id, fut).await
+}
+
+/// The request id of the HTTP request currently being served, if any
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// Extra headers and query parameters for one scope (all tools, or a single tool)
+#[derive(Debug, Clone, Default)]
+pub struct RequestExtras {
+    pub headers: HeaderMap,
+    pub query: Vec<(String, String)>,
+}
+
+/// Extra request parts for proxies in front of the provider, e.g. Azure's `api-version`
+/// query or a gateway's auth header. Tool-specific entries are applied after global ones.
+#[derive(Debug, Clone, Default)]
+pub struct OpenAiExtras {
+    pub global: RequestExtras,
+    pub per_tool: HashMap<String, RequestExtras>,
+}
+
+impl OpenAiExtras {
+    /// Parse `[tool/]Name: value` headers and `[tool/]key=value` query parameters.
+    /// Tool names, header names and values are validated here rather than at request time.
+    pub fn parse(headers: &[String], query: &[String]) -> anyhow::Result<Self> {
+        let mut extras = Self::default();
+        for entry in headers {
+            let (tool, header) = split_tool(entry)?;
+            let (name, value) = header
+                .split_once(':')
+                .ok_or_else(|| anyhow::anyhow!("expected `Name: value` header, got {entry:?}"))?;
+            let name = HeaderName::try_from(name.trim())
+                .map_err(|e| anyhow::anyhow!("invalid header name in {entry:?}: {e}"))?;
+            let value = HeaderValue::try_from(value.trim())
+                .map_err(|e| anyhow::anyhow!("invalid header value in {entry:?}: {e}"))?;
+            extras.scope(tool).headers.append(name, value);
+        }
+        for entry in query {
+            let (tool, param) = split_tool(entry)?;
+            let (key, value) = param
+                .split_once('=')
+                .ok_or_else(|| anyhow::anyhow!("expected `key=value` query, got {entry:?}"))?;
+            extras
+                .scope(tool)
+                .query
+                .push((key.trim().to_string(), value.trim().to_string()));
+        }
+        Ok(extras)
+    }
+
+    fn scope(&mut self, tool: Option<&str>) -> &mut RequestExtras {
+        match tool {
+            Some(tool) => self.per_tool.entry(tool.to_string()).or_default(),
+            None => &mut self.global,
+        }
+    }
+
+    pub fn apply(&self, builder: reqwest::RequestBuilder, tool: &str) -> reqwest::RequestBuilder {
+        std::iter::once(&self.global)
+            .chain(self.per_tool.get(tool))
+            .fold(builder, |builder, extras| {
+                builder.headers(extras.headers.clone()).query(&extras.query)
+            })
+    }
+}
+
+/// Every tool an AI call is made for; `tool/` settings must name one of them
+pub const TOOLS: &[&str] = &[
+    "backseater",
+    "emoji_replacer",
+    "extender",
+    "linter",
+    "refiner",
+    "researcher",
+    "summarizer",
+    "titler",
+    "translator",
+];
+
+/// `tool/rest` -> (Some(tool), rest); header names can't contain `/`, so this is
+/// unambiguous. A tool that doesn't exist is an error, not a setting that never applies.
+fn split_tool(entry: &str) -> anyhow::Result<(Option<&str>, &str)> {
+    match entry.split_once('/') {
+        Some((tool, rest)) if !tool.contains(':') && !tool.contains('=') => {
+            if !TOOLS.contains(&tool) {
+                anyhow::bail!(
+                    "unknown tool {tool:?} in {entry:?}, expected one of {}",
+                    TOOLS.join(", ")
+                );
+            }
+            Ok((Some(tool), rest))
+        }
+        _ => Ok((None, entry)),
+    }
+}
+
+static EXTRAS: OnceLock<OpenAiExtras> = OnceLock::new();
+
+/// Install the extras every `chat_completions` call uses; only the first call takes effect
+pub fn configure_extras(extras: OpenAiExtras) {
+    if EXTRAS.set(extras).is_err() {
+        tracing::warn!("OpenAI request extras already configured, ignoring");
+    }
+}
+
+static BASE_URL: OnceLock<String> = OnceLock::new();
+
+/// Send every OpenAI call under `base_url` instead of [`DEFAULT_BASE_URL`]; only
+/// the first call takes effect
+pub fn configure_base_url(base_url: &str) -> anyhow::Result<()> {
+    let url = chat_completions_url_under(base_url);
+    reqwest::Url::parse(&url)
+        .map_err(|e| anyhow::anyhow!("invalid OpenAI base URL {base_url:?}: {e}"))?;
+    if BASE_URL.set(url).is_err() {
+        tracing::warn!("OpenAI base URL already configured, ignoring");
+    }
+    Ok(())
+}
+
+/// The chat completions endpoint under the configured base URL
+pub fn chat_completions_url() -> &'static str {
+    BASE_URL
+        .get()
+        .map(String::as_str)
+        .unwrap_or(CHAT_COMPLETIONS_URL)
+}
+
+/// `base_url/chat/completions`, with or without a trailing slash on `base_url`
+fn chat_completions_url_under(base_url: &str) -> String {
+    format!("{}/chat/completions", base_url.trim_end_matches('/'))
+}
+
+/// Models to call instead of the ones the tools were written for, e.g. the
+/// models a local server has pulled
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ModelOverrides {
+    /// For every tool without its own entry
+    pub all: Option<String>,
+    pub per_tool: HashMap<String, String>,
+}
+
+impl ModelOverrides {
+    /// Parse `model` (every tool) and `tool=model` entries; model names may
+    /// contain `/` and `:`, as in `meta-llama/Llama-3.1-8B` or `llama3.1:8b`
+    pub fn parse(entries: &[String]) -> anyhow::Result<Self> {
+        let mut overrides = Self::default();
+        for entry in entries {
+            match entry.split_once('=') {
+                Some((tool, model)) if !tool.trim().is_empty() && !model.trim().is_empty() => {
+                    overrides
+                        .per_tool
+                        .insert(tool.trim().to_string(), model.trim().to_string());
+                }
+                None if !entry.trim().is_empty() => overrides.all = Some(entry.trim().to_string()),
+                _ => anyhow::bail!("expected `model` or `tool=model`, got {entry:?}"),
+            }
+        }
+        Ok(overrides)
+    }
+
+    /// The model `tool` calls when it was written for `model`
+    pub fn model<'a>(&'a self, tool: &str, model: &'a str) -> &'a str {
+        self.per_tool
+            .get(tool)
+            .or(self.all.as_ref())
+            .map_or(model, String::as_str)
+    }
+}
+
+static MODELS: OnceLock<ModelOverrides> = OnceLock::new();
+
+/// Install the model overrides every OpenAI call uses; only the first call takes effect
+pub fn configure_models(models: ModelOverrides) {
+    if MODELS.set(models).is_err() {
+        tracing::warn!("OpenAI model overrides already configured, ignoring");
+    }
+}
+
+/// The model `tool` calls, after the configured overrides
+pub fn model_for<'a>(tool: &str, model: &'a str) -> &'a str {
+    match MODELS.get() {
+        Some(models) => models.model(tool, model),
+        None => model,
+    }
+}
+
+/// How long an OpenAI call may take when no timeout was configured
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(120);
+
+static TIMEOUT: OnceLock<Duration> = OnceLock::new();
+
+/// Give up on an OpenAI call after `timeout`; only the first call takes effect
+pub fn configure_timeout(timeout: Duration) {
+    if TIMEOUT.set(timeout).is_err() {
+        tracing::warn!("OpenAI timeout already configured, ignoring");
+    }
+}
+
+/// The configured timeout, or `DEFAULT_TIMEOUT`
+pub fn timeout() -> Duration {
+    TIMEOUT.get().copied().unwrap_or(DEFAULT_TIMEOUT)
+}
+
+/// A client for OpenAI calls that gives up after `timeout()`
+pub fn client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(timeout())
+        .build()
+        .expect("A client with only a timeout set always builds. qed")
+}
+
+/// Start a chat completions POST for `tool`, forwarding the current request id
+/// when there is one and adding any configured extras. An empty `api_key` sends
+/// no `Authorization` header, as a local server such as Ollama wants none.
+pub fn chat_completions(
+    client: &reqwest::Client,
+    api_key: &str,
+    tool: &str,
+) -> reqwest::RequestBuilder {
+    chat_completions_at(client, chat_completions_url(), api_key, tool)
+}
+
+/// `chat_completions` against another endpoint, e.g. a mock server in tests
+pub fn chat_completions_at(
+    client: &reqwest::Client,
+    url: &str,
+    api_key: &str,
+    tool: &str,
+) -> reqwest::RequestBuilder {
+    let mut builder = client.post(url);
+    if !api_key.is_empty() {
+        builder = builder.bearer_auth(api_key);
+    }
+    forward(builder, tool)
+}
+
+/// Add the current request id and any configured extras to a call for `tool`,
+/// whichever provider it goes to
+pub fn forward(mut builder: reqwest::RequestBuilder, tool: &str) -> reqwest::RequestBuilder {
+    if let Some(id) = current_request_id() {
+        builder = builder.header(CLIENT_REQUEST_ID_HEADER, id);
+    }
+    match EXTRAS.get() {
+        Some(extras) => extras.apply(builder, tool),
+        None => builder,
+    }
+}
+
+static LAST_SUCCESS: Mutex<Option<Instant>> = Mutex::new(None);
+
+/// Send a chat completions request, counting it under `tool` along with its
+/// latency and whether it failed (transport error or non-2xx status).
+pub async fn send(
+    request: reqwest::RequestBuilder,
+    tool: &'static str,
+) -> reqwest::Result<reqwest::Response> {
+    let started = Instant::now();
+    let result = request.send().await;
+    metrics::counter!("ai_calls_total", "tool" => tool).increment(1);
+    metrics::histogram!("ai_call_duration_seconds", "tool" => tool)
+        .record(started.elapsed().as_secs_f64());
+    if matches!(&result, Ok(response) if response.status().is_success()) {
+        *LAST_SUCCESS.lock().unwrap_or_else(|e| e.into_inner()) = Some(Instant::now());
+    } else {
+        metrics::counter!("ai_call_errors_total", "tool" => tool).increment(1);
+    }
+    result
+}
+
+/// Whether a chat completions call succeeded, key and all, in the last `window`
+pub fn succeeded_within(window: Duration) -> bool {
+    LAST_SUCCESS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .is_some_and(|at| at.elapsed() <= window)
+}
+
+/// HEAD a provider's `url`; any HTTP answer means it is reachable
+pub async fn reachable(url: &str) -> reqwest::Result<()> {
+    reqwest::Client::new().head(url).send().await?;
+    Ok(())
+}
+
+/// One-shot OpenAI stand-in: answers a single chat completion with `reply`
+/// and hands back the JSON body it received.
+#[cfg(test)]
+pub(crate) fn mock_openai(reply: &str) -> (String, tokio::task::JoinHandle<serde_json::Value>) {
+    serve_mock(content_reply(reply), None)
+}
+
+/// `mock_openai` for function calling: the reply is one call of `name` with `arguments`
+#[cfg(test)]
+pub(crate) fn mock_openai_tool_call(
+    name: &str,
+    arguments: &serde_json::Value,
+) -> (String, tokio::task::JoinHandle<serde_json::Value>) {
+    serve_mock(tool_call_reply(name, arguments), None)
+}
+
+/// `mock_openai` replying with a whole response `body`, for other providers' shapes
+#[cfg(test)]
+pub(crate) fn mock_reply(
+    body: serde_json::Value,
+) -> (String, tokio::task::JoinHandle<serde_json::Value>) {
+    serve_mock(body, None)
+}
+
+/// `mock_reply` handing back the request line and headers instead of the body,
+/// for tests about what is sent besides the prompt
+#[cfg(test)]
+pub(crate) fn mock_request_head(
+    body: serde_json::Value,
+) -> (String, tokio::task::JoinHandle<String>) {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = mock_url(&listener);
+    let body = body.to_string();
+
+    let handle = tokio::task::spawn_blocking(move || {
+        let (mut socket, _) = listener.accept().unwrap();
+        let (head, _) = read_head_and_body(&mut socket);
+        write_reply(&mut socket, &body);
+        head
+    });
+    (url, handle)
+}
+
+#[cfg(test)]
+pub(crate) fn tool_call_reply(name: &str, arguments: &serde_json::Value) -> serde_json::Value {
+    serde_json::json!({
+        "choices": [{ "message": {
+            "role": "assistant",
+            "content": null,
+            "tool_calls": [{
+                "id": "call_1",
+                "type": "function",
+                "function": { "name": name, "arguments": arguments.to_string() }
+            }]
+        } }]
+    })
+}
+
+#[cfg(test)]
+pub(crate) fn content_reply(reply: &str) -> serde_json::Value {
+    serde_json::json!({
+        "choices": [{ "message": { "role": "assistant", "content": reply } }]
+    })
+}
+
+/// Holds a gated mock's reply: `arrived` fires once the request is in, and the
+/// reply is only sent after something is sent on `release`
+#[cfg(test)]
+pub(crate) struct MockGate {
+    pub arrived: tokio::sync::oneshot::Receiver<()>,
+    pub release: std::sync::mpsc::Sender<()>,
+}
+
+/// `mock_openai` for a slow upstream: the test decides when the reply goes out
+#[cfg(test)]
+pub(crate) fn mock_openai_gated(
+    reply: &str,
+) -> (String, tokio::task::JoinHandle<serde_json::Value>, MockGate) {
+    let (arrived_tx, arrived) = tokio::sync::oneshot::channel();
+    let (release, release_rx) = std::sync::mpsc::channel();
+    let (url, handle) = serve_mock(content_reply(reply), Some((arrived_tx, release_rx)));
+    (url, handle, MockGate { arrived, release })
+}
+
+/// `mock_openai` for a tool that makes several calls: each connection gets the
+/// next reply in turn, and the bodies come back in the order they arrived
+#[cfg(test)]
+pub(crate) fn mock_openai_replies(
+    replies: &[&str],
+) -> (String, tokio::task::JoinHandle<Vec<serde_json::Value>>) {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = mock_url(&listener);
+    let bodies: Vec<String> = replies
+        .iter()
+        .map(|r| content_reply(r).to_string())
+        .collect();
+
+    let handle = tokio::task::spawn_blocking(move || {
+        bodies
+            .iter()
+            .map(|body| {
+                let (mut socket, _) = listener.accept().unwrap();
+                let payload = read_request(&mut socket);
+                write_reply(&mut socket, body);
+                serde_json::from_slice(&payload).unwrap()
+            })
+            .collect()
+    });
+    (url, handle)
+}
+
+#[cfg(test)]
+fn mock_url(listener: &std::net::TcpListener) -> String {
+    format!(
+        "http://{}/v1/chat/completions",
+        listener.local_addr().unwrap()
+    )
+}
+
+/// Read headers, then exactly Content-Length bytes of body
+#[cfg(test)]
+fn read_request(socket: &mut std::net::TcpStream) -> Vec<u8> {
+    read_head_and_body(socket).1
+}
+
+/// `read_request`, keeping the request line and headers too
+#[cfg(test)]
+fn read_head_and_body(socket: &mut std::net::TcpStream) -> (String, Vec<u8>) {
+    use std::io::Read;
+
+    let mut request = Vec::new();
+    let mut buf = [0u8; 4096];
+    loop {
+        let n = socket.read(&mut buf).unwrap();
+        assert!(n > 0, "client closed before sending the full request");
+        request.extend_from_slice(&buf[..n]);
+        let text = String::from_utf8_lossy(&request);
+        let Some(header_end) = text.find("\r\n\r\n") else {
+            continue;
+        };
+        let length = text[..header_end]
+            .lines()
+            .find_map(|line| {
+                let (name, value) = line.split_once(':')?;
+                name.eq_ignore_ascii_case("content-length")
+                    .then(|| value.trim().parse::<usize>().ok())?
+            })
+            .unwrap_or(0);
+        if request.len() >= header_end + 4 + length {
+            let body = request[header_end + 4..header_end + 4 + length].to_vec();
+            return (text[..header_end].to_string(), body);
+        }
+    }
+}
+
+#[cfg(test)]
+fn write_reply(socket: &mut std::net::TcpStream, body: &str) {
+    use std::io::Write;
+
+    let response = format!(
+        "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    );
+    socket.write_all(response.as_bytes()).unwrap();
+}
+
+#[cfg(test)]
+fn serve_mock(
+    body: serde_json::Value,
+    gate: Option<(
+        tokio::sync::oneshot::Sender<()>,
+        std::sync::mpsc::Receiver<()>,
+    )>,
+) -> (String, tokio::task::JoinHandle<serde_json::Value>) {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = mock_url(&listener);
+    let body = body.to_string();
+
+    let handle = tokio::task::spawn_blocking(move || {
+        let (mut socket, _) = listener.accept().unwrap();
+        let payload = read_request(&mut socket);
+        if let Some((arrived, release)) = gate {
+            let _ = arrived.send(());
+            release.recv().unwrap();
+        }
+        write_reply(&mut socket, &body);
+        serde_json::from_slice(&payload).unwrap()
+    });
+    (url, handle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_request_id_is_forwarded_upstream() {
+        let client = reqwest::Client::new();
+
+        let without = chat_completions(&client, "key", "linter").build().unwrap();
+        assert!(without.headers().get(CLIENT_REQUEST_ID_HEADER).is_none());
+
+        let with = with_request_id("req-42".to_string(), async {
+            chat_completions(&client, "key", "linter").build().unwrap()
+        })
+        .await;
+        assert_eq!(
+            with.headers().get(CLIENT_REQUEST_ID_HEADER).unwrap(),
+            "req-42"
+        );
+    }
+
+    #[test]
+    fn test_empty_key_sends_no_authorization() {
+        let client = reqwest::Client::new();
+        let keyed = chat_completions_at(&client, CHAT_COMPLETIONS_URL, "key", "linter")
+            .build()
+            .unwrap();
+        assert_eq!(keyed.headers().get("authorization").unwrap(), "Bearer key");
+
+        let local = chat_completions_at(&client, "http://localhost:11434/v1", "", "linter")
+            .build()
+            .unwrap();
+        assert!(local.headers().get("authorization").is_none());
+    }
+
+    #[test]
+    fn test_model_overrides() {
+        let overrides = ModelOverrides::parse(&[
+            "llama3.1:8b".to_string(),
+            "linter = qwen2.5:7b".to_string(),
+            "researcher=meta-llama/Llama-3.1-70B".to_string(),
+        ])
+        .unwrap();
+        assert_eq!(overrides.model("linter", "gpt-4o-mini"), "qwen2.5:7b");
+        assert_eq!(
+            overrides.model("researcher", "gpt-4o"),
+            "meta-llama/Llama-3.1-70B"
+        );
+        assert_eq!(overrides.model("titler", "gpt-4o-mini"), "llama3.1:8b");
+        assert_eq!(
+            ModelOverrides::default().model("titler", "gpt-4o-mini"),
+            "gpt-4o-mini"
+        );
+
+        assert!(ModelOverrides::parse(&["linter=".to_string()]).is_err());
+        assert!(ModelOverrides::parse(&["=qwen2.5".to_string()]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_successful_calls_are_remembered() {
+        let (url, _received) = mock_openai("ok");
+        let client = reqwest::Client::new();
+        let request =
+            chat_completions_at(&client, &url, "key", "linter").json(&serde_json::json!({}));
+
+        send(request, "linter").await.unwrap();
+        assert!(succeeded_within(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn test_extras_are_added_to_request() {
+        let extras = OpenAiExtras::parse(
+            &[
+                "X-Gateway-Key: abc".to_string(),
+                "linter/X-Route: cheap-pool".to_string(),
+            ],
+            &[
+                "api-version=2024-06-01".to_string(),
+                "linter/deployment=mini".to_string(),
+            ],
+        )
+        .unwrap();
+        let client = reqwest::Client::new();
+
+        let linter = extras
+            .apply(client.post(CHAT_COMPLETIONS_URL), "linter")
+            .build()
+            .unwrap();
+        assert_eq!(linter.headers().get("x-gateway-key").unwrap(), "abc");
+        assert_eq!(linter.headers().get("x-route").unwrap(), "cheap-pool");
+        assert_eq!(
+            linter.url().query(),
+            Some("api-version=2024-06-01&deployment=mini")
+        );
+
+        // Another tool only gets the global extras
+        let refiner = extras
+            .apply(client.post(CHAT_COMPLETIONS_URL), "refiner")
+            .build()
+            .unwrap();
+        assert!(refiner.headers().get("x-route").is_none());
+        assert_eq!(refiner.url().query(), Some("api-version=2024-06-01"));
+    }
+
+    #[test]
+    fn test_configured_base_url_is_used() {
+        assert_eq!(
+            chat_completions_url_under(DEFAULT_BASE_URL),
+            CHAT_COMPLETIONS_URL
+        );
+        assert!(configure_base_url("not a url").is_err());
+
+        // The only test that configures it, so nothing else sees the change
+        configure_base_url("http://localhost:11434/v1/").unwrap();
+        let request = chat_completions(&reqwest::Client::new(), "key", "linter")
+            .build()
+            .unwrap();
+        assert_eq!(
+            request.url().as_str(),
+            "http://localhost:11434/v1/chat/completions"
+        );
+    }
+
+    #[test]
+    fn test_invalid_extras_are_rejected() {
+        assert!(OpenAiExtras::parse(&["Bad Header: x".to_string()], &[]).is_err());
+        assert!(OpenAiExtras::parse(&["no-colon".to_string()], &[]).is_err());
+        assert!(OpenAiExtras::parse(&[], &["no-equals".to_string()]).is_err());
+        // A misspelt tool would otherwise be silently ignored
+        assert!(OpenAiExtras::parse(&["lintr/X-Route: a".to_string()], &[]).is_err());
+        assert!(OpenAiExtras::parse(&[], &["lintr/deployment=mini".to_string()]).is_err());
+    }
+}
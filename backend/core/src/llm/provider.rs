@@ -0,0 +1,409 @@
+use crate::llm::openai::{chat_completions_url, model_for};
+use crate::llm::types::McpTool;
+use crate::refiner::error::{RefineError, check_response};
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::sync::{Arc, OnceLock};
+use tracing::Instrument;
+
+/// One turn of the conversation sent to the model
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChatMessage {
+    /// `system`, `user` or `assistant`
+    pub role: String,
+    pub content: String,
+}
+
+impl ChatMessage {
+    pub fn system(content: impl Into<String>) -> Self {
+        Self {
+            role: "system".to_string(),
+            content: content.into(),
+        }
+    }
+
+    pub fn user(content: impl Into<String>) -> Self {
+        Self {
+            role: "user".to_string(),
+            content: content.into(),
+        }
+    }
+
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self {
+            role: "assistant".to_string(),
+            content: content.into(),
+        }
+    }
+}
+
+/// How the text of the answer is shaped
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum ResponseFormat {
+    #[default]
+    Text,
+    /// Any JSON object
+    JsonObject,
+    /// A JSON object following `schema`; `name` identifies the schema to the model
+    JsonSchema {
+        name: String,
+        schema: serde_json::Value,
+    },
+}
+
+/// One chat completion, in terms every provider can translate
+#[derive(Debug, Clone, Default)]
+pub struct ChatCompletion {
+    /// The OpenAI model the tool was written for; other providers pick their
+    /// closest model
+    pub model: String,
+    pub messages: Vec<ChatMessage>,
+    /// Functions the model may call
+    pub tools: Vec<McpTool>,
+    /// Make the model call this one of `tools` instead of answering in text
+    pub tool_choice: Option<String>,
+    pub temperature: Option<f64>,
+    pub format: ResponseFormat,
+}
+
+/// A function call the model made, with its arguments parsed
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolCall {
+    pub name: String,
+    /// The arguments object; a string holding the raw text when the model sent invalid JSON
+    pub arguments: serde_json::Value,
+}
+
+/// What the model answered
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChatReply {
+    pub content: Option<String>,
+    pub tool_calls: Vec<ToolCall>,
+}
+
+/// A chat model API the tools can run on
+pub trait LlmProvider: Send + Sync {
+    /// Run `request`; `tool` names the caller in metrics, usage and upstream extras
+    fn chat_completion<'a>(
+        &'a self,
+        tool: &'static str,
+        request: &'a ChatCompletion,
+    ) -> BoxFuture<'a, Result<ChatReply, RefineError>>;
+
+    /// The model and endpoint `tool` ends up on when it asks for `model`, for logs
+    fn describe(&self, tool: &'static str, model: &str) -> String;
+
+    /// Whether the provider's endpoint answers at all, for readiness checks
+    fn reachable(&self) -> BoxFuture<'_, reqwest::Result<()>>;
+}
+
+/// The span a provider runs one call in; usage fills in the token and cost fields
+pub(crate) fn call_span(tool: &'static str, model: &str) -> tracing::Span {
+    tracing::info_span!(
+        "ai_call",
+        tool,
+        model,
+        prompt_tokens = tracing::field::Empty,
+        completion_tokens = tracing::field::Empty,
+        cost_usd = tracing::field::Empty,
+    )
+}
+
+static PROVIDER: OnceLock<Arc<dyn LlmProvider>> = OnceLock::new();
+
+/// Install the provider every tool calls; only the first call takes effect.
+/// Without one, tools call OpenAI with the key they are given.
+pub fn configure_provider(provider: Arc<dyn LlmProvider>) {
+    if PROVIDER.set(provider).is_err() {
+        tracing::warn!("LLM provider already configured, ignoring");
+    }
+}
+
+/// The installed provider, or OpenAI with `openai_api_key` when none is
+pub fn configured(openai_api_key: &str) -> Arc<dyn LlmProvider> {
+    match PROVIDER.get() {
+        Some(provider) => provider.clone(),
+        None => Arc::new(OpenAi::new(openai_api_key)),
+    }
+}
+
+/// The default provider: OpenAI's chat completions API, or a compatible local
+/// server under the configured base URL
+pub struct OpenAi {
+    client: reqwest::Client,
+    url: String,
+    api_key: String,
+}
+
+impl OpenAi {
+    pub fn new(api_key: impl Into<String>) -> Self {
+        Self::at(chat_completions_url(), api_key)
+    }
+
+    /// `new` against another endpoint, e.g. a mock server in tests
+    pub fn at(url: impl Into<String>, api_key: impl Into<String>) -> Self {
+        Self {
+            client: crate::llm::openai::client(),
+            url: url.into(),
+            api_key: api_key.into(),
+        }
+    }
+}
+
+impl LlmProvider for OpenAi {
+    fn chat_completion<'a>(
+        &'a self,
+        tool: &'static str,
+        request: &'a ChatCompletion,
+    ) -> BoxFuture<'a, Result<ChatReply, RefineError>> {
+        let model = model_for(tool, &request.model);
+        let span = call_span(tool, model);
+        let call = async move {
+            let request = crate::llm::openai::chat_completions_at(
+                &self.client,
+                &self.url,
+                &self.api_key,
+                tool,
+            )
+            .json(&openai_body(model, request));
+            let response = crate::llm::openai::send(request, tool).await?;
+            let response = check_response(response).await?;
+
+            let body: serde_json::Value = response
+                .json()
+                .await
+                .map_err(|e| RefineError::Parse(e.to_string()))?;
+            crate::llm::usage::record_response(tool, &body);
+            Ok(openai_reply(&body))
+        };
+        Box::pin(call.instrument(span))
+    }
+
+    fn describe(&self, tool: &'static str, model: &str) -> String {
+        format!("{} at {}", model_for(tool, model), self.url)
+    }
+
+    fn reachable(&self) -> BoxFuture<'_, reqwest::Result<()>> {
+        Box::pin(crate::llm::openai::reachable(&self.url))
+    }
+}
+
+/// `request` as a chat completions body, calling `model`
+fn openai_body(model: &str, request: &ChatCompletion) -> serde_json::Value {
+    let mut body = json!({
+        "model": model,
+        "messages": request.messages,
+    });
+    if let Some(temperature) = request.temperature {
+        body["temperature"] = json!(temperature);
+    }
+    match &request.format {
+        ResponseFormat::Text => {}
+        ResponseFormat::JsonObject => body["response_format"] = json!({ "type": "json_object" }),
+        ResponseFormat::JsonSchema { name, schema } => {
+            body["response_format"] = json!({
+                "type": "json_schema",
+                "json_schema": { "name": name, "strict": true, "schema": schema }
+            })
+        }
+    }
+    if !request.tools.is_empty() {
+        let tools: Vec<_> = request
+            .tools
+            .iter()
+            .map(|tool| {
+                json!({
+                    "type": "function",
+                    "function": {
+                        "name": tool.name,
+                        "description": tool.description,
+                        "parameters": tool.input_schema
+                    }
+                })
+            })
+            .collect();
+        body["tools"] = json!(tools);
+    }
+    if let Some(name) = &request.tool_choice {
+        body["tool_choice"] = json!({ "type": "function", "function": { "name": name } });
+    }
+    body
+}
+
+/// The first choice of a chat completions response
+fn openai_reply(body: &serde_json::Value) -> ChatReply {
+    let message = &body["choices"][0]["message"];
+    let tool_calls = message["tool_calls"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|call| {
+            let function = call.get("function")?;
+            let arguments = function["arguments"].as_str().unwrap_or_default();
+            Some(ToolCall {
+                name: function["name"].as_str()?.to_string(),
+                arguments: serde_json::from_str(arguments)
+                    .unwrap_or_else(|_| serde_json::Value::String(arguments.to_string())),
+            })
+        })
+        .collect();
+    ChatReply {
+        content: message["content"].as_str().map(str::to_string),
+        tool_calls,
+    }
+}
+
+/// Stand-in provider for tool tests: answers from a queue of replies, without
+/// any network, and keeps every request it was sent
+#[cfg(test)]
+pub(crate) struct FakeProvider {
+    replies: std::sync::Mutex<std::collections::VecDeque<ChatReply>>,
+    requests: std::sync::Mutex<Vec<ChatCompletion>>,
+}
+
+#[cfg(test)]
+impl FakeProvider {
+    pub fn new(replies: impl IntoIterator<Item = ChatReply>) -> Self {
+        Self {
+            replies: std::sync::Mutex::new(replies.into_iter().collect()),
+            requests: Default::default(),
+        }
+    }
+
+    /// A provider answering once with `content`
+    pub fn text(content: &str) -> Self {
+        Self::new([ChatReply {
+            content: Some(content.to_string()),
+            tool_calls: Vec::new(),
+        }])
+    }
+
+    pub fn requests(&self) -> Vec<ChatCompletion> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+impl LlmProvider for FakeProvider {
+    fn chat_completion<'a>(
+        &'a self,
+        _tool: &'static str,
+        request: &'a ChatCompletion,
+    ) -> BoxFuture<'a, Result<ChatReply, RefineError>> {
+        self.requests.lock().unwrap().push(request.clone());
+        let reply = self.replies.lock().unwrap().pop_front();
+        Box::pin(async move {
+            reply.ok_or_else(|| RefineError::Parse("FakeProvider has no reply left".to_string()))
+        })
+    }
+
+    fn describe(&self, _tool: &'static str, model: &str) -> String {
+        format!("{model} on a fake provider")
+    }
+
+    fn reachable(&self) -> BoxFuture<'_, reqwest::Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report_tool() -> McpTool {
+        McpTool {
+            name: "report".to_string(),
+            description: "Deliver the report.".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": { "summary": { "type": "string" } },
+                "required": ["summary"]
+            }),
+        }
+    }
+
+    #[test]
+    fn test_openai_body_carries_tools_and_format() {
+        let request = ChatCompletion {
+            model: "gpt-4o".to_string(),
+            messages: vec![ChatMessage::system("Be brief."), ChatMessage::user("Hi")],
+            tools: vec![report_tool()],
+            tool_choice: Some("report".to_string()),
+            temperature: Some(0.3),
+            format: ResponseFormat::JsonObject,
+        };
+        let body = openai_body(&request.model, &request);
+        assert_eq!(
+            body["messages"][0],
+            json!({ "role": "system", "content": "Be brief." })
+        );
+        assert_eq!(body["temperature"], 0.3);
+        assert_eq!(body["response_format"]["type"], "json_object");
+        assert_eq!(body["tools"][0]["type"], "function");
+        assert_eq!(
+            body["tools"][0]["function"]["parameters"]["required"][0],
+            "summary"
+        );
+        assert_eq!(body["tool_choice"]["function"]["name"], "report");
+
+        // Plain requests stay plain
+        let body = openai_body(
+            "llama3.1:8b",
+            &ChatCompletion {
+                model: "gpt-4o-mini".to_string(),
+                messages: vec![ChatMessage::user("Hi")],
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            body,
+            json!({ "model": "llama3.1:8b", "messages": [{ "role": "user", "content": "Hi" }] })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_openai_tool_calls_are_parsed() {
+        let (url, _received) =
+            crate::llm::openai::mock_openai_tool_call("report", &json!({ "summary": "Done." }));
+        let request = ChatCompletion {
+            model: "gpt-4o".to_string(),
+            messages: vec![ChatMessage::user("Report")],
+            tools: vec![report_tool()],
+            tool_choice: Some("report".to_string()),
+            ..Default::default()
+        };
+
+        let reply = OpenAi::at(&url, "test-key")
+            .chat_completion("researcher", &request)
+            .await
+            .unwrap();
+        assert_eq!(reply.content, None);
+        assert_eq!(
+            reply.tool_calls,
+            vec![ToolCall {
+                name: "report".to_string(),
+                arguments: json!({ "summary": "Done." }),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_local_server_without_a_key() {
+        let (url, received) = crate::llm::openai::mock_openai("Offline answer");
+        let llm = OpenAi::at(&url, "");
+        let request = ChatCompletion {
+            model: "gpt-4o-mini".to_string(),
+            messages: vec![ChatMessage::user("Hi")],
+            ..Default::default()
+        };
+
+        let reply = llm.chat_completion("titler", &request).await.unwrap();
+        assert_eq!(reply.content.as_deref(), Some("Offline answer"));
+        assert_eq!(received.await.unwrap()["model"], "gpt-4o-mini");
+        assert_eq!(
+            llm.describe("titler", "gpt-4o-mini"),
+            format!("gpt-4o-mini at {url}")
+        );
+    }
+}
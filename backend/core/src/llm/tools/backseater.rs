@@ -1,97 +1,73 @@
-use anyhow::{Context, Result};
+use crate::llm::provider::{self, ChatCompletion, ChatMessage, LlmProvider};
+use crate::llm::types::McpTool;
+use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 
+/// Prompt budget for the document text, about 2000 characters of English
+const CONTEXT_TOKENS: usize = 500;
+
 /// Execute the backseater tool - generates unhelpful comments on user's writing
 /// Uses direct function calling API (single call, no Agent loop)
 pub async fn execute_tool(content: &str, api_key: &str) -> Result<Vec<BackseaterArgs>> {
-    let client = reqwest::Client::new();
+    comments_at(&*provider::configured(api_key), content).await
+}
 
+/// `execute_tool` on a given provider, e.g. one pointed at a mock server in tests
+pub(crate) async fn comments_at(
+    llm: &dyn LlmProvider,
+    content: &str,
+) -> Result<Vec<BackseaterArgs>> {
     // Limit content length to avoid token limits
-    let truncated_content = if content.len() > 2000 {
-        &content[content.len() - 2000..]
-    } else {
-        content
-    };
-
-    let request_payload = json!({
-        "model": "gpt-4o-mini",
-        "messages": [
-            {
-                "role": "system",
-                "content": "You will generate very short, unhelpful and nitpicky comments on the user's writing. Use the commenter tool to provide your comments."
-            },
-            {
-                "role": "user",
-                "content": format!("Generate unhelpful comments on this text:\n\n{}", truncated_content)
-            }
+    let truncated_content = crate::llm::truncate::last_tokens(content, CONTEXT_TOKENS);
+
+    let request = ChatCompletion {
+        model: "gpt-4o-mini".to_string(),
+        messages: vec![
+            ChatMessage::system(
+                "You will generate very short, unhelpful and nitpicky comments on the user's writing. Use the commenter tool to provide your comments.",
+            ),
+            ChatMessage::user(format!(
+                "Generate unhelpful comments on this text:\n\n{}",
+                truncated_content
+            )),
         ],
-        "tools": [
-            {
-                "type": "function",
-                "function": {
-                    "name": "commenter",
-                    "description": "Generate a read-only comment on a specific part of the user's writing.",
-                    "parameters": {
-                        "type": "object",
-                        "properties": {
-                            "comment_on": {
-                                "type": "string",
-                                "description": "The specific part of the user's writing to comment on."
-                            },
-                            "comment": {
-                                "type": "string",
-                                "description": "The comment to generate."
-                            },
-                            "color_hex": {
-                                "type": "string",
-                                "description": "The color of the comment in hex format."
-                            }
-                        },
-                        "required": ["comment_on", "comment"]
+        tools: vec![McpTool {
+            name: "commenter".to_string(),
+            description: "Generate a read-only comment on a specific part of the user's writing."
+                .to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "comment_on": {
+                        "type": "string",
+                        "description": "The specific part of the user's writing to comment on."
+                    },
+                    "comment": {
+                        "type": "string",
+                        "description": "The comment to generate."
+                    },
+                    "color_hex": {
+                        "type": "string",
+                        "description": "The color of the comment in hex format."
                     }
-                }
-            }
-        ],
-        "tool_choice": {
-            "type": "function",
-            "function": {
-                "name": "commenter"
-            }
-        }
-    });
-
-    let response = client
-        .post("https://api.openai.com/v1/chat/completions")
-        .bearer_auth(api_key)
-        .json(&request_payload)
-        .send()
-        .await
-        .context("Failed to connect to OpenAI during backseater execution")?;
-
-    if !response.status().is_success() {
-        let error_msg = response.text().await.unwrap_or_default();
-        return Err(anyhow::anyhow!("Backseater Tool Error: {}", error_msg));
-    }
-
-    let result: serde_json::Value = response.json().await?;
+                },
+                "required": ["comment_on", "comment"]
+            }),
+        }],
+        tool_choice: Some("commenter".to_string()),
+        ..Default::default()
+    };
 
-    // Extract function call arguments directly from the first response
+    // Function call arguments come straight back in the first response
     // No second API call needed!
-    let tool_calls = result["choices"][0]["message"]["tool_calls"]
-        .as_array()
-        .context("No tool_calls in response")?;
+    let reply = llm.chat_completion("backseater", &request).await?;
 
     let mut comments = Vec::new();
-    for tool_call in tool_calls {
-        if let Some(function) = tool_call.get("function") {
-            if let Some(args_str) = function.get("arguments").and_then(|v| v.as_str()) {
-                if let Ok(comment) = serde_json::from_str::<BackseaterArgs>(args_str) {
-                    comments.push(comment);
-                } else {
-                    tracing::warn!("Failed to parse tool call arguments: {}", args_str);
-                }
-            }
+    for tool_call in reply.tool_calls {
+        match serde_json::from_value::<BackseaterArgs>(tool_call.arguments) {
+            Ok(comment) => comments.push(comment),
+            Err(e) => tracing::warn!("Failed to parse tool call arguments: {}", e),
         }
     }
 
@@ -1,6 +1,6 @@
+use crate::llm::provider::{self, ChatCompletion, ChatMessage, LlmProvider, ResponseFormat};
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
-use serde_json::json;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Replacement {
@@ -8,19 +8,24 @@ pub struct Replacement {
     pub with: String,
 }
 
+/// Prompt budget for the document text, about 2000 characters of English
+const CONTEXT_TOKENS: usize = 500;
+
 /// Execute the emoji replacer tool
 /// 
 /// Takes plain text content and asks AI to suggest word-to-emoji replacements.
 /// Returns a JSON array of Replacement structs.
 pub async fn execute_tool(content: &str, api_key: &str) -> Result<Vec<Replacement>> {
-    let client = reqwest::Client::new();
+    replacements_at(&*provider::configured(api_key), content).await
+}
 
-    // Limit content length to avoid token limits (keep last 2000 chars)
-    let truncated_content = if content.len() > 2000 {
-        &content[content.len() - 2000..]
-    } else {
-        content
-    };
+/// `execute_tool` on a given provider, e.g. one pointed at a mock server in tests
+pub(crate) async fn replacements_at(
+    llm: &dyn LlmProvider,
+    content: &str,
+) -> Result<Vec<Replacement>> {
+    // Limit content length to avoid token limits (keep the most recent writing)
+    let truncated_content = crate::llm::truncate::last_tokens(content, CONTEXT_TOKENS);
 
     let system_content = r#"You are a helpful assistant that suggests emoji replacements for words in text.
 Given a text, return a JSON object with a "replacements" key containing an array of replacement suggestions.
@@ -33,46 +38,25 @@ Return ONLY valid JSON in this format: {"replacements": [{"replace": "word1", "w
         truncated_content
     );
 
-    let request_payload = json!({
-        "model": "gpt-4o-mini",
-        "messages": [
-            {
-                "role": "system",
-                "content": system_content
-            },
-            {
-                "role": "user",
-                "content": user_content
-            }
+    let request = ChatCompletion {
+        model: "gpt-4o-mini".to_string(),
+        messages: vec![
+            ChatMessage::system(system_content),
+            ChatMessage::user(user_content),
         ],
-        "response_format": {
-            "type": "json_object"
-        }
-    });
-
-    let response = client
-        .post("https://api.openai.com/v1/chat/completions")
-        .bearer_auth(api_key)
-        .json(&request_payload)
-        .send()
-        .await
-        .context("Failed to connect to OpenAI during emoji replacer execution")?;
-
-    if !response.status().is_success() {
-        let error_msg = response.text().await.unwrap_or_default();
-        return Err(anyhow::anyhow!("Emoji Replacer Tool Error: {}", error_msg));
-    }
-
-    let result: serde_json::Value = response.json().await?;
+        format: ResponseFormat::JsonObject,
+        ..Default::default()
+    };
 
-    let content_str = result["choices"][0]["message"]["content"]
-        .as_str()
+    let reply = llm.chat_completion("emoji_replacer", &request).await?;
+    let content_str = reply
+        .content
         .context("Failed to get content from Emoji Replacer response")?;
 
     // Parse the JSON response
     // With json_object format, we expect {"replacements": [...]}
     // But also handle cases where it might return just [...]
-    let parsed: serde_json::Value = serde_json::from_str(content_str)
+    let parsed: serde_json::Value = serde_json::from_str(&content_str)
         .context("Failed to parse JSON response")?;
     
     let replacements: Vec<Replacement> = if let Some(arr) = parsed.get("replacements").and_then(|v| v.as_array()) {
@@ -1,45 +1,210 @@
-use anyhow::{Context, Result};
-use serde_json::json;
-
-pub async fn execute_tool(article_draft: &str, identity: &str, api_key: &str) -> Result<String> {
-    let client = reqwest::Client::new();
-
-    let system_content = 
-        "You will finish the user's sentence as aggressively pessimistic as possible. **ONLY** respond with your generated part of the sentence, excluding the user's original context.".to_string();
-
-    let request_payload = json!({
-        "model": "gpt-4o-mini",
-        "messages": [
-            {
-                "role": "system",
-                "content": system_content
-            },
-            {
-                "role": "user",
-                "content": article_draft
-            }
-        ]
-    });
-
-    let response = client
-        .post("https://api.openai.com/v1/chat/completions")
-        .bearer_auth(api_key)
-        .json(&request_payload)
-        .send()
-        .await
-        .context("Failed to connect to OpenAI during extender execution")?;
-
-    if !response.status().is_success() {
-        let error_msg = response.text().await.unwrap_or_default();
-        return Err(anyhow::anyhow!("Extender Tool Error: {}", error_msg));
-    }
-
-    let result: serde_json::Value = response.json().await?;
-
-    let extended_output = result["choices"][0]["message"]["content"]
-        .as_str()
-        .context("Failed to get content from Extender response")?
-        .to_string();
+use crate::llm::provider::{self, ChatCompletion, ChatMessage, LlmProvider};
+use crate::refiner::error::RefineError;
+use serde::{Deserialize, Serialize};
+
+const CONTINUE_SYSTEM_PROMPT: &str = "You will finish the user's sentence as aggressively pessimistic as possible. **ONLY** respond with your generated part of the sentence, excluding the user's original context.";
+
+const DIRECTIVE_SYSTEM_PROMPT: &str = "You are a writing assistant. The user gives you their draft for context and a separate instruction. Write the new passage the instruction asks for so it fits into the draft. **ONLY** respond with the generated passage, excluding the user's original context and the instruction itself.";
+
+/// Most earlier messages a session replays, so a long session does not grow
+/// the prompt without bound
+const MAX_HISTORY_MESSAGES: usize = 20;
+
+/// The turns of one composer session: what the writer asked for and what was
+/// written, replayed before the next request so a follow-up such as
+/// "continue" or "make the next section about X" knows the story so far
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Conversation {
+    messages: Vec<ChatMessage>,
+}
+
+impl Conversation {
+    pub fn messages(&self) -> &[ChatMessage] {
+        &self.messages
+    }
+
+    /// Remember one turn; the oldest turns are dropped once the session is long
+    pub fn record(&mut self, instruction: Option<&str>, reply: &str) {
+        let request = match instruction {
+            Some(instruction) => format!("Instruction: {}", instruction),
+            None => "Continue the draft.".to_string(),
+        };
+        self.messages.push(ChatMessage::user(request));
+        self.messages.push(ChatMessage::assistant(reply));
+        let excess = self.messages.len().saturating_sub(MAX_HISTORY_MESSAGES);
+        self.messages.drain(..excess);
+    }
+}
+
+/// A bracketed instruction highlighted by the writer, e.g. `[expand on the economic impact]`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Directive<'a> {
+    /// The selection exactly as it appears in the document, brackets included
+    pub raw: &'a str,
+    /// The instruction inside the brackets
+    pub instruction: &'a str,
+}
+
+/// Detect a `[instruction]` directive in a selection.
+///
+/// Returns `None` for ordinary text, which the composer continues verbatim.
+pub fn parse_directive(selection: &str) -> Option<Directive<'_>> {
+    let raw = selection.trim();
+    let instruction = raw.strip_prefix('[')?.strip_suffix(']')?.trim();
+    if instruction.is_empty() || instruction.contains(['[', ']']) {
+        return None;
+    }
+    Some(Directive { raw, instruction })
+}
+
+/// The prompt for one turn; `history` goes between the system prompt and the
+/// current draft
+fn build_messages(
+    article_draft: &str,
+    instruction: Option<&str>,
+    history: &[ChatMessage],
+) -> Vec<ChatMessage> {
+    let system_prompt = match instruction {
+        Some(_) => DIRECTIVE_SYSTEM_PROMPT,
+        None => CONTINUE_SYSTEM_PROMPT,
+    };
+    let mut messages = vec![ChatMessage::system(system_prompt)];
+    messages.extend_from_slice(history);
+    match instruction {
+        Some(instruction) => messages.extend([
+            ChatMessage::user(format!("Draft for context:\n\n{}", article_draft)),
+            ChatMessage::user(format!("Instruction: {}", instruction)),
+        ]),
+        None => messages.push(ChatMessage::user(article_draft)),
+    }
+    messages
+}
+
+pub async fn execute_tool(
+    article_draft: &str,
+    identity: &str,
+    api_key: &str,
+    instruction: Option<&str>,
+    history: &[ChatMessage],
+) -> Result<String, RefineError> {
+    execute_tool_at(
+        &*provider::configured(api_key),
+        article_draft,
+        identity,
+        instruction,
+        history,
+    )
+    .await
+}
+
+/// `execute_tool` on a given provider, e.g. one pointed at a mock server in tests
+pub async fn execute_tool_at(
+    llm: &dyn LlmProvider,
+    article_draft: &str,
+    _identity: &str,
+    instruction: Option<&str>,
+    history: &[ChatMessage],
+) -> Result<String, RefineError> {
+    let request = ChatCompletion {
+        model: "gpt-4o-mini".to_string(),
+        messages: build_messages(article_draft, instruction, history),
+        ..Default::default()
+    };
+
+    let extended_output = llm
+        .chat_completion("extender", &request)
+        .await?
+        .content
+        .ok_or_else(|| {
+            RefineError::Parse("Failed to get content from Extender response".to_string())
+        })?;
 
     Ok(extended_output)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_directive() {
+        let directive = parse_directive("  [expand on the economic impact] ").unwrap();
+        assert_eq!(directive.raw, "[expand on the economic impact]");
+        assert_eq!(directive.instruction, "expand on the economic impact");
+
+        assert!(parse_directive("plain text to continue").is_none());
+        assert!(parse_directive("[]").is_none());
+        assert!(parse_directive("[   ]").is_none());
+        assert!(parse_directive("[one] and [two]").is_none());
+    }
+
+    #[test]
+    fn test_directive_is_sent_as_separate_instruction() {
+        let messages = build_messages(
+            "The economy grew.",
+            Some("expand on the economic impact"),
+            &[],
+        );
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[0].content, DIRECTIVE_SYSTEM_PROMPT);
+        assert_eq!(
+            messages[1].content,
+            "Draft for context:\n\nThe economy grew."
+        );
+        assert_eq!(
+            messages[2].content,
+            "Instruction: expand on the economic impact"
+        );
+    }
+
+    #[test]
+    fn test_plain_continuation_keeps_original_prompt() {
+        let messages = build_messages("The economy grew.", None, &[]);
+        assert_eq!(
+            messages,
+            vec![
+                ChatMessage::system(CONTINUE_SYSTEM_PROMPT),
+                ChatMessage::user("The economy grew."),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_history_goes_before_the_current_draft() {
+        let mut conversation = Conversation::default();
+        conversation.record(Some("open with the harvest"), "The harvest failed.");
+        conversation.record(None, "Prices rose.");
+
+        let messages = build_messages(
+            "The harvest failed. Prices rose.",
+            None,
+            conversation.messages(),
+        );
+        assert_eq!(
+            messages,
+            vec![
+                ChatMessage::system(CONTINUE_SYSTEM_PROMPT),
+                ChatMessage::user("Instruction: open with the harvest"),
+                ChatMessage::assistant("The harvest failed."),
+                ChatMessage::user("Continue the draft."),
+                ChatMessage::assistant("Prices rose."),
+                ChatMessage::user("The harvest failed. Prices rose."),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_long_sessions_keep_the_latest_turns() {
+        let mut conversation = Conversation::default();
+        for turn in 0..MAX_HISTORY_MESSAGES {
+            conversation.record(None, &format!("Turn {turn}."));
+        }
+        let messages = conversation.messages();
+        assert_eq!(messages.len(), MAX_HISTORY_MESSAGES);
+        assert_eq!(messages[0], ChatMessage::user("Continue the draft."));
+        assert_eq!(
+            messages[MAX_HISTORY_MESSAGES - 1],
+            ChatMessage::assistant(format!("Turn {}.", MAX_HISTORY_MESSAGES - 1))
+        );
+    }
+}
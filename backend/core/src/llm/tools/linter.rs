@@ -1,17 +1,116 @@
+use crate::editor::write::AI_ORIGIN;
+use crate::llm::cache::{self, ResponseCache};
+use crate::llm::provider::{
+    self, ChatCompletion, ChatMessage, ChatReply, LlmProvider, ResponseFormat,
+};
+use crate::refiner::error::RefineError;
+use crate::refiner::language::Language;
 use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
 use serde_json::json;
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 use std::sync::Arc;
-use tracing::info;
+use tracing::{info, warn};
 use yrs::types::xml::{XmlElementRef, XmlFragmentRef};
-use yrs::{Doc, GetString, Transact, Xml, XmlFragment};
+use yrs::{Doc, GetString, ReadTxn, Transact, Xml, XmlFragment};
+
+pub const LINTER_MODEL: &str = "gpt-4o-mini";
+
+const SYSTEM_PROMPT: &str = r#"You are the "Schema Sentry," a specialized linguistic linter for Yjs XmlFragments.
+
+Your sole purpose is to:
+1. Fix grammatical errors and spelling mistakes within the text nodes.
+2. Refine vocabulary for better clarity while maintaining the original tone.
+3. Strict Constraint: Do NOT provide any explanations, comments, or markdown code blocks (like ```xml).
+4. Output Format: Return ONLY the complete, corrected XML string. Do NOT change the XML tag names or structure; only improve the text content within them.
+5. If no errors are found, return the original XML string exactly as it is."#;
+
+const STRUCTURED_PROMPT: &str = r#"You are the "Schema Sentry," a specialized linguistic linter.
+
+The user sends the document's paragraphs as a JSON array of {"paragraph_index", "text"} objects. Your sole purpose is to:
+1. Fix grammatical errors and spelling mistakes.
+2. Refine vocabulary for better clarity while maintaining the original tone.
+3. Report every change as a correction: the paragraph_index, the original text copied exactly as it appears in that paragraph (only the words that change, with enough context to occur once), and the corrected text.
+4. If no errors are found, return an empty list of corrections."#;
+
+/// How the linter asks for its fixes and applies them
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LintMode {
+    /// The model lists corrections, and each one is applied to its paragraph's text
+    #[default]
+    Structured,
+    /// The model echoes the whole XML with fixes, which replaces the linted scope.
+    /// Kept for rollback: a malformed echo can rewrite the document's structure.
+    Xml,
+}
+
+/// The JSON schema structured-mode answers must follow
+fn corrections_format() -> ResponseFormat {
+    ResponseFormat::JsonSchema {
+        name: "lint_corrections".to_string(),
+        schema: json!({
+            "type": "object",
+            "properties": {
+                "corrections": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": {
+                            "paragraph_index": { "type": "integer" },
+                            "original": { "type": "string" },
+                            "corrected": { "type": "string" }
+                        },
+                        "required": ["paragraph_index", "original", "corrected"],
+                        "additionalProperties": false
+                    }
+                }
+            },
+            "required": ["corrections"],
+            "additionalProperties": false
+        }),
+    }
+}
+
+/// A structured-mode answer; `paragraph_index` counts from the first linted node
+#[derive(Debug, Serialize, Deserialize)]
+struct StructuredCorrections {
+    corrections: Vec<StructuredCorrection>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StructuredCorrection {
+    paragraph_index: u32,
+    original: String,
+    corrected: String,
+}
+
+/// The document was edited while the linter waited on OpenAI, so its output is stale
+/// and was not applied; run the linter again on the new content.
+#[derive(Debug, Clone, Copy, thiserror::Error)]
+#[error("Document changed while the linter was running; result discarded")]
+pub struct DocumentChanged;
+
+/// Hash of the whole fragment, structure included; in focus mode an edit to another
+/// paragraph can shift the focused index, so the whole document is compared
+fn document_hash(txn: &impl ReadTxn, fragment: &XmlFragmentRef) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    fragment_xml(txn, fragment).hash(&mut hasher);
+    hasher.finish()
+}
 
 fn xml_fragment_to_string(doc: &Doc, fragment: &XmlFragmentRef) -> String {
-    let txn = doc.transact();
+    fragment_xml(&doc.transact(), fragment)
+}
+
+fn fragment_xml(txn: &impl ReadTxn, fragment: &XmlFragmentRef) -> String {
     let mut result = String::new();
-    let len = fragment.len(&txn);
+    let len = fragment.len(txn);
     for i in 0..len {
-        if let Some(child) = fragment.get(&txn, i) {
-            result.push_str(&xml_node_to_string(&child, &txn));
+        if let Some(child) = fragment.get(txn, i) {
+            result.push_str(&xml_node_to_string(&child, txn));
         }
     }
     result
@@ -55,21 +154,292 @@ fn xml_node_to_string(node: &yrs::types::xml::XmlOut, txn: &impl yrs::ReadTxn) -
 }
 
 fn replace_xml_fragment_content(doc: &Doc, fragment: &XmlFragmentRef, new_xml: &str) -> Result<()> {
-    let mut txn = doc.transact_mut();
+    apply_lint_output(doc, fragment, None, new_xml, None)
+}
 
-    // Clear existing content
-    let len = fragment.len(&txn);
+/// XML sent to the linter: the whole fragment, or just the focused paragraph
+fn lint_scope_xml(doc: &Doc, fragment: &XmlFragmentRef, focus: Option<u32>) -> Result<String> {
+    let Some(index) = focus else {
+        return Ok(xml_fragment_to_string(doc, fragment));
+    };
+    let txn = doc.transact();
+    let node = fragment
+        .get(&txn, index)
+        .with_context(|| format!("Focused paragraph {} does not exist", index))?;
+    Ok(xml_node_to_string(&node, &txn))
+}
+
+/// Replace the whole fragment with `output`, or in focus mode only the top-level
+/// node at the focused index, in one transaction so clients receive a single update.
+///
+/// With `hash`, the document is checked against it inside that same transaction,
+/// so no edit can land between the check and the write; a mismatch is `DocumentChanged`.
+fn apply_lint_output(
+    doc: &Doc,
+    fragment: &XmlFragmentRef,
+    focus: Option<u32>,
+    output: &str,
+    hash: Option<u64>,
+) -> Result<()> {
+    // Parse before opening the transaction: a parse error must not commit a cleared document
+    let parsed = parse_xml_string(output)?;
+    let mut txn = doc.transact_mut_with(AI_ORIGIN);
+    if hash.is_some_and(|hash| document_hash(&txn, fragment) != hash) {
+        return Err(DocumentChanged.into());
+    }
+    let (index, len) = match focus {
+        Some(index) if index >= fragment.len(&txn) => {
+            return Err(anyhow::anyhow!(
+                "Focused paragraph {} no longer exists",
+                index
+            ));
+        }
+        Some(index) => (index, 1),
+        None => (0, fragment.len(&txn)),
+    };
     if len > 0 {
-        fragment.remove_range(&mut txn, 0, len);
+        fragment.remove_range(&mut txn, index, len);
     }
+    insert_xml_prelim(&mut txn, fragment, index, &parsed);
+    Ok(())
+}
 
-    // Parse and insert new XML
-    // For simplicity, we'll use a basic XML parser approach
-    // In production, you'd want to use a proper XML parser
-    let parsed = parse_xml_string(new_xml)?;
-    insert_xml_prelim(&mut txn, fragment, &parsed);
+/// One change the linter made, for the changelog shown to the writer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LintCorrection {
+    /// Index of the changed top-level node (usually a paragraph); when the model
+    /// merged, split, added or dropped nodes, the first of them, with the text of
+    /// each side's nodes joined by newlines
+    pub paragraph: u32,
+    pub original: String,
+    pub corrected: String,
+}
 
-    Ok(())
+/// Compare the linted scope before and after, node by node; `first_index` is the
+/// document index of the scope's first node (the focused paragraph in focus mode)
+fn lint_corrections(
+    original_xml: &str,
+    corrected_xml: &str,
+    first_index: u32,
+) -> Result<Vec<LintCorrection>> {
+    let before = parse_xml_string(original_xml)?;
+    let after = parse_xml_string(corrected_xml)?;
+    Ok(align_nodes(&before, &after)
+        .into_iter()
+        .filter_map(|aligned| match aligned {
+            Aligned::Pair(offset, before, after) => {
+                let (original, corrected) =
+                    changed_span(&prelim_text(before), &prelim_text(after))?;
+                Some(LintCorrection {
+                    paragraph: first_index + offset as u32,
+                    original,
+                    corrected,
+                })
+            }
+            Aligned::Hunk(offset, before, after) => Some(LintCorrection {
+                paragraph: first_index + offset as u32,
+                original: joined_text(before),
+                corrected: joined_text(after),
+            }),
+        })
+        .collect())
+}
+
+/// How the top-level nodes of two versions of a scope line up
+enum Aligned<'a> {
+    /// The node at this offset of the scope, and what it became
+    Pair(usize, &'a XmlPrelim, &'a XmlPrelim),
+    /// Nodes from this offset that the model merged, split, added or dropped
+    Hunk(usize, &'a [XmlPrelim], &'a [XmlPrelim]),
+}
+
+/// Line up the top-level nodes of the scope before and after linting, so a node
+/// the model merged, split, added or dropped can't shift every later comparison.
+///
+/// Nodes that kept at least half their words anchor the alignment; between two
+/// anchors, runs of the same length pair up in order and runs of different
+/// lengths come back as one hunk.
+fn align_nodes<'a>(before: &'a [XmlPrelim], after: &'a [XmlPrelim]) -> Vec<Aligned<'a>> {
+    let texts = |nodes: &[XmlPrelim]| nodes.iter().map(prelim_text).collect::<Vec<_>>();
+    let (old_texts, new_texts) = (texts(before), texts(after));
+    let old: Vec<Vec<&str>> = old_texts
+        .iter()
+        .map(|t| t.split_whitespace().collect())
+        .collect();
+    let new: Vec<Vec<&str>> = new_texts
+        .iter()
+        .map(|t| t.split_whitespace().collect())
+        .collect();
+    let same_node = |i: usize, j: usize| similar(&old[i], &new[j]);
+    // Longest run of matching nodes from each pair of positions, filled in from the end
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if same_node(i, j) {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut aligned = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    let (mut run_i, mut run_j) = (0, 0);
+    loop {
+        let end = i == old.len() && j == new.len();
+        let anchor = !end
+            && i < old.len()
+            && j < new.len()
+            && same_node(i, j)
+            && lcs[i][j] == lcs[i + 1][j + 1] + 1;
+        if anchor || end {
+            // The nodes since the previous anchor
+            let (run_before, run_after) = (&before[run_i..i], &after[run_j..j]);
+            if run_before.len() == run_after.len() {
+                aligned.extend(
+                    run_before
+                        .iter()
+                        .zip(run_after)
+                        .enumerate()
+                        .map(|(k, (b, a))| Aligned::Pair(run_i + k, b, a)),
+                );
+            } else {
+                aligned.push(Aligned::Hunk(run_i, run_before, run_after));
+            }
+            if end {
+                return aligned;
+            }
+            aligned.push(Aligned::Pair(i, &before[i], &after[j]));
+            (i, j) = (i + 1, j + 1);
+            (run_i, run_j) = (i, j);
+        } else if j == new.len() || (i < old.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+}
+
+/// Whether two versions of a node share at least half their words, counting repeats
+fn similar(old: &[&str], new: &[&str]) -> bool {
+    if old.is_empty() || new.is_empty() {
+        return old.len() == new.len();
+    }
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for word in old {
+        *counts.entry(word).or_default() += 1;
+    }
+    let common = new
+        .iter()
+        .filter(|word| match counts.get_mut(*word) {
+            Some(count) if *count > 0 => {
+                *count -= 1;
+                true
+            }
+            _ => false,
+        })
+        .count();
+    4 * common >= old.len() + new.len()
+}
+
+/// The text of several top-level nodes, one line each
+fn joined_text(nodes: &[XmlPrelim]) -> String {
+    nodes.iter().map(prelim_text).collect::<Vec<_>>().join("\n")
+}
+
+/// The words that differ between two versions of a paragraph, without the
+/// unchanged words around them; `None` when only whitespace changed
+fn changed_span(original: &str, corrected: &str) -> Option<(String, String)> {
+    let old: Vec<&str> = original.split_whitespace().collect();
+    let new: Vec<&str> = corrected.split_whitespace().collect();
+    if old == new {
+        return None;
+    }
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    Some((
+        old[prefix..old.len() - suffix].join(" "),
+        new[prefix..new.len() - suffix].join(" "),
+    ))
+}
+
+/// One change a lint pass would make, for previewing it before anything is applied
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LintDiff {
+    /// Child indices from the top of the document down to the changed node
+    pub node_path: Vec<u32>,
+    pub before: String,
+    pub after: String,
+}
+
+/// Like `lint_corrections`, but pointing at the innermost node that changed and
+/// keeping its whole text, so a client can show the change in place
+fn lint_diffs(original_xml: &str, corrected_xml: &str, first_index: u32) -> Result<Vec<LintDiff>> {
+    let before = parse_xml_string(original_xml)?;
+    let after = parse_xml_string(corrected_xml)?;
+    let mut diffs = Vec::new();
+    for aligned in align_nodes(&before, &after) {
+        match aligned {
+            Aligned::Pair(offset, before, after) => {
+                collect_diffs(before, after, vec![first_index + offset as u32], &mut diffs);
+            }
+            Aligned::Hunk(offset, before, after) => diffs.push(LintDiff {
+                node_path: vec![first_index + offset as u32],
+                before: joined_text(before),
+                after: joined_text(after),
+            }),
+        }
+    }
+    Ok(diffs)
+}
+
+/// Descend while both versions have the same shape; once they differ (or at a
+/// text node) compare the text of the whole node
+fn collect_diffs(before: &XmlPrelim, after: &XmlPrelim, path: Vec<u32>, diffs: &mut Vec<LintDiff>) {
+    match (before, after) {
+        (
+            XmlPrelim::Element {
+                tag: old_tag,
+                children: old_children,
+                ..
+            },
+            XmlPrelim::Element {
+                tag: new_tag,
+                children: new_children,
+                ..
+            },
+        ) if old_tag == new_tag && old_children.len() == new_children.len() => {
+            for (i, (before, after)) in old_children.iter().zip(new_children).enumerate() {
+                let mut child_path = path.clone();
+                child_path.push(i as u32);
+                collect_diffs(before, after, child_path, diffs);
+            }
+            return;
+        }
+        _ => {}
+    }
+    let (before, after) = (prelim_text(before), prelim_text(after));
+    if before.split_whitespace().ne(after.split_whitespace()) {
+        diffs.push(LintDiff {
+            node_path: path,
+            before,
+            after,
+        });
+    }
+}
+
+/// Text content of a parsed node, tags removed
+fn prelim_text(node: &XmlPrelim) -> String {
+    match node {
+        XmlPrelim::Text(text) => text.clone(),
+        XmlPrelim::Element { children, .. } => children.iter().map(prelim_text).collect(),
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -241,9 +611,11 @@ fn parse_text(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
 fn insert_xml_prelim(
     txn: &mut yrs::TransactionMut,
     fragment: &XmlFragmentRef,
+    start: u32,
     prelims: &[XmlPrelim],
 ) {
-    for prelim in prelims {
+    for (offset, prelim) in prelims.iter().enumerate() {
+        let index = start + offset as u32;
         match prelim {
             XmlPrelim::Element {
                 tag,
@@ -251,7 +623,7 @@ fn insert_xml_prelim(
                 children,
             } => {
                 let elem_prelim = yrs::types::xml::XmlElementPrelim::empty(tag.as_str());
-                let elem = fragment.insert(txn, fragment.len(txn), elem_prelim);
+                let elem = fragment.insert(txn, index, elem_prelim);
 
                 for (key, value) in attrs {
                     elem.insert_attribute(txn, key.as_str(), value.as_str());
@@ -262,7 +634,7 @@ fn insert_xml_prelim(
                 }
             }
             XmlPrelim::Text(text) => {
-                fragment.insert(txn, fragment.len(txn), yrs::XmlTextPrelim::new(text));
+                fragment.insert(txn, index, yrs::XmlTextPrelim::new(text));
             }
         }
     }
@@ -296,15 +668,497 @@ fn insert_xml_prelim_into_element(
     }
 }
 
-pub async fn execute_tool(doc: Arc<Doc>, api_key: &str) -> Result<(String, Arc<Doc>)> {
+/// The Schema Sentry prompt, with a locale rule when a language is forced
+fn system_prompt(language: Option<Language>) -> String {
+    match language {
+        Some(language) => format!(
+            "{SYSTEM_PROMPT}\n6. Language: Write the text content in {language}, following that locale's grammar, spelling and punctuation conventions."
+        ),
+        None => SYSTEM_PROMPT.to_string(),
+    }
+}
+
+/// The text of each top-level node in `original_xml` with its index, leaving out
+/// the nodes without any
+fn scope_paragraphs(original_xml: &str) -> Result<Vec<(u32, String)>, RefineError> {
+    let nodes = parse_xml_string(original_xml).map_err(|e| RefineError::Parse(e.to_string()))?;
+    Ok(nodes
+        .iter()
+        .map(prelim_text)
+        .enumerate()
+        .filter(|(_, text)| !text.trim().is_empty())
+        .map(|(index, text)| (index as u32, text))
+        .collect())
+}
+
+/// Chat completion request asking for the corrections to `paragraphs` as JSON;
+/// the model reads each node's text, never the markup
+fn structured_lint_request(
+    paragraphs: &[(u32, String)],
+    language: Option<Language>,
+) -> ChatCompletion {
+    let paragraphs: Vec<_> = paragraphs
+        .iter()
+        .map(|(index, text)| json!({ "paragraph_index": index, "text": text }))
+        .collect();
+    let system = match language {
+        Some(language) => format!(
+            "{STRUCTURED_PROMPT}\n5. Language: Write the corrected text in {language}, following that locale's grammar, spelling and punctuation conventions."
+        ),
+        None => STRUCTURED_PROMPT.to_string(),
+    };
+    ChatCompletion {
+        model: LINTER_MODEL.to_string(),
+        messages: vec![
+            ChatMessage::system(system),
+            ChatMessage::user(json!(paragraphs).to_string()),
+        ],
+        format: corrections_format(),
+        ..Default::default()
+    }
+}
+
+/// Chat completion request for linting `original_xml`
+fn lint_request(original_xml: &str, language: Option<Language>) -> ChatCompletion {
+    ChatCompletion {
+        model: LINTER_MODEL.to_string(),
+        messages: vec![
+            ChatMessage::system(system_prompt(language)),
+            ChatMessage::user(original_xml),
+        ],
+        ..Default::default()
+    }
+}
+
+/// Lint the document, or only the paragraph at `focus` when focus mode is on,
+/// and report what changed
+pub async fn execute_tool(
+    doc: Arc<Doc>,
+    api_key: &str,
+    focus: Option<u32>,
+    language: Option<Language>,
+    mode: LintMode,
+) -> Result<Vec<LintCorrection>> {
+    lint_at(&*provider::configured(api_key), doc, focus, language, mode).await
+}
+
+async fn lint_at(
+    llm: &dyn LlmProvider,
+    doc: Arc<Doc>,
+    focus: Option<u32>,
+    language: Option<Language>,
+    mode: LintMode,
+) -> Result<Vec<LintCorrection>> {
+    let scope = lint_scope(&doc, focus, mode)?;
+    let ai_output = lint_xml_at(llm, &scope.xml, language, mode).await?;
+    apply_lint(&doc, &scope, &ai_output)
+}
+
+/// What a lint pass over the whole document would change, without applying it
+pub async fn preview(
+    doc: Arc<Doc>,
+    api_key: &str,
+    language: Option<Language>,
+) -> Result<Vec<LintDiff>> {
+    preview_at(&*provider::configured(api_key), doc, language).await
+}
+
+async fn preview_at(
+    llm: &dyn LlmProvider,
+    doc: Arc<Doc>,
+    language: Option<Language>,
+) -> Result<Vec<LintDiff>> {
+    // Diffs are taken between the two XML versions, so the preview asks for the echo
+    let scope = lint_scope(&doc, None, LintMode::Xml)?;
+    let ai_output = lint_xml_at(llm, &scope.xml, language, LintMode::Xml).await?;
+    if ai_output.trim() == scope.xml.trim() {
+        info!("Linter preview found nothing to correct");
+        return Ok(Vec::new());
+    }
+    lint_diffs(&scope.xml, &ai_output, 0)
+}
+
+/// Apply one previewed change on its own. `false` when its text has been edited
+/// since the preview, in which case the document is left alone; so is a change
+/// that adds, drops, merges or splits paragraphs, which isn't an edit of one node's text.
+pub fn accept_diff(doc: &Arc<Doc>, diff: &LintDiff) -> Result<bool> {
+    let Some(&index) = diff.node_path.first() else {
+        return Ok(false);
+    };
+    let (before, after) = (&diff.before, &diff.after);
+    if before.is_empty() || after.is_empty() || before.contains('\n') || after.contains('\n') {
+        return Ok(false);
+    }
+    crate::editor::replace_text_in_node(doc, index, before, after)
+}
+
+/// What one lint pass reads: the XML sent to the model, and enough of the
+/// document's state to tell whether its answer is still current
+#[derive(Debug, Clone)]
+pub struct LintScope {
+    pub xml: String,
+    pub mode: LintMode,
+    focus: Option<u32>,
+    hash: u64,
+}
+
+/// Snapshot the part of the document a lint pass covers
+pub fn lint_scope(doc: &Doc, focus: Option<u32>, mode: LintMode) -> Result<LintScope> {
     let fragment = doc.get_or_insert_xml_fragment("content");
+    Ok(LintScope {
+        xml: lint_scope_xml(doc, &fragment, focus)?,
+        mode,
+        focus,
+        hash: document_hash(&doc.transact(), &fragment),
+    })
+}
 
-    // Get original XML string
-    let original_xml = xml_fragment_to_string(&doc, &fragment);
+/// The model's answer for `xml`: the corrected XML, or in structured mode the
+/// corrections as JSON. The document is not touched.
+///
+/// Answers are cached per top-level node, so after an edit only the nodes that
+/// changed are sent again.
+pub async fn lint_xml_at(
+    llm: &dyn LlmProvider,
+    xml: &str,
+    language: Option<Language>,
+    mode: LintMode,
+) -> Result<String, RefineError> {
+    lint_xml_cached(llm, cache::configured(), xml, language, mode).await
+}
 
-    let client = reqwest::Client::new();
+async fn lint_xml_cached(
+    llm: &dyn LlmProvider,
+    cache: Option<&ResponseCache>,
+    xml: &str,
+    language: Option<Language>,
+    mode: LintMode,
+) -> Result<String, RefineError> {
+    let ai_output = match mode {
+        LintMode::Structured => lint_paragraphs(llm, cache, xml, language).await?,
+        LintMode::Xml => lint_nodes(llm, cache, xml, language).await?,
+    };
+    info!("Linter response: {:?}", ai_output);
+    Ok(ai_output)
+}
 
-    let system_content = r#"You are the "Schema Sentry," a specialized linguistic linter for Yjs XmlFragments.
+/// The content of the model's answer to `request`
+async fn ask(llm: &dyn LlmProvider, request: &ChatCompletion) -> Result<String, RefineError> {
+    llm.chat_completion("linter", request)
+        .await?
+        .content
+        .ok_or_else(|| RefineError::Parse("No content in Linter response".to_string()))
+}
+
+/// The cached answer to `request`, if there is a cache and it holds one
+fn cached(
+    llm: &dyn LlmProvider,
+    cache: Option<&ResponseCache>,
+    request: &ChatCompletion,
+) -> Option<String> {
+    cache?.get(llm, "linter", request)?.content
+}
+
+/// Cache `content` as the answer to `request`, when there is a cache
+fn remember(
+    llm: &dyn LlmProvider,
+    cache: Option<&ResponseCache>,
+    request: &ChatCompletion,
+    content: String,
+) {
+    if let Some(cache) = cache {
+        let reply = ChatReply {
+            content: Some(content),
+            tool_calls: Vec::new(),
+        };
+        cache.insert(llm, "linter", request, reply);
+    }
+}
+
+/// XML mode: the corrected XML of each top-level node comes from the cache, or
+/// from one request covering every node it doesn't hold
+async fn lint_nodes(
+    llm: &dyn LlmProvider,
+    cache: Option<&ResponseCache>,
+    xml: &str,
+    language: Option<Language>,
+) -> Result<String, RefineError> {
+    let nodes = match (cache, top_level_nodes(xml)) {
+        (Some(_), Some(nodes)) if !nodes.is_empty() => nodes,
+        _ => return ask(llm, &lint_request(xml, language)).await,
+    };
+    let mut answers: Vec<Option<String>> = nodes
+        .iter()
+        .map(|node| cached(llm, cache, &lint_request(node, language)))
+        .collect();
+    let missing: Vec<usize> = (0..nodes.len()).filter(|&i| answers[i].is_none()).collect();
+    if missing.is_empty() {
+        return Ok(answers.into_iter().flatten().collect());
+    }
+
+    let sent: String = if missing.len() == nodes.len() {
+        xml.to_string()
+    } else {
+        missing.iter().map(|&i| nodes[i]).collect()
+    };
+    let output = ask(llm, &lint_request(&sent, language)).await?;
+    let outputs = top_level_nodes(&output).unwrap_or_default();
+    if outputs.len() != missing.len() {
+        // The model merged or split nodes, so its answer can't be matched to them
+        if missing.len() == nodes.len() {
+            return Ok(output);
+        }
+        return ask(llm, &lint_request(xml, language)).await;
+    }
+    for (&i, corrected) in missing.iter().zip(outputs) {
+        let request = lint_request(nodes[i], language);
+        remember(llm, cache, &request, corrected.to_string());
+        answers[i] = Some(corrected.to_string());
+    }
+    if missing.len() == nodes.len() {
+        return Ok(output);
+    }
+    Ok(answers.into_iter().flatten().collect())
+}
+
+/// The top-level elements of `xml` as they appear in it, markup and all; `None`
+/// when there is text outside of them or a tag is left open
+fn top_level_nodes(xml: &str) -> Option<Vec<&str>> {
+    let mut nodes = Vec::new();
+    let (mut depth, mut start, mut at) = (0usize, 0, 0);
+    while let Some(open) = xml[at..].find('<').map(|i| at + i) {
+        if depth == 0 && !xml[at..open].trim().is_empty() {
+            return None;
+        }
+        let close = open + xml[open..].find('>')?;
+        let tag = &xml[open..=close];
+        if tag.starts_with("</") {
+            depth = depth.checked_sub(1)?;
+            if depth == 0 {
+                nodes.push(&xml[start..=close]);
+            }
+        } else if tag.ends_with("/>") {
+            if depth == 0 {
+                nodes.push(tag);
+            }
+        } else {
+            if depth == 0 {
+                start = open;
+            }
+            depth += 1;
+        }
+        at = close + 1;
+    }
+    (depth == 0 && xml[at..].trim().is_empty()).then_some(nodes)
+}
+
+/// Structured mode: each paragraph's corrections come from the cache, or from
+/// one request covering every paragraph it doesn't hold
+async fn lint_paragraphs(
+    llm: &dyn LlmProvider,
+    cache: Option<&ResponseCache>,
+    xml: &str,
+    language: Option<Language>,
+) -> Result<String, RefineError> {
+    // A paragraph is cached on its own, as if it were the whole scope
+    let alone = |text: &str| structured_lint_request(&[(0, text.to_string())], language);
+    let mut corrections = Vec::new();
+    let mut missing = Vec::new();
+    for (index, text) in scope_paragraphs(xml)? {
+        let answer = cached(llm, cache, &alone(&text))
+            .and_then(|content| serde_json::from_str::<StructuredCorrections>(&content).ok());
+        match answer {
+            Some(answer) => {
+                for correction in answer.corrections {
+                    corrections.push(StructuredCorrection {
+                        paragraph_index: index,
+                        ..correction
+                    });
+                }
+            }
+            None => missing.push((index, text)),
+        }
+    }
+
+    if !missing.is_empty() {
+        let output = ask(llm, &structured_lint_request(&missing, language)).await?;
+        let answer: StructuredCorrections =
+            serde_json::from_str(&output).map_err(|e| RefineError::Parse(e.to_string()))?;
+        for (index, text) in &missing {
+            let own = StructuredCorrections {
+                corrections: answer
+                    .corrections
+                    .iter()
+                    .filter(|c| c.paragraph_index == *index)
+                    .map(|c| StructuredCorrection {
+                        paragraph_index: 0,
+                        ..c.clone()
+                    })
+                    .collect(),
+            };
+            remember(llm, cache, &alone(text), json!(own).to_string());
+        }
+        corrections.extend(answer.corrections);
+    }
+    corrections.sort_by_key(|c| c.paragraph_index);
+    Ok(json!(StructuredCorrections { corrections }).to_string())
+}
+
+/// Apply the model's answer to the scope it was asked about and report what changed.
+///
+/// In XML mode the output replaces the scope, and `DocumentChanged` is returned
+/// when the document was edited after `scope` was taken. In structured mode each
+/// correction is applied on its own, and those whose text was edited are skipped.
+pub fn apply_lint(
+    doc: &Arc<Doc>,
+    scope: &LintScope,
+    ai_output: &str,
+) -> Result<Vec<LintCorrection>> {
+    if scope.mode == LintMode::Structured {
+        return apply_structured_lint(doc, scope, ai_output);
+    }
+    // Nothing to fix: leave the document alone and skip the diff
+    if ai_output.trim() == scope.xml.trim() {
+        info!("Linter found nothing to correct");
+        return Ok(Vec::new());
+    }
+    let corrections = lint_corrections(&scope.xml, ai_output, scope.focus.unwrap_or(0))?;
+
+    // The writer (or another agent) kept editing during the call: applying now would
+    // overwrite their changes with a rewrite of the old text
+    let fragment = doc.get_or_insert_xml_fragment("content");
+    info!("About to replace XML fragment content, this should trigger observer...");
+    let applied = apply_lint_output(doc, &fragment, scope.focus, ai_output, Some(scope.hash));
+    if applied.as_ref().is_err_and(|e| e.is::<DocumentChanged>()) {
+        info!("Document changed during lint, discarding the stale result");
+    }
+    applied?;
+    info!(
+        "XML fragment content replaced, transaction should have committed and triggered observer"
+    );
+
+    Ok(corrections)
+}
+
+/// Apply each correction whose `original` is still in its paragraph, in one
+/// transaction; the rest are dropped with a warning
+fn apply_structured_lint(
+    doc: &Arc<Doc>,
+    scope: &LintScope,
+    ai_output: &str,
+) -> Result<Vec<LintCorrection>> {
+    let answer: StructuredCorrections =
+        serde_json::from_str(ai_output).map_err(|e| RefineError::Parse(e.to_string()))?;
+    let first_index = scope.focus.unwrap_or(0);
+    let mut edits = Vec::new();
+    let mut corrections = Vec::new();
+    for correction in answer.corrections {
+        let paragraph = first_index.saturating_add(correction.paragraph_index);
+        let Some((original, corrected)) = changed_span(&correction.original, &correction.corrected)
+        else {
+            continue;
+        };
+        edits.push(crate::editor::NodeEdit {
+            index: paragraph,
+            original: correction.original,
+            replacement: correction.corrected,
+        });
+        corrections.push(LintCorrection {
+            paragraph,
+            original,
+            corrected,
+        });
+    }
+    if edits.is_empty() {
+        info!("Linter found nothing to correct");
+        return Ok(Vec::new());
+    }
+
+    let applied = crate::editor::replace_texts_in_nodes(doc, &edits)?;
+    Ok(corrections
+        .into_iter()
+        .zip(applied)
+        .filter_map(|(correction, applied)| {
+            if !applied {
+                warn!(
+                    "⚠️ Skipping lint correction for paragraph {}: {:?} is no longer there",
+                    correction.paragraph, correction.original
+                );
+            }
+            applied.then_some(correction)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::provider::{FakeProvider, OpenAi};
+
+    fn doc_with_paragraphs(texts: &[&str]) -> Arc<Doc> {
+        let doc = Arc::new(Doc::new());
+        let xml = texts
+            .iter()
+            .map(|t| format!("<paragraph>{}</paragraph>", t))
+            .collect::<String>();
+        let fragment = doc.get_or_insert_xml_fragment("content");
+        replace_xml_fragment_content(&doc, &fragment, &xml).unwrap();
+        doc
+    }
+
+    #[test]
+    fn test_focus_mode_lints_only_focused_paragraph() {
+        let doc = doc_with_paragraphs(&["teh first", "teh second", "teh third"]);
+        let fragment = doc.get_or_insert_xml_fragment("content");
+
+        let scope = lint_scope_xml(&doc, &fragment, Some(1)).unwrap();
+        assert_eq!(scope, "<paragraph>teh second</paragraph>");
+
+        apply_lint_output(
+            &doc,
+            &fragment,
+            Some(1),
+            "<paragraph>the second</paragraph>",
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(
+            xml_fragment_to_string(&doc, &fragment),
+            "<paragraph>teh first</paragraph>\
+             <paragraph>the second</paragraph>\
+             <paragraph>teh third</paragraph>"
+        );
+    }
+
+    #[test]
+    fn test_without_focus_the_whole_document_is_linted() {
+        let doc = doc_with_paragraphs(&["teh first", "teh second"]);
+        let fragment = doc.get_or_insert_xml_fragment("content");
+
+        let scope = lint_scope_xml(&doc, &fragment, None).unwrap();
+        assert_eq!(
+            scope,
+            "<paragraph>teh first</paragraph><paragraph>teh second</paragraph>"
+        );
+        assert!(lint_scope_xml(&doc, &fragment, Some(5)).is_err());
+    }
+
+    #[test]
+    fn test_language_is_embedded_in_the_linter_payload() {
+        let language = Language::parse("zh-TW").unwrap();
+        let request = lint_request("<paragraph>teh</paragraph>", Some(language));
+
+        let system = &request.messages[0].content;
+        assert!(system.starts_with(SYSTEM_PROMPT));
+        assert!(system.contains("Traditional Chinese (Taiwan) (zh-TW)"));
+        assert_eq!(request.messages[1].content, "<paragraph>teh</paragraph>");
+    }
+
+    #[test]
+    fn test_without_language_the_linter_prompt_is_unchanged() {
+        // The prompt from before languages were added, spelled out so any change to it shows here
+        let original = r#"You are the "Schema Sentry," a specialized linguistic linter for Yjs XmlFragments.
 
 Your sole purpose is to:
 1. Fix grammatical errors and spelling mistakes within the text nodes.
@@ -312,49 +1166,492 @@ Your sole purpose is to:
 3. Strict Constraint: Do NOT provide any explanations, comments, or markdown code blocks (like ```xml).
 4. Output Format: Return ONLY the complete, corrected XML string. Do NOT change the XML tag names or structure; only improve the text content within them.
 5. If no errors are found, return the original XML string exactly as it is."#;
+        let request = lint_request("<paragraph>teh</paragraph>", None);
+        assert_eq!(request.messages[0], ChatMessage::system(original));
+        assert_eq!(
+            request.messages[1],
+            ChatMessage::user("<paragraph>teh</paragraph>")
+        );
+        assert_eq!(request.messages.len(), 2);
+    }
 
-    let request_payload = json!({
-        "model": "gpt-4o-mini",
-        "messages": [
-            {
-                "role": "system",
-                "content": system_content
-            },
-            {
-                "role": "user",
-                "content": original_xml
-            }
-        ]
-    });
-
-    let response = client
-        .post("https://api.openai.com/v1/chat/completions")
-        .bearer_auth(api_key)
-        .json(&request_payload)
-        .send()
+    #[tokio::test]
+    async fn test_one_fixed_typo_is_one_correction() {
+        let doc = doc_with_paragraphs(&["The first line.", "Fix teh second line.", "The end."]);
+        let (url, received) = crate::llm::openai::mock_openai(
+            "<paragraph>The first line.</paragraph>\
+             <paragraph>Fix the second line.</paragraph>\
+             <paragraph>The end.</paragraph>",
+        );
+
+        let llm = OpenAi::at(&url, "test-key");
+        let corrections = lint_at(&llm, doc.clone(), None, None, LintMode::Xml)
+            .await
+            .unwrap();
+        assert_eq!(
+            corrections,
+            vec![LintCorrection {
+                paragraph: 1,
+                original: "teh".to_string(),
+                corrected: "the".to_string(),
+            }]
+        );
+        assert_eq!(received.await.unwrap()["model"], LINTER_MODEL);
+        assert!(crate::editor::get_doc_content(&doc).contains("Fix the second line."));
+    }
+
+    #[tokio::test]
+    async fn test_unchanged_output_reports_nothing() {
+        let doc = doc_with_paragraphs(&["teh first", "All good here."]);
+        let (url, _received) =
+            crate::llm::openai::mock_openai("<paragraph>All good here.</paragraph>");
+
+        let llm = OpenAi::at(&url, "test-key");
+        let corrections = lint_at(&llm, doc.clone(), Some(1), None, LintMode::Xml)
+            .await
+            .unwrap();
+        assert!(corrections.is_empty());
+        assert_eq!(
+            lint_corrections(
+                "<paragraph>teh first</paragraph>",
+                "<paragraph>the first</paragraph>",
+                3
+            )
+            .unwrap(),
+            vec![LintCorrection {
+                paragraph: 3,
+                original: "teh".to_string(),
+                corrected: "the".to_string(),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_structured_corrections_change_only_their_paragraph() {
+        let doc = doc_with_paragraphs(&["Fix teh first.", "Leave teh second.", "The end."]);
+        let reply = json!({
+            "corrections": [
+                { "paragraph_index": 0, "original": "teh first", "corrected": "the first" },
+                // Not in that paragraph, and no such paragraph: both are skipped
+                { "paragraph_index": 2, "original": "teh end", "corrected": "the end" },
+                { "paragraph_index": 9, "original": "teh", "corrected": "the" }
+            ]
+        });
+        let (url, received) = crate::llm::openai::mock_openai(&reply.to_string());
+
+        let corrections = lint_at(
+            &OpenAi::at(&url, "test-key"),
+            doc.clone(),
+            None,
+            None,
+            LintMode::Structured,
+        )
         .await
-        .context("Failed to connect to OpenAI during linter execution")?;
+        .unwrap();
+        assert_eq!(
+            corrections,
+            vec![LintCorrection {
+                paragraph: 0,
+                original: "teh".to_string(),
+                corrected: "the".to_string(),
+            }]
+        );
+        let fragment = doc.get_or_insert_xml_fragment("content");
+        assert_eq!(
+            xml_fragment_to_string(&doc, &fragment),
+            "<paragraph>Fix the first.</paragraph>\
+             <paragraph>Leave teh second.</paragraph>\
+             <paragraph>The end.</paragraph>"
+        );
 
-    if !response.status().is_success() {
-        let error_msg = response.text().await.unwrap_or_default();
-        return Err(anyhow::anyhow!("Linter Tool Error: {}", error_msg));
+        // The model sees each paragraph's text and answers against the schema
+        let body = received.await.unwrap();
+        assert_eq!(body["response_format"]["type"], "json_schema");
+        let paragraphs: serde_json::Value =
+            serde_json::from_str(body["messages"][1]["content"].as_str().unwrap()).unwrap();
+        assert_eq!(
+            paragraphs[1],
+            json!({ "paragraph_index": 1, "text": "Leave teh second." })
+        );
     }
 
-    let result: serde_json::Value = response.json().await?;
+    fn replies(contents: &[String]) -> FakeProvider {
+        FakeProvider::new(contents.iter().map(|content| ChatReply {
+            content: Some(content.clone()),
+            tool_calls: Vec::new(),
+        }))
+    }
 
-    let ai_output = result["choices"][0]["message"]["content"]
-        .as_str()
-        .context("Failed to get content from Linter response")?
-        .to_string();
+    #[tokio::test]
+    async fn test_only_edited_nodes_are_sent_again() {
+        let cache = ResponseCache::new(100, std::time::Duration::from_secs(60));
+        let llm = replies(&[
+            "<paragraph>Fix the first.</paragraph><paragraph>Fix the second.</paragraph>".into(),
+            "<paragraph>Fix the last.</paragraph>".into(),
+        ]);
+        let lint =
+            |xml: &'static str| lint_xml_cached(&llm, Some(&cache), xml, None, LintMode::Xml);
 
-    // Replace content with AI output
-    info!("Linter response: {:?}", ai_output);
+        lint("<paragraph>Fix teh first.</paragraph><paragraph>Fix teh second.</paragraph>")
+            .await
+            .unwrap();
+        // The writer edits the second paragraph: only it is linted again
+        let output =
+            lint("<paragraph>Fix teh first.</paragraph><paragraph>Fix teh last.</paragraph>")
+                .await
+                .unwrap();
+        assert_eq!(
+            output,
+            "<paragraph>Fix the first.</paragraph><paragraph>Fix the last.</paragraph>"
+        );
+        let requests = llm.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(
+            requests[1].messages[1].content,
+            "<paragraph>Fix teh last.</paragraph>"
+        );
+    }
 
-    info!("About to replace XML fragment content, this should trigger observer...");
-    replace_xml_fragment_content(&doc, &fragment, &ai_output)?;
-    info!(
-        "XML fragment content replaced, transaction should have committed and triggered observer"
-    );
+    #[tokio::test]
+    async fn test_only_edited_paragraphs_are_asked_about_again() {
+        let cache = ResponseCache::new(100, std::time::Duration::from_secs(60));
+        let llm = replies(&[
+            json!({ "corrections": [
+                { "paragraph_index": 0, "original": "teh first", "corrected": "the first" },
+                { "paragraph_index": 1, "original": "teh second", "corrected": "the second" }
+            ]})
+            .to_string(),
+            json!({ "corrections": [
+                { "paragraph_index": 1, "original": "teh last", "corrected": "the last" }
+            ]})
+            .to_string(),
+        ]);
+        let lint = |xml: &'static str| {
+            lint_xml_cached(&llm, Some(&cache), xml, None, LintMode::Structured)
+        };
+
+        lint("<paragraph>Fix teh first.</paragraph><paragraph>Fix teh second.</paragraph>")
+            .await
+            .unwrap();
+        let output =
+            lint("<paragraph>Fix teh first.</paragraph><paragraph>Fix teh last.</paragraph>")
+                .await
+                .unwrap();
+
+        // The first paragraph's correction comes from the cache, at its own index
+        let answer: StructuredCorrections = serde_json::from_str(&output).unwrap();
+        let found: Vec<_> = answer
+            .corrections
+            .iter()
+            .map(|c| (c.paragraph_index, c.original.as_str()))
+            .collect();
+        assert_eq!(found, [(0, "teh first"), (1, "teh last")]);
+        let requests = llm.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(
+            requests[1].messages[1].content,
+            json!([{ "paragraph_index": 1, "text": "Fix teh last." }]).to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_structured_mode_asks_for_the_corrections_schema() {
+        let doc = doc_with_paragraphs(&["Fix teh first."]);
+        let llm = FakeProvider::text(r#"{"corrections": []}"#);
+
+        let corrections = lint_at(&llm, doc.clone(), None, None, LintMode::Structured)
+            .await
+            .unwrap();
+        assert!(corrections.is_empty());
+        let request = &llm.requests()[0];
+        assert_eq!(request.model, LINTER_MODEL);
+        assert!(matches!(
+            &request.format,
+            ResponseFormat::JsonSchema { name, .. } if name == "lint_corrections"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_structured_lint_survives_edits_elsewhere() {
+        let doc = doc_with_paragraphs(&["Fix teh first.", "Fix teh second."]);
+        let reply = json!({
+            "corrections": [
+                { "paragraph_index": 0, "original": "teh second", "corrected": "the second" }
+            ]
+        });
+        let (url, _received, gate) = crate::llm::openai::mock_openai_gated(&reply.to_string());
+
+        // Only the focused paragraph is linted; the writer edits another one meanwhile
+        let (llm, task_doc) = (OpenAi::at(&url, "test-key"), doc.clone());
+        let lint = tokio::spawn(async move {
+            lint_at(&llm, task_doc, Some(1), None, LintMode::Structured).await
+        });
+        gate.arrived.await.unwrap();
+        assert!(
+            crate::editor::replace_text_in_node(&doc, 0, "Fix teh first.", "Fixed by hand.")
+                .unwrap()
+        );
+        gate.release.send(()).unwrap();
+
+        let corrections = lint.await.unwrap().unwrap();
+        assert_eq!(corrections[0].paragraph, 1);
+        let fragment = doc.get_or_insert_xml_fragment("content");
+        assert_eq!(
+            xml_fragment_to_string(&doc, &fragment),
+            "<paragraph>Fixed by hand.</paragraph><paragraph>Fix the second.</paragraph>"
+        );
+
+        let scope = lint_scope(&doc, None, LintMode::Structured).unwrap();
+        assert!(apply_lint(&doc, &scope, "<paragraph>not json</paragraph>").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_preview_returns_diffs_and_leaves_the_document_alone() {
+        let doc = doc_with_paragraphs(&["The first line.", "Fix teh second line."]);
+        let fragment = doc.get_or_insert_xml_fragment("content");
+        let before = xml_fragment_to_string(&doc, &fragment);
+        let (url, _received) = crate::llm::openai::mock_openai(
+            "<paragraph>The first line.</paragraph><paragraph>Fix the second line.</paragraph>",
+        );
+
+        let diffs = preview_at(&OpenAi::at(&url, "test-key"), doc.clone(), None)
+            .await
+            .unwrap();
+        assert_eq!(
+            diffs,
+            vec![LintDiff {
+                node_path: vec![1, 0],
+                before: "Fix teh second line.".to_string(),
+                after: "Fix the second line.".to_string(),
+            }]
+        );
+        assert_eq!(xml_fragment_to_string(&doc, &fragment), before);
 
-    Ok((ai_output, doc))
+        // A changed structure is reported on the node whose children differ
+        let diffs = lint_diffs(
+            "<paragraph>teh <bold>end</bold></paragraph>",
+            "<paragraph>The end.</paragraph>",
+            4,
+        )
+        .unwrap();
+        assert_eq!(diffs[0].node_path, vec![4]);
+        assert_eq!(diffs[0].after, "The end.");
+    }
+
+    #[tokio::test]
+    async fn test_accepting_one_of_several_previewed_edits() {
+        let doc = doc_with_paragraphs(&["Fix teh first.", "Fix teh second.", "Fix teh third."]);
+        let (url, _received) = crate::llm::openai::mock_openai(
+            "<paragraph>Fix the first.</paragraph>\
+             <paragraph>Fix the second.</paragraph>\
+             <paragraph>Fix the third.</paragraph>",
+        );
+        let diffs = preview_at(&OpenAi::at(&url, "test-key"), doc.clone(), None)
+            .await
+            .unwrap();
+        assert_eq!(diffs.len(), 3);
+
+        assert!(accept_diff(&doc, &diffs[1]).unwrap());
+        let fragment = doc.get_or_insert_xml_fragment("content");
+        assert_eq!(
+            xml_fragment_to_string(&doc, &fragment),
+            "<paragraph>Fix teh first.</paragraph>\
+             <paragraph>Fix the second.</paragraph>\
+             <paragraph>Fix teh third.</paragraph>"
+        );
+        // Already applied: its `before` text is gone
+        assert!(!accept_diff(&doc, &diffs[1]).unwrap());
+    }
+
+    const DROPPED_BEFORE: &str = "<paragraph>Fix teh first.</paragraph>\
+                                  <paragraph>Delete me.</paragraph>\
+                                  <paragraph>Fix teh last.</paragraph>";
+    const DROPPED_AFTER: &str =
+        "<paragraph>Fix the first.</paragraph><paragraph>Fix the last.</paragraph>";
+
+    #[test]
+    fn test_dropped_paragraph_does_not_shift_later_corrections() {
+        // The model fixed both typos and dropped the middle paragraph
+        let correction = |paragraph: u32, original: &str, corrected: &str| LintCorrection {
+            paragraph,
+            original: original.to_string(),
+            corrected: corrected.to_string(),
+        };
+        assert_eq!(
+            lint_corrections(DROPPED_BEFORE, DROPPED_AFTER, 0).unwrap(),
+            vec![
+                correction(0, "teh", "the"),
+                correction(1, "Delete me.", ""),
+                correction(2, "teh", "the"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_added_paragraph_is_its_own_correction() {
+        let before = "<paragraph>Keep this.</paragraph><paragraph>Fix teh end.</paragraph>";
+        let after = "<paragraph>Keep this.</paragraph>\
+                     <paragraph>Brand new.</paragraph>\
+                     <paragraph>Fix the end.</paragraph>";
+        let corrections = lint_corrections(before, after, 5).unwrap();
+        let changes: Vec<_> = corrections
+            .iter()
+            .map(|c| (c.paragraph, c.original.as_str(), c.corrected.as_str()))
+            .collect();
+        assert_eq!(changes, [(6, "", "Brand new."), (6, "teh", "the")]);
+    }
+
+    #[test]
+    fn test_previewed_diffs_follow_dropped_and_merged_paragraphs() {
+        let diffs = lint_diffs(DROPPED_BEFORE, DROPPED_AFTER, 0).unwrap();
+        let paths: Vec<_> = diffs.iter().map(|d| d.node_path.clone()).collect();
+        assert_eq!(paths, [vec![0, 0], vec![1], vec![2, 0]]);
+        assert_eq!(diffs[2].before, "Fix teh last.");
+
+        // The last fix lands on the last paragraph; dropping a node isn't a text edit
+        let doc = doc_with_paragraphs(&["Fix teh first.", "Delete me.", "Fix teh last."]);
+        assert!(!accept_diff(&doc, &diffs[1]).unwrap());
+        assert!(accept_diff(&doc, &diffs[2]).unwrap());
+        let fragment = doc.get_or_insert_xml_fragment("content");
+        assert_eq!(
+            xml_fragment_to_string(&doc, &fragment),
+            "<paragraph>Fix teh first.</paragraph>\
+             <paragraph>Delete me.</paragraph>\
+             <paragraph>Fix the last.</paragraph>"
+        );
+
+        // Two unrelated paragraphs become one that matches neither
+        let diffs = lint_diffs(
+            "<paragraph>Alpha beta.</paragraph><paragraph>Gamma delta.</paragraph>",
+            "<paragraph>Epsilon.</paragraph>",
+            0,
+        )
+        .unwrap();
+        assert_eq!(
+            diffs,
+            vec![LintDiff {
+                node_path: vec![0],
+                before: "Alpha beta.\nGamma delta.".to_string(),
+                after: "Epsilon.".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_accepting_a_change_that_spans_marks() {
+        let doc = doc_with_paragraphs(&["Fix teh <bold>frist</bold> one."]);
+        let fragment = doc.get_or_insert_xml_fragment("content");
+        let before = xml_fragment_to_string(&doc, &fragment);
+
+        // The model dropped the mark, so the change covers the paragraph's text
+        // across all three of its text nodes
+        let diffs = lint_diffs(&before, "<paragraph>Fix the first one.</paragraph>", 0).unwrap();
+        assert_eq!(diffs.len(), 1);
+        assert_eq!(diffs[0].before, "Fix teh frist one.");
+
+        assert!(accept_diff(&doc, &diffs[0]).unwrap());
+        assert_eq!(
+            xml_fragment_to_string(&doc, &fragment),
+            "<paragraph>Fix the fir<bold>st</bold> one.</paragraph>"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_lint_reaches_clients_as_one_converging_update() {
+        use crate::editor::write::is_ai_origin;
+        use yrs::updates::decoder::Decode;
+        use yrs::{ReadTxn, StateVector, Update};
+
+        let doc = doc_with_paragraphs(&["Fix teh first.", "And teh second."]);
+        // A connected client starts from the same state
+        let client = Doc::new();
+        let snapshot = doc
+            .transact()
+            .encode_state_as_update_v1(&StateVector::default());
+        client
+            .transact_mut()
+            .apply_update(Update::decode_v1(&snapshot).unwrap())
+            .unwrap();
+
+        // What the WebSocket observer would broadcast
+        let broadcast = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = broadcast.clone();
+        let origins = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let origin_sink = origins.clone();
+        let _sub = doc
+            .observe_update_v1(move |txn, event| {
+                sink.lock().unwrap().push(event.update.clone());
+                origin_sink.lock().unwrap().push(is_ai_origin(txn.origin()));
+            })
+            .unwrap();
+
+        let (url, _received) = crate::llm::openai::mock_openai(
+            "<paragraph>Fix the first.</paragraph><paragraph>And the second.</paragraph>",
+        );
+        let llm = OpenAi::at(&url, "test-key");
+        let corrections = lint_at(&llm, doc.clone(), None, None, LintMode::Xml)
+            .await
+            .unwrap();
+        assert_eq!(corrections.len(), 2);
+
+        let updates = broadcast.lock().unwrap().clone();
+        assert_eq!(updates.len(), 1, "one lint, one update");
+        assert_eq!(*origins.lock().unwrap(), [true], "the lint is tagged as AI");
+        client
+            .transact_mut()
+            .apply_update(Update::decode_v1(&updates[0]).unwrap())
+            .unwrap();
+
+        let server_fragment = doc.get_or_insert_xml_fragment("content");
+        let client_fragment = client.get_or_insert_xml_fragment("content");
+        assert_eq!(
+            xml_fragment_to_string(&client, &client_fragment),
+            xml_fragment_to_string(&doc, &server_fragment)
+        );
+        assert_eq!(
+            xml_fragment_to_string(&client, &client_fragment),
+            "<paragraph>Fix the first.</paragraph><paragraph>And the second.</paragraph>"
+        );
+    }
+
+    #[test]
+    fn test_unparseable_output_leaves_the_document_alone() {
+        let doc = doc_with_paragraphs(&["keep me"]);
+        let fragment = doc.get_or_insert_xml_fragment("content");
+
+        let output = "<paragraph>fixed</paragraph><>";
+        assert!(replace_xml_fragment_content(&doc, &fragment, output).is_err());
+        assert_eq!(
+            xml_fragment_to_string(&doc, &fragment),
+            "<paragraph>keep me</paragraph>"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stale_lint_result_is_discarded() {
+        let doc = doc_with_paragraphs(&["Fix teh first."]);
+        let (url, _received, gate) =
+            crate::llm::openai::mock_openai_gated("<paragraph>Fix the first.</paragraph>");
+
+        // The writer keeps typing while the (slow) OpenAI call is in flight
+        let (llm, task_doc) = (OpenAi::at(&url, "test-key"), doc.clone());
+        let lint =
+            tokio::spawn(async move { lint_at(&llm, task_doc, None, None, LintMode::Xml).await });
+        gate.arrived.await.unwrap();
+        let fragment = doc.get_or_insert_xml_fragment("content");
+        replace_xml_fragment_content(
+            &doc,
+            &fragment,
+            "<paragraph>Fix teh first. And more</paragraph>",
+        )
+        .unwrap();
+        gate.release.send(()).unwrap();
+
+        let err = lint.await.unwrap().unwrap_err();
+        assert!(err.is::<DocumentChanged>());
+        assert_eq!(
+            xml_fragment_to_string(&doc, &fragment),
+            "<paragraph>Fix teh first. And more</paragraph>"
+        );
+    }
 }
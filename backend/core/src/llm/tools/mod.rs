@@ -1,6 +1,132 @@
+pub mod backseater;
 pub mod emoji_replacer;
 pub mod extender;
 pub mod linter;
+pub mod readability;
 pub mod refiner;
 pub mod researcher;
-pub mod backseater;
+pub mod summarizer;
+pub mod titler;
+pub mod tone;
+pub mod translator;
+
+#[cfg(test)]
+mod tests {
+    use crate::llm::openai::{content_reply, mock_request_head, tool_call_reply};
+    use crate::llm::provider::OpenAi;
+    use crate::refiner::types::RefineInput;
+    use serde_json::json;
+    use tokio::task::JoinHandle;
+
+    /// A keyless provider on a mock server, as `--llm-provider local` runs the tools
+    fn keyless(reply: serde_json::Value) -> (OpenAi, JoinHandle<String>) {
+        let (url, head) = mock_request_head(reply);
+        (OpenAi::at(url, ""), head)
+    }
+
+    async fn assert_sent_no_key(head: JoinHandle<String>) {
+        let head = head.await.unwrap().to_ascii_lowercase();
+        assert!(!head.contains("authorization:"), "{head}");
+    }
+
+    fn input(content: &str) -> RefineInput {
+        RefineInput {
+            content: content.to_string(),
+            language: None,
+            tone: None,
+            audience: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_every_tool_runs_without_a_key() {
+        let (llm, head) = keyless(tool_call_reply(
+            "commenter",
+            &json!({ "comment_on": "shipped", "comment": "Really?" }),
+        ));
+        let comments = super::backseater::comments_at(&llm, "We shipped.")
+            .await
+            .unwrap();
+        assert_eq!(comments[0].comment, "Really?");
+        assert_sent_no_key(head).await;
+
+        let (llm, head) = keyless(content_reply(
+            r#"{"replacements": [{"replace": "cat", "with": "🐱"}]}"#,
+        ));
+        let replacements = super::emoji_replacer::replacements_at(&llm, "The cat sat.")
+            .await
+            .unwrap();
+        assert_eq!(replacements[0].with, "🐱");
+        assert_sent_no_key(head).await;
+
+        let (llm, head) = keyless(content_reply("And then it rained."));
+        let extended = super::extender::execute_tool_at(&llm, "It was sunny.", "", None, &[])
+            .await
+            .unwrap();
+        assert_eq!(extended, "And then it rained.");
+        assert_sent_no_key(head).await;
+
+        let (llm, head) = keyless(content_reply("<paragraph>Fixed.</paragraph>"));
+        let xml = super::linter::lint_xml_at(
+            &llm,
+            "<paragraph>Fixd.</paragraph>",
+            None,
+            super::linter::LintMode::Xml,
+        )
+        .await
+        .unwrap();
+        assert_eq!(xml, "<paragraph>Fixed.</paragraph>");
+        assert_sent_no_key(head).await;
+
+        let (llm, head) = keyless(content_reply("Improved."));
+        let output = crate::refiner::processor::refine_at(&llm, "Improve it.", input("Good."))
+            .await
+            .unwrap();
+        assert_eq!(output.content, "Improved.");
+        assert_sent_no_key(head).await;
+
+        let (llm, head) = keyless(tool_call_reply(
+            "report_research",
+            &json!({ "summary": "Ada wrote the first program.", "sources": [] }),
+        ));
+        let report = super::researcher::research_at(&llm, "Ada Lovelace", None)
+            .await
+            .unwrap();
+        assert_eq!(report.summary, "Ada wrote the first program.");
+        assert_sent_no_key(head).await;
+
+        let (llm, head) = keyless(content_reply("TL;DR: shipped."));
+        let summary = super::summarizer::summarize_at(
+            &llm,
+            "We shipped.",
+            super::summarizer::SummaryStyle::TlDr,
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(summary, "TL;DR: shipped.");
+        assert_sent_no_key(head).await;
+
+        let (llm, head) = keyless(content_reply(r#"{"titles": ["Shipped"]}"#));
+        let titles = super::titler::titles_at(&llm, "We shipped.", None)
+            .await
+            .unwrap();
+        assert_eq!(titles, ["Shipped"]);
+        assert_sent_no_key(head).await;
+
+        let (llm, head) = keyless(content_reply("We are pleased to announce the release."));
+        let output =
+            super::tone::tone_at(&llm, input("we shipped"), super::tone::TonePreset::Formal)
+                .await
+                .unwrap();
+        assert_eq!(output.content, "We are pleased to announce the release.");
+        assert_sent_no_key(head).await;
+
+        let (llm, head) = keyless(content_reply("Bonjour"));
+        let translated = super::translator::translate_text_at(&llm, "Hello", "fr")
+            .await
+            .unwrap();
+        assert_eq!(translated, "Bonjour");
+        assert_sent_no_key(head).await;
+    }
+}
@@ -0,0 +1,257 @@
+use crate::editor::read::{count_words, is_cjk_word_char};
+use serde::Serialize;
+use std::sync::Arc;
+use yrs::Doc;
+
+/// A sentence with more words than this is flagged as very long
+const LONG_SENTENCE_WORDS: usize = 30;
+
+/// The same limit for CJK text, counted in characters
+const LONG_SENTENCE_CJK_CHARS: usize = 60;
+
+/// Forms of "to be" that start a passive construction
+const BE_FORMS: &[&str] = &["am", "is", "are", "was", "were", "be", "been", "being"];
+
+/// Common past participles that don't end in "-ed"
+const IRREGULAR_PARTICIPLES: &[&str] = &[
+    "born", "brought", "built", "bought", "caught", "chosen", "done", "driven", "eaten", "found",
+    "given", "held", "hidden", "kept", "known", "left", "lost", "made", "paid", "put", "said",
+    "seen", "sent", "set", "shown", "sold", "spoken", "taken", "taught", "thought", "told", "won",
+    "written",
+];
+
+/// How easy a text is to read, and the sentences worth a second look
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Readability {
+    /// Flesch reading ease from 0 (hard) to 100 (easy); `None` for CJK text,
+    /// which has no syllables to count
+    pub reading_ease: Option<f64>,
+    /// Flesch–Kincaid US school grade; `None` for CJK text
+    pub grade_level: Option<f64>,
+    pub sentences: usize,
+    /// Words per sentence, or characters per sentence for CJK text
+    pub avg_sentence_length: f64,
+    /// Sentences that look like passive voice; a heuristic, not a parse
+    pub passive_sentences: usize,
+    /// Sentences over the long-sentence limit, as written
+    pub long_sentences: Vec<String>,
+}
+
+/// Readability of the shared document's text
+pub fn document_readability(doc: &Arc<Doc>) -> Readability {
+    analyze(&crate::editor::get_doc_content(doc))
+}
+
+/// Readability of `content`.
+///
+/// Text that is mostly Chinese or Japanese is measured in characters, since it
+/// has no spaces between words; Flesch scores don't apply to it and are `None`.
+pub fn analyze(content: &str) -> Readability {
+    let sentences = split_sentences(content);
+    if sentences.is_empty() {
+        return Readability::default();
+    }
+    let cjk_chars = content.chars().filter(|c| is_cjk_word_char(*c)).count();
+    let cjk = cjk_chars * 2 > count_words(content);
+
+    let (mut words, mut syllables, mut passive_sentences) = (0, 0, 0);
+    let mut long_sentences = Vec::new();
+    for sentence in &sentences {
+        let (length, limit) = if cjk {
+            (count_words(sentence), LONG_SENTENCE_CJK_CHARS)
+        } else {
+            let sentence_words = sentence_words(sentence);
+            syllables += sentence_words
+                .iter()
+                .map(|w| count_syllables(w))
+                .sum::<usize>();
+            (sentence_words.len(), LONG_SENTENCE_WORDS)
+        };
+        words += length;
+        if length > limit {
+            long_sentences.push(sentence.to_string());
+        }
+        if is_passive(sentence, cjk) {
+            passive_sentences += 1;
+        }
+    }
+
+    let words_per_sentence = words as f64 / sentences.len() as f64;
+    let (reading_ease, grade_level) = if cjk || words == 0 {
+        (None, None)
+    } else {
+        let syllables_per_word = syllables as f64 / words as f64;
+        let ease = 206.835 - 1.015 * words_per_sentence - 84.6 * syllables_per_word;
+        let grade = 0.39 * words_per_sentence + 11.8 * syllables_per_word - 15.59;
+        (
+            Some(round1(ease.clamp(0.0, 100.0))),
+            Some(round1(grade.max(0.0))),
+        )
+    };
+    Readability {
+        reading_ease,
+        grade_level,
+        sentences: sentences.len(),
+        avg_sentence_length: round1(words_per_sentence),
+        passive_sentences,
+        long_sentences,
+    }
+}
+
+fn round1(value: f64) -> f64 {
+    (value * 10.0).round() / 10.0
+}
+
+/// Sentences end at a line break, at `!` or `?` (half- or full-width), at `。`,
+/// and at a `.` followed by a space, so "3.14" and "e.g" stay whole. CJK
+/// punctuation needs no space after it.
+fn split_sentences(content: &str) -> Vec<&str> {
+    let mut sentences = Vec::new();
+    let mut start = 0;
+    let mut chars = content.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let ends = match c {
+            '\n' | '!' | '?' | '。' | '！' | '？' => true,
+            '.' => chars.peek().is_none_or(|(_, next)| next.is_whitespace()),
+            _ => false,
+        };
+        if ends {
+            let end = i + c.len_utf8();
+            sentences.push(content[start..end].trim());
+            start = end;
+        }
+    }
+    sentences.push(content[start..].trim());
+    sentences.retain(|s| s.chars().any(char::is_alphanumeric));
+    sentences
+}
+
+/// The sentence's words, lowercased and without the punctuation around them
+fn sentence_words(sentence: &str) -> Vec<String> {
+    sentence
+        .split_whitespace()
+        .map(|token| {
+            token
+                .trim_matches(|c: char| !c.is_alphanumeric())
+                .to_lowercase()
+        })
+        .filter(|word| !word.is_empty())
+        .collect()
+}
+
+/// Vowel groups, less a silent final "e"; every word has at least one
+fn count_syllables(word: &str) -> usize {
+    let mut count = 0;
+    let mut in_vowels = false;
+    for c in word.chars() {
+        let vowel = matches!(c, 'a' | 'e' | 'i' | 'o' | 'u' | 'y');
+        if vowel && !in_vowels {
+            count += 1;
+        }
+        in_vowels = vowel;
+    }
+    if count > 1 && word.ends_with('e') && !word.ends_with("le") {
+        count -= 1;
+    }
+    count.max(1)
+}
+
+/// "Was written", "are being reviewed", "is quickly fixed"; in Chinese, a `被`
+fn is_passive(sentence: &str, cjk: bool) -> bool {
+    if cjk {
+        return sentence.contains('被');
+    }
+    let words = sentence_words(sentence);
+    words.iter().enumerate().any(|(i, word)| {
+        if !BE_FORMS.contains(&word.as_str()) {
+            return false;
+        }
+        // Skip one adverb or "being": "was quickly fixed", "is being fixed"
+        let next = match words.get(i + 1) {
+            Some(next) if next.ends_with("ly") || next == "being" => words.get(i + 2),
+            next => next,
+        };
+        next.is_some_and(|next| is_participle(next))
+    })
+}
+
+fn is_participle(word: &str) -> bool {
+    (word.len() > 3 && word.ends_with("ed")) || IRREGULAR_PARTICIPLES.contains(&word)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_simple_sentences_score_as_easy() {
+        let readability = analyze("The cat sat on the mat. The dog ran.");
+        assert_eq!(readability.sentences, 2);
+        assert_eq!(readability.avg_sentence_length, 4.5);
+        assert_eq!(readability.reading_ease, Some(100.0));
+        assert_eq!(readability.grade_level, Some(0.0));
+        assert_eq!(readability.passive_sentences, 0);
+        assert!(readability.long_sentences.is_empty());
+
+        let readability = analyze(
+            "We wrote a small editor for the team. \
+             It checks your writing as you type and suggests clearer words.",
+        );
+        assert_eq!(readability.avg_sentence_length, 9.5);
+        assert_eq!(readability.reading_ease, Some(90.3));
+        assert_eq!(readability.grade_level, Some(3.0));
+    }
+
+    #[test]
+    fn test_denser_prose_scores_lower() {
+        let readability = analyze(
+            "The committee was informed that the proposed infrastructure modifications \
+             would necessitate considerable additional investment. \
+             Implementation was postponed.",
+        );
+        assert_eq!(readability.sentences, 2);
+        assert_eq!(readability.avg_sentence_length, 8.5);
+        assert_eq!(readability.reading_ease, Some(0.0));
+        assert_eq!(readability.grade_level, Some(20.3));
+        assert_eq!(readability.passive_sentences, 2);
+    }
+
+    #[test]
+    fn test_very_long_sentences_are_listed() {
+        let long = format!("We {} shipped it.", "really ".repeat(30));
+        let readability = analyze(&format!("Short one. {long}\nAnother short one?"));
+        assert_eq!(readability.sentences, 3);
+        assert_eq!(readability.long_sentences, vec![long.trim().to_string()]);
+    }
+
+    #[test]
+    fn test_cjk_text_is_measured_in_characters() {
+        let readability = analyze("我們用 Rust 寫編輯器。文件被儲存了！");
+        assert_eq!(readability.sentences, 2);
+        // 我們用 + Rust + 寫編輯器 = 8, 文件被儲存了 = 6
+        assert_eq!(readability.avg_sentence_length, 7.0);
+        assert_eq!(readability.reading_ease, None);
+        assert_eq!(readability.grade_level, None);
+        assert_eq!(readability.passive_sentences, 1);
+    }
+
+    #[test]
+    fn test_empty_or_punctuation_only_text_has_no_scores() {
+        for content in ["", "   \n\n", "。。。", "..."] {
+            let readability = analyze(content);
+            assert_eq!(readability, Readability::default());
+            assert!(!readability.avg_sentence_length.is_nan());
+        }
+    }
+
+    #[test]
+    fn test_decimals_do_not_end_a_sentence() {
+        assert_eq!(
+            split_sentences("Pi is 3.14 or so. Right"),
+            vec!["Pi is 3.14 or so.", "Right"]
+        );
+        assert_eq!(count_syllables("readable"), 3);
+        assert_eq!(count_syllables("make"), 1);
+        assert_eq!(count_syllables("rhythm"), 1);
+    }
+}
@@ -25,6 +25,9 @@ pub async fn execute_tool(text: &str, api_key: &str) -> Result<String> {
 
     let input = RefineInput {
         content: text.to_string(),
+        language: None,
+        tone: None,
+        audience: None,
     };
     let output = processor::call_improve_api(input, api_key).await?;
     Ok(output.content)
@@ -1,6 +1,16 @@
+use crate::llm::provider::{self, ChatCompletion, ChatMessage, LlmProvider};
 use crate::llm::types::McpTool;
 use anyhow::{Context, Result};
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
 use serde_json::json;
+use std::sync::OnceLock;
+
+/// Brave Search web endpoint used by [`WebSearch`]
+pub const SEARCH_API_URL: &str = "https://api.search.brave.com/res/v1/web/search";
+
+/// How many search results are handed to the synthesis prompt
+const MAX_SEARCH_RESULTS: usize = 5;
 
 pub fn to_tool_definition() -> McpTool {
     McpTool {
@@ -20,45 +30,349 @@ pub fn to_tool_definition() -> McpTool {
     }
 }
 
-pub async fn execute_tool(query: &str, api_key: &str) -> Result<String> {
-    let client = reqwest::Client::new();
-
-    let request_payload = json!({
-        "model": "gpt-4o",
-        "messages": [
-            {
-                "role": "system",
-                "content": "You are a professional research assistant. Your goal is to take a query and provide a structured, in-depth analysis. \
-                            Break down the topic into logical sections: Overview, Key Facts, and Implications. \
-                            Provide a comprehensive summary even if you are using your internal knowledge base."
-            },
-            {
-                "role": "user",
-                "content": format!("Please conduct a research on the following topic: \"{}\"", query)
+/// One web source: a search hit, and a citation once the report relies on it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Citation {
+    pub title: String,
+    pub url: String,
+    pub snippet: String,
+}
+
+/// The researcher's report and the sources it cites, for the frontend to render as footnotes
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResearchResult {
+    pub summary: String,
+    pub citations: Vec<Citation>,
+}
+
+/// Arguments of the `report_research` call the model is made to answer with
+#[derive(Debug, Deserialize)]
+struct ReportArgs {
+    summary: String,
+    /// 1-based numbers of the search results the summary relies on
+    #[serde(default)]
+    sources: Vec<usize>,
+}
+
+/// Where the researcher looks a query up before writing its report
+pub trait SearchProvider: Send + Sync {
+    fn search<'a>(&'a self, query: &'a str) -> BoxFuture<'a, Result<Vec<Citation>>>;
+}
+
+/// The default provider: Brave Search's web API
+pub struct WebSearch {
+    client: reqwest::Client,
+    url: String,
+    api_key: String,
+}
+
+impl WebSearch {
+    pub fn new(api_key: impl Into<String>) -> Self {
+        Self::at(SEARCH_API_URL, api_key)
+    }
+
+    /// `new` against another endpoint, e.g. a mock server in tests
+    pub fn at(url: impl Into<String>, api_key: impl Into<String>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            url: url.into(),
+            api_key: api_key.into(),
+        }
+    }
+}
+
+impl SearchProvider for WebSearch {
+    fn search<'a>(&'a self, query: &'a str) -> BoxFuture<'a, Result<Vec<Citation>>> {
+        Box::pin(async move {
+            let response = self
+                .client
+                .get(&self.url)
+                .header("X-Subscription-Token", &self.api_key)
+                .query(&[("q", query), ("count", &MAX_SEARCH_RESULTS.to_string())])
+                .send()
+                .await
+                .context("Failed to connect to the search API")?;
+            if !response.status().is_success() {
+                let error_msg = response.text().await.unwrap_or_default();
+                return Err(anyhow::anyhow!("Search API Error: {}", error_msg));
             }
-        ],
-        "temperature": 0.3
-    });
+            Ok(parse_search_results(&response.json().await?))
+        })
+    }
+}
 
-    let response = client
-        .post("https://api.openai.com/v1/chat/completions")
-        .bearer_auth(api_key)
-        .json(&request_payload)
-        .send()
-        .await
-        .context("Failed to connect to OpenAI during researcher execution")?;
+/// Pull title, url and description out of a Brave `web.results` list
+fn parse_search_results(body: &serde_json::Value) -> Vec<Citation> {
+    body["web"]["results"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|result| {
+            Some(Citation {
+                title: result["title"].as_str()?.to_string(),
+                url: result["url"].as_str()?.to_string(),
+                snippet: result["description"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string(),
+            })
+        })
+        .take(MAX_SEARCH_RESULTS)
+        .collect()
+}
+
+static SEARCH: OnceLock<Box<dyn SearchProvider>> = OnceLock::new();
 
-    if !response.status().is_success() {
-        let error_msg = response.text().await.unwrap_or_default();
-        return Err(anyhow::anyhow!("Researcher Tool Error: {}", error_msg));
+/// Install the provider every research call searches with; only the first call takes effect.
+/// Without one the researcher answers from the model's own knowledge.
+pub fn configure_search(provider: Box<dyn SearchProvider>) {
+    if SEARCH.set(provider).is_err() {
+        tracing::warn!("Researcher search provider already configured, ignoring");
     }
+}
+
+pub async fn execute_tool(query: &str, api_key: &str) -> Result<ResearchResult> {
+    research_at(
+        &*provider::configured(api_key),
+        query,
+        SEARCH.get().map(|p| p.as_ref()),
+    )
+    .await
+}
+
+pub(crate) async fn research_at(
+    llm: &dyn LlmProvider,
+    query: &str,
+    search: Option<&dyn SearchProvider>,
+) -> Result<ResearchResult> {
+    let snippets = match search {
+        Some(provider) => provider.search(query).await.unwrap_or_else(|e| {
+            tracing::warn!("🔎 web search failed, using model knowledge only: {:?}", e);
+            Vec::new()
+        }),
+        None => Vec::new(),
+    };
+
+    let request = research_request(query, &snippets);
+    let reply = llm.chat_completion("researcher", &request).await?;
 
-    let result: serde_json::Value = response.json().await?;
+    let call = reply
+        .tool_calls
+        .into_iter()
+        .find(|call| call.name == "report_research")
+        .context("No report_research call in Researcher response")?;
+    let report: ReportArgs = serde_json::from_value(call.arguments)
+        .context("Failed to parse report_research arguments")?;
 
-    let research_output = result["choices"][0]["message"]["content"]
-        .as_str()
-        .context("Failed to get content from Researcher response")?
-        .to_string();
+    Ok(ResearchResult {
+        summary: report.summary,
+        citations: cited(&report.sources, &snippets),
+    })
+}
 
-    Ok(research_output)
+/// The search results behind `sources`, in citation order; numbers the model
+/// made up are dropped, so every citation is a page that was actually fetched
+fn cited(sources: &[usize], snippets: &[Citation]) -> Vec<Citation> {
+    let mut citations: Vec<Citation> = Vec::new();
+    for n in sources {
+        if let Some(snippet) = n.checked_sub(1).and_then(|i| snippets.get(i)) {
+            if !citations.contains(snippet) {
+                citations.push(snippet.clone());
+            }
+        }
+    }
+    citations
+}
+
+/// The synthesis prompt; search results, when there are any, become its sources
+fn research_request(query: &str, snippets: &[Citation]) -> ChatCompletion {
+    let (system, user) = if snippets.is_empty() {
+        (
+            "You are a professional research assistant. Your goal is to take a query and provide a structured, in-depth analysis. \
+             Break down the topic into logical sections: Overview, Key Facts, and Implications. \
+             Provide a comprehensive summary even if you are using your internal knowledge base."
+                .to_string(),
+            format!("Please conduct a research on the following topic: \"{}\"", query),
+        )
+    } else {
+        let results = snippets
+            .iter()
+            .enumerate()
+            .map(|(i, s)| format!("[{}] {} ({})\n{}", i + 1, s.title, s.url, s.snippet))
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        (
+            "You are a professional research assistant. Your goal is to take a query and provide a structured, in-depth analysis. \
+             Break down the topic into logical sections: Overview, Key Facts, and Implications. \
+             Base the Key Facts on the numbered web search results and cite them as [n]. \
+             Say so when the results do not cover part of the topic."
+                .to_string(),
+            format!(
+                "Please conduct a research on the following topic: \"{}\"\n\nWeb search results:\n\n{}",
+                query, results
+            ),
+        )
+    };
+
+    ChatCompletion {
+        model: "gpt-4o".to_string(),
+        messages: vec![ChatMessage::system(system), ChatMessage::user(user)],
+        tools: vec![McpTool {
+            name: "report_research".to_string(),
+            description: "Deliver the finished research report.".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "summary": {
+                        "type": "string",
+                        "description": "The full report, in sections."
+                    },
+                    "sources": {
+                        "type": "array",
+                        "items": { "type": "integer" },
+                        "description": "Numbers of the web search results cited in the report."
+                    }
+                },
+                "required": ["summary", "sources"]
+            }),
+        }],
+        tool_choice: Some("report_research".to_string()),
+        temperature: Some(0.3),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::provider::{ChatReply, FakeProvider, OpenAi, ToolCall};
+
+    struct FixedSearch(Result<Vec<Citation>, &'static str>);
+
+    impl SearchProvider for FixedSearch {
+        fn search<'a>(&'a self, _query: &'a str) -> BoxFuture<'a, Result<Vec<Citation>>> {
+            let result = self.0.clone().map_err(|e| anyhow::anyhow!(e));
+            Box::pin(async move { result })
+        }
+    }
+
+    fn snippet() -> Citation {
+        Citation {
+            title: "Ada Lovelace - Wikipedia".to_string(),
+            url: "https://en.wikipedia.org/wiki/Ada_Lovelace".to_string(),
+            snippet: "English mathematician, born 10 December 1815.".to_string(),
+        }
+    }
+
+    fn user_message(body: &serde_json::Value) -> &str {
+        body["messages"][1]["content"].as_str().unwrap()
+    }
+
+    fn other_snippet() -> Citation {
+        Citation {
+            title: "Analytical Engine".to_string(),
+            url: "https://en.wikipedia.org/wiki/Analytical_engine".to_string(),
+            snippet: "A proposed mechanical general-purpose computer.".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_search_results_reach_the_prompt_and_come_back_as_citations() {
+        let (url, received) = crate::llm::openai::mock_openai_tool_call(
+            "report_research",
+            &json!({ "summary": "Overview: ... [2]", "sources": [2] }),
+        );
+        let search: &dyn SearchProvider = &FixedSearch(Ok(vec![snippet(), other_snippet()]));
+
+        let llm = OpenAi::at(&url, "test-key");
+        let report = research_at(&llm, "Ada Lovelace", Some(search))
+            .await
+            .unwrap();
+        assert_eq!(
+            report,
+            ResearchResult {
+                summary: "Overview: ... [2]".to_string(),
+                citations: vec![other_snippet()],
+            }
+        );
+
+        let body = received.await.unwrap();
+        let user = user_message(&body);
+        assert!(
+            user.contains(
+                "[1] Ada Lovelace - Wikipedia (https://en.wikipedia.org/wiki/Ada_Lovelace)"
+            )
+        );
+        assert!(user.contains("born 10 December 1815"));
+        assert_eq!(body["tool_choice"]["function"]["name"], "report_research");
+    }
+
+    #[tokio::test]
+    async fn test_report_is_read_from_the_forced_call() {
+        let llm = FakeProvider::new([ChatReply {
+            content: Some("Let me write that up.".to_string()),
+            tool_calls: vec![ToolCall {
+                name: "report_research".to_string(),
+                arguments: json!({ "summary": "Overview: ...", "sources": [] }),
+            }],
+        }]);
+
+        let report = research_at(&llm, "Ada Lovelace", None).await.unwrap();
+        assert_eq!(report.summary, "Overview: ...");
+
+        let request = &llm.requests()[0];
+        assert_eq!(request.tools[0].name, "report_research");
+        assert_eq!(request.tool_choice.as_deref(), Some("report_research"));
+        assert_eq!(request.temperature, Some(0.3));
+    }
+
+    #[tokio::test]
+    async fn test_failed_search_falls_back_to_model_knowledge() {
+        let (url, received) = crate::llm::openai::mock_openai_tool_call(
+            "report_research",
+            &json!({ "summary": "Overview: ...", "sources": [1] }),
+        );
+        let search: &dyn SearchProvider = &FixedSearch(Err("quota exceeded"));
+
+        let llm = OpenAi::at(&url, "test-key");
+        let report = research_at(&llm, "Ada Lovelace", Some(search))
+            .await
+            .unwrap();
+        // Nothing was fetched, so there is nothing to cite
+        assert!(report.citations.is_empty());
+
+        let body = received.await.unwrap();
+        assert!(!user_message(&body).contains("Web search results"));
+        assert_eq!(
+            body["messages"],
+            json!(research_request("Ada Lovelace", &[]).messages),
+            "same prompt as running without a provider"
+        );
+    }
+
+    #[test]
+    fn test_only_fetched_results_are_cited() {
+        let snippets = [snippet(), other_snippet()];
+        assert_eq!(
+            cited(&[2, 0, 7, 2, 1], &snippets),
+            vec![other_snippet(), snippet()]
+        );
+        assert!(cited(&[], &snippets).is_empty());
+    }
+
+    #[test]
+    fn test_parse_search_results() {
+        let body = json!({
+            "web": { "results": [
+                {
+                    "title": "Ada Lovelace - Wikipedia",
+                    "url": "https://en.wikipedia.org/wiki/Ada_Lovelace",
+                    "description": "English mathematician, born 10 December 1815."
+                },
+                { "title": "No url, skipped" }
+            ]}
+        });
+        assert_eq!(parse_search_results(&body), vec![snippet()]);
+        assert!(parse_search_results(&json!({})).is_empty());
+    }
 }
@@ -0,0 +1,242 @@
+use crate::llm::provider::{self, ChatCompletion, ChatMessage, LlmProvider};
+use crate::llm::truncate::{estimate_tokens, first_tokens};
+use crate::refiner::error::RefineError;
+use crate::refiner::language::Language;
+use futures::{StreamExt, TryStreamExt, stream};
+use serde::{Deserialize, Serialize};
+
+pub const SUMMARIZER_MODEL: &str = "gpt-4o";
+
+/// Most text sent in one call; longer content is summarized a chunk at a time
+pub const MAX_CHUNK_TOKENS: usize = 3000;
+
+/// Chunk summaries requested at once
+const MAX_CONCURRENT_CHUNKS: usize = 4;
+
+/// Rounds of summarizing summaries before the final call takes whatever fits
+const MAX_LEVELS: usize = 3;
+
+const CHUNK_PROMPT: &str = "You are an AI writing assistant summarizing one part of a longer document. \
+     Reply with a concise plain-text summary of this part only, keeping its key facts, names and figures.";
+
+/// The shape of the finished summary
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SummaryStyle {
+    /// A Markdown list of the key points
+    #[default]
+    Bullet,
+    /// One paragraph of prose
+    Abstract,
+    /// A single sentence
+    TlDr,
+}
+
+impl SummaryStyle {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Bullet => "bullet",
+            Self::Abstract => "abstract",
+            Self::TlDr => "tl_dr",
+        }
+    }
+
+    fn instruction(self) -> &'static str {
+        match self {
+            Self::Bullet => {
+                "Reply with a Markdown bulleted list of its key points only, at most five bullets."
+            }
+            Self::Abstract => {
+                "Reply with a single paragraph abstract of at most 120 words, in plain prose."
+            }
+            Self::TlDr => "Reply with one sentence that starts with \"TL;DR:\".",
+        }
+    }
+}
+
+/// Summarize `content` in `style`, written in `language` when one is given.
+///
+/// Content over `MAX_CHUNK_TOKENS` is split into chunks that are summarized
+/// first; the final call then summarizes those summaries.
+pub async fn execute_tool(
+    content: &str,
+    style: SummaryStyle,
+    language: Option<Language>,
+    api_key: &str,
+) -> Result<String, RefineError> {
+    summarize_at(&*provider::configured(api_key), content, style, language).await
+}
+
+pub(crate) async fn summarize_at(
+    llm: &dyn LlmProvider,
+    content: &str,
+    style: SummaryStyle,
+    language: Option<Language>,
+) -> Result<String, RefineError> {
+    let mut text = content.trim().to_string();
+    for level in 1..=MAX_LEVELS {
+        if estimate_tokens(&text) <= MAX_CHUNK_TOKENS {
+            break;
+        }
+        let parts = chunks(&text, MAX_CHUNK_TOKENS);
+        tracing::info!("📚 Summarizing {} chunks (level {})", parts.len(), level);
+        let summaries: Vec<String> = stream::iter(&parts)
+            .map(|part| complete(llm, CHUNK_PROMPT, part))
+            .buffered(MAX_CONCURRENT_CHUNKS)
+            .try_collect()
+            .await?;
+        text = summaries.join("\n\n");
+    }
+    let text = first_tokens(&text, MAX_CHUNK_TOKENS);
+    complete(llm, &system_message(style, language), text).await
+}
+
+fn system_message(style: SummaryStyle, language: Option<Language>) -> String {
+    let message = format!(
+        "You are an AI writing assistant that summarizes existing text. {}",
+        style.instruction()
+    );
+    match language {
+        Some(language) => format!(
+            "{message} Write your response in {language}, whatever language the existing text is in."
+        ),
+        None => message,
+    }
+}
+
+/// Split `text` at line breaks into chunks of at most `max_tokens`; a line over
+/// the budget on its own is cut into budget-sized pieces
+fn chunks(text: &str, max_tokens: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    for line in text.lines().filter(|line| !line.trim().is_empty()) {
+        let mut rest = line;
+        while !rest.is_empty() {
+            let piece = first_tokens(rest, max_tokens);
+            rest = &rest[piece.len()..];
+            if !current.is_empty()
+                && estimate_tokens(&current) + estimate_tokens(piece) > max_tokens
+            {
+                chunks.push(std::mem::take(&mut current));
+            }
+            if !current.is_empty() {
+                current.push('\n');
+            }
+            current.push_str(piece);
+        }
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+/// One chat completion: `system` as the instructions, `text` as the user message
+async fn complete(llm: &dyn LlmProvider, system: &str, text: &str) -> Result<String, RefineError> {
+    let request = ChatCompletion {
+        model: SUMMARIZER_MODEL.to_string(),
+        messages: vec![ChatMessage::system(system), ChatMessage::user(text)],
+        ..Default::default()
+    };
+    llm.chat_completion("summarizer", &request)
+        .await?
+        .content
+        .ok_or_else(|| RefineError::Parse("No content in Summarizer response".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::provider::OpenAi;
+
+    #[tokio::test]
+    async fn test_short_content_is_summarized_in_one_call() {
+        let (url, received) = crate::llm::openai::mock_openai("TL;DR: we shipped.");
+
+        let summary = summarize_at(
+            &OpenAi::at(&url, "test-key"),
+            "  We shipped the editor.  ",
+            SummaryStyle::TlDr,
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(summary, "TL;DR: we shipped.");
+
+        let body = received.await.unwrap();
+        assert_eq!(body["model"], SUMMARIZER_MODEL);
+        assert!(
+            body["messages"][0]["content"]
+                .as_str()
+                .unwrap()
+                .ends_with(SummaryStyle::TlDr.instruction())
+        );
+        assert_eq!(body["messages"][1]["content"], "We shipped the editor.");
+    }
+
+    #[tokio::test]
+    async fn test_long_content_summarizes_chunks_then_their_summaries() {
+        // Three 1250-token paragraphs: the first two fit one chunk, the third starts another
+        let paragraph = "abcd ".repeat(1000);
+        let content = [paragraph.as_str(); 3].join("\n");
+        let (url, received) = crate::llm::openai::mock_openai_replies(&[
+            "Part summary.",
+            "Part summary.",
+            "- The whole story",
+        ]);
+
+        let language = Language::parse("ja").unwrap();
+        let summary = summarize_at(
+            &OpenAi::at(&url, "test-key"),
+            &content,
+            SummaryStyle::Bullet,
+            Some(language),
+        )
+        .await
+        .unwrap();
+        assert_eq!(summary, "- The whole story");
+
+        let bodies = received.await.unwrap();
+        assert_eq!(bodies.len(), 3);
+        for chunk in &bodies[..2] {
+            assert_eq!(chunk["messages"][0]["content"], CHUNK_PROMPT);
+        }
+        let last = &bodies[2];
+        assert_eq!(
+            last["messages"][0]["content"],
+            system_message(SummaryStyle::Bullet, Some(language))
+        );
+        assert_eq!(
+            last["messages"][1]["content"],
+            "Part summary.\n\nPart summary."
+        );
+    }
+
+    #[test]
+    fn test_chunks_stay_within_budget_and_keep_every_line() {
+        let long_line = "字".repeat(250);
+        let text = format!("short one\n\n{long_line}\nshort two");
+        let parts = chunks(&text, 100);
+
+        assert!(parts.len() >= 3);
+        assert!(parts.iter().all(|part| estimate_tokens(part) <= 100));
+        assert_eq!(
+            parts.concat().replace('\n', ""),
+            text.replace('\n', ""),
+            "nothing is dropped"
+        );
+    }
+
+    #[test]
+    fn test_styles_parse_from_their_wire_names() {
+        for style in [
+            SummaryStyle::Bullet,
+            SummaryStyle::Abstract,
+            SummaryStyle::TlDr,
+        ] {
+            let parsed: SummaryStyle = serde_json::from_value(json!(style.as_str())).unwrap();
+            assert_eq!(parsed, style);
+        }
+        assert!(serde_json::from_value::<SummaryStyle>(json!("haiku")).is_err());
+    }
+}
@@ -0,0 +1,135 @@
+use crate::llm::provider::{self, ChatCompletion, ChatMessage, LlmProvider, ResponseFormat};
+use crate::llm::truncate::first_tokens;
+use crate::refiner::error::RefineError;
+use crate::refiner::language::Language;
+use serde::Deserialize;
+
+pub const TITLER_MODEL: &str = "gpt-4o-mini";
+
+/// Most titles offered for one request
+pub const MAX_TITLES: usize = 3;
+
+/// How much of the document the model reads; a title needs the gist, not every word
+const MAX_INPUT_TOKENS: usize = 3000;
+
+/// Longest title kept, in characters; longer suggestions are dropped
+const MAX_TITLE_CHARS: usize = 120;
+
+/// The JSON object the model answers with
+#[derive(Debug, Deserialize)]
+struct Titles {
+    titles: Vec<String>,
+}
+
+/// Between one and `MAX_TITLES` title candidates for `content`, best first,
+/// written in `language` when one is given
+pub async fn execute_tool(
+    content: &str,
+    language: Option<Language>,
+    api_key: &str,
+) -> Result<Vec<String>, RefineError> {
+    titles_at(&*provider::configured(api_key), content, language).await
+}
+
+pub(crate) async fn titles_at(
+    llm: &dyn LlmProvider,
+    content: &str,
+    language: Option<Language>,
+) -> Result<Vec<String>, RefineError> {
+    let request = ChatCompletion {
+        model: TITLER_MODEL.to_string(),
+        messages: vec![
+            ChatMessage::system(system_message(language)),
+            ChatMessage::user(first_tokens(content.trim(), MAX_INPUT_TOKENS)),
+        ],
+        format: ResponseFormat::JsonObject,
+        ..Default::default()
+    };
+    let reply = llm.chat_completion("titler", &request).await?;
+    let content = reply
+        .content
+        .ok_or_else(|| RefineError::Parse("No content in Titler response".to_string()))?;
+    let titles: Titles =
+        serde_json::from_str(&content).map_err(|e| RefineError::Parse(e.to_string()))?;
+
+    let candidates = clean_titles(titles.titles);
+    if candidates.is_empty() {
+        return Err(RefineError::Parse(
+            "Titler suggested no usable titles".to_string(),
+        ));
+    }
+    Ok(candidates)
+}
+
+fn system_message(language: Option<Language>) -> String {
+    let message = format!(
+        "You are an AI writing assistant that titles documents. \
+         Suggest up to {MAX_TITLES} short, distinct titles for the document, best first, \
+         without quotes or a trailing period. \
+         Reply with a JSON object {{\"titles\": [...]}}."
+    );
+    match language {
+        Some(language) => format!(
+            "{message} Write the titles in {language}, whatever language the document is in."
+        ),
+        None => message,
+    }
+}
+
+/// Trimmed and unquoted, with empty, overlong and repeated titles dropped
+fn clean_titles(titles: Vec<String>) -> Vec<String> {
+    let mut candidates: Vec<String> = Vec::new();
+    for title in titles {
+        let title = title
+            .trim()
+            .trim_matches(|c| c == '"' || c == '“' || c == '”')
+            .trim()
+            .to_string();
+        if title.is_empty()
+            || title.chars().count() > MAX_TITLE_CHARS
+            || candidates.contains(&title)
+        {
+            continue;
+        }
+        candidates.push(title);
+    }
+    candidates.truncate(MAX_TITLES);
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::provider::OpenAi;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_titles_are_parsed_and_cleaned() {
+        let reply = json!({
+            "titles": ["\"Shipping the Editor\"", "Shipping the Editor", "  ", "Launch Notes", "Extra", "More"]
+        });
+        let (url, received) = crate::llm::openai::mock_openai(&reply.to_string());
+
+        let language = Language::parse("en").unwrap();
+        let llm = OpenAi::at(&url, "test-key");
+        let titles = titles_at(&llm, "We shipped the editor.", Some(language))
+            .await
+            .unwrap();
+        assert_eq!(titles, vec!["Shipping the Editor", "Launch Notes", "Extra"]);
+
+        let body = received.await.unwrap();
+        assert_eq!(body["model"], TITLER_MODEL);
+        assert_eq!(body["response_format"]["type"], "json_object");
+        assert_eq!(body["messages"][1]["content"], "We shipped the editor.");
+    }
+
+    #[tokio::test]
+    async fn test_no_usable_title_is_a_parse_error() {
+        let (url, _) = crate::llm::openai::mock_openai(r#"{"titles": [""]}"#);
+        let llm = OpenAi::at(&url, "test-key");
+        let e = titles_at(&llm, "We shipped the editor.", None)
+            .await
+            .unwrap_err();
+        assert!(matches!(e, RefineError::Parse(_)));
+    }
+}
@@ -0,0 +1,132 @@
+use crate::llm::provider::{self, LlmProvider};
+use crate::refiner::error::RefineError;
+use crate::refiner::processor::refine_at;
+use crate::refiner::types::{RefineInput, RefineOutput};
+use serde::{Deserialize, Serialize};
+
+/// The voice a `tone` rewrite takes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TonePreset {
+    Formal,
+    Casual,
+    Academic,
+    Marketing,
+    Empathetic,
+}
+
+impl TonePreset {
+    pub const ALL: [Self; 5] = [
+        Self::Formal,
+        Self::Casual,
+        Self::Academic,
+        Self::Marketing,
+        Self::Empathetic,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Formal => "formal",
+            Self::Casual => "casual",
+            Self::Academic => "academic",
+            Self::Marketing => "marketing",
+            Self::Empathetic => "empathetic",
+        }
+    }
+
+    /// The system prompt for this preset; every preset's wording lives here
+    fn system_message(self) -> &'static str {
+        match self {
+            Self::Formal => {
+                "You are an AI writing assistant that rewrites existing text in a formal tone: \
+                 precise, polite and professional, without slang or contractions. \
+                 Keep the meaning and every fact unchanged. Reply with the rewritten text only. \
+                 Use Markdown formatting when appropriate."
+            }
+            Self::Casual => {
+                "You are an AI writing assistant that rewrites existing text in a casual tone: \
+                 relaxed and conversational, like a note to a friend, with contractions where they fit. \
+                 Keep the meaning and every fact unchanged. Reply with the rewritten text only. \
+                 Use Markdown formatting when appropriate."
+            }
+            Self::Academic => {
+                "You are an AI writing assistant that rewrites existing text in an academic tone: \
+                 objective, exact and carefully hedged, with no personal asides. \
+                 Keep the meaning and every fact unchanged. Reply with the rewritten text only. \
+                 Use Markdown formatting when appropriate."
+            }
+            Self::Marketing => {
+                "You are an AI writing assistant that rewrites existing text in a marketing tone: \
+                 upbeat and persuasive, leading with what the reader gains. \
+                 Keep the meaning and every fact unchanged, and invent no claims. \
+                 Reply with the rewritten text only. Use Markdown formatting when appropriate."
+            }
+            Self::Empathetic => {
+                "You are an AI writing assistant that rewrites existing text in an empathetic tone: \
+                 warm and understanding, acknowledging how the reader may feel. \
+                 Keep the meaning and every fact unchanged. Reply with the rewritten text only. \
+                 Use Markdown formatting when appropriate."
+            }
+        }
+    }
+}
+
+/// Rewrite `input` in the voice of `preset`, keeping its meaning
+pub async fn execute_tool(
+    input: RefineInput,
+    preset: TonePreset,
+    api_key: &str,
+) -> Result<RefineOutput, RefineError> {
+    tone_at(&*provider::configured(api_key), input, preset).await
+}
+
+pub(crate) async fn tone_at(
+    llm: &dyn LlmProvider,
+    input: RefineInput,
+    preset: TonePreset,
+) -> Result<RefineOutput, RefineError> {
+    // The preset is the tone; a free-form one on top would contradict it
+    let input = RefineInput {
+        tone: None,
+        ..input
+    };
+    refine_at(llm, preset.system_message(), input).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[tokio::test]
+    async fn test_every_preset_sends_its_own_prompt() {
+        let mut prompts = HashSet::new();
+        for preset in TonePreset::ALL {
+            let (url, received) = crate::llm::openai::mock_openai("Rewritten");
+            let input = RefineInput {
+                content: "we shipped it".to_string(),
+                language: None,
+                tone: Some("pirate".to_string()),
+                audience: None,
+            };
+
+            let llm = crate::llm::provider::OpenAi::at(&url, "test-key");
+            let output = tone_at(&llm, input, preset).await.unwrap();
+            assert_eq!(output.content, "Rewritten");
+
+            let body = received.await.unwrap();
+            let system = body["messages"][0]["content"].as_str().unwrap().to_string();
+            assert_eq!(system, preset.system_message());
+            assert!(system.contains(&format!("{} tone", preset.as_str())));
+            prompts.insert(system);
+        }
+        assert_eq!(prompts.len(), TonePreset::ALL.len());
+    }
+
+    #[test]
+    fn test_unknown_preset_is_rejected() {
+        let preset: TonePreset = serde_json::from_str("\"academic\"").unwrap();
+        assert_eq!(preset, TonePreset::Academic);
+        assert!(serde_json::from_str::<TonePreset>("\"sarcastic\"").is_err());
+    }
+}
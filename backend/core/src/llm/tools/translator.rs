@@ -0,0 +1,228 @@
+use crate::editor::{TextPatch, patch_text_nodes, text_node_contents};
+use crate::llm::provider::{self, ChatCompletion, ChatMessage, LlmProvider, ResponseFormat};
+use crate::llm::truncate::estimate_tokens;
+use crate::refiner::error::RefineError;
+use crate::refiner::language::Language;
+use crate::refiner::processor::{REFINE_MODEL, translate_at};
+use crate::refiner::types::RefineInput;
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+use yrs::Doc;
+
+/// Most paragraph text sent in one call; longer documents are translated a batch at a time
+const MAX_BATCH_TOKENS: usize = 3000;
+
+/// The JSON object the model answers a batch with
+#[derive(Debug, Deserialize)]
+struct Translations {
+    paragraphs: Vec<String>,
+}
+
+/// Translate `content` into `target_lang`, one of the supported BCP-47 tags such as `ja` or `zh-TW`
+pub async fn execute_tool(
+    content: &str,
+    target_lang: &str,
+    api_key: &str,
+) -> Result<String, RefineError> {
+    translate_text_at(&*provider::configured(api_key), content, target_lang).await
+}
+
+pub(crate) async fn translate_text_at(
+    llm: &dyn LlmProvider,
+    content: &str,
+    target_lang: &str,
+) -> Result<String, RefineError> {
+    let input = RefineInput {
+        content: content.to_string(),
+        language: None,
+        tone: None,
+        audience: None,
+    };
+    let output = translate_at(llm, input, target_lang).await?;
+    Ok(output.content)
+}
+
+/// Translate the shared document in place, paragraph by paragraph, and return how
+/// many paragraphs changed.
+///
+/// Each text node is patched where it stands, so headings, lists and the marks on
+/// untouched text survive. A paragraph the writer edits while the model is working
+/// keeps the edit and stays untranslated.
+pub async fn translate_document(
+    doc: &Arc<Doc>,
+    target_lang: &str,
+    api_key: &str,
+) -> Result<usize, RefineError> {
+    translate_document_at(&*provider::configured(api_key), doc, target_lang).await
+}
+
+async fn translate_document_at(
+    llm: &dyn LlmProvider,
+    doc: &Arc<Doc>,
+    target_lang: &str,
+) -> Result<usize, RefineError> {
+    // Reject an unknown target before reading the document or calling the model
+    let language = Language::parse(target_lang)?;
+    let paragraphs = text_node_contents(doc);
+
+    let mut patches = Vec::new();
+    for batch in batches(&paragraphs, MAX_BATCH_TOKENS) {
+        let texts: Vec<&str> = batch.iter().map(|&i| paragraphs[i].trim()).collect();
+        let translated = translate_batch(llm, language, &texts).await?;
+        patches.extend(batch.into_iter().zip(translated).map(|(node, text)| {
+            let original = &paragraphs[node];
+            TextPatch {
+                node,
+                original: original.clone(),
+                replacement: keep_outer_whitespace(original, text.trim()),
+            }
+        }));
+    }
+    tracing::info!(
+        "🌐 Translated {} paragraphs into {}",
+        patches.len(),
+        language
+    );
+    Ok(patch_text_nodes(doc, &patches)?)
+}
+
+/// Indices of the non-blank paragraphs, grouped into batches of at most
+/// `max_tokens`; a paragraph over the budget on its own is a batch by itself
+fn batches(paragraphs: &[String], max_tokens: usize) -> Vec<Vec<usize>> {
+    let mut batches = Vec::new();
+    let mut current = Vec::new();
+    let mut tokens = 0;
+    for (i, paragraph) in paragraphs.iter().enumerate() {
+        if paragraph.trim().is_empty() {
+            continue;
+        }
+        let cost = estimate_tokens(paragraph);
+        if !current.is_empty() && tokens + cost > max_tokens {
+            batches.push(std::mem::take(&mut current));
+            tokens = 0;
+        }
+        current.push(i);
+        tokens += cost;
+    }
+    if !current.is_empty() {
+        batches.push(current);
+    }
+    batches
+}
+
+/// `translated` wrapped in the whitespace `original` starts and ends with
+fn keep_outer_whitespace(original: &str, translated: &str) -> String {
+    let leading = &original[..original.len() - original.trim_start().len()];
+    let trailing = &original[original.trim_end().len()..];
+    format!("{leading}{translated}{trailing}")
+}
+
+fn batch_system_message(language: Language) -> String {
+    format!(
+        "You are an AI translator. Translate every paragraph of the JSON array into {language}, \
+         keeping its meaning, tone and Markdown formatting. \
+         Reply with a JSON object {{\"paragraphs\": [...]}} holding one translation per paragraph, in the same order."
+    )
+}
+
+/// Translations of `paragraphs`, in order; a reply with a different number of
+/// paragraphs is a parse error, since it can't be lined up with the document
+async fn translate_batch(
+    llm: &dyn LlmProvider,
+    language: Language,
+    paragraphs: &[&str],
+) -> Result<Vec<String>, RefineError> {
+    let request = ChatCompletion {
+        model: REFINE_MODEL.to_string(),
+        messages: vec![
+            ChatMessage::system(batch_system_message(language)),
+            ChatMessage::user(json!(paragraphs).to_string()),
+        ],
+        format: ResponseFormat::JsonObject,
+        ..Default::default()
+    };
+    let reply = llm.chat_completion("translator", &request).await?;
+    let content = reply
+        .content
+        .ok_or_else(|| RefineError::Parse("No content in Translator response".to_string()))?;
+    let translations: Translations =
+        serde_json::from_str(&content).map_err(|e| RefineError::Parse(e.to_string()))?;
+    if translations.paragraphs.len() != paragraphs.len() {
+        return Err(RefineError::Parse(format!(
+            "Translator returned {} paragraphs for {}",
+            translations.paragraphs.len(),
+            paragraphs.len()
+        )));
+    }
+    Ok(translations.paragraphs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::editor::{export_markdown, import_markdown};
+    use crate::llm::provider::OpenAi;
+
+    #[tokio::test]
+    async fn test_text_is_translated_with_the_target_in_the_prompt() {
+        let (url, received) = crate::llm::openai::mock_openai("Bonjour, le monde");
+
+        let llm = OpenAi::at(&url, "test-key");
+        let output = translate_text_at(&llm, "Hello, world", "fr").await.unwrap();
+        assert_eq!(output, "Bonjour, le monde");
+
+        let body = received.await.unwrap();
+        let system = body["messages"][0]["content"].as_str().unwrap();
+        assert!(system.contains("Translate the existing text into French (fr)."));
+    }
+
+    #[tokio::test]
+    async fn test_document_is_translated_paragraph_by_paragraph() {
+        let doc = Arc::new(Doc::new());
+        import_markdown(&doc, "# Hello, world\n\nWe shipped the **editor**.").unwrap();
+        let reply = json!({ "paragraphs": ["Hola, mundo", "We shipped the release."] });
+        let (url, received) = crate::llm::openai::mock_openai(&reply.to_string());
+
+        let changed = translate_document_at(&OpenAi::at(&url, "test-key"), &doc, "es")
+            .await
+            .unwrap();
+        assert_eq!(changed, 2);
+        // The heading is still a heading, and only the changed words were rewritten
+        assert_eq!(
+            export_markdown(&doc),
+            "# Hola, mundo\n\nWe shipped the release."
+        );
+
+        let body = received.await.unwrap();
+        assert_eq!(body["response_format"]["type"], "json_object");
+        assert_eq!(
+            body["messages"][1]["content"],
+            json!(["Hello, world", "We shipped the editor."]).to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_target_is_rejected_before_any_call() {
+        let doc = Arc::new(Doc::new());
+        import_markdown(&doc, "Hello").unwrap();
+        let llm = OpenAi::at("http://127.0.0.1:9", "test-key");
+        let e = translate_document_at(&llm, &doc, "tlh").await.unwrap_err();
+        assert!(matches!(e, RefineError::UnsupportedLanguage(tag) if tag == "tlh"));
+        assert_eq!(export_markdown(&doc), "Hello");
+    }
+
+    #[test]
+    fn test_batches_skip_blank_paragraphs_and_stay_within_budget() {
+        let long = "word ".repeat(200);
+        let paragraphs: Vec<String> = ["one", "  ", "two", long.as_str(), "three"]
+            .iter()
+            .map(|p| p.to_string())
+            .collect();
+        assert_eq!(
+            batches(&paragraphs, 100),
+            vec![vec![0, 2], vec![3], vec![4]]
+        );
+        assert_eq!(keep_outer_whitespace(" a ", "b"), " b ");
+    }
+}
@@ -0,0 +1,102 @@
+//! Character-safe trimming of document text before it goes into a prompt.
+//!
+//! Slicing a `&str` at a byte offset panics inside a multi-byte character, which
+//! CJK text and emoji hit constantly, so every cut here lands on a char boundary.
+
+/// Rough prompt size in quarter-tokens: an ASCII character is about a quarter of a
+/// token, while a CJK character or emoji is usually a whole one or more.
+fn quarter_tokens(c: char) -> usize {
+    if c.is_ascii() { 1 } else { 4 }
+}
+
+/// Approximate number of tokens `text` costs in a prompt
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().map(quarter_tokens).sum::<usize>().div_ceil(4)
+}
+
+/// The first `max` characters of `text`
+pub fn first_chars(text: &str, max: usize) -> &str {
+    match text.char_indices().nth(max) {
+        Some((end, _)) => &text[..end],
+        None => text,
+    }
+}
+
+/// The last `max` characters of `text`
+pub fn last_chars(text: &str, max: usize) -> &str {
+    if max == 0 {
+        return "";
+    }
+    match text.char_indices().nth_back(max - 1) {
+        Some((start, _)) => &text[start..],
+        None => text,
+    }
+}
+
+/// The start of `text` that fits in roughly `max_tokens`
+pub fn first_tokens(text: &str, max_tokens: usize) -> &str {
+    let budget = max_tokens * 4;
+    let mut spent = 0;
+    for (end, c) in text.char_indices() {
+        spent += quarter_tokens(c);
+        if spent > budget {
+            return &text[..end];
+        }
+    }
+    text
+}
+
+/// The end of `text` that fits in roughly `max_tokens`; the most recent writing
+/// is what the auto-agents comment on, so that is the part kept.
+pub fn last_tokens(text: &str, max_tokens: usize) -> &str {
+    let budget = max_tokens * 4;
+    let mut spent = 0;
+    for (start, c) in text.char_indices().rev() {
+        spent += quarter_tokens(c);
+        if spent > budget {
+            return &text[start + c.len_utf8()..];
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIXED: &str = "Rust 🦀 很好用！絵文字😀もOK 👍🏽";
+
+    #[test]
+    fn test_cuts_never_split_a_character() {
+        let text = MIXED.repeat(200);
+        let total = text.chars().count();
+        for n in 0..total + 2 {
+            assert_eq!(last_chars(&text, n).chars().count(), n.min(total));
+            assert_eq!(first_chars(&text, n).chars().count(), n.min(total));
+        }
+        for max_tokens in 0..600 {
+            let kept = last_tokens(&text, max_tokens);
+            assert!(text.ends_with(kept));
+            assert!(estimate_tokens(kept) <= max_tokens);
+            let kept = first_tokens(&text, max_tokens);
+            assert!(text.starts_with(kept));
+            assert!(estimate_tokens(kept) <= max_tokens);
+        }
+    }
+
+    #[test]
+    fn test_ascii_keeps_four_characters_per_token() {
+        let text = "a".repeat(3000);
+        assert_eq!(last_tokens(&text, 500).len(), 2000);
+        assert_eq!(first_tokens(&text, 500).len(), 2000);
+        assert_eq!(last_tokens("short", 500), "short");
+        assert_eq!(estimate_tokens("abcd"), 1);
+    }
+
+    #[test]
+    fn test_cjk_and_emoji_cost_a_token_each() {
+        let text = "字".repeat(1000);
+        assert_eq!(last_tokens(&text, 500).chars().count(), 500);
+        assert_eq!(estimate_tokens("😀😀"), 2);
+    }
+}
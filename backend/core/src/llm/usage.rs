@@ -0,0 +1,317 @@
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::mpsc;
+
+/// USD per million prompt and completion tokens; a dated model name such as
+/// `gpt-4o-2024-08-06` is priced by its prefix, longest first
+const PRICES_PER_MILLION: &[(&str, f64, f64)] = &[
+    ("gpt-4o-mini", 0.15, 0.60),
+    ("gpt-4o", 2.50, 10.00),
+    ("claude-3-5-haiku", 0.80, 4.00),
+    ("claude-sonnet-4", 3.00, 15.00),
+];
+
+/// A price for the models named `model` or starting with it, in USD per million tokens
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelPrice {
+    pub model: String,
+    pub prompt: f64,
+    pub completion: f64,
+}
+
+impl ModelPrice {
+    /// Parse `model=prompt/completion`, e.g. `gpt-4o=2.5/10` or `llama3.1=0/0`
+    pub fn parse(entry: &str) -> anyhow::Result<Self> {
+        let parsed = entry.split_once('=').and_then(|(model, prices)| {
+            let (prompt, completion) = prices.split_once('/')?;
+            Some(Self {
+                model: model.trim().to_string(),
+                prompt: prompt.trim().parse().ok()?,
+                completion: completion.trim().parse().ok()?,
+            })
+        });
+        parsed
+            .filter(|price| !price.model.is_empty())
+            .ok_or_else(|| anyhow::anyhow!("expected `model=prompt/completion`, got {entry:?}"))
+    }
+}
+
+static PRICES: OnceLock<Vec<ModelPrice>> = OnceLock::new();
+
+/// Price models on top of, or instead of, the built-in table; only the first
+/// call takes effect
+pub fn configure_prices(prices: Vec<ModelPrice>) {
+    if PRICES.set(prices).is_err() {
+        tracing::warn!("Model prices already configured, ignoring");
+    }
+}
+
+/// USD per million prompt and completion tokens for `model`: the longest
+/// configured match, else the longest built-in one
+fn price(model: &str) -> Option<(f64, f64)> {
+    let configured = PRICES
+        .get()
+        .into_iter()
+        .flatten()
+        .filter(|price| model.starts_with(&price.model))
+        .max_by_key(|price| price.model.len())
+        .map(|price| (price.prompt, price.completion));
+    configured.or_else(|| {
+        PRICES_PER_MILLION
+            .iter()
+            .find(|(name, _, _)| model.starts_with(*name))
+            .map(|(_, prompt, completion)| (*prompt, *completion))
+    })
+}
+
+/// Tokens one chat completion used, from the `usage` object OpenAI returns
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+impl TokenUsage {
+    /// The `usage` of a chat completions response body; `None` when it has none
+    pub fn from_response(body: &serde_json::Value) -> Option<Self> {
+        serde_json::from_value(body.get("usage")?.clone()).ok()
+    }
+
+    pub fn total_tokens(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+
+    /// What the call cost in USD; `None` for a model without a known price
+    pub fn cost_usd(&self, model: &str) -> Option<f64> {
+        let (prompt, completion) = price(model)?;
+        Some(
+            (self.prompt_tokens as f64 * prompt + self.completion_tokens as f64 * completion)
+                / 1_000_000.0,
+        )
+    }
+}
+
+/// Usage summed over every call this process made
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct UsageTotals {
+    pub calls: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    /// Cost of the calls to models with a known price
+    pub cost_usd: f64,
+}
+
+/// One call's usage, as handed to the sink for storage
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallUsage {
+    pub tool: &'static str,
+    pub model: String,
+    pub usage: TokenUsage,
+    pub cost_usd: Option<f64>,
+}
+
+/// Calls a sink may fall behind by before further ones are dropped
+pub const SINK_CAPACITY: usize = 1024;
+
+static SINK: OnceLock<mpsc::Sender<CallUsage>> = OnceLock::new();
+
+/// Also send every recorded call to `sink`, e.g. to store it; only the first
+/// call takes effect
+pub fn configure_sink(sink: mpsc::Sender<CallUsage>) {
+    if SINK.set(sink).is_err() {
+        tracing::warn!("Usage sink already configured, ignoring");
+    }
+}
+
+/// Lock-free counters behind [`totals`]; cost is kept in nano-dollars so it can be an integer
+struct UsageCounters {
+    calls: AtomicU64,
+    prompt_tokens: AtomicU64,
+    completion_tokens: AtomicU64,
+    cost_nano_usd: AtomicU64,
+}
+
+static COUNTERS: UsageCounters = UsageCounters {
+    calls: AtomicU64::new(0),
+    prompt_tokens: AtomicU64::new(0),
+    completion_tokens: AtomicU64::new(0),
+    cost_nano_usd: AtomicU64::new(0),
+};
+
+/// Log a finished call's usage under `tool` and on the current span, add it to
+/// the process totals, and hand it to the sink if there is one
+pub fn record(tool: &'static str, model: &str, usage: TokenUsage) {
+    record_to(SINK.get(), tool, model, usage);
+}
+
+/// `record` with the sink given; a full one loses the call rather than block the caller
+fn record_to(
+    sink: Option<&mpsc::Sender<CallUsage>>,
+    tool: &'static str,
+    model: &str,
+    usage: TokenUsage,
+) {
+    let cost = usage.cost_usd(model);
+    let span = tracing::Span::current();
+    span.record("prompt_tokens", usage.prompt_tokens);
+    span.record("completion_tokens", usage.completion_tokens);
+    if let Some(cost) = cost {
+        span.record("cost_usd", cost);
+    }
+    tracing::info!(
+        tool,
+        model,
+        prompt_tokens = usage.prompt_tokens,
+        completion_tokens = usage.completion_tokens,
+        cost_usd = ?cost,
+        "🪙 {} used {} tokens",
+        tool,
+        usage.total_tokens()
+    );
+    metrics::counter!("ai_prompt_tokens_total", "tool" => tool).increment(usage.prompt_tokens);
+    metrics::counter!("ai_completion_tokens_total", "tool" => tool)
+        .increment(usage.completion_tokens);
+
+    COUNTERS.calls.fetch_add(1, Ordering::Relaxed);
+    COUNTERS
+        .prompt_tokens
+        .fetch_add(usage.prompt_tokens, Ordering::Relaxed);
+    COUNTERS
+        .completion_tokens
+        .fetch_add(usage.completion_tokens, Ordering::Relaxed);
+    if let Some(cost) = cost {
+        COUNTERS
+            .cost_nano_usd
+            .fetch_add((cost * 1e9).round() as u64, Ordering::Relaxed);
+    }
+    if let Some(sink) = sink {
+        let call = CallUsage {
+            tool,
+            model: model.to_string(),
+            usage,
+            cost_usd: cost,
+        };
+        if let Err(mpsc::error::TrySendError::Full(_)) = sink.try_send(call) {
+            tracing::warn!("🪙 usage sink is full, {} usage not stored", tool);
+        }
+    }
+}
+
+/// `record` for a response body still in JSON form; the model is the one the
+/// response names. Returns the usage, or `None` when the body has none.
+pub fn record_response(tool: &'static str, body: &serde_json::Value) -> Option<TokenUsage> {
+    let usage = TokenUsage::from_response(body)?;
+    record(tool, body["model"].as_str().unwrap_or("unknown"), usage);
+    Some(usage)
+}
+
+/// What this process has used so far
+pub fn totals() -> UsageTotals {
+    UsageTotals {
+        calls: COUNTERS.calls.load(Ordering::Relaxed),
+        prompt_tokens: COUNTERS.prompt_tokens.load(Ordering::Relaxed),
+        completion_tokens: COUNTERS.completion_tokens.load(Ordering::Relaxed),
+        cost_usd: COUNTERS.cost_nano_usd.load(Ordering::Relaxed) as f64 / 1e9,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_usage_is_parsed_from_a_response_body() {
+        let body = json!({
+            "id": "chatcmpl-123",
+            "model": "gpt-4o-mini-2024-07-18",
+            "choices": [{ "message": { "role": "assistant", "content": "Hi" } }],
+            "usage": { "prompt_tokens": 1000, "completion_tokens": 500, "total_tokens": 1500 }
+        });
+        let before = totals();
+
+        let usage = record_response("linter", &body).unwrap();
+        assert_eq!(
+            usage,
+            TokenUsage {
+                prompt_tokens: 1000,
+                completion_tokens: 500,
+            }
+        );
+        assert_eq!(usage.total_tokens(), 1500);
+
+        // Other tests record too, so only check that this call was counted
+        let after = totals();
+        assert!(after.calls > before.calls);
+        assert!(after.prompt_tokens >= before.prompt_tokens + 1000);
+        assert!(after.cost_usd > before.cost_usd);
+
+        let mock = json!({ "choices": [{ "message": { "content": "Hi" } }] });
+        assert_eq!(record_response("linter", &mock), None);
+    }
+
+    #[test]
+    fn test_cost_uses_the_longest_matching_price() {
+        let usage = TokenUsage {
+            prompt_tokens: 1_000_000,
+            completion_tokens: 1_000_000,
+        };
+        assert_eq!(usage.cost_usd("gpt-4o-mini"), Some(0.75));
+        assert_eq!(usage.cost_usd("gpt-4o-2024-08-06"), Some(12.5));
+        assert_eq!(usage.cost_usd("o1-preview"), None);
+    }
+
+    #[test]
+    fn test_configured_prices_come_first() {
+        assert_eq!(
+            ModelPrice::parse("llama3.1 = 0/0").unwrap(),
+            ModelPrice {
+                model: "llama3.1".to_string(),
+                prompt: 0.0,
+                completion: 0.0,
+            }
+        );
+        for bad in ["gpt-4o", "gpt-4o=2.5", "gpt-4o=cheap/10", "=1/2"] {
+            assert!(ModelPrice::parse(bad).is_err(), "{bad}");
+        }
+
+        // The only test that configures prices, so nothing else sees them
+        configure_prices(vec![
+            ModelPrice::parse("llama3.1=0/0").unwrap(),
+            ModelPrice::parse("llama3.1:70b=0.5/1").unwrap(),
+        ]);
+
+        let usage = TokenUsage {
+            prompt_tokens: 1_000_000,
+            completion_tokens: 1_000_000,
+        };
+        assert_eq!(usage.cost_usd("llama3.1:70b"), Some(1.5));
+        assert_eq!(usage.cost_usd("llama3.1:8b"), Some(0.0));
+        // Built-in prices still apply to what isn't configured
+        assert_eq!(usage.cost_usd("gpt-4o-mini"), Some(0.75));
+    }
+
+    #[test]
+    fn test_calls_reach_the_sink_until_it_is_full() {
+        let (sink, mut calls) = mpsc::channel(1);
+        let usage = TokenUsage {
+            prompt_tokens: 120,
+            completion_tokens: 30,
+        };
+        record_to(Some(&sink), "usage-test", "gpt-4o-mini", usage);
+        // Nobody drained the first call, so the second is dropped
+        record_to(Some(&sink), "usage-test", "gpt-4o", usage);
+
+        assert_eq!(
+            calls.try_recv().unwrap(),
+            CallUsage {
+                tool: "usage-test",
+                model: "gpt-4o-mini".to_string(),
+                usage,
+                cost_usd: usage.cost_usd("gpt-4o-mini"),
+            }
+        );
+        assert!(calls.try_recv().is_err());
+    }
+}
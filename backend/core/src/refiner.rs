@@ -1,2 +1,4 @@
+pub mod error;
+pub mod language;
 pub mod processor;
 pub mod types;
@@ -0,0 +1,48 @@
+use reqwest::StatusCode;
+
+/// Failures surfaced by the refiner and composer, so callers can pick a
+/// user-facing message without inspecting error strings.
+#[derive(Debug, thiserror::Error)]
+pub enum RefineError {
+    #[error("Document has no content structure yet. User needs to create content first.")]
+    NoContentStructure,
+
+    #[error("Directive is no longer present in the document")]
+    DirectiveNotFound,
+
+    #[error("OpenAI rate limit reached")]
+    RateLimited,
+
+    /// A custom instruction that is empty or too long; nothing was sent upstream
+    #[error("Invalid instruction: {0}")]
+    InvalidInstruction(String),
+
+    /// A target language outside `language::SUPPORTED_LANGUAGES`; nothing was sent upstream
+    #[error("Unsupported language: {0}")]
+    UnsupportedLanguage(String),
+
+    #[error("OpenAI returned {0}: {1}")]
+    OpenAiStatus(StatusCode, String),
+
+    #[error("Failed to parse OpenAI response: {0}")]
+    Parse(String),
+
+    #[error("Failed to reach OpenAI: {0}")]
+    Request(#[from] reqwest::Error),
+
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+/// Turn a non-success OpenAI response into the matching `RefineError`
+pub async fn check_response(response: reqwest::Response) -> Result<reqwest::Response, RefineError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    if status == StatusCode::TOO_MANY_REQUESTS {
+        return Err(RefineError::RateLimited);
+    }
+    let body = response.text().await.unwrap_or_default();
+    Err(RefineError::OpenAiStatus(status, body))
+}
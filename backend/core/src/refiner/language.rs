@@ -0,0 +1,66 @@
+use crate::refiner::error::RefineError;
+
+/// BCP-47 tags a caller may force output into, with the name the prompts use
+pub const SUPPORTED_LANGUAGES: &[(&str, &str)] = &[
+    ("en", "English"),
+    ("zh-TW", "Traditional Chinese (Taiwan)"),
+    ("zh-CN", "Simplified Chinese (China)"),
+    ("ja", "Japanese"),
+    ("ko", "Korean"),
+    ("es", "Spanish"),
+    ("fr", "French"),
+    ("de", "German"),
+];
+
+/// A language from `SUPPORTED_LANGUAGES`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Language {
+    pub tag: &'static str,
+    pub name: &'static str,
+}
+
+impl Language {
+    /// Look up `tag` in the allow-list; tags compare case-insensitively, as BCP-47 specifies
+    pub fn parse(tag: &str) -> Result<Self, RefineError> {
+        let tag = tag.trim();
+        SUPPORTED_LANGUAGES
+            .iter()
+            .find(|(known, _)| known.eq_ignore_ascii_case(tag))
+            .map(|&(tag, name)| Self { tag, name })
+            .ok_or_else(|| RefineError::UnsupportedLanguage(tag.to_string()))
+    }
+
+    /// `None` stays `None`: without a language the output follows the input's
+    pub fn parse_optional(tag: Option<&str>) -> Result<Option<Self>, RefineError> {
+        tag.map(Self::parse).transpose()
+    }
+}
+
+impl std::fmt::Display for Language {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({})", self.name, self.tag)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_is_case_insensitive_and_canonicalizes() {
+        let language = Language::parse(" zh-tw ").unwrap();
+        assert_eq!(language.tag, "zh-TW");
+        assert_eq!(language.to_string(), "Traditional Chinese (Taiwan) (zh-TW)");
+    }
+
+    #[test]
+    fn test_unknown_tags_are_rejected() {
+        for tag in ["klingon", "zh", "", "en-US"] {
+            assert!(matches!(
+                Language::parse(tag),
+                Err(RefineError::UnsupportedLanguage(_))
+            ));
+        }
+        assert_eq!(Language::parse_optional(None).unwrap(), None);
+    }
+}
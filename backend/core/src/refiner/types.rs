@@ -1,6 +1,12 @@
 #[derive(Debug)]
 pub struct RefineInput {
     pub content: String,
+    /// BCP-47 tag to write the output in; `None` keeps the input's language
+    pub language: Option<String>,
+    /// Free-form tone such as "formal" or "casual"; passed to the prompt as given
+    pub tone: Option<String>,
+    /// Who the text is for, e.g. "new hires"; passed to the prompt as given
+    pub audience: Option<String>,
 }
 
 #[derive(Debug)]
@@ -0,0 +1,204 @@
+use super::*;
+use atb_types::{DateTime, Utc, Uuid};
+use std::time::Duration;
+
+/// The document every editor shares; suggestions are recorded against it
+pub const SHARED_DOC: &str = "shared";
+
+/// How much of a suggestion's input and output the history keeps
+pub const PREVIEW_CHARS: usize = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AiEventStatus {
+    Ok,
+    Error,
+    Cancelled,
+}
+
+impl AiEventStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Ok => "ok",
+            Self::Error => "error",
+            Self::Cancelled => "cancelled",
+        }
+    }
+}
+
+/// One finished AI call, ready to be written to the history
+#[derive(Debug, Clone, PartialEq)]
+pub struct NewAiEvent {
+    pub doc_id: String,
+    pub tool: String,
+    pub input_preview: String,
+    pub output_preview: String,
+    pub status: AiEventStatus,
+    pub latency: Duration,
+}
+
+impl NewAiEvent {
+    /// An event on the shared document; `input` and `output` are cut to `PREVIEW_CHARS`
+    pub fn new(
+        tool: impl Into<String>,
+        input: &str,
+        output: &str,
+        status: AiEventStatus,
+        latency: Duration,
+    ) -> Self {
+        Self {
+            doc_id: SHARED_DOC.to_string(),
+            tool: tool.into(),
+            input_preview: preview(input),
+            output_preview: preview(output),
+            status,
+            latency,
+        }
+    }
+}
+
+fn preview(text: &str) -> String {
+    text.trim().chars().take(PREVIEW_CHARS).collect()
+}
+
+/// One row of the AI suggestion history
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct AiEventRecord {
+    pub id: Uuid,
+    pub doc_id: String,
+    pub tool: String,
+    pub input_preview: String,
+    pub output_preview: String,
+    pub status: String,
+    pub latency_ms: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+pub async fn record_ai_event(pool: &PgPool, event: &NewAiEvent) -> sqlx::Result<AiEventRecord> {
+    sqlx::query_as(
+        "INSERT INTO ai_events \
+         (id, doc_id, tool, input_preview, output_preview, status, latency_ms) \
+         VALUES ($1, $2, $3, $4, $5, $6, $7) \
+         RETURNING id, doc_id, tool, input_preview, output_preview, status, latency_ms, created_at",
+    )
+    .bind(Uuid::new_v4())
+    .bind(&event.doc_id)
+    .bind(&event.tool)
+    .bind(&event.input_preview)
+    .bind(&event.output_preview)
+    .bind(event.status.as_str())
+    .bind(i64::try_from(event.latency.as_millis()).unwrap_or(i64::MAX))
+    .fetch_one(pool)
+    .await
+}
+
+/// Write `event` in the background so the AI response never waits on the
+/// database; a failed write is only logged
+pub fn spawn_record(pool: &PgPool, event: NewAiEvent) {
+    let pool = pool.clone();
+    tokio::spawn(async move {
+        if let Err(e) = record_ai_event(&pool, &event).await {
+            tracing::warn!("📝 could not record {} AI event: {:?}", event.tool, e);
+        }
+    });
+}
+
+/// Up to `limit` events, newest first; `before` pages back from the previous page's
+/// oldest `(created_at, id)`, so events sharing a timestamp are neither skipped nor repeated
+pub async fn list_ai_events(
+    pool: &PgPool,
+    limit: i64,
+    before: Option<(DateTime<Utc>, Uuid)>,
+) -> sqlx::Result<Vec<AiEventRecord>> {
+    sqlx::query_as(
+        "SELECT id, doc_id, tool, input_preview, output_preview, status, latency_ms, created_at \
+         FROM ai_events WHERE $2::timestamptz IS NULL OR (created_at, id) < ($2, $3) \
+         ORDER BY created_at DESC, id DESC LIMIT $1",
+    )
+    .bind(limit)
+    .bind(before.map(|(at, _)| at))
+    .bind(before.map(|(_, id)| id))
+    .fetch_all(pool)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_previews_are_trimmed_and_cut() {
+        let event = NewAiEvent::new(
+            "improve",
+            "  teh text \n",
+            &"字".repeat(PREVIEW_CHARS + 10),
+            AiEventStatus::Ok,
+            Duration::from_millis(1200),
+        );
+        assert_eq!(event.doc_id, SHARED_DOC);
+        assert_eq!(event.input_preview, "teh text");
+        assert_eq!(event.output_preview.chars().count(), PREVIEW_CHARS);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires local postgres on localhost:5432"]
+    async fn test_history_pages_newest_first() {
+        let pool = setup_test_db("ai_events").await.expect("db setup");
+
+        for tool in ["improve", "fix", "translate"] {
+            let event = NewAiEvent::new(
+                tool,
+                "input",
+                "output",
+                AiEventStatus::Ok,
+                Duration::from_millis(5),
+            );
+            record_ai_event(&pool, &event).await.unwrap();
+        }
+
+        let first = list_ai_events(&pool, 2, None).await.unwrap();
+        let tools: Vec<_> = first.iter().map(|e| e.tool.as_str()).collect();
+        assert_eq!(tools, ["translate", "fix"]);
+        assert_eq!(first[0].latency_ms, 5);
+        assert_eq!(first[0].status, "ok");
+
+        let second = list_ai_events(&pool, 2, Some((first[1].created_at, first[1].id)))
+            .await
+            .unwrap();
+        let tools: Vec<_> = second.iter().map(|e| e.tool.as_str()).collect();
+        assert_eq!(tools, ["improve"]);
+
+        // Events written in one transaction share `now()`, and paging still
+        // visits each of them exactly once
+        let mut txn = pool.begin().await.unwrap();
+        for tool in ["summarize", "title", "tone"] {
+            sqlx::query(
+                "INSERT INTO ai_events \
+                 (id, doc_id, tool, input_preview, output_preview, status, latency_ms) \
+                 VALUES ($1, 'shared', $2, '', '', 'ok', 1)",
+            )
+            .bind(Uuid::new_v4())
+            .bind(tool)
+            .execute(&mut *txn)
+            .await
+            .unwrap();
+        }
+        txn.commit().await.unwrap();
+        let mut seen = Vec::new();
+        let mut before = None;
+        loop {
+            let page = list_ai_events(&pool, 1, before).await.unwrap();
+            let Some(event) = page.last() else { break };
+            before = Some((event.created_at, event.id));
+            seen.push(event.tool.clone());
+        }
+        seen.sort();
+        assert_eq!(
+            seen,
+            ["fix", "improve", "summarize", "title", "tone", "translate"]
+        );
+
+        teardown_test_db("ai_events", pool)
+            .await
+            .expect("db teardown");
+    }
+}
@@ -0,0 +1,192 @@
+use super::*;
+use crate::llm::usage::{self, CallUsage, UsageTotals};
+use atb_types::{DateTime, Utc, Uuid};
+use tokio::sync::mpsc;
+
+/// Most calls stored in one INSERT
+const BATCH_SIZE: usize = 100;
+
+/// Store `calls` in one INSERT
+pub async fn record_ai_usage(pool: &PgPool, calls: &[CallUsage]) -> sqlx::Result<()> {
+    let tokens = |count: u64| i64::try_from(count).unwrap_or(i64::MAX);
+    let ids: Vec<Uuid> = calls.iter().map(|_| Uuid::new_v4()).collect();
+    let tools: Vec<&str> = calls.iter().map(|c| c.tool).collect();
+    let models: Vec<&str> = calls.iter().map(|c| c.model.as_str()).collect();
+    let prompt_tokens: Vec<i64> = calls
+        .iter()
+        .map(|c| tokens(c.usage.prompt_tokens))
+        .collect();
+    let completion_tokens: Vec<i64> = calls
+        .iter()
+        .map(|c| tokens(c.usage.completion_tokens))
+        .collect();
+    let costs: Vec<Option<f64>> = calls.iter().map(|c| c.cost_usd).collect();
+    sqlx::query(
+        "INSERT INTO ai_usage (id, tool, model, prompt_tokens, completion_tokens, cost_usd) \
+         SELECT * FROM UNNEST($1::UUID[], $2::TEXT[], $3::TEXT[], $4::BIGINT[], $5::BIGINT[], \
+         $6::DOUBLE PRECISION[])",
+    )
+    .bind(ids)
+    .bind(tools)
+    .bind(models)
+    .bind(prompt_tokens)
+    .bind(completion_tokens)
+    .bind(costs)
+    .execute(pool)
+    .await
+    .and_then(ensure_affected(calls.len() as u64))
+}
+
+/// Store every call `calls` yields until its senders are gone, in batches of
+/// whatever arrived while the last one was written; a failed write is only logged
+pub fn spawn_store(pool: &PgPool, mut calls: mpsc::Receiver<CallUsage>) {
+    let pool = pool.clone();
+    tokio::spawn(async move {
+        let mut batch = Vec::with_capacity(BATCH_SIZE);
+        while calls.recv_many(&mut batch, BATCH_SIZE).await > 0 {
+            if let Err(e) = record_ai_usage(&pool, &batch).await {
+                tracing::warn!("🪙 could not store usage of {} calls: {:?}", batch.len(), e);
+            }
+            batch.clear();
+        }
+    });
+}
+
+/// Store the usage of every AI call this process makes in `pool`
+pub fn store_usage(pool: &PgPool) {
+    let (sink, calls) = mpsc::channel(usage::SINK_CAPACITY);
+    usage::configure_sink(sink);
+    spawn_store(pool, calls);
+}
+
+/// Usage of one tool over a period
+#[derive(Debug, Clone, PartialEq, sqlx::FromRow)]
+pub struct ToolUsageRecord {
+    pub tool: String,
+    pub calls: i64,
+    pub prompt_tokens: i64,
+    pub completion_tokens: i64,
+    /// Cost of the calls to models with a known price
+    pub cost_usd: f64,
+}
+
+/// Usage per tool since `since`, most expensive first
+pub async fn usage_since(
+    pool: &PgPool,
+    since: DateTime<Utc>,
+) -> sqlx::Result<Vec<ToolUsageRecord>> {
+    sqlx::query_as(
+        "SELECT tool, COUNT(*) AS calls, \
+         COALESCE(SUM(prompt_tokens), 0)::BIGINT AS prompt_tokens, \
+         COALESCE(SUM(completion_tokens), 0)::BIGINT AS completion_tokens, \
+         COALESCE(SUM(cost_usd), 0)::DOUBLE PRECISION AS cost_usd \
+         FROM ai_usage WHERE created_at >= $1 \
+         GROUP BY tool ORDER BY cost_usd DESC, tool",
+    )
+    .bind(since)
+    .fetch_all(pool)
+    .await
+}
+
+/// The per-tool rows added up
+pub fn totals(tools: &[ToolUsageRecord]) -> UsageTotals {
+    let sum = |field: fn(&ToolUsageRecord) -> i64| -> u64 {
+        tools.iter().map(|t| field(t).max(0) as u64).sum()
+    };
+    UsageTotals {
+        calls: sum(|t| t.calls),
+        prompt_tokens: sum(|t| t.prompt_tokens),
+        completion_tokens: sum(|t| t.completion_tokens),
+        cost_usd: tools.iter().map(|t| t.cost_usd).sum(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::usage::TokenUsage;
+
+    fn tool_usage(tool: &str, calls: i64, tokens: (i64, i64), cost_usd: f64) -> ToolUsageRecord {
+        ToolUsageRecord {
+            tool: tool.to_string(),
+            calls,
+            prompt_tokens: tokens.0,
+            completion_tokens: tokens.1,
+            cost_usd,
+        }
+    }
+
+    #[test]
+    fn test_totals_add_up_the_tools() {
+        let tools = [
+            tool_usage("linter", 40, (80_000, 20_000), 0.024),
+            tool_usage("improve", 3, (1_500, 900), 0.01275),
+        ];
+        let total = totals(&tools);
+        assert_eq!(total.calls, 43);
+        assert_eq!(total.prompt_tokens, 81_500);
+        assert_eq!(total.completion_tokens, 20_900);
+        assert!((total.cost_usd - 0.03675).abs() < 1e-12);
+
+        assert_eq!(totals(&[]), UsageTotals::default());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires local postgres on localhost:5432"]
+    async fn test_usage_is_aggregated_per_tool() {
+        let pool = setup_test_db("ai_usage").await.expect("db setup");
+        let started = Utc::now();
+
+        let call = |tool, model: &str, prompt_tokens, completion_tokens| {
+            let usage = TokenUsage {
+                prompt_tokens,
+                completion_tokens,
+            };
+            CallUsage {
+                tool,
+                model: model.to_string(),
+                usage,
+                cost_usd: usage.cost_usd(model),
+            }
+        };
+        record_ai_usage(
+            &pool,
+            &[
+                call("linter", "gpt-4o-mini", 1_000, 200),
+                call("linter", "gpt-4o-mini", 3_000, 600),
+                call("improve", "gpt-4o", 500, 300),
+            ],
+        )
+        .await
+        .unwrap();
+        record_ai_usage(&pool, &[call("improve", "llama3.1", 500, 300)])
+            .await
+            .unwrap();
+
+        let tools = usage_since(&pool, started).await.unwrap();
+        let summary: Vec<_> = tools
+            .iter()
+            .map(|t| {
+                (
+                    t.tool.as_str(),
+                    t.calls,
+                    t.prompt_tokens,
+                    t.completion_tokens,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [("improve", 2, 1_000, 600), ("linter", 2, 4_000, 800)]
+        );
+        // 500 * 2.50 / 1M + 300 * 10.00 / 1M; the unpriced call adds only tokens
+        assert!((tools[0].cost_usd - 0.00425).abs() < 1e-12);
+        // 4000 * 0.15 / 1M + 800 * 0.60 / 1M
+        assert!((tools[1].cost_usd - 0.00108).abs() < 1e-12);
+        assert!(usage_since(&pool, Utc::now()).await.unwrap().is_empty());
+
+        teardown_test_db("ai_usage", pool)
+            .await
+            .expect("db teardown");
+    }
+}
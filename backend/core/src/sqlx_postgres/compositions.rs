@@ -0,0 +1,194 @@
+use super::*;
+use crate::editor::ParagraphMode;
+use crate::temporal::WorkflowExecution;
+use atb_types::{DateTime, Utc};
+use std::time::Duration;
+
+/// A compose workflow whose text hasn't reached the document yet. The row
+/// outlives the HTTP process, so a restart can still apply what the worker wrote.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct PendingComposition {
+    pub workflow_id: String,
+    pub run_id: Option<String>,
+    pub directive: Option<String>,
+    pub occurrence: i32,
+    pub paragraph_mode: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl PendingComposition {
+    pub fn execution(&self) -> WorkflowExecution {
+        WorkflowExecution {
+            workflow_id: self.workflow_id.clone(),
+            run_id: self.run_id.clone(),
+        }
+    }
+
+    /// The highlighted directive the text replaces, with which occurrence of it
+    pub fn directive(&self) -> Option<(&str, usize)> {
+        let nth = usize::try_from(self.occurrence).unwrap_or(0);
+        self.directive.as_deref().map(|raw| (raw, nth))
+    }
+
+    /// The stored mode; one this build doesn't know falls back to the default
+    pub fn paragraph_mode(&self) -> ParagraphMode {
+        serde_json::from_value(serde_json::Value::String(self.paragraph_mode.clone()))
+            .unwrap_or_default()
+    }
+}
+
+fn mode_name(mode: ParagraphMode) -> String {
+    match serde_json::to_value(mode) {
+        Ok(serde_json::Value::String(name)) => name,
+        _ => String::new(),
+    }
+}
+
+/// Remember that `execution` will write into the document once it finishes
+pub async fn record_pending(
+    pool: &PgPool,
+    execution: &WorkflowExecution,
+    directive: Option<(&str, usize)>,
+    paragraph_mode: ParagraphMode,
+) -> sqlx::Result<()> {
+    sqlx::query(
+        "INSERT INTO compositions (workflow_id, run_id, directive, occurrence, paragraph_mode) \
+         VALUES ($1, $2, $3, $4, $5)",
+    )
+    .bind(&execution.workflow_id)
+    .bind(&execution.run_id)
+    .bind(directive.map(|(raw, _)| raw))
+    .bind(directive.map_or(0, |(_, nth)| i32::try_from(nth).unwrap_or(i32::MAX)))
+    .bind(mode_name(paragraph_mode))
+    .execute(pool)
+    .await
+    .and_then(ensure_affected(1))
+}
+
+/// Forget a composition once it was applied, failed or was cancelled
+pub async fn finish_pending(pool: &PgPool, workflow_id: &str) -> sqlx::Result<()> {
+    sqlx::query("DELETE FROM compositions WHERE workflow_id = $1")
+        .bind(workflow_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Forget compositions older than `timeout`: their workflows have timed out, so
+/// there is no text left to apply. Returns how many were dropped.
+pub async fn clear_expired(pool: &PgPool, timeout: Duration) -> sqlx::Result<u64> {
+    let result = sqlx::query(
+        "DELETE FROM compositions WHERE created_at < now() - make_interval(secs => $1)",
+    )
+    .bind(timeout.as_secs_f64())
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+/// Every composition still waiting to be applied, oldest first
+pub async fn list_pending(pool: &PgPool) -> sqlx::Result<Vec<PendingComposition>> {
+    sqlx::query_as(
+        "SELECT workflow_id, run_id, directive, occurrence, paragraph_mode, created_at \
+         FROM compositions ORDER BY created_at, workflow_id",
+    )
+    .fetch_all(pool)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn execution(workflow_id: &str) -> WorkflowExecution {
+        WorkflowExecution {
+            workflow_id: workflow_id.to_string(),
+            run_id: Some("run".to_string()),
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "requires local postgres on localhost:5432"]
+    async fn test_pending_compositions_round_trip() {
+        let pool = setup_test_db("compositions").await.expect("db setup");
+
+        record_pending(
+            &pool,
+            &execution("compose_1"),
+            Some(("[add a conclusion]", 1)),
+            ParagraphMode::SplitOnBlankLines,
+        )
+        .await
+        .unwrap();
+        record_pending(
+            &pool,
+            &execution("compose_2"),
+            None,
+            ParagraphMode::default(),
+        )
+        .await
+        .unwrap();
+
+        let pending = list_pending(&pool).await.unwrap();
+        assert_eq!(pending.len(), 2);
+        assert_eq!(pending[0].directive(), Some(("[add a conclusion]", 1)));
+        assert_eq!(
+            pending[0].paragraph_mode(),
+            ParagraphMode::SplitOnBlankLines
+        );
+        assert_eq!(pending[1].directive(), None);
+        assert_eq!(pending[1].execution().run_id.as_deref(), Some("run"));
+
+        finish_pending(&pool, "compose_1").await.unwrap();
+        let left: Vec<_> = list_pending(&pool)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|p| p.workflow_id)
+            .collect();
+        assert_eq!(left, ["compose_2"]);
+
+        teardown_test_db("compositions", pool)
+            .await
+            .expect("db teardown");
+    }
+
+    #[tokio::test]
+    #[ignore = "requires local postgres on localhost:5432"]
+    async fn test_expired_compositions_are_cleared() {
+        let pool = setup_test_db("compositions_expired")
+            .await
+            .expect("db setup");
+        let mode = ParagraphMode::default();
+        record_pending(&pool, &execution("compose_old"), None, mode)
+            .await
+            .unwrap();
+        record_pending(&pool, &execution("compose_new"), None, mode)
+            .await
+            .unwrap();
+        // Started an hour ago, long past its workflow's timeout
+        sqlx::query(
+            "UPDATE compositions SET created_at = now() - interval '1 hour' \
+             WHERE workflow_id = 'compose_old'",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let cleared = clear_expired(&pool, Duration::from_secs(600))
+            .await
+            .unwrap();
+        assert_eq!(cleared, 1);
+        let left: Vec<_> = list_pending(&pool)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|p| p.workflow_id)
+            .collect();
+        assert_eq!(left, ["compose_new"]);
+
+        teardown_test_db("compositions_expired", pool)
+            .await
+            .expect("db teardown");
+    }
+}
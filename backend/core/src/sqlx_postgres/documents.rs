@@ -0,0 +1,172 @@
+use super::*;
+use crate::editor::get_doc_content;
+use atb_types::{DateTime, Utc, Uuid};
+use std::sync::Arc;
+use yrs::{Doc, ReadTxn, StateVector, Transact, Update, updates::decoder::Decode};
+
+/// How much of a document's text list and detail views show
+pub const PREVIEW_CHARS: usize = 280;
+
+/// One stored document; `yjs_state` is the full Yjs update that rebuilds it
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct DocumentRecord {
+    pub id: Uuid,
+    pub title: String,
+    pub created_by: Option<Uuid>,
+    pub yjs_state: Vec<u8>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl DocumentRecord {
+    /// Rebuild the Yjs document from the stored state
+    pub fn load_doc(&self) -> anyhow::Result<Doc> {
+        let doc = Doc::new();
+        let update = Update::decode_v1(&self.yjs_state)?;
+        doc.transact_mut().apply_update(update)?;
+        Ok(doc)
+    }
+
+    /// The first `PREVIEW_CHARS` characters of the document's plain text;
+    /// empty when the stored state can't be decoded
+    pub fn preview(&self) -> String {
+        match self.load_doc() {
+            Ok(doc) => get_doc_content(&Arc::new(doc))
+                .chars()
+                .take(PREVIEW_CHARS)
+                .collect(),
+            Err(e) => {
+                tracing::warn!("📄 document {} has unreadable state: {:?}", self.id, e);
+                String::new()
+            }
+        }
+    }
+}
+
+/// The Yjs state of a document with nothing in it
+pub fn empty_state() -> Vec<u8> {
+    Doc::new()
+        .transact()
+        .encode_state_as_update_v1(&StateVector::default())
+}
+
+pub async fn create_document(
+    pool: &PgPool,
+    title: &str,
+    created_by: Option<Uuid>,
+) -> sqlx::Result<DocumentRecord> {
+    sqlx::query_as(
+        "INSERT INTO documents (id, title, created_by, yjs_state) VALUES ($1, $2, $3, $4) \
+         RETURNING id, title, created_by, yjs_state, updated_at",
+    )
+    .bind(Uuid::new_v4())
+    .bind(title)
+    .bind(created_by)
+    .bind(empty_state())
+    .fetch_one(pool)
+    .await
+}
+
+/// Every document, most recently updated first
+pub async fn list_documents(pool: &PgPool) -> sqlx::Result<Vec<DocumentRecord>> {
+    sqlx::query_as(
+        "SELECT id, title, created_by, yjs_state, updated_at FROM documents \
+         ORDER BY updated_at DESC",
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// `RowNotFound` when there is no such document
+pub async fn get_document(pool: &PgPool, id: Uuid) -> sqlx::Result<DocumentRecord> {
+    sqlx::query_as(
+        "SELECT id, title, created_by, yjs_state, updated_at FROM documents WHERE id = $1",
+    )
+    .bind(id)
+    .fetch_one(pool)
+    .await
+}
+
+/// Replace a document's Yjs state, e.g. when its room is saved
+pub async fn save_document_state(pool: &PgPool, id: Uuid, yjs_state: &[u8]) -> sqlx::Result<()> {
+    sqlx::query("UPDATE documents SET yjs_state = $2, updated_at = now() WHERE id = $1")
+        .bind(id)
+        .bind(yjs_state)
+        .execute(pool)
+        .await
+        .and_then(ensure_affected(1))
+}
+
+/// `RowNotFound` when there is no such document
+pub async fn delete_document(pool: &PgPool, id: Uuid) -> sqlx::Result<()> {
+    sqlx::query("DELETE FROM documents WHERE id = $1")
+        .bind(id)
+        .execute(pool)
+        .await
+        .and_then(ensure_affected(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yrs::{XmlElementPrelim, XmlFragment, XmlTextPrelim};
+
+    fn record(yjs_state: Vec<u8>) -> DocumentRecord {
+        DocumentRecord {
+            id: Uuid::new_v4(),
+            title: "Draft".to_string(),
+            created_by: None,
+            yjs_state,
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_preview_of_empty_and_written_documents() {
+        assert_eq!(record(empty_state()).preview(), "");
+
+        let doc = Doc::new();
+        let fragment = doc.get_or_insert_xml_fragment("content");
+        {
+            let mut txn = doc.transact_mut();
+            let paragraph = fragment.insert(&mut txn, 0, XmlElementPrelim::empty("paragraph"));
+            paragraph.insert(&mut txn, 0, XmlTextPrelim::new("word ".repeat(100)));
+        }
+        let state = doc
+            .transact()
+            .encode_state_as_update_v1(&StateVector::default());
+
+        let preview = record(state).preview();
+        assert_eq!(preview.chars().count(), PREVIEW_CHARS);
+        assert!(preview.starts_with("word word"));
+        assert_eq!(record(vec![0xff, 0x00]).preview(), "", "unreadable state");
+    }
+
+    #[tokio::test]
+    #[ignore = "requires local postgres on localhost:5432"]
+    async fn test_document_crud() {
+        let pool = setup_test_db("documents").await.expect("db setup");
+        let alice = Uuid::new_v4();
+
+        let created = create_document(&pool, "Plan", Some(alice)).await.unwrap();
+        assert_eq!(created.yjs_state, empty_state());
+        assert_eq!(created.created_by, Some(alice));
+
+        let fetched = get_document(&pool, created.id).await.unwrap();
+        assert_eq!(fetched.title, "Plan");
+        assert_eq!(list_documents(&pool).await.unwrap().len(), 1);
+
+        delete_document(&pool, created.id).await.unwrap();
+        assert!(matches!(
+            get_document(&pool, created.id).await,
+            Err(SqlxError::RowNotFound)
+        ));
+        assert!(matches!(
+            delete_document(&pool, created.id).await,
+            Err(SqlxError::RowNotFound)
+        ));
+
+        teardown_test_db("documents", pool)
+            .await
+            .expect("db teardown");
+    }
+}
@@ -1,3 +1,7 @@
+pub mod ai_events;
+pub mod ai_usage;
+pub mod compositions;
+pub mod documents;
 pub mod example;
 
 pub use sqlx::{
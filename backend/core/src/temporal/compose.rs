@@ -0,0 +1,149 @@
+//! The AGENT command as a Temporal workflow, so a composition survives the
+//! HTTP process restarting while OpenAI is still writing.
+//!
+//! The workflow only produces the text; the process holding the live document
+//! applies it once the workflow completes.
+
+use super::openai::{activity_error, openai_api_key};
+use super::{ActContext, ActExitValue, ActivityResult, WfContext, WorkflowResult};
+use crate::llm::provider::{self, ChatMessage, LlmProvider};
+use crate::llm::tools::extender;
+use crate::refiner::error::RefineError;
+use atb_temporal_ext::activity;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use temporalio_common::protos::coresdk::FromJsonPayloadExt;
+
+pub const WF_COMPOSE: &str = "compose";
+
+/// Model calls a composition gets time for, so a timed-out one can be retried
+const COMPOSE_ATTEMPTS: u32 = 3;
+
+/// How long a compose workflow may run before Temporal times it out, so a
+/// stuck one doesn't keep its pending composition around forever
+pub fn compose_timeout() -> Duration {
+    crate::llm::openai::timeout() * COMPOSE_ATTEMPTS
+}
+
+/// What the composer writes from; the OpenAI key stays with the worker
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ComposeInput {
+    pub article_draft: String,
+    pub role: String,
+    /// The instruction of a highlighted `[directive]`; `None` continues the draft
+    pub instruction: Option<String>,
+    /// Earlier turns of the writer's session, oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub history: Vec<ChatMessage>,
+}
+
+pub async fn compose_workflow(ctx: WfContext) -> WorkflowResult<String> {
+    let input = ctx
+        .get_args()
+        .first()
+        .map(ComposeInput::from_json_payload)
+        .transpose()?
+        .ok_or_else(|| anyhow::anyhow!("compose workflow started without input"))?;
+    let text = compose_draft(&ctx, &input)?.run().await?;
+    Ok(text.into())
+}
+
+#[activity]
+pub async fn compose_draft(
+    _ctx: ActContext,
+    input: ComposeInput,
+) -> ActivityResult<ActExitValue<String>> {
+    compose_at(&*provider::configured(openai_api_key()?), &input)
+        .await
+        .map(Into::into)
+        .map_err(activity_error)
+}
+
+/// The activity's work: one extender call on `llm`
+pub async fn compose_at(
+    llm: &dyn LlmProvider,
+    input: &ComposeInput,
+) -> Result<String, RefineError> {
+    extender::execute_tool_at(
+        llm,
+        &input.article_draft,
+        &input.role,
+        input.instruction.as_deref(),
+        &input.history,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(instruction: Option<&str>) -> ComposeInput {
+        ComposeInput {
+            article_draft: "The economy grew.".to_string(),
+            role: "writer".to_string(),
+            instruction: instruction.map(str::to_string),
+            history: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_compose_sends_the_draft_and_returns_the_passage() {
+        let (url, received) = crate::llm::openai::mock_openai("Exports rose sharply.");
+
+        let llm = crate::llm::provider::OpenAi::at(&url, "test-key");
+        let text = compose_at(&llm, &input(Some("expand on exports")))
+            .await
+            .unwrap();
+        assert_eq!(text, "Exports rose sharply.");
+
+        let body = received.await.unwrap();
+        assert_eq!(
+            body["messages"][1]["content"],
+            "Draft for context:\n\nThe economy grew."
+        );
+        assert_eq!(
+            body["messages"][2]["content"],
+            "Instruction: expand on exports"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_follow_up_sees_the_earlier_turns() {
+        let (url, received) = crate::llm::openai::mock_openai("Exports rose sharply.");
+        let llm = crate::llm::provider::OpenAi::at(&url, "test-key");
+        let mut conversation = extender::Conversation::default();
+        let first = input(Some("expand on exports"));
+        let text = compose_at(&llm, &first).await.unwrap();
+        conversation.record(first.instruction.as_deref(), &text);
+        received.await.unwrap();
+
+        let (url, received) = crate::llm::openai::mock_openai("Imports fell.");
+        let llm = crate::llm::provider::OpenAi::at(&url, "test-key");
+        let follow_up = ComposeInput {
+            history: conversation.messages().to_vec(),
+            ..input(None)
+        };
+        compose_at(&llm, &follow_up).await.unwrap();
+
+        let body = received.await.unwrap();
+        assert_eq!(
+            body["messages"][1]["content"],
+            "Instruction: expand on exports"
+        );
+        assert_eq!(body["messages"][2]["role"], "assistant");
+        assert_eq!(body["messages"][2]["content"], "Exports rose sharply.");
+        assert_eq!(body["messages"][3]["content"], "The economy grew.");
+    }
+
+    #[test]
+    fn test_input_without_history_still_parses() {
+        let input: ComposeInput = serde_json::from_value(serde_json::json!({
+            "article_draft": "The economy grew.",
+            "role": "writer",
+            "instruction": null
+        }))
+        .unwrap();
+        assert!(input.history.is_empty());
+    }
+}
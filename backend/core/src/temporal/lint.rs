@@ -0,0 +1,99 @@
+//! HTTP mode's auto-linter call as a Temporal workflow, so transient failures
+//! are retried by Temporal's policy and every attempt shows up in its UI.
+//!
+//! Like compose, the workflow only returns the corrected XML; the process
+//! holding the live document checks it is still current and applies it.
+
+use super::openai::{activity_error, openai_api_key};
+use super::{ActContext, ActExitValue, ActivityResult, WfContext, WorkflowResult};
+use crate::llm::provider::{self, LlmProvider};
+use crate::llm::tools::linter;
+use crate::refiner::error::RefineError;
+use crate::refiner::language::Language;
+use atb_temporal_ext::activity;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use temporalio_common::protos::coresdk::FromJsonPayloadExt;
+
+pub const WF_LINT: &str = "lint";
+
+/// How long a lint may keep retrying: as long as one model call may take, so a
+/// slow local model gets its longer timeout here too
+pub fn lint_timeout() -> Duration {
+    crate::llm::openai::timeout()
+}
+
+/// The XML a lint pass covers, as `linter::lint_scope` took it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LintInput {
+    pub xml: String,
+    /// BCP-47 tag the text is corrected in; `None` keeps its own language
+    pub language: Option<String>,
+    /// Whether the workflow returns corrections as JSON or the corrected XML
+    #[serde(default)]
+    pub mode: linter::LintMode,
+}
+
+pub async fn lint_workflow(ctx: WfContext) -> WorkflowResult<String> {
+    let input = ctx
+        .get_args()
+        .first()
+        .map(LintInput::from_json_payload)
+        .transpose()?
+        .ok_or_else(|| anyhow::anyhow!("lint workflow started without input"))?;
+    let xml = lint_xml(&ctx, &input)?.run().await?;
+    Ok(xml.into())
+}
+
+#[activity]
+pub async fn lint_xml(_ctx: ActContext, input: LintInput) -> ActivityResult<ActExitValue<String>> {
+    lint_at(&*provider::configured(openai_api_key()?), &input)
+        .await
+        .map(Into::into)
+        .map_err(activity_error)
+}
+
+/// The activity's work: one linter call on `llm`
+pub async fn lint_at(llm: &dyn LlmProvider, input: &LintInput) -> Result<String, RefineError> {
+    let language = Language::parse_optional(input.language.as_deref())?;
+    linter::lint_xml_at(llm, &input.xml, language, input.mode).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_lint_sends_the_scope_and_returns_the_corrected_xml() {
+        let (url, received) =
+            crate::llm::openai::mock_openai("<paragraph>Fix the first.</paragraph>");
+        let input = LintInput {
+            xml: "<paragraph>Fix teh first.</paragraph>".to_string(),
+            language: Some("en".to_string()),
+            mode: linter::LintMode::Xml,
+        };
+
+        let llm = crate::llm::provider::OpenAi::at(&url, "test-key");
+        let xml = lint_at(&llm, &input).await.unwrap();
+        assert_eq!(xml, "<paragraph>Fix the first.</paragraph>");
+
+        let body = received.await.unwrap();
+        assert_eq!(body["model"], linter::LINTER_MODEL);
+        assert_eq!(
+            body["messages"][1]["content"],
+            "<paragraph>Fix teh first.</paragraph>"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_language_fails_without_calling_openai() {
+        let input = LintInput {
+            xml: "<paragraph>teh</paragraph>".to_string(),
+            language: Some("xx".to_string()),
+            mode: linter::LintMode::Structured,
+        };
+        let llm = crate::llm::provider::OpenAi::at("http://127.0.0.1:9", "test-key");
+        let err = lint_at(&llm, &input).await.unwrap_err();
+        assert!(matches!(err, RefineError::UnsupportedLanguage(_)));
+    }
+}
@@ -1,10 +1,18 @@
+pub mod compose;
+pub mod lint;
+pub mod openai;
+
 use std::{str::FromStr, time::Duration};
 
 use atb_temporal_ext::activity;
 use atb_types::Uuid;
-use temporalio_client::WorkflowOptions;
+use compose::{ComposeDraftActivity, ComposeInput, WF_COMPOSE, compose_timeout, compose_workflow};
+use lint::{LintInput, LintXmlActivity, WF_LINT, lint_timeout, lint_workflow};
+use serde::Serialize;
+use temporalio_client::{WfClientExt, WorkflowExecutionResult, WorkflowOptions};
+use temporalio_common::protos::coresdk::{AsJsonPayloadExt, FromJsonPayloadExt};
 pub use temporalio_common::{
-    protos::temporal::api::enums::v1::WorkflowIdReusePolicy,
+    protos::temporal::api::enums::v1::{WorkflowExecutionStatus, WorkflowIdReusePolicy},
     telemetry::TelemetryOptions,
     worker::{WorkerConfig, WorkerConfigBuilder, WorkerTaskTypes, WorkerVersioningStrategy},
 };
@@ -57,6 +65,109 @@ impl WorkflowEngine {
             run_id: Some(response.run_id),
         })
     }
+
+    /// Start composing on a worker, for up to `compose_timeout()`; the text comes
+    /// from `compose_result`
+    pub async fn start_compose(&self, input: &ComposeInput) -> anyhow::Result<WorkflowExecution> {
+        let options = WorkflowOptions {
+            execution_timeout: Some(compose_timeout()),
+            ..Default::default()
+        };
+        self.start(WF_COMPOSE, input, options).await
+    }
+
+    /// Wait for a compose workflow and return the text it wrote
+    pub async fn compose_result(&self, execution: &WorkflowExecution) -> anyhow::Result<String> {
+        self.string_result(execution).await
+    }
+
+    /// Stop a compose workflow nobody is waiting on anymore, so its text is never written
+    pub async fn terminate(&self, execution: &WorkflowExecution) -> anyhow::Result<()> {
+        self.client
+            .terminate_workflow_execution(execution.workflow_id.clone(), execution.run_id.clone())
+            .await?;
+        Ok(())
+    }
+
+    /// Lint `input` on a worker, with Temporal retrying transient OpenAI failures
+    /// for up to `lint_timeout()`, and return the corrected XML
+    pub async fn lint(&self, input: &LintInput) -> anyhow::Result<String> {
+        let options = WorkflowOptions {
+            execution_timeout: Some(lint_timeout()),
+            ..Default::default()
+        };
+        let execution = self.start(WF_LINT, input, options).await?;
+        self.string_result(&execution).await
+    }
+
+    /// Start `workflow_type` with `input` as its only argument, under a fresh id
+    async fn start(
+        &self,
+        workflow_type: &str,
+        input: &impl Serialize,
+        options: WorkflowOptions,
+    ) -> anyhow::Result<WorkflowExecution> {
+        let workflow_id = format!("{workflow_type}_{}", Uuid::now_v7());
+        let response = self
+            .client
+            .start_workflow(
+                vec![input.as_json_payload()?],
+                self.task_queue.clone(),
+                workflow_id.clone(),
+                workflow_type.to_string(),
+                None,
+                WorkflowOptions {
+                    id_reuse_policy: WorkflowIdReusePolicy::RejectDuplicate,
+                    ..options
+                },
+            )
+            .await?;
+
+        Ok(WorkflowExecution {
+            workflow_id,
+            run_id: Some(response.run_id),
+        })
+    }
+
+    /// Wait for a workflow that returns a string
+    async fn string_result(&self, execution: &WorkflowExecution) -> anyhow::Result<String> {
+        let handle = self.client.get_untyped_workflow_handle(
+            execution.workflow_id.clone(),
+            execution.run_id.clone().unwrap_or_default(),
+        );
+        match handle.get_workflow_result(Default::default()).await? {
+            WorkflowExecutionResult::Succeeded(payloads) => {
+                let payload = payloads.first().ok_or_else(|| {
+                    anyhow::anyhow!("workflow {} returned nothing", execution.workflow_id)
+                })?;
+                Ok(String::from_json_payload(payload)?)
+            }
+            other => Err(anyhow::anyhow!(
+                "workflow {} did not succeed: {:?}",
+                execution.workflow_id,
+                other
+            )),
+        }
+    }
+
+    /// Where a workflow is in its lifecycle
+    pub async fn workflow_status(&self, workflow_id: &str) -> anyhow::Result<WorkflowStatus> {
+        let response = self
+            .client
+            .describe_workflow_execution(workflow_id.to_string(), None)
+            .await?;
+        let status = response
+            .workflow_execution_info
+            .map(|info| info.status)
+            .unwrap_or_default();
+        Ok(WorkflowStatus::from_proto(status))
+    }
+
+    /// Cheapest round trip to the Temporal frontend, for readiness probes
+    pub async fn ping(&self) -> anyhow::Result<()> {
+        self.client.list_namespaces().await?;
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -65,6 +176,35 @@ pub struct WorkflowExecution {
     pub run_id: Option<String>,
 }
 
+/// A workflow's execution status, as clients polling it see it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WorkflowStatus {
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+    Terminated,
+    TimedOut,
+    ContinuedAsNew,
+    Unknown,
+}
+
+impl WorkflowStatus {
+    fn from_proto(status: i32) -> Self {
+        match WorkflowExecutionStatus::try_from(status) {
+            Ok(WorkflowExecutionStatus::Running) => Self::Running,
+            Ok(WorkflowExecutionStatus::Completed) => Self::Completed,
+            Ok(WorkflowExecutionStatus::Failed) => Self::Failed,
+            Ok(WorkflowExecutionStatus::Canceled) => Self::Cancelled,
+            Ok(WorkflowExecutionStatus::Terminated) => Self::Terminated,
+            Ok(WorkflowExecutionStatus::TimedOut) => Self::TimedOut,
+            Ok(WorkflowExecutionStatus::ContinuedAsNew) => Self::ContinuedAsNew,
+            _ => Self::Unknown,
+        }
+    }
+}
+
 /// Create a Temporal client, retrying until the timeout elapses.
 pub async fn try_connect_temporal(
     temporal_url: &str,
@@ -100,6 +240,10 @@ impl TemporalWorker {
     pub fn new(mut worker: Worker) -> anyhow::Result<Self> {
         worker.register_wf(WF_HEALTH_CHECK, health_check_workflow);
         HealthCheckActivity::bind(&mut worker);
+        worker.register_wf(WF_COMPOSE, compose_workflow);
+        ComposeDraftActivity::bind(&mut worker);
+        worker.register_wf(WF_LINT, lint_workflow);
+        LintXmlActivity::bind(&mut worker);
 
         Ok(Self { inner: worker })
     }
@@ -179,6 +323,7 @@ mod tests {
 
     const TASK_QUEUE: &str = "backend-template-tests";
     const HEALTH_CHECK_ACTIVITY: &str = "health_check";
+    const LINT_ACTIVITY: &str = "lint_xml";
 
     #[derive(Debug, Clone, PartialEq, Eq)]
     enum ActivityOutcome {
@@ -254,6 +399,29 @@ mod tests {
         HealthCheckActivity::handler(ctx, payload).await
     }
 
+    /// Stands in for the OpenAI call: "corrects" the XML by echoing it back
+    async fn interceptable_lint(
+        ctx: ActContext,
+        input: LintInput,
+    ) -> ActivityResult<ActExitValue<String>> {
+        let info = ctx.get_info();
+        if let Some(wf) = &info.workflow_execution {
+            match next_outcome(&wf.workflow_id, &info.activity_type) {
+                Some(ActivityOutcome::FailRetryable(msg)) => {
+                    return Err(ActivityError::Retryable {
+                        source: anyhow!(msg),
+                        explicit_delay: None,
+                    });
+                }
+                Some(ActivityOutcome::FailNonRetryable(msg)) => {
+                    return Err(ActivityError::NonRetryable(anyhow!(msg)));
+                }
+                Some(ActivityOutcome::Succeed) | None => {}
+            }
+        }
+        Ok(input.xml.into())
+    }
+
     struct Harness {
         client: TemporalClient,
         task_queue: String,
@@ -330,6 +498,8 @@ mod tests {
                         let mut worker = Worker::new_from_core(core_worker, TASK_QUEUE.to_string());
                         worker.register_wf(WF_HEALTH_CHECK, health_check_workflow);
                         worker.register_activity(HEALTH_CHECK_ACTIVITY, interceptable_health_check);
+                        worker.register_wf(WF_LINT, lint_workflow);
+                        worker.register_activity(LINT_ACTIVITY, interceptable_lint);
                         let shutdown = worker.shutdown_handle();
                         let _ = shutdown_tx.send(shutdown);
                         worker.run().await.expect("worker run");
@@ -470,4 +640,98 @@ mod tests {
 
         assert!(matches!(result, WorkflowExecutionResult::Failed(_)));
     }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn lint_is_retried_after_a_transient_failure() {
+        let h = Harness::init().await;
+        let wf_id = format!("wf-lint-retry-{}", Uuid::new_v4());
+
+        set_activity_scenario(
+            &wf_id,
+            LINT_ACTIVITY,
+            [
+                ActivityOutcome::FailRetryable("openai returned 503".into()),
+                ActivityOutcome::Succeed,
+            ],
+        );
+
+        let input = LintInput {
+            xml: "<paragraph>Fix teh first.</paragraph>".to_string(),
+            language: None,
+            mode: crate::llm::tools::linter::LintMode::Xml,
+        };
+        let run = h
+            .client
+            .start_workflow(
+                vec![input.as_json_payload().unwrap()],
+                h.task_queue.clone(),
+                wf_id.clone(),
+                WF_LINT.to_string(),
+                None,
+                WorkflowOptions::default(),
+            )
+            .await
+            .expect("start workflow");
+
+        let handle = h
+            .client
+            .get_untyped_workflow_handle(wf_id, run.run_id.clone());
+        let result = handle
+            .get_workflow_result(Default::default())
+            .await
+            .expect("workflow result");
+
+        let WorkflowExecutionResult::Succeeded(payloads) = result else {
+            panic!("lint did not succeed after a retry");
+        };
+        assert_eq!(String::from_json_payload(&payloads[0]).unwrap(), input.xml);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn lint_is_not_retried_after_a_permanent_failure() {
+        let h = Harness::init().await;
+        let wf_id = format!("wf-lint-permanent-{}", Uuid::new_v4());
+
+        // A second outcome left queued proves the policy never made another attempt
+        set_activity_scenario(
+            &wf_id,
+            LINT_ACTIVITY,
+            [
+                ActivityOutcome::FailNonRetryable("openai rejected the key".into()),
+                ActivityOutcome::Succeed,
+            ],
+        );
+
+        let input = LintInput {
+            xml: "<paragraph>Fix teh first.</paragraph>".to_string(),
+            language: None,
+            mode: crate::llm::tools::linter::LintMode::Xml,
+        };
+        let run = h
+            .client
+            .start_workflow(
+                vec![input.as_json_payload().unwrap()],
+                h.task_queue.clone(),
+                wf_id.clone(),
+                WF_LINT.to_string(),
+                None,
+                WorkflowOptions::default(),
+            )
+            .await
+            .expect("start workflow");
+
+        let handle = h
+            .client
+            .get_untyped_workflow_handle(wf_id.clone(), run.run_id.clone());
+        let result = handle
+            .get_workflow_result(Default::default())
+            .await
+            .expect("workflow result");
+
+        assert!(matches!(result, WorkflowExecutionResult::Failed(_)));
+        assert_eq!(
+            next_outcome(&wf_id, LINT_ACTIVITY),
+            Some(ActivityOutcome::Succeed)
+        );
+    }
 }
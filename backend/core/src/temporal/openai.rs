@@ -0,0 +1,59 @@
+//! What the activities that call OpenAI share: the worker's key, and which
+//! failures Temporal should retry.
+
+use super::ActivityError;
+use crate::refiner::error::RefineError;
+use std::sync::OnceLock;
+
+static API_KEY: OnceLock<String> = OnceLock::new();
+
+/// Set the OpenAI key activities use; only the first call takes effect
+pub fn configure_api_key(api_key: impl Into<String>) {
+    if API_KEY.set(api_key.into()).is_err() {
+        tracing::warn!("Activity OpenAI key already configured, ignoring");
+    }
+}
+
+/// The configured key; without one the activity fails for good
+pub(crate) fn openai_api_key() -> Result<&'static str, ActivityError> {
+    API_KEY.get().map(String::as_str).ok_or_else(|| {
+        ActivityError::NonRetryable(anyhow::anyhow!("no OpenAI key configured for this worker"))
+    })
+}
+
+/// Let Temporal retry what may pass on its own: rate limits, transport errors and 5xx
+pub(crate) fn activity_error(e: RefineError) -> ActivityError {
+    let retryable = match &e {
+        RefineError::RateLimited | RefineError::Request(_) => true,
+        RefineError::OpenAiStatus(status, _) => status.is_server_error(),
+        _ => false,
+    };
+    if retryable {
+        ActivityError::Retryable {
+            source: e.into(),
+            explicit_delay: None,
+        }
+    } else {
+        ActivityError::NonRetryable(e.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_transient_failures_are_retried() {
+        let retried = |e| matches!(activity_error(e), ActivityError::Retryable { .. });
+        assert!(retried(RefineError::RateLimited));
+        assert!(retried(RefineError::OpenAiStatus(
+            reqwest::StatusCode::BAD_GATEWAY,
+            String::new()
+        )));
+        assert!(!retried(RefineError::OpenAiStatus(
+            reqwest::StatusCode::UNAUTHORIZED,
+            String::new()
+        )));
+        assert!(!retried(RefineError::Parse("no content".to_string())));
+    }
+}